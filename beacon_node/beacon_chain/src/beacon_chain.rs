@@ -1,24 +1,27 @@
 use crate::checkpoint::CheckPoint;
+use crate::epoch_duties::{EpochDuties, EpochDutiesCache};
 use crate::errors::{BeaconChainError as Error, BlockProductionError};
 use db::{
-    stores::{BeaconBlockStore, BeaconStateStore},
+    stores::{BeaconBlockStore, BeaconStateStore, ForkChoiceStore, OperationPoolStore},
     ClientDB, DBError,
 };
 use fork_choice::{ForkChoice, ForkChoiceError};
 use log::{debug, trace};
 use operation_pool::DepositInsertStatus;
-use operation_pool::OperationPool;
+use operation_pool::{OperationCounts, OperationPool, PersistedOperationPool};
 use parking_lot::{RwLock, RwLockReadGuard};
 use slot_clock::SlotClock;
-use ssz::ssz_encode;
+use ssz::{decode, ssz_encode};
 use state_processing::per_block_processing::errors::{
     AttestationValidationError, AttesterSlashingValidationError, DepositValidationError,
     ExitValidationError, ProposerSlashingValidationError, TransferValidationError,
 };
+use state_processing::per_epoch_processing::get_attestation_participants::get_attestation_participants;
 use state_processing::{
     per_block_processing, per_block_processing_without_verifying_block_signature,
     per_slot_processing, BlockProcessingError, SlotProcessingError,
 };
+use std::collections::HashSet;
 use std::sync::Arc;
 use types::*;
 
@@ -86,6 +89,8 @@ impl BlockProcessingOutcome {
 pub struct BeaconChain<T: ClientDB + Sized, U: SlotClock, F: ForkChoice> {
     pub block_store: Arc<BeaconBlockStore<T>>,
     pub state_store: Arc<BeaconStateStore<T>>,
+    pub op_pool_store: Arc<OperationPoolStore<T>>,
+    pub fork_choice_store: Arc<ForkChoiceStore<T>>,
     pub slot_clock: U,
     pub op_pool: OperationPool,
     canonical_head: RwLock<CheckPoint>,
@@ -93,6 +98,9 @@ pub struct BeaconChain<T: ClientDB + Sized, U: SlotClock, F: ForkChoice> {
     pub state: RwLock<BeaconState>,
     pub spec: ChainSpec,
     pub fork_choice: RwLock<F>,
+    duties_cache: RwLock<EpochDutiesCache>,
+    /// The total number of operations pruned from the `op_pool`.
+    op_pool_pruned: RwLock<OperationCounts>,
 }
 
 impl<T, U, F> BeaconChain<T, U, F>
@@ -105,6 +113,8 @@ where
     pub fn from_genesis(
         state_store: Arc<BeaconStateStore<T>>,
         block_store: Arc<BeaconBlockStore<T>>,
+        op_pool_store: Arc<OperationPoolStore<T>>,
+        fork_choice_store: Arc<ForkChoiceStore<T>>,
         slot_clock: U,
         mut genesis_state: BeaconState,
         genesis_block: BeaconBlock,
@@ -135,6 +145,8 @@ where
         Ok(Self {
             block_store,
             state_store,
+            op_pool_store,
+            fork_choice_store,
             slot_clock,
             op_pool: OperationPool::new(),
             state: RwLock::new(genesis_state),
@@ -142,6 +154,8 @@ where
             canonical_head,
             spec,
             fork_choice: RwLock::new(fork_choice),
+            duties_cache: RwLock::new(EpochDutiesCache::default()),
+            op_pool_pruned: RwLock::new(OperationCounts::default()),
         })
     }
 
@@ -153,7 +167,7 @@ where
             .iter()
             .map(|root| match self.get_block(root)? {
                 Some(block) => Ok(block.body),
-                None => Err(Error::DBInconsistent("Missing block".into())),
+                None => Err(Error::MissingBeaconBlock(*root)),
             })
             .collect();
 
@@ -168,7 +182,7 @@ where
             .iter()
             .map(|root| match self.get_block(root)? {
                 Some(block) => Ok(block.block_header()),
-                None => Err(Error::DBInconsistent("Missing block".into())),
+                None => Err(Error::MissingBeaconBlock(*root)),
             })
             .collect();
 
@@ -265,6 +279,39 @@ where
         Ok(self.block_store.get_deserialized(block_root)?)
     }
 
+    /// Returns the SSZ encoding of the state at the given root, if any.
+    ///
+    /// The state is not decoded, so that it may be served to peers cheaply.
+    ///
+    /// ## Errors
+    ///
+    /// May return a database error.
+    pub fn get_state_ssz(&self, state_root: &Hash256) -> Result<Option<Vec<u8>>, Error> {
+        Ok(self.state_store.get(state_root)?)
+    }
+
+    /// Writes `state` to the state store under its root, which is returned.
+    ///
+    /// ## Errors
+    ///
+    /// May return a database error.
+    pub fn store_state(&self, state: &BeaconState) -> Result<Hash256, Error> {
+        let state_root = state.canonical_root();
+        self.state_store.put(&state_root, &ssz_encode(state)[..])?;
+        Ok(state_root)
+    }
+
+    /// Writes `block` to the block store under its root, which is returned.
+    ///
+    /// ## Errors
+    ///
+    /// May return a database error.
+    pub fn store_block(&self, block: &BeaconBlock) -> Result<Hash256, Error> {
+        let block_root = block.block_header().canonical_root();
+        self.block_store.put(&block_root, &ssz_encode(block)[..])?;
+        Ok(block_root)
+    }
+
     /// Update the canonical head to some new values.
     pub fn update_canonical_head(
         &self,
@@ -315,8 +362,15 @@ where
 
         state.build_all_caches(&self.spec)?;
 
+        let previous_epoch = self.state.read().current_epoch(&self.spec);
+        let new_epoch = state.current_epoch(&self.spec);
+
         *self.state.write() = state;
 
+        if new_epoch > previous_epoch {
+            self.prune_op_pool_at_epoch_transition();
+        }
+
         Ok(())
     }
 
@@ -327,18 +381,27 @@ where
             _ => return Err(Error::UnableToReadSlot),
         };
 
-        let mut state = self.state.write();
+        let epoch_transition = {
+            let mut state = self.state.write();
+            let previous_epoch = state.current_epoch(&self.spec);
 
-        // If required, transition the new state to the present slot.
-        for _ in state.slot.as_u64()..present_slot.as_u64() {
-            // Ensure the next epoch state caches are built in case of an epoch transition.
-            state.build_epoch_cache(RelativeEpoch::NextWithoutRegistryChange, &self.spec)?;
-            state.build_epoch_cache(RelativeEpoch::NextWithRegistryChange, &self.spec)?;
+            // If required, transition the new state to the present slot.
+            for _ in state.slot.as_u64()..present_slot.as_u64() {
+                // Ensure the next epoch state caches are built in case of an epoch transition.
+                state.build_epoch_cache(RelativeEpoch::NextWithoutRegistryChange, &self.spec)?;
+                state.build_epoch_cache(RelativeEpoch::NextWithRegistryChange, &self.spec)?;
 
-            per_slot_processing(&mut *state, &self.spec)?;
-        }
+                per_slot_processing(&mut *state, &self.spec)?;
+            }
 
-        state.build_all_caches(&self.spec)?;
+            state.build_all_caches(&self.spec)?;
+
+            state.current_epoch(&self.spec) > previous_epoch
+        };
+
+        if epoch_transition {
+            self.prune_op_pool_at_epoch_transition();
+        }
 
         Ok(())
     }
@@ -375,6 +438,93 @@ where
         self.finalized_head.read()
     }
 
+    /// Updates the finalized head if the state of the canonical head has finalized an epoch later
+    /// than the present finalized head.
+    ///
+    /// Whenever the finalized head is updated, the `op_pool` is pruned against the new finalized
+    /// state.
+    fn update_finalized_head_from_canonical_head(&self) -> Result<(), Error> {
+        let (finalized_epoch, finalized_root) = {
+            let head = self.head();
+            (
+                head.beacon_state.finalized_epoch,
+                head.beacon_state.finalized_root,
+            )
+        };
+
+        let present_finalized_epoch = self
+            .finalized_head()
+            .beacon_block
+            .slot
+            .epoch(self.spec.slots_per_epoch);
+
+        if (finalized_epoch <= present_finalized_epoch)
+            || (finalized_root == self.finalized_head().beacon_block_root)
+        {
+            return Ok(());
+        }
+
+        let block = self
+            .block_store
+            .get_deserialized(&finalized_root)?
+            .ok_or_else(|| Error::MissingBeaconBlock(finalized_root))?;
+        let state_root = block.state_root;
+        let state = self
+            .state_store
+            .get_deserialized(&state_root)?
+            .ok_or_else(|| Error::MissingBeaconState(state_root))?;
+
+        debug!(
+            "Finalized head advanced from epoch {} to epoch {}",
+            present_finalized_epoch, finalized_epoch
+        );
+
+        let pruned = self.op_pool.prune_all(&state, &self.spec);
+        self.record_op_pool_pruned("finalization", pruned);
+
+        self.update_finalized_head(block, finalized_root, state, state_root);
+
+        self.fork_choice
+            .write()
+            .update_finalized_root(&finalized_root, &self.spec)?;
+
+        Ok(())
+    }
+
+    /// Prunes the `op_pool` of operations which can no longer be included in a block produced
+    /// from the present state.
+    ///
+    /// Only time-dependent operations (attestations and transfers) are pruned against the present
+    /// state, other operations are pruned when the finalized head advances.
+    fn prune_op_pool_at_epoch_transition(&self) {
+        let state = self.state.read();
+
+        let pruned = OperationCounts {
+            attestations: self.op_pool.prune_attestations(&state, &self.spec),
+            transfers: self.op_pool.prune_transfers(&state),
+            ..OperationCounts::default()
+        };
+
+        self.record_op_pool_pruned("epoch_transition", pruned);
+    }
+
+    /// Logs the result of an `op_pool` prune and adds it to the running total.
+    fn record_op_pool_pruned(&self, reason: &str, pruned: OperationCounts) {
+        debug!(
+            "Pruned {} operations from the op pool ({}): {:?}",
+            pruned.total(),
+            reason,
+            pruned
+        );
+
+        *self.op_pool_pruned.write() += pruned;
+    }
+
+    /// Returns the total number of operations pruned from the `op_pool` since startup.
+    pub fn op_pool_pruned(&self) -> OperationCounts {
+        *self.op_pool_pruned.read()
+    }
+
     /// Returns the validator index (if any) for the given public key.
     ///
     /// Information is retrieved from the present `beacon_state.validator_registry`.
@@ -473,6 +623,99 @@ where
         }
     }
 
+    /// Returns the block proposers and attestation duties of all validators during `epoch`.
+    ///
+    /// Future epochs are computed by advancing a clone of the present state, as long as `epoch`
+    /// is within the seed lookahead of the present epoch. Past epochs are computed from the
+    /// historical state at the start of `epoch`.
+    ///
+    /// Results are cached per epoch and shuffling seed.
+    pub fn epoch_duties(&self, epoch: Epoch) -> Result<Arc<EpochDuties>, Error> {
+        let base_block_root = self.epoch_duties_base_block_root(epoch)?;
+
+        if let Some(duties) = self.duties_cache.read().get(epoch, base_block_root) {
+            return Ok(duties);
+        }
+
+        let state = self.state_at_epoch_start(epoch, base_block_root)?;
+        let duties = Arc::new(EpochDuties::from_state(&state, &self.spec)?);
+
+        self.duties_cache
+            .write()
+            .insert(base_block_root, duties.clone());
+
+        Ok(duties)
+    }
+
+    /// Returns the root of the block from which the duties for `epoch` should be computed.
+    ///
+    /// For present and future epochs this is the canonical head, for past epochs this is the
+    /// latest block at or prior to the first slot of `epoch`.
+    fn epoch_duties_base_block_root(&self, epoch: Epoch) -> Result<Hash256, Error> {
+        let state_epoch = self.state.read().current_epoch(&self.spec);
+        let present_epoch = self
+            .read_slot_clock()
+            .map(|slot| slot.epoch(self.spec.slots_per_epoch))
+            .unwrap_or(state_epoch);
+
+        let max_epoch = std::cmp::max(present_epoch, state_epoch) + self.spec.min_seed_lookahead;
+        if epoch > max_epoch {
+            return Err(Error::EpochBeyondLookahead { epoch, max_epoch });
+        }
+
+        if epoch >= state_epoch {
+            Ok(self.head().beacon_block_root)
+        } else {
+            let start_slot = epoch.start_slot(self.spec.slots_per_epoch);
+            let roots = self.get_block_roots(start_slot, 1, 0)?;
+
+            roots
+                .first()
+                .cloned()
+                .ok_or_else(|| BeaconStateError::SlotOutOfBounds.into())
+        }
+    }
+
+    /// Returns a state in `epoch` (with the current epoch cache built), derived by advancing the
+    /// state of the block at `base_block_root`.
+    ///
+    /// If `base_block_root` is the canonical head and `epoch` is not prior to the present state,
+    /// the present state is used as a starting point instead of the state stored in the DB.
+    fn state_at_epoch_start(
+        &self,
+        epoch: Epoch,
+        base_block_root: Hash256,
+    ) -> Result<BeaconState, Error> {
+        let use_present_state = (base_block_root == self.head().beacon_block_root)
+            && (epoch >= self.state.read().current_epoch(&self.spec));
+
+        let mut state = if use_present_state {
+            self.state.read().clone()
+        } else {
+            let block = self
+                .block_store
+                .get_deserialized(&base_block_root)?
+                .ok_or_else(|| Error::MissingBeaconBlock(base_block_root))?;
+            self.state_store
+                .get_deserialized(&block.state_root)?
+                .ok_or_else(|| Error::MissingBeaconState(block.state_root))?
+        };
+
+        let start_slot = epoch.start_slot(self.spec.slots_per_epoch);
+
+        for _ in state.slot.as_u64()..start_slot.as_u64() {
+            // Ensure the next epoch state caches are built in case of an epoch transition.
+            state.build_epoch_cache(RelativeEpoch::NextWithoutRegistryChange, &self.spec)?;
+            state.build_epoch_cache(RelativeEpoch::NextWithRegistryChange, &self.spec)?;
+
+            per_slot_processing(&mut state, &self.spec)?;
+        }
+
+        state.build_epoch_cache(RelativeEpoch::Current, &self.spec)?;
+
+        Ok(state)
+    }
+
     /// Produce an `AttestationData` that is valid for the present `slot` and given `shard`.
     pub fn produce_attestation_data(&self, shard: u64) -> Result<AttestationData, Error> {
         trace!("BeaconChain::produce_attestation: shard: {}", shard);
@@ -526,8 +769,16 @@ where
         &self,
         attestation: Attestation,
     ) -> Result<(), AttestationValidationError> {
+        let state = self.state.read();
+
         self.op_pool
-            .insert_attestation(attestation, &*self.state.read(), &self.spec)
+            .insert_attestation(attestation.clone(), &*state, &self.spec)?;
+
+        if let Err(e) = self.add_attestation_to_fork_choice(&attestation, &*state) {
+            debug!("Unable to add attestation to fork choice: {:?}", e);
+        }
+
+        Ok(())
     }
 
     /// Accept some deposit and queue it for inclusion in an appropriate block.
@@ -565,8 +816,128 @@ where
         &self,
         attester_slashing: AttesterSlashing,
     ) -> Result<(), AttesterSlashingValidationError> {
-        self.op_pool
-            .insert_attester_slashing(attester_slashing, &*self.state.read(), &self.spec)
+        self.op_pool.insert_attester_slashing(
+            attester_slashing.clone(),
+            &*self.state.read(),
+            &self.spec,
+        )?;
+
+        if let Err(e) = self.add_attester_slashing_to_fork_choice(&attester_slashing) {
+            debug!("Unable to add attester slashing to fork choice: {:?}", e);
+        }
+
+        Ok(())
+    }
+
+    /// Adds the votes of all participants in `attestation` to the fork choice, weighted by their
+    /// effective balances in `state`.
+    fn add_attestation_to_fork_choice(
+        &self,
+        attestation: &Attestation,
+        state: &BeaconState,
+    ) -> Result<(), Error> {
+        let participants = get_attestation_participants(
+            state,
+            &attestation.data,
+            &attestation.aggregation_bitfield,
+            &self.spec,
+        )?;
+        let target_epoch = attestation.data.slot.epoch(self.spec.slots_per_epoch);
+
+        let mut fork_choice = self.fork_choice.write();
+        for validator_index in participants {
+            let balance = state.get_effective_balance(validator_index, &self.spec)?;
+            fork_choice.add_attestation(
+                validator_index as u64,
+                &attestation.data.beacon_block_root,
+                target_epoch,
+                balance,
+                &self.spec,
+            )?;
+        }
+
+        Ok(())
+    }
+
+    /// Removes the weight of every validator proven to equivocate by `attester_slashing` from the
+    /// fork choice.
+    fn add_attester_slashing_to_fork_choice(
+        &self,
+        attester_slashing: &AttesterSlashing,
+    ) -> Result<(), Error> {
+        // The indices are controlled by the block proposer, so avoid a quadratic intersection.
+        let indices_2: HashSet<u64> = attester_slashing
+            .slashable_attestation_2
+            .validator_indices
+            .iter()
+            .cloned()
+            .collect();
+
+        let mut fork_choice = self.fork_choice.write();
+        for validator_index in &attester_slashing.slashable_attestation_1.validator_indices {
+            if indices_2.contains(validator_index) {
+                fork_choice.add_equivocation(*validator_index, &self.spec)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Writes all operations in the `op_pool` to the DB, replacing any previously persisted pool.
+    pub fn persist_op_pool(&self) -> Result<(), Error> {
+        let persisted = PersistedOperationPool::from_operation_pool(&self.op_pool);
+
+        self.op_pool_store.put(&ssz_encode(&persisted)[..])?;
+
+        Ok(())
+    }
+
+    /// Loads the operation pool persisted in the DB (if any) into the `op_pool`.
+    ///
+    /// Each operation is re-validated against the present state and stale operations are pruned.
+    /// Returns `None` if there was no persisted pool.
+    pub fn restore_op_pool(&self) -> Result<Option<OperationCounts>, Error> {
+        let ssz = match self.op_pool_store.get()? {
+            Some(ssz) => ssz,
+            None => return Ok(None),
+        };
+
+        let persisted = decode::<PersistedOperationPool>(&ssz)
+            .map_err(|_| Error::DBInconsistent("Bad operation pool SSZ.".to_string()))?;
+
+        Ok(Some(persisted.insert_into(
+            &self.op_pool,
+            &*self.state.read(),
+            &self.spec,
+        )))
+    }
+
+    /// Writes the state of the `fork_choice` to the DB, replacing any previously persisted state.
+    pub fn persist_fork_choice(&self) -> Result<(), Error> {
+        let ssz = self.fork_choice.read().as_ssz_bytes();
+
+        self.fork_choice_store.put(&ssz[..])?;
+
+        Ok(())
+    }
+
+    /// Loads the fork choice state persisted in the DB (if any) into the `fork_choice`.
+    ///
+    /// The `fork_choice` is not modified if the persisted state is invalid or refers to blocks
+    /// which are not in the `block_store`. Returns `false` if there was no persisted state.
+    ///
+    /// The canonical head is recomputed from the restored votes, rather than waiting for the next
+    /// block to arrive.
+    pub fn restore_fork_choice(&self) -> Result<bool, Error> {
+        let ssz = match self.fork_choice_store.get()? {
+            Some(ssz) => ssz,
+            None => return Ok(false),
+        };
+
+        self.fork_choice.write().restore_from_ssz_bytes(&ssz)?;
+        self.fork_choice()?;
+
+        Ok(true)
     }
 
     /// Accept some block and attempt to add it to block DAG.
@@ -646,6 +1017,21 @@ where
             .write()
             .add_block(&block, &block_root, &self.spec)?;
 
+        // Register the votes and equivocations contained in the block with the fork choice.
+        for attestation in &block.body.attestations {
+            if let Err(e) = self.add_attestation_to_fork_choice(attestation, &state) {
+                debug!("Unable to add block attestation to fork choice: {:?}", e);
+            }
+        }
+        for attester_slashing in &block.body.attester_slashings {
+            if let Err(e) = self.add_attester_slashing_to_fork_choice(attester_slashing) {
+                debug!(
+                    "Unable to add block attester slashing to fork choice: {:?}",
+                    e
+                );
+            }
+        }
+
         // If the parent block was the parent_block, automatically update the canonical head.
         //
         // TODO: this is a first-in-best-dressed scenario that is not ideal; fork_choice should be
@@ -655,6 +1041,8 @@ where
 
             // Update the canonical `BeaconState`.
             self.update_state(state)?;
+
+            self.update_finalized_head_from_canonical_head()?;
         }
 
         Ok(BlockProcessingOutcome::ValidBlock(ValidBlock::Processed))
@@ -748,6 +1136,8 @@ where
 
             // Update the canonical `BeaconState`.
             self.update_state(state)?;
+
+            self.update_finalized_head_from_canonical_head()?;
         }
 
         Ok(())
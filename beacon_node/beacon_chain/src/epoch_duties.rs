@@ -0,0 +1,152 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use types::*;
+
+/// The maximum number of `EpochDuties` held in an `EpochDutiesCache` before the oldest epochs are
+/// evicted.
+pub const MAX_CACHED_EPOCHS: usize = 16;
+
+/// The block proposers and attestation duties for every validator during a single epoch.
+#[derive(Debug, PartialEq, Clone)]
+pub struct EpochDuties {
+    /// The epoch these duties apply to.
+    pub epoch: Epoch,
+    /// The shuffling seed that generated these duties.
+    pub shuffling_seed: Hash256,
+    /// The index of the block proposer for each slot in the epoch.
+    pub block_proposers: Vec<usize>,
+    /// Maps validator index to a slot, shard and committee index for attestation.
+    pub attestation_duties: Vec<Option<AttestationDuty>>,
+}
+
+impl EpochDuties {
+    /// Reads the duties for the current epoch of `state`.
+    ///
+    /// Note: Utilizes the cache and will fail if the current epoch cache is not initialized.
+    pub fn from_state(state: &BeaconState, spec: &ChainSpec) -> Result<Self, BeaconStateError> {
+        let epoch = state.current_epoch(spec);
+
+        let block_proposers = epoch
+            .slot_iter(spec.slots_per_epoch)
+            .map(|slot| state.get_beacon_proposer_index(slot, RelativeEpoch::Current, spec))
+            .collect::<Result<Vec<usize>, _>>()?;
+
+        let attestation_duties = (0..state.validator_registry.len())
+            .map(|i| state.get_attestation_duties(i, spec).map(|duty| *duty))
+            .collect::<Result<Vec<Option<AttestationDuty>>, _>>()?;
+
+        Ok(Self {
+            epoch,
+            shuffling_seed: state.current_shuffling_seed,
+            block_proposers,
+            attestation_duties,
+        })
+    }
+
+    /// Returns the slot at which `validator_index` must propose a block, if any.
+    pub fn block_production_slot(&self, validator_index: usize, spec: &ChainSpec) -> Option<Slot> {
+        self.block_proposers
+            .iter()
+            .position(|&proposer| proposer == validator_index)
+            .map(|i| self.epoch.start_slot(spec.slots_per_epoch) + i as u64)
+    }
+
+    /// Returns the attestation duty for `validator_index`.
+    ///
+    /// Returns `None` if the validator is unknown or inactive during this epoch.
+    pub fn attestation_duty(&self, validator_index: usize) -> Option<AttestationDuty> {
+        self.attestation_duties
+            .get(validator_index)
+            .and_then(|duty| *duty)
+    }
+}
+
+/// Caches `EpochDuties` by epoch and shuffling seed.
+///
+/// Duties are looked up via the root of the block the duties were computed from. Several blocks
+/// may share the same shuffling seed for some epoch, in which case they share a single
+/// `EpochDuties`.
+#[derive(Debug, Default)]
+pub struct EpochDutiesCache {
+    /// Maps `(epoch, shuffling_seed)` to the duties for that epoch.
+    duties: HashMap<(Epoch, Hash256), Arc<EpochDuties>>,
+    /// Maps `(epoch, block_root)` to the shuffling seed of `epoch` when built upon `block_root`.
+    seeds: HashMap<(Epoch, Hash256), Hash256>,
+}
+
+impl EpochDutiesCache {
+    /// Returns the duties for `epoch` when computed from `block_root`, if known.
+    pub fn get(&self, epoch: Epoch, block_root: Hash256) -> Option<Arc<EpochDuties>> {
+        let seed = self.seeds.get(&(epoch, block_root))?;
+        self.duties.get(&(epoch, *seed)).cloned()
+    }
+
+    /// Stores some `duties` which were computed from `block_root`.
+    ///
+    /// Evicts the lowest epochs if the cache exceeds `MAX_CACHED_EPOCHS` distinct entries.
+    pub fn insert(&mut self, block_root: Hash256, duties: Arc<EpochDuties>) {
+        let epoch = duties.epoch;
+        let seed = duties.shuffling_seed;
+
+        self.seeds.insert((epoch, block_root), seed);
+        self.duties.entry((epoch, seed)).or_insert(duties);
+
+        while self.duties.len() > MAX_CACHED_EPOCHS {
+            let lowest_epoch = match self.duties.keys().map(|(epoch, _)| *epoch).min() {
+                Some(epoch) => epoch,
+                None => break,
+            };
+            self.prune(lowest_epoch + 1);
+        }
+    }
+
+    /// Removes all duties for epochs lower than `epoch`.
+    pub fn prune(&mut self, epoch: Epoch) {
+        self.duties.retain(|(e, _), _| *e >= epoch);
+        self.seeds.retain(|(e, _), _| *e >= epoch);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn duties(epoch: u64, seed: u64) -> Arc<EpochDuties> {
+        Arc::new(EpochDuties {
+            epoch: Epoch::new(epoch),
+            shuffling_seed: Hash256::from_low_u64_be(seed),
+            block_proposers: vec![],
+            attestation_duties: vec![],
+        })
+    }
+
+    #[test]
+    fn shares_duties_between_roots_with_equal_seeds() {
+        let mut cache = EpochDutiesCache::default();
+
+        let root_a = Hash256::from_low_u64_be(1);
+        let root_b = Hash256::from_low_u64_be(2);
+
+        cache.insert(root_a, duties(3, 42));
+        cache.insert(root_b, duties(3, 42));
+
+        assert_eq!(cache.duties.len(), 1);
+        assert_eq!(cache.get(Epoch::new(3), root_a), Some(duties(3, 42)));
+        assert_eq!(cache.get(Epoch::new(3), root_b), Some(duties(3, 42)));
+        assert_eq!(cache.get(Epoch::new(4), root_a), None);
+    }
+
+    #[test]
+    fn evicts_lowest_epochs() {
+        let mut cache = EpochDutiesCache::default();
+        let root = Hash256::zero();
+
+        for epoch in 0..=MAX_CACHED_EPOCHS as u64 {
+            cache.insert(root, duties(epoch, epoch));
+        }
+
+        assert_eq!(cache.duties.len(), MAX_CACHED_EPOCHS);
+        assert_eq!(cache.get(Epoch::new(0), root), None);
+        assert!(cache.get(Epoch::new(MAX_CACHED_EPOCHS as u64), root).is_some());
+    }
+}
@@ -25,6 +25,11 @@ pub enum BeaconChainError {
     MissingBeaconBlock(Hash256),
     MissingBeaconState(Hash256),
     SlotProcessingError(SlotProcessingError),
+    /// The requested epoch is beyond the seed lookahead of the present epoch.
+    EpochBeyondLookahead {
+        epoch: Epoch,
+        max_epoch: Epoch,
+    },
 }
 
 easy_from_to!(SlotProcessingError, BeaconChainError);
@@ -3,9 +3,9 @@
 // testnet. These are examples. Also. there is code duplication which can/should be cleaned up.
 
 use crate::BeaconChain;
-use db::stores::{BeaconBlockStore, BeaconStateStore};
+use db::stores::{BeaconBlockStore, BeaconStateStore, ForkChoiceStore, OperationPoolStore};
 use db::{DiskDB, MemoryDB};
-use fork_choice::BitwiseLMDGhost;
+use fork_choice::ForkChoice;
 use slot_clock::SystemTimeSlotClock;
 use std::path::PathBuf;
 use std::sync::Arc;
@@ -15,10 +15,18 @@ use types::{BeaconBlock, ChainSpec, Hash256};
 
 //TODO: Correct this for prod
 //TODO: Account for historical db
-pub fn initialise_beacon_chain(
+/// Initialisation of a beacon chain, uses an on-disk db at `db_name`.
+///
+/// The fork choice is built by `fork_choice` from the block and state stores of the new chain.
+pub fn initialise_beacon_chain<F, TForkChoice>(
     spec: &ChainSpec,
     db_name: Option<&PathBuf>,
-) -> Arc<BeaconChain<DiskDB, SystemTimeSlotClock, BitwiseLMDGhost<DiskDB>>> {
+    fork_choice: F,
+) -> Arc<BeaconChain<DiskDB, SystemTimeSlotClock, TForkChoice>>
+where
+    F: FnOnce(Arc<BeaconBlockStore<DiskDB>>, Arc<BeaconStateStore<DiskDB>>) -> TForkChoice,
+    TForkChoice: ForkChoice,
+{
     // set up the db
     let db = Arc::new(DiskDB::open(
         db_name.expect("Database directory must be included"),
@@ -27,6 +35,8 @@ pub fn initialise_beacon_chain(
 
     let block_store = Arc::new(BeaconBlockStore::new(db.clone()));
     let state_store = Arc::new(BeaconStateStore::new(db.clone()));
+    let op_pool_store = Arc::new(OperationPoolStore::new(db.clone()));
+    let fork_choice_store = Arc::new(ForkChoiceStore::new(db.clone()));
 
     let state_builder = TestingBeaconStateBuilder::from_default_keypairs_file_if_exists(8, &spec);
     let (genesis_state, _keypairs) = state_builder.build();
@@ -42,7 +52,7 @@ pub fn initialise_beacon_chain(
     )
     .expect("Unable to load SystemTimeSlotClock");
     // Choose the fork choice
-    let fork_choice = BitwiseLMDGhost::new(block_store.clone(), state_store.clone());
+    let fork_choice = fork_choice(block_store.clone(), state_store.clone());
 
     // Genesis chain
     //TODO: Handle error correctly
@@ -50,6 +60,8 @@ pub fn initialise_beacon_chain(
         BeaconChain::from_genesis(
             state_store.clone(),
             block_store.clone(),
+            op_pool_store,
+            fork_choice_store,
             slot_clock,
             genesis_state,
             genesis_block,
@@ -61,13 +73,22 @@ pub fn initialise_beacon_chain(
 }
 
 /// Initialisation of a test beacon chain, uses an in memory db with fixed genesis time.
-pub fn initialise_test_beacon_chain(
+///
+/// The fork choice is built by `fork_choice` from the block and state stores of the new chain.
+pub fn initialise_test_beacon_chain<F, TForkChoice>(
     spec: &ChainSpec,
     _db_name: Option<&PathBuf>,
-) -> Arc<BeaconChain<MemoryDB, SystemTimeSlotClock, BitwiseLMDGhost<MemoryDB>>> {
+    fork_choice: F,
+) -> Arc<BeaconChain<MemoryDB, SystemTimeSlotClock, TForkChoice>>
+where
+    F: FnOnce(Arc<BeaconBlockStore<MemoryDB>>, Arc<BeaconStateStore<MemoryDB>>) -> TForkChoice,
+    TForkChoice: ForkChoice,
+{
     let db = Arc::new(MemoryDB::open());
     let block_store = Arc::new(BeaconBlockStore::new(db.clone()));
     let state_store = Arc::new(BeaconStateStore::new(db.clone()));
+    let op_pool_store = Arc::new(OperationPoolStore::new(db.clone()));
+    let fork_choice_store = Arc::new(ForkChoiceStore::new(db.clone()));
 
     let state_builder = TestingBeaconStateBuilder::from_default_keypairs_file_if_exists(8, spec);
     let (genesis_state, _keypairs) = state_builder.build();
@@ -83,7 +104,7 @@ pub fn initialise_test_beacon_chain(
     )
     .expect("Unable to load SystemTimeSlotClock");
     // Choose the fork choice
-    let fork_choice = BitwiseLMDGhost::new(block_store.clone(), state_store.clone());
+    let fork_choice = fork_choice(block_store.clone(), state_store.clone());
 
     // Genesis chain
     //TODO: Handle error correctly
@@ -91,6 +112,8 @@ pub fn initialise_test_beacon_chain(
         BeaconChain::from_genesis(
             state_store.clone(),
             block_store.clone(),
+            op_pool_store,
+            fork_choice_store,
             slot_clock,
             genesis_state,
             genesis_block,
@@ -1,11 +1,13 @@
 mod beacon_chain;
 mod checkpoint;
+mod epoch_duties;
 mod errors;
 pub mod initialise;
 pub mod test_utils;
 
 pub use self::beacon_chain::{BeaconChain, BlockProcessingOutcome, InvalidBlock, ValidBlock};
 pub use self::checkpoint::CheckPoint;
+pub use self::epoch_duties::{EpochDuties, EpochDutiesCache};
 pub use self::errors::{BeaconChainError, BlockProductionError};
 pub use db;
 pub use fork_choice;
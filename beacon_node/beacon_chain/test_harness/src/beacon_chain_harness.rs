@@ -2,7 +2,7 @@ use super::ValidatorHarness;
 use beacon_chain::{BeaconChain, BlockProcessingOutcome};
 pub use beacon_chain::{BeaconChainError, CheckPoint};
 use db::{
-    stores::{BeaconBlockStore, BeaconStateStore},
+    stores::{BeaconBlockStore, BeaconStateStore, ForkChoiceStore, OperationPoolStore},
     MemoryDB,
 };
 use fork_choice::BitwiseLMDGhost;
@@ -48,8 +48,10 @@ impl BeaconChainHarness {
         let db = Arc::new(MemoryDB::open());
         let block_store = Arc::new(BeaconBlockStore::new(db.clone()));
         let state_store = Arc::new(BeaconStateStore::new(db.clone()));
+        let op_pool_store = Arc::new(OperationPoolStore::new(db.clone()));
+        let fork_choice_store = Arc::new(ForkChoiceStore::new(db.clone()));
         let slot_clock = TestingSlotClock::new(spec.genesis_slot.as_u64());
-        let fork_choice = BitwiseLMDGhost::new(block_store.clone(), state_store.clone());
+        let fork_choice = BitwiseLMDGhost::new(block_store.clone());
 
         let (mut genesis_state, keypairs) = state_builder.build();
 
@@ -74,6 +76,8 @@ impl BeaconChainHarness {
             BeaconChain::from_genesis(
                 state_store.clone(),
                 block_store.clone(),
+                op_pool_store,
+                fork_choice_store,
                 slot_clock,
                 genesis_state,
                 genesis_block,
@@ -2,6 +2,7 @@
 
 use env_logger::{Builder, Env};
 use log::debug;
+use std::sync::Arc;
 use test_harness::BeaconChainHarness;
 use types::ChainSpec;
 
@@ -44,3 +45,52 @@ fn it_can_produce_past_first_epoch_boundary() {
 
     assert_eq!(dump.len() as u64, blocks + 1); // + 1 for genesis block.
 }
+
+#[test]
+fn it_can_compute_duties_for_the_next_epoch() {
+    let spec = ChainSpec::few_validators();
+    let validator_count = 8;
+
+    let mut harness = BeaconChainHarness::new(spec, validator_count);
+
+    harness.advance_chain_with_block();
+
+    let epoch = harness.epoch();
+    let duties = harness
+        .beacon_chain
+        .epoch_duties(epoch + 1)
+        .expect("Should compute duties for the next epoch.");
+
+    assert_eq!(duties.epoch, epoch + 1);
+    assert_eq!(
+        duties.block_proposers.len() as u64,
+        harness.spec.slots_per_epoch
+    );
+    assert!(duties.attestation_duties.iter().all(Option::is_some));
+
+    let cached = harness.beacon_chain.epoch_duties(epoch + 1).unwrap();
+    assert!(Arc::ptr_eq(&duties, &cached));
+
+    assert!(harness.beacon_chain.epoch_duties(epoch + 2).is_err());
+}
+
+#[test]
+fn it_keeps_the_op_pool_bounded() {
+    let spec = ChainSpec::few_validators();
+    let validator_count = 8;
+
+    let mut harness = BeaconChainHarness::new(spec, validator_count);
+
+    // Crossing two epoch boundaries is enough to see attestations from the first epoch pruned.
+    let blocks = harness.spec.slots_per_epoch * 2 + 1;
+
+    for _ in 0..blocks {
+        harness.advance_chain_with_block();
+
+        // Attestations older than an epoch are pruned at each epoch transition, so the pool
+        // should never hold more than two epochs worth of attestations.
+        assert!(harness.beacon_chain.op_pool.num_attestations() <= 2 * validator_count);
+    }
+
+    assert!(harness.beacon_chain.op_pool_pruned().attestations > 0);
+}
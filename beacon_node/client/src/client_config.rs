@@ -1,7 +1,7 @@
 use clap::ArgMatches;
 use db::DBType;
 use fork_choice::ForkChoiceAlgorithm;
-use network::NetworkConfig;
+use network::{NetworkConfig, NodeKeyType};
 use slog::error;
 use std::fs;
 use std::net::SocketAddr;
@@ -22,6 +22,7 @@ pub struct ClientConfig {
     pub db_type: DBType,
     pub db_name: PathBuf,
     pub rpc_conf: rpc::RPCConfig,
+    pub api_conf: rest_api::ApiConfig,
     //pub ipc_conf:
 }
 
@@ -36,7 +37,8 @@ impl Default for ClientConfig {
             .unwrap_or_else(|_| panic!("Unable to create {:?}", &data_dir));
 
         let default_spec = ChainSpec::lighthouse_testnet();
-        let default_net_conf = NetworkConfig::new(default_spec.boot_nodes.clone());
+        let mut default_net_conf = NetworkConfig::new(default_spec.boot_nodes.clone());
+        default_net_conf.network_dir = Some(data_dir.join("network"));
 
         Self {
             data_dir: data_dir.clone(),
@@ -50,6 +52,7 @@ impl Default for ClientConfig {
             // default db name for disk-based dbs
             db_name: data_dir.join("chain.db"),
             rpc_conf: rpc::RPCConfig::default(),
+            api_conf: rest_api::ApiConfig::default(),
         }
     }
 }
@@ -100,13 +103,41 @@ impl ClientConfig {
             }
         }
 
+        // Custom node key
+        if let Some(key_file) = args.value_of("network-key") {
+            config.net_conf.node_key_file = Some(PathBuf::from(key_file));
+        }
+
+        if let Some(key_type_str) = args.value_of("network-key-type") {
+            if let Ok(key_type) = key_type_str.parse::<NodeKeyType>() {
+                config.net_conf.node_key_type = key_type;
+            } else {
+                error!(log, "Invalid network key type"; "type" => key_type_str);
+                return Err("Invalid network key type");
+            }
+        }
+
         /* Filesystem related arguments */
 
         // Custom datadir
         if let Some(dir) = args.value_of("datadir") {
             config.data_dir = PathBuf::from(dir.to_string());
+            config.net_conf.network_dir = Some(config.data_dir.join("network"));
         };
 
+        /* Beacon chain related arguments */
+
+        if let Some(fork_choice) = args.value_of("fork-choice") {
+            config.fork_choice = match fork_choice {
+                "bitwise" => ForkChoiceAlgorithm::BitwiseLMDGhost,
+                "proto-array" => ForkChoiceAlgorithm::ProtoArray,
+                _ => {
+                    error!(log, "Unsupported fork choice"; "algorithm" => fork_choice);
+                    return Err("Unsupported fork choice");
+                }
+            };
+        }
+
         /* RPC related arguments */
 
         if args.is_present("rpc") {
@@ -131,6 +162,30 @@ impl ClientConfig {
             }
         }
 
+        /* REST API related arguments */
+
+        if args.is_present("api") {
+            config.api_conf.enabled = true;
+        }
+
+        if let Some(api_address) = args.value_of("api-address") {
+            if let Ok(listen_address) = api_address.parse::<Ipv4Addr>() {
+                config.api_conf.listen_address = listen_address;
+            } else {
+                error!(log, "Invalid REST API listen address"; "Address" => api_address);
+                return Err("Invalid REST API listen address");
+            }
+        }
+
+        if let Some(api_port) = args.value_of("api-port") {
+            if let Ok(port) = api_port.parse::<u16>() {
+                config.api_conf.port = port;
+            } else {
+                error!(log, "Invalid REST API port"; "port" => api_port);
+                return Err("Invalid REST API port");
+            }
+        }
+
         Ok(config)
     }
 }
@@ -1,7 +1,7 @@
 use crate::ClientConfig;
 use beacon_chain::{
     db::{ClientDB, DiskDB, MemoryDB},
-    fork_choice::BitwiseLMDGhost,
+    fork_choice::{BitwiseLMDGhost, ProtoArrayForkChoice},
     initialise,
     slot_clock::{SlotClock, SystemTimeSlotClock},
     BeaconChain,
@@ -30,7 +30,11 @@ impl ClientTypes for StandardClientType {
     fn initialise_beacon_chain(
         config: &ClientConfig,
     ) -> Arc<BeaconChain<Self::DB, Self::SlotClock, Self::ForkChoice>> {
-        initialise::initialise_beacon_chain(&config.spec, Some(&config.db_name))
+        initialise::initialise_beacon_chain(
+            &config.spec,
+            Some(&config.db_name),
+            |block_store, _| BitwiseLMDGhost::new(block_store),
+        )
     }
 }
 
@@ -44,6 +48,42 @@ impl ClientTypes for TestingClientType {
     fn initialise_beacon_chain(
         config: &ClientConfig,
     ) -> Arc<BeaconChain<Self::DB, Self::SlotClock, Self::ForkChoice>> {
-        initialise::initialise_test_beacon_chain(&config.spec, None)
+        initialise::initialise_test_beacon_chain(&config.spec, None, |block_store, _| {
+            BitwiseLMDGhost::new(block_store)
+        })
+    }
+}
+
+/// As `StandardClientType`, but uses the proto-array fork choice.
+pub struct ProtoArrayClientType;
+
+impl ClientTypes for ProtoArrayClientType {
+    type DB = DiskDB;
+    type SlotClock = SystemTimeSlotClock;
+    type ForkChoice = ProtoArrayForkChoice<DiskDB>;
+
+    fn initialise_beacon_chain(
+        config: &ClientConfig,
+    ) -> Arc<BeaconChain<Self::DB, Self::SlotClock, Self::ForkChoice>> {
+        initialise::initialise_beacon_chain(
+            &config.spec,
+            Some(&config.db_name),
+            ProtoArrayForkChoice::new,
+        )
+    }
+}
+
+/// As `TestingClientType`, but uses the proto-array fork choice.
+pub struct TestingProtoArrayClientType;
+
+impl ClientTypes for TestingProtoArrayClientType {
+    type DB = MemoryDB;
+    type SlotClock = SystemTimeSlotClock;
+    type ForkChoice = ProtoArrayForkChoice<MemoryDB>;
+
+    fn initialise_beacon_chain(
+        config: &ClientConfig,
+    ) -> Arc<BeaconChain<Self::DB, Self::SlotClock, Self::ForkChoice>> {
+        initialise::initialise_test_beacon_chain(&config.spec, None, ProtoArrayForkChoice::new)
     }
 }
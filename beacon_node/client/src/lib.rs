@@ -13,13 +13,14 @@ use exit_future::Signal;
 use fork_choice::ForkChoice;
 use futures::{future::Future, Stream};
 use network::Service as NetworkService;
-use slog::{error, info, o};
+use slog::{error, info, o, warn};
 use slot_clock::SlotClock;
 use std::marker::PhantomData;
 use std::sync::Arc;
 use std::time::{Duration, Instant};
 use tokio::runtime::TaskExecutor;
 use tokio::timer::Interval;
+use types::Epoch;
 
 /// Main beacon node client service. This provides the connection and initialisation of the clients
 /// sub-services in multiple threads.
@@ -32,6 +33,8 @@ pub struct Client<T: ClientTypes> {
     pub network: Arc<NetworkService>,
     /// Signal to terminate the RPC server.
     pub rpc_exit_signal: Option<Signal>,
+    /// Signal to terminate the REST API server.
+    pub api_exit_signal: Option<Signal>,
     /// Signal to terminate the slot timer.
     pub slot_timer_exit_signal: Option<Signal>,
     /// The clients logger.
@@ -79,6 +82,37 @@ impl<TClientType: ClientTypes> Client<TClientType> {
             "wall_clock_slot" => beacon_chain.read_slot_clock().unwrap(),
         );
 
+        // Reload any operations that were pending when the node last shut down.
+        match beacon_chain.restore_op_pool() {
+            Ok(Some(restored)) => info!(
+                log,
+                "Operation pool restored";
+                "attestations" => restored.attestations,
+                "deposits" => restored.deposits,
+                "attester_slashings" => restored.attester_slashings,
+                "proposer_slashings" => restored.proposer_slashings,
+                "voluntary_exits" => restored.voluntary_exits,
+                "transfers" => restored.transfers,
+            ),
+            Ok(None) => {}
+            Err(e) => warn!(
+                log,
+                "Unable to restore operation pool";
+                "error" => format!("{:?}", e)
+            ),
+        }
+
+        // Reload the votes and block tree known to fork choice when the node last shut down.
+        match beacon_chain.restore_fork_choice() {
+            Ok(true) => info!(log, "Fork choice restored"),
+            Ok(false) => {}
+            Err(e) => warn!(
+                log,
+                "Unable to restore fork choice";
+                "error" => format!("{:?}", e)
+            ),
+        }
+
         // Start the network service, libp2p and syncing threads
         // TODO: Add beacon_chain reference to network parameters
         let network_config = &config.net_conf;
@@ -103,6 +137,19 @@ impl<TClientType: ClientTypes> Client<TClientType> {
             None
         };
 
+        // spawn the REST API server
+        let api_exit_signal = if config.api_conf.enabled {
+            Some(rest_api::start_server(
+                &config.api_conf,
+                executor,
+                beacon_chain.clone(),
+                network.clone(),
+                &log,
+            ))
+        } else {
+            None
+        };
+
         let (slot_timer_exit_signal, exit) = exit_future::signal();
         if let Ok(Some(duration_to_next_slot)) = beacon_chain.slot_clock.duration_to_next_slot() {
             // set up the validator work interval - start at next slot and proceed every slot
@@ -115,11 +162,15 @@ impl<TClientType: ClientTypes> Client<TClientType> {
 
             let chain = beacon_chain.clone();
             let log = log.new(o!("Service" => "SlotTimer"));
+            // the pool and fork choice were just restored, so there is nothing new to persist
+            // until the next epoch
+            let mut last_persisted_epoch = chain.state.read().current_epoch(&chain.spec);
             executor.spawn(
                 exit.until(
                     interval
                         .for_each(move |_| {
                             do_state_catchup(&chain, &log);
+                            do_persistence(&chain, &mut last_persisted_epoch, &log);
 
                             Ok(())
                         })
@@ -133,6 +184,7 @@ impl<TClientType: ClientTypes> Client<TClientType> {
             _config: config,
             _beacon_chain: beacon_chain,
             rpc_exit_signal,
+            api_exit_signal,
             slot_timer_exit_signal: Some(slot_timer_exit_signal),
             log,
             network,
@@ -141,6 +193,63 @@ impl<TClientType: ClientTypes> Client<TClientType> {
     }
 }
 
+impl<T: ClientTypes> Drop for Client<T> {
+    fn drop(&mut self) {
+        // Save the operation pool so pending operations survive a restart.
+        match self._beacon_chain.persist_op_pool() {
+            Ok(()) => info!(self.log, "Operation pool persisted"),
+            Err(e) => error!(
+                self.log,
+                "Unable to persist operation pool";
+                "error" => format!("{:?}", e)
+            ),
+        }
+        // Save the fork choice so votes survive a restart.
+        match self._beacon_chain.persist_fork_choice() {
+            Ok(()) => info!(self.log, "Fork choice persisted"),
+            Err(e) => error!(
+                self.log,
+                "Unable to persist fork choice";
+                "error" => format!("{:?}", e)
+            ),
+        }
+    }
+}
+
+/// Persists the operation pool and fork choice to the DB once per epoch.
+///
+/// The timer may tick several times during a slot, so `last_persisted_epoch` records the epoch of
+/// the last write.
+fn do_persistence<T, U, F>(
+    chain: &Arc<BeaconChain<T, U, F>>,
+    last_persisted_epoch: &mut Epoch,
+    log: &slog::Logger,
+) where
+    T: ClientDB,
+    U: SlotClock,
+    F: ForkChoice,
+{
+    let epoch = chain.state.read().current_epoch(&chain.spec);
+
+    if *last_persisted_epoch != epoch {
+        *last_persisted_epoch = epoch;
+        if let Err(e) = chain.persist_op_pool() {
+            error!(
+                log,
+                "Unable to persist operation pool";
+                "error" => format!("{:?}", e)
+            );
+        }
+        if let Err(e) = chain.persist_fork_choice() {
+            error!(
+                log,
+                "Unable to persist fork choice";
+                "error" => format!("{:?}", e)
+            );
+        }
+    }
+}
+
 fn do_state_catchup<T, U, F>(chain: &Arc<BeaconChain<T, U, F>>, log: &slog::Logger)
 where
     T: ClientDB,
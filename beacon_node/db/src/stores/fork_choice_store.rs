@@ -0,0 +1,30 @@
+use super::FORK_CHOICE_DB_COLUMN as DB_COLUMN;
+use super::{ClientDB, DBError};
+use std::sync::Arc;
+
+/// The key under which the fork choice is stored. There is only ever one persisted fork choice.
+const FORK_CHOICE_KEY: &[u8] = b"fork_choice";
+
+pub struct ForkChoiceStore<T>
+where
+    T: ClientDB,
+{
+    db: Arc<T>,
+}
+
+// Implements `put`, `get` and `delete` for the store.
+impl_single_key_store!(ForkChoiceStore, DB_COLUMN, FORK_CHOICE_KEY);
+
+impl<T: ClientDB> ForkChoiceStore<T> {
+    pub fn new(db: Arc<T>) -> Self {
+        Self { db }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::super::MemoryDB;
+    use super::*;
+
+    test_single_key_store!(ForkChoiceStore, DB_COLUMN, FORK_CHOICE_KEY);
+}
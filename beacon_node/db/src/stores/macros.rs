@@ -101,3 +101,66 @@ macro_rules! test_crud_for_store {
         }
     };
 }
+
+/// Implements `put`, `get` and `delete` for a store which holds a single value under `$key`.
+macro_rules! impl_single_key_store {
+    ($store: ident, $db_column: expr, $key: expr) => {
+        impl<T: ClientDB> $store<T> {
+            /// Stores `ssz`, replacing any previous value.
+            pub fn put(&self, ssz: &[u8]) -> Result<(), DBError> {
+                self.db.put($db_column, $key, ssz)
+            }
+
+            /// Returns the stored value, if any.
+            pub fn get(&self) -> Result<Option<Vec<u8>>, DBError> {
+                self.db.get($db_column, $key)
+            }
+
+            /// Removes the stored value, if any.
+            pub fn delete(&self) -> Result<(), DBError> {
+                self.db.delete($db_column, $key)
+            }
+        }
+    };
+}
+
+#[cfg(test)]
+macro_rules! test_single_key_store {
+    ($store: ident, $db_column: expr, $key: expr) => {
+        #[test]
+        fn test_put_get() {
+            let db = Arc::new(MemoryDB::open());
+            let store = $store::new(db.clone());
+
+            let ssz = "some bytes".as_bytes();
+
+            assert_eq!(store.get().unwrap(), None);
+
+            store.put(ssz).unwrap();
+            assert_eq!(store.get().unwrap().unwrap(), ssz);
+            assert!(db.exists($db_column, $key).unwrap());
+        }
+
+        #[test]
+        fn test_put_replaces() {
+            let db = Arc::new(MemoryDB::open());
+            let store = $store::new(db.clone());
+
+            store.put("some bytes".as_bytes()).unwrap();
+            store.put("other bytes".as_bytes()).unwrap();
+
+            assert_eq!(store.get().unwrap().unwrap(), "other bytes".as_bytes());
+        }
+
+        #[test]
+        fn test_delete() {
+            let db = Arc::new(MemoryDB::open());
+            let store = $store::new(db.clone());
+
+            store.put("some bytes".as_bytes()).unwrap();
+            store.delete().unwrap();
+
+            assert_eq!(store.get().unwrap(), None);
+        }
+    };
+}
@@ -4,11 +4,15 @@ use super::{ClientDB, DBError};
 mod macros;
 mod beacon_block_store;
 mod beacon_state_store;
+mod fork_choice_store;
+mod operation_pool_store;
 mod pow_chain_store;
 mod validator_store;
 
 pub use self::beacon_block_store::{BeaconBlockAtSlotError, BeaconBlockStore};
 pub use self::beacon_state_store::BeaconStateStore;
+pub use self::fork_choice_store::ForkChoiceStore;
+pub use self::operation_pool_store::OperationPoolStore;
 pub use self::pow_chain_store::PoWChainStore;
 pub use self::validator_store::{ValidatorStore, ValidatorStoreError};
 
@@ -16,10 +20,14 @@ pub const BLOCKS_DB_COLUMN: &str = "blocks";
 pub const STATES_DB_COLUMN: &str = "states";
 pub const POW_CHAIN_DB_COLUMN: &str = "powchain";
 pub const VALIDATOR_DB_COLUMN: &str = "validator";
+pub const OPERATION_POOL_DB_COLUMN: &str = "op_pool";
+pub const FORK_CHOICE_DB_COLUMN: &str = "fork_choice";
 
-pub const COLUMNS: [&str; 4] = [
+pub const COLUMNS: [&str; 6] = [
     BLOCKS_DB_COLUMN,
     STATES_DB_COLUMN,
     POW_CHAIN_DB_COLUMN,
     VALIDATOR_DB_COLUMN,
+    OPERATION_POOL_DB_COLUMN,
+    FORK_CHOICE_DB_COLUMN,
 ];
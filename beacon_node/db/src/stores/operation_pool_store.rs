@@ -0,0 +1,30 @@
+use super::OPERATION_POOL_DB_COLUMN as DB_COLUMN;
+use super::{ClientDB, DBError};
+use std::sync::Arc;
+
+/// The key under which the operation pool is stored. There is only ever one persisted pool.
+const OPERATION_POOL_KEY: &[u8] = b"op_pool";
+
+pub struct OperationPoolStore<T>
+where
+    T: ClientDB,
+{
+    db: Arc<T>,
+}
+
+// Implements `put`, `get` and `delete` for the store.
+impl_single_key_store!(OperationPoolStore, DB_COLUMN, OPERATION_POOL_KEY);
+
+impl<T: ClientDB> OperationPoolStore<T> {
+    pub fn new(db: Arc<T>) -> Self {
+        Self { db }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::super::MemoryDB;
+    use super::*;
+
+    test_single_key_store!(OperationPoolStore, DB_COLUMN, OPERATION_POOL_KEY);
+}
@@ -1,4 +1,9 @@
-use crate::rpc::{RPCEvent, RPCMessage, Rpc};
+use crate::discovery::Discovery;
+use crate::rpc::methods::GoodbyeReason;
+use crate::rpc::{RPCEvent, RPCMessage, RPCRequest, RequestId, ResponseCode, Rpc};
+use crate::snappy;
+use crate::topics::{GossipEncoding, GossipKind, GossipTopic};
+use crate::validated_gossipsub::{MessageId, ValidatedGossipsub, ValidatedGossipsubEvent};
 use crate::NetworkConfig;
 use futures::prelude::*;
 use libp2p::{
@@ -6,16 +11,16 @@ use libp2p::{
         swarm::{NetworkBehaviourAction, NetworkBehaviourEventProcess},
         PublicKey,
     },
-    gossipsub::{Gossipsub, GossipsubEvent},
     identify::{protocol::IdentifyInfo, Identify, IdentifyEvent},
+    kad::KademliaOut,
     ping::{Ping, PingEvent},
     tokio_io::{AsyncRead, AsyncWrite},
     NetworkBehaviour, PeerId,
 };
 use slog::{debug, o, trace, warn};
-use ssz::{ssz_encode, Decodable, DecodeError, Encodable, SszStream};
+use ssz::{ssz_encode, Decodable};
+use types::TopicHash;
 use types::{Attestation, BeaconBlock};
-use types::{Topic, TopicHash};
 
 /// Builds the network behaviour for the libp2p Swarm.
 /// Implements gossipsub message routing.
@@ -23,8 +28,9 @@ use types::{Topic, TopicHash};
 #[behaviour(out_event = "BehaviourEvent", poll_method = "poll")]
 pub struct Behaviour<TSubstream: AsyncRead + AsyncWrite> {
     /// The routing pub-sub mechanism for eth2.
-    gossipsub: Gossipsub<TSubstream>,
-    // TODO: Add Kademlia for peer discovery
+    gossipsub: ValidatedGossipsub<TSubstream>,
+    /// Finds and dials new peers.
+    discovery: Discovery<TSubstream>,
     /// The events generated by this behaviour to be consumed in the swarm poll.
     serenity_rpc: Rpc<TSubstream>,
     /// Allows discovery of IP addresses for peers on the network.
@@ -33,6 +39,9 @@ pub struct Behaviour<TSubstream: AsyncRead + AsyncWrite> {
     // TODO: Keepalive, likely remove this later.
     // TODO: Make the ping time customizeable.
     ping: Ping<TSubstream>,
+    /// The encoding of the messages we publish.
+    #[behaviour(ignore)]
+    gossip_encoding: GossipEncoding,
     #[behaviour(ignore)]
     events: Vec<BehaviourEvent>,
     /// Logger for behaviour actions.
@@ -41,35 +50,39 @@ pub struct Behaviour<TSubstream: AsyncRead + AsyncWrite> {
 }
 
 // Implement the NetworkBehaviourEventProcess trait so that we can derive NetworkBehaviour for Behaviour
-impl<TSubstream: AsyncRead + AsyncWrite> NetworkBehaviourEventProcess<GossipsubEvent>
+impl<TSubstream: AsyncRead + AsyncWrite> NetworkBehaviourEventProcess<ValidatedGossipsubEvent>
     for Behaviour<TSubstream>
 {
-    fn inject_event(&mut self, event: GossipsubEvent) {
+    fn inject_event(&mut self, event: ValidatedGossipsubEvent) {
         match event {
-            GossipsubEvent::Message(gs_msg) => {
+            ValidatedGossipsubEvent::Message(propagation_source, id, gs_msg) => {
                 trace!(self.log, "Received GossipEvent"; "msg" => format!("{:?}", gs_msg));
 
-                let pubsub_message = match PubsubMessage::ssz_decode(&gs_msg.data, 0) {
-                    //TODO: Punish peer on error
+                // Messages are only propagated once they have been validated, an undecodable
+                // message is never propagated.
+                let pubsub_message = match PubsubMessage::decode(&gs_msg.topics, &gs_msg.data) {
                     Err(e) => {
                         warn!(
                             self.log,
-                            "Received undecodable message from Peer {:?} error", gs_msg.source;
-                            "error" => format!("{:?}", e)
+                            "Received undecodable message from Peer {:?} error", propagation_source;
+                            "error" => e
                         );
+                        self.events
+                            .push(BehaviourEvent::UndecodableGossip(propagation_source));
                         return;
                     }
-                    Ok((msg, _index)) => msg,
+                    Ok(msg) => msg,
                 };
 
                 self.events.push(BehaviourEvent::GossipMessage {
-                    source: gs_msg.source,
+                    id,
+                    source: propagation_source,
                     topics: gs_msg.topics,
                     message: Box::new(pubsub_message),
                 });
             }
-            GossipsubEvent::Subscribed { .. } => {}
-            GossipsubEvent::Unsubscribed { .. } => {}
+            ValidatedGossipsubEvent::Subscribed { .. } => {}
+            ValidatedGossipsubEvent::Unsubscribed { .. } => {}
         }
     }
 }
@@ -82,9 +95,24 @@ impl<TSubstream: AsyncRead + AsyncWrite> NetworkBehaviourEventProcess<RPCMessage
             RPCMessage::PeerDialed(peer_id) => {
                 self.events.push(BehaviourEvent::PeerDialed(peer_id))
             }
+            RPCMessage::PeerDisconnected(peer_id) => {
+                self.events.push(BehaviourEvent::PeerDisconnected(peer_id))
+            }
             RPCMessage::RPC(peer_id, rpc_event) => {
                 self.events.push(BehaviourEvent::RPC(peer_id, rpc_event))
             }
+            RPCMessage::RequestRejected(peer_id, code) => self
+                .events
+                .push(BehaviourEvent::RequestRejected(peer_id, code)),
+            RPCMessage::RequestTimeout {
+                peer_id,
+                id,
+                method_id,
+            } => self.events.push(BehaviourEvent::RequestTimeout {
+                peer_id,
+                id,
+                method_id,
+            }),
         }
     }
 }
@@ -104,6 +132,10 @@ impl<TSubstream: AsyncRead + AsyncWrite> NetworkBehaviourEventProcess<IdentifyEv
                     );
                     info.listen_addrs.truncate(20);
                 }
+                for address in &info.listen_addrs {
+                    self.discovery
+                        .add_connected_address(&peer_id, address.clone());
+                }
                 self.events
                     .push(BehaviourEvent::Identified(peer_id, Box::new(info)));
             }
@@ -113,6 +145,14 @@ impl<TSubstream: AsyncRead + AsyncWrite> NetworkBehaviourEventProcess<IdentifyEv
     }
 }
 
+impl<TSubstream: AsyncRead + AsyncWrite> NetworkBehaviourEventProcess<KademliaOut>
+    for Behaviour<TSubstream>
+{
+    fn inject_event(&mut self, _event: KademliaOut) {
+        // discovery consumes kademlia events internally.
+    }
+}
+
 impl<TSubstream: AsyncRead + AsyncWrite> NetworkBehaviourEventProcess<PingEvent>
     for Behaviour<TSubstream>
 {
@@ -128,14 +168,20 @@ impl<TSubstream: AsyncRead + AsyncWrite> Behaviour<TSubstream> {
         let behaviour_log = log.new(o!());
 
         Behaviour {
-            gossipsub: Gossipsub::new(local_peer_id, net_conf.gs_config.clone()),
-            serenity_rpc: Rpc::new(log),
+            gossipsub: ValidatedGossipsub::new(
+                local_peer_id.clone(),
+                net_conf.gs_config.clone(),
+                log,
+            ),
+            discovery: Discovery::new(local_peer_id, net_conf, log),
+            serenity_rpc: Rpc::new(net_conf.rpc_rate_limits.clone(), log),
             identify: Identify::new(
                 identify_config.version,
                 identify_config.user_agent,
                 local_public_key,
             ),
             ping: Ping::new(),
+            gossip_encoding: net_conf.gossip_encoding,
             events: Vec::new(),
             log: behaviour_log,
         }
@@ -155,9 +201,55 @@ impl<TSubstream: AsyncRead + AsyncWrite> Behaviour<TSubstream> {
 
 /// Implements the combined behaviour for the libp2p service.
 impl<TSubstream: AsyncRead + AsyncWrite> Behaviour<TSubstream> {
-    /// Subscribes to a gossipsub topic.
-    pub fn subscribe(&mut self, topic: Topic) -> bool {
-        self.gossipsub.subscribe(topic)
+    /// Returns the number of peers we are currently connected to.
+    pub fn connected_peers(&self) -> usize {
+        self.discovery.connected_peers()
+    }
+
+    /// Prevents discovery from dialling a banned peer.
+    pub fn ban_peer(&mut self, peer_id: &PeerId) {
+        self.discovery.ban_peer(peer_id);
+    }
+
+    /// Allows discovery to dial a previously banned peer.
+    pub fn unban_peer(&mut self, peer_id: &PeerId) {
+        self.discovery.unban_peer(peer_id);
+    }
+
+    /// Subscribes to a gossipsub topic in every encoding, so that we receive messages from peers
+    /// which publish with an encoding other than ours.
+    pub fn subscribe(&mut self, topic: GossipTopic) -> bool {
+        GossipEncoding::all()
+            .iter()
+            .fold(false, |subscribed, encoding| {
+                self.gossipsub
+                    .subscribe(topic.with_encoding(*encoding).into())
+                    || subscribed
+            })
+    }
+
+    /// Unsubscribes from a gossipsub topic in every encoding.
+    pub fn unsubscribe(&mut self, topic: GossipTopic) -> bool {
+        GossipEncoding::all()
+            .iter()
+            .fold(false, |unsubscribed, encoding| {
+                self.gossipsub
+                    .unsubscribe(topic.with_encoding(*encoding).into())
+                    || unsubscribed
+            })
+    }
+
+    /// Propagates a received gossip message to our mesh peers if it was accepted by validation.
+    ///
+    /// Messages which are ignored or rejected are never propagated.
+    pub fn report_message_validation_result(
+        &mut self,
+        message_id: &MessageId,
+        propagation_source: &PeerId,
+        acceptance: MessageAcceptance,
+    ) {
+        self.gossipsub
+            .report_validation_result(message_id, propagation_source, acceptance);
     }
 
     /// Sends an RPC Request/Response via the RPC protocol.
@@ -165,11 +257,32 @@ impl<TSubstream: AsyncRead + AsyncWrite> Behaviour<TSubstream> {
         self.serenity_rpc.send_rpc(peer_id, rpc_event);
     }
 
+    /// Sends a `Goodbye` with `reason` to every connected peer, e.g., when shutting down.
+    ///
+    /// A `Goodbye` is never responded to, so its request id is not used.
+    pub fn goodbye_peers(&mut self, reason: GoodbyeReason) {
+        let peers: Vec<PeerId> = self.serenity_rpc.connected_peers().cloned().collect();
+        for peer_id in peers {
+            let goodbye = RPCRequest::Goodbye(reason);
+            self.serenity_rpc.send_rpc(
+                peer_id,
+                RPCEvent::Request {
+                    id: RequestId::from(0),
+                    method_id: goodbye.method_id(),
+                    body: goodbye,
+                },
+            );
+        }
+    }
+
     /// Publishes a message on the pubsub (gossipsub) behaviour.
-    pub fn publish(&mut self, topics: Vec<Topic>, message: PubsubMessage) {
-        let message_bytes = ssz_encode(&message);
+    ///
+    /// The message is published in our gossip encoding, regardless of the encoding of `topics`.
+    pub fn publish(&mut self, topics: Vec<GossipTopic>, message: PubsubMessage) {
+        let message_bytes = message.encode(self.gossip_encoding);
         for topic in topics {
-            self.gossipsub.publish(topic, message_bytes.clone());
+            let topic = topic.with_encoding(self.gossip_encoding);
+            self.gossipsub.publish(topic.into(), message_bytes.clone());
         }
     }
 }
@@ -177,14 +290,37 @@ impl<TSubstream: AsyncRead + AsyncWrite> Behaviour<TSubstream> {
 /// The types of events than can be obtained from polling the behaviour.
 pub enum BehaviourEvent {
     RPC(PeerId, RPCEvent),
+    RequestRejected(PeerId, ResponseCode),
+    RequestTimeout {
+        peer_id: PeerId,
+        id: RequestId,
+        method_id: u16,
+    },
     PeerDialed(PeerId),
+    PeerDisconnected(PeerId),
     Identified(PeerId, Box<IdentifyInfo>),
-    // TODO: This is a stub at the moment
+    /// A gossip message which must be validated before it is propagated. `source` is the peer
+    /// which forwarded the message to us, not necessarily its author.
     GossipMessage {
+        id: MessageId,
         source: PeerId,
         topics: Vec<TopicHash>,
         message: Box<PubsubMessage>,
     },
+    /// A peer sent a gossip message which could not be decoded.
+    UndecodableGossip(PeerId),
+}
+
+/// The outcome of validating a gossip message.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MessageAcceptance {
+    /// The message is valid, propagate it to our peers.
+    Accept,
+    /// The message is not invalid, but should not be propagated (e.g., it is a duplicate or falls
+    /// outside the slot window).
+    Ignore,
+    /// The message is invalid, do not propagate it and penalise the peer which sent it.
+    Reject,
 }
 
 /// Messages that are passed to and from the pubsub (Gossipsub) behaviour.
@@ -196,35 +332,45 @@ pub enum PubsubMessage {
     Attestation(Attestation),
 }
 
-//TODO: Correctly encode/decode enums. Prefixing with integer for now.
-impl Encodable for PubsubMessage {
-    fn ssz_append(&self, s: &mut SszStream) {
-        match self {
-            PubsubMessage::Block(block_gossip) => {
-                0u32.ssz_append(s);
-                block_gossip.ssz_append(s);
-            }
-            PubsubMessage::Attestation(attestation_gossip) => {
-                1u32.ssz_append(s);
-                attestation_gossip.ssz_append(s);
+impl PubsubMessage {
+    /// Decodes a message received on `topics`. The type and encoding of the message are determined
+    /// by the first of its topics which is a known `GossipTopic`.
+    pub fn decode(topics: &[TopicHash], data: &[u8]) -> Result<Self, String> {
+        let topic = topics
+            .iter()
+            .filter_map(|topic| GossipTopic::decode(&topic.clone().into_string()).ok())
+            .next()
+            .ok_or_else(|| format!("No known topic in {:?}", topics))?;
+
+        let ssz_bytes = match topic.encoding() {
+            GossipEncoding::SSZ => data.to_vec(),
+            GossipEncoding::SSZSnappy => snappy::decompress(data)
+                .map_err(|e| format!("Invalid snappy compression: {:?}", e))?,
+        };
+
+        match topic.kind() {
+            GossipKind::BeaconBlock => BeaconBlock::ssz_decode(&ssz_bytes, 0)
+                .map(|(block, _)| PubsubMessage::Block(block))
+                .map_err(|e| format!("Invalid block: {:?}", e)),
+            GossipKind::BeaconAttestation | GossipKind::AttestationSubnet(_) => {
+                Attestation::ssz_decode(&ssz_bytes, 0)
+                    .map(|(attestation, _)| PubsubMessage::Attestation(attestation))
+                    .map_err(|e| format!("Invalid attestation: {:?}", e))
             }
         }
     }
-}
 
-impl Decodable for PubsubMessage {
-    fn ssz_decode(bytes: &[u8], index: usize) -> Result<(Self, usize), DecodeError> {
-        let (id, index) = u32::ssz_decode(bytes, index)?;
-        match id {
-            0 => {
-                let (block, index) = BeaconBlock::ssz_decode(bytes, index)?;
-                Ok((PubsubMessage::Block(block), index))
-            }
-            1 => {
-                let (attestation, index) = Attestation::ssz_decode(bytes, index)?;
-                Ok((PubsubMessage::Attestation(attestation), index))
-            }
-            _ => Err(DecodeError::Invalid),
+    /// Encodes the message for publishing with `encoding`. Its type is implied by the topic it is
+    /// published on.
+    pub fn encode(&self, encoding: GossipEncoding) -> Vec<u8> {
+        let ssz_bytes = match self {
+            PubsubMessage::Block(block) => ssz_encode(block),
+            PubsubMessage::Attestation(attestation) => ssz_encode(attestation),
+        };
+
+        match encoding {
+            GossipEncoding::SSZ => ssz_bytes,
+            GossipEncoding::SSZSnappy => snappy::compress(&ssz_bytes),
         }
     }
 }
@@ -238,12 +384,41 @@ mod test {
     fn ssz_encoding() {
         let original = PubsubMessage::Block(BeaconBlock::empty(&ChainSpec::foundation()));
 
-        let encoded = ssz_encode(&original);
+        for encoding in GossipEncoding::all().iter() {
+            let topic: Topic = GossipTopic::new(GossipKind::BeaconBlock, [0; 4])
+                .with_encoding(*encoding)
+                .into();
 
-        println!("{:?}", encoded);
+            let encoded = original.encode(*encoding);
+            let decoded = PubsubMessage::decode(&[topic.hash().clone()], &encoded).unwrap();
 
-        let (decoded, _i) = PubsubMessage::ssz_decode(&encoded, 0).unwrap();
+            assert_eq!(original, decoded);
+        }
+    }
+
+    #[test]
+    fn decoding_requires_a_known_topic() {
+        let original = PubsubMessage::Block(BeaconBlock::empty(&ChainSpec::foundation()));
+        let topic = TopicBuilder::new("beacon_chain").build();
+
+        assert!(PubsubMessage::decode(
+            &[topic.hash().clone()],
+            &original.encode(GossipEncoding::SSZ)
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn decoding_requires_the_topic_encoding() {
+        let original = PubsubMessage::Block(BeaconBlock::empty(&ChainSpec::foundation()));
+        let topic: Topic = GossipTopic::new(GossipKind::BeaconBlock, [0; 4])
+            .with_encoding(GossipEncoding::SSZSnappy)
+            .into();
 
-        assert_eq!(original, decoded);
+        assert!(PubsubMessage::decode(
+            &[topic.hash().clone()],
+            &original.encode(GossipEncoding::SSZ)
+        )
+        .is_err());
     }
 }
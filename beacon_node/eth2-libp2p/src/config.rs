@@ -1,5 +1,10 @@
+use crate::node_key::NodeKeyType;
+use crate::rpc::RateLimiterConfig;
+use crate::topics::GossipEncoding;
 use crate::Multiaddr;
 use libp2p::gossipsub::{GossipsubConfig, GossipsubConfigBuilder};
+use std::path::PathBuf;
+use std::time::Duration;
 
 #[derive(Clone, Debug)]
 /// Network configuration for lighthouse.
@@ -11,14 +16,28 @@ pub struct Config {
     pub listen_port: u16,
     /// Gossipsub configuration parameters.
     pub gs_config: GossipsubConfig,
+    /// The encoding of the gossip messages we publish. We receive messages in every encoding.
+    pub gossip_encoding: GossipEncoding,
     /// Configuration parameters for node identification protocol.
     pub identify_config: IdentifyConfig,
     /// List of nodes to initially connect to.
     pub boot_nodes: Vec<Multiaddr>,
+    /// The number of connected peers that discovery tries to maintain.
+    pub target_peers: usize,
+    /// The time between searches for new peers.
+    pub discovery_interval: Duration,
+    /// Directory to store persistent network data (e.g., known peers). Nothing is persisted if
+    /// `None`.
+    pub network_dir: Option<PathBuf>,
+    /// File containing the key which determines our `PeerId`. Defaults to a file in
+    /// `network_dir` if `None`.
+    pub node_key_file: Option<PathBuf>,
+    /// The type of key to generate if there is no stored key.
+    pub node_key_type: NodeKeyType,
+    /// The limits on RPC requests from each peer.
+    pub rpc_rate_limits: RateLimiterConfig,
     /// Client version
     pub client_version: String,
-    /// List of topics to subscribe to as strings
-    pub topics: Vec<String>,
 }
 
 impl Default for Config {
@@ -32,10 +51,16 @@ impl Default for Config {
             gs_config: GossipsubConfigBuilder::new()
                 .max_gossip_size(4_000_000)
                 .build(),
+            gossip_encoding: GossipEncoding::default(),
             identify_config: IdentifyConfig::default(),
             boot_nodes: Vec::new(),
+            target_peers: 16,
+            discovery_interval: Duration::from_secs(10),
+            network_dir: None,
+            node_key_file: None,
+            node_key_type: NodeKeyType::Secp256k1,
+            rpc_rate_limits: RateLimiterConfig::default(),
             client_version: version::version(),
-            topics: vec![String::from("beacon_chain")],
         }
     }
 }
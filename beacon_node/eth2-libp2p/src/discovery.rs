@@ -0,0 +1,505 @@
+/// Peer discovery for the beacon node.
+///
+/// Peers are found by running random Kademlia queries, seeded by the addresses of the boot nodes
+/// (learned through the identify protocol). Discovered peers are dialled until the target number
+/// of peers is connected. Peers that we have connected to are persisted to disk, so that they can
+/// be dialled immediately after a restart.
+use crate::NetworkConfig;
+use futures::prelude::*;
+use libp2p::core::protocols_handler::ProtocolsHandler;
+use libp2p::core::swarm::{
+    ConnectedPoint, NetworkBehaviour, NetworkBehaviourAction, PollParameters,
+};
+use libp2p::kad::{Kademlia, KademliaOut};
+use libp2p::{Multiaddr, PeerId};
+use slog::{debug, o, trace, warn};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fs::{self, File};
+use std::io::{self, prelude::*, BufReader};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::timer::Delay;
+
+/// The name of the file in the network directory which stores known-good peers.
+pub const KNOWN_PEERS_FILENAME: &str = "known_peers";
+
+/// The maximum number of addresses stored for a single peer.
+const MAX_ADDRESSES_PER_PEER: usize = 8;
+
+/// The maximum number of peers whose addresses are stored.
+const MAX_KNOWN_PEERS: usize = 1024;
+
+/// The maximum number of known-good peers stored and persisted to disk.
+const MAX_GOOD_PEERS: usize = 128;
+
+/// Maintains the target number of connected peers by discovering new peers through Kademlia and
+/// dialling them.
+pub struct Discovery<TSubstream> {
+    /// The Kademlia DHT used to find new peers.
+    kademlia: Kademlia<TSubstream>,
+    /// Fires when it is time to search for new peers.
+    discovery_delay: Delay,
+    /// The time between searches for new peers.
+    discovery_interval: Duration,
+    /// The number of connected peers to maintain.
+    target_peers: usize,
+    /// The peers we are currently connected to.
+    connected_peers: HashSet<PeerId>,
+    /// Peers that have been dialled since the last search.
+    dialled_peers: HashSet<PeerId>,
+    /// Known addresses of discovered peers.
+    known_peers: HashMap<PeerId, Vec<Multiaddr>>,
+    /// Peers we have successfully connected to, along with the address used to reach them.
+    good_peers: HashMap<PeerId, Multiaddr>,
+    /// Set when `good_peers` has changed since it was last written to disk.
+    good_peers_changed: bool,
+    /// The file `good_peers` are persisted to. Peers are not persisted if `None`.
+    known_peers_file: Option<PathBuf>,
+    /// Peers which must not be dialled.
+    banned_peers: HashSet<PeerId>,
+    /// Dials which have been requested but not yet returned from `poll`.
+    pending_dials: VecDeque<PeerId>,
+    /// Logger for the discovery behaviour.
+    log: slog::Logger,
+}
+
+impl<TSubstream> Discovery<TSubstream> {
+    pub fn new(local_peer_id: PeerId, net_conf: &NetworkConfig, log: &slog::Logger) -> Self {
+        let log = log.new(o!("Service" => "Libp2p-Discovery"));
+
+        let known_peers_file = net_conf
+            .network_dir
+            .as_ref()
+            .map(|dir| dir.join(KNOWN_PEERS_FILENAME));
+
+        let mut discovery = Self {
+            kademlia: Kademlia::new(local_peer_id),
+            // search immediately, the routing table will be populated by the boot nodes.
+            discovery_delay: Delay::new(Instant::now()),
+            discovery_interval: net_conf.discovery_interval,
+            target_peers: net_conf.target_peers,
+            connected_peers: HashSet::new(),
+            dialled_peers: HashSet::new(),
+            known_peers: HashMap::new(),
+            good_peers: HashMap::new(),
+            good_peers_changed: false,
+            known_peers_file,
+            banned_peers: HashSet::new(),
+            pending_dials: VecDeque::new(),
+            log,
+        };
+
+        if let Some(path) = discovery.known_peers_file.clone() {
+            match load_known_peers(&path) {
+                Ok(peers) => {
+                    debug!(discovery.log, "Loaded known peers"; "count" => peers.len());
+                    for (peer_id, address) in peers {
+                        discovery
+                            .kademlia
+                            .add_not_connected_address(&peer_id, address.clone());
+                        discovery.add_known_address(&peer_id, address.clone());
+                        discovery.add_good_peer(peer_id, address);
+                    }
+                }
+                Err(e) => warn!(
+                    discovery.log,
+                    "Unable to load known peers";
+                    "file" => format!("{:?}", path),
+                    "error" => format!("{:?}", e)
+                ),
+            }
+        }
+
+        discovery
+    }
+
+    /// Returns the number of peers we are currently connected to.
+    pub fn connected_peers(&self) -> usize {
+        self.connected_peers.len()
+    }
+
+    /// Records an address that a connected peer is listening on (e.g., learned through the
+    /// identify protocol), making it available to Kademlia queries.
+    pub fn add_connected_address(&mut self, peer_id: &PeerId, address: Multiaddr) {
+        self.add_known_address(peer_id, address.clone());
+        self.kademlia.add_connected_address(peer_id, address);
+    }
+
+    /// Stops dialling `peer_id` and forgets it as a known-good peer.
+    pub fn ban_peer(&mut self, peer_id: &PeerId) {
+        self.banned_peers.insert(peer_id.clone());
+        if self.good_peers.remove(peer_id).is_some() {
+            self.good_peers_changed = true;
+        }
+        self.pending_dials.retain(|pending| pending != peer_id);
+    }
+
+    /// Allows `peer_id` to be dialled again.
+    pub fn unban_peer(&mut self, peer_id: &PeerId) {
+        self.banned_peers.remove(peer_id);
+    }
+
+    /// Writes the known-good peers to disk, if they have changed.
+    pub fn persist_known_peers(&mut self) {
+        if !self.good_peers_changed {
+            return;
+        }
+
+        if let Some(path) = &self.known_peers_file {
+            match save_known_peers(path, &self.good_peers) {
+                Ok(()) => {
+                    trace!(self.log, "Persisted known peers"; "count" => self.good_peers.len());
+                    self.good_peers_changed = false;
+                }
+                Err(e) => warn!(
+                    self.log,
+                    "Unable to persist known peers";
+                    "file" => format!("{:?}", path),
+                    "error" => format!("{:?}", e)
+                ),
+            }
+        }
+    }
+
+    /// Adds `address` to the known addresses of `peer_id`.
+    ///
+    /// If `MAX_KNOWN_PEERS` are already known, a peer which is neither connected nor known-good is
+    /// forgotten to make room. The address is dropped if there is no such peer.
+    fn add_known_address(&mut self, peer_id: &PeerId, address: Multiaddr) {
+        if !self.known_peers.contains_key(peer_id) && self.known_peers.len() >= MAX_KNOWN_PEERS {
+            let evictable = self
+                .known_peers
+                .keys()
+                .find(|known| {
+                    !self.connected_peers.contains(known) && !self.good_peers.contains_key(known)
+                })
+                .cloned();
+            match evictable {
+                Some(evicted) => {
+                    self.known_peers.remove(&evicted);
+                }
+                None => return,
+            }
+        }
+
+        let addresses = self
+            .known_peers
+            .entry(peer_id.clone())
+            .or_insert_with(|| vec![]);
+        if !addresses.contains(&address) && addresses.len() < MAX_ADDRESSES_PER_PEER {
+            addresses.push(address);
+        }
+    }
+
+    /// Records `address` as the address `peer_id` was last reached on.
+    ///
+    /// If `MAX_GOOD_PEERS` are already stored, a peer which is not connected is forgotten to make
+    /// room. Returns `false` if the peer could not be stored.
+    fn add_good_peer(&mut self, peer_id: PeerId, address: Multiaddr) -> bool {
+        if !self.good_peers.contains_key(&peer_id) && self.good_peers.len() >= MAX_GOOD_PEERS {
+            let evictable = self
+                .good_peers
+                .keys()
+                .find(|good| !self.connected_peers.contains(good))
+                .cloned();
+            match evictable {
+                Some(evicted) => {
+                    self.good_peers.remove(&evicted);
+                }
+                None => return false,
+            }
+        }
+
+        self.good_peers.insert(peer_id, address);
+        true
+    }
+
+    /// Starts a random Kademlia query if we are below the target number of peers.
+    fn find_peers(&mut self) {
+        // Peers which failed to connect can be retried after each search.
+        self.dialled_peers.clear();
+
+        if self.connected_peers.len() < self.target_peers {
+            debug!(
+                self.log,
+                "Searching for peers";
+                "connected" => self.connected_peers.len(),
+                "target" => self.target_peers
+            );
+            self.kademlia.find_node(PeerId::random());
+        }
+
+        self.dial_known_peers();
+        self.persist_known_peers();
+
+        self.discovery_delay
+            .reset(Instant::now() + self.discovery_interval);
+    }
+
+    /// Queues dials to known peers until the target number of peers would be reached.
+    fn dial_known_peers(&mut self) {
+        let candidates: Vec<PeerId> = self
+            .known_peers
+            .keys()
+            .filter(|peer_id| {
+                !self.connected_peers.contains(peer_id)
+                    && !self.dialled_peers.contains(peer_id)
+                    && !self.banned_peers.contains(peer_id)
+            })
+            .cloned()
+            .collect();
+
+        for peer_id in candidates {
+            if self.connected_peers.len() + self.dialled_peers.len() >= self.target_peers {
+                break;
+            }
+            debug!(self.log, "Dialing discovered peer"; "peer_id" => format!("{:?}", peer_id));
+            self.dialled_peers.insert(peer_id.clone());
+            self.pending_dials.push_back(peer_id);
+        }
+    }
+}
+
+impl<TSubstream> NetworkBehaviour for Discovery<TSubstream>
+where
+    TSubstream: AsyncRead + AsyncWrite,
+{
+    type ProtocolsHandler = <Kademlia<TSubstream> as NetworkBehaviour>::ProtocolsHandler;
+    type OutEvent = <Kademlia<TSubstream> as NetworkBehaviour>::OutEvent;
+
+    fn new_handler(&mut self) -> Self::ProtocolsHandler {
+        NetworkBehaviour::new_handler(&mut self.kademlia)
+    }
+
+    fn addresses_of_peer(&mut self, peer_id: &PeerId) -> Vec<Multiaddr> {
+        let mut addresses = NetworkBehaviour::addresses_of_peer(&mut self.kademlia, peer_id);
+        if let Some(known) = self.known_peers.get(peer_id) {
+            for address in known {
+                if !addresses.contains(address) {
+                    addresses.push(address.clone());
+                }
+            }
+        }
+        addresses
+    }
+
+    fn inject_connected(&mut self, peer_id: PeerId, endpoint: ConnectedPoint) {
+        // Only dialled addresses are persisted, the address of an inbound connection is not
+        // necessarily one that the peer listens on.
+        if let ConnectedPoint::Dialer { address } = &endpoint {
+            if self.good_peers.get(&peer_id) != Some(address)
+                && !self.banned_peers.contains(&peer_id)
+                && self.add_good_peer(peer_id.clone(), address.clone())
+            {
+                self.good_peers_changed = true;
+            }
+        }
+        self.connected_peers.insert(peer_id.clone());
+        self.dialled_peers.remove(&peer_id);
+
+        NetworkBehaviour::inject_connected(&mut self.kademlia, peer_id, endpoint)
+    }
+
+    fn inject_disconnected(&mut self, peer_id: &PeerId, endpoint: ConnectedPoint) {
+        self.connected_peers.remove(peer_id);
+
+        NetworkBehaviour::inject_disconnected(&mut self.kademlia, peer_id, endpoint)
+    }
+
+    fn inject_replaced(&mut self, peer_id: PeerId, closed: ConnectedPoint, opened: ConnectedPoint) {
+        NetworkBehaviour::inject_replaced(&mut self.kademlia, peer_id, closed, opened)
+    }
+
+    fn inject_node_event(
+        &mut self,
+        peer_id: PeerId,
+        event: <Self::ProtocolsHandler as ProtocolsHandler>::OutEvent,
+    ) {
+        NetworkBehaviour::inject_node_event(&mut self.kademlia, peer_id, event)
+    }
+
+    fn poll(
+        &mut self,
+        params: &mut PollParameters<'_>,
+    ) -> Async<
+        NetworkBehaviourAction<
+            <Self::ProtocolsHandler as ProtocolsHandler>::InEvent,
+            Self::OutEvent,
+        >,
+    > {
+        // check if it is time to search for new peers
+        loop {
+            match self.discovery_delay.poll() {
+                Ok(Async::Ready(_)) => self.find_peers(),
+                Ok(Async::NotReady) => break,
+                Err(e) => {
+                    warn!(self.log, "Discovery timer failed"; "error" => format!("{:?}", e));
+                    break;
+                }
+            }
+        }
+
+        if let Some(peer_id) = self.pending_dials.pop_front() {
+            return Async::Ready(NetworkBehaviourAction::DialPeer { peer_id });
+        }
+
+        // Kademlia results are consumed here, all other actions are passed to the swarm.
+        loop {
+            match self.kademlia.poll(params) {
+                Async::Ready(NetworkBehaviourAction::GenerateEvent(event)) => match event {
+                    KademliaOut::Discovered {
+                        peer_id, addresses, ..
+                    } => {
+                        trace!(
+                            self.log,
+                            "Kademlia peer discovered";
+                            "peer_id" => format!("{:?}", peer_id),
+                            "addresses" => format!("{:?}", addresses)
+                        );
+                        for address in addresses {
+                            self.add_known_address(&peer_id, address);
+                        }
+                    }
+                    KademliaOut::FindNodeResult { closer_peers, .. } => {
+                        debug!(
+                            self.log,
+                            "Peer search complete";
+                            "peers_found" => closer_peers.len()
+                        );
+                        self.dial_known_peers();
+                        if let Some(peer_id) = self.pending_dials.pop_front() {
+                            return Async::Ready(NetworkBehaviourAction::DialPeer { peer_id });
+                        }
+                    }
+                    _ => {}
+                },
+                Async::Ready(action) => return Async::Ready(action),
+                Async::NotReady => break,
+            }
+        }
+
+        Async::NotReady
+    }
+}
+
+impl<TSubstream> Drop for Discovery<TSubstream> {
+    fn drop(&mut self) {
+        self.persist_known_peers();
+    }
+}
+
+/// Reads the peers stored by `save_known_peers`. Returns no peers if the file does not exist.
+///
+/// Lines which can't be parsed are skipped.
+pub fn load_known_peers(path: &Path) -> io::Result<Vec<(PeerId, Multiaddr)>> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(e) => return Err(e),
+    };
+
+    let mut peers = vec![];
+    for line in BufReader::new(file).lines() {
+        let line = line?;
+        let mut parts = line.split_whitespace();
+        if let (Some(peer_id), Some(address)) = (parts.next(), parts.next()) {
+            if let (Ok(peer_id), Ok(address)) = (peer_id.parse(), address.parse()) {
+                peers.push((peer_id, address));
+            }
+        }
+    }
+
+    Ok(peers)
+}
+
+/// Writes `peers` to `path`, one `<peer_id> <multiaddr>` pair per line.
+pub fn save_known_peers(path: &Path, peers: &HashMap<PeerId, Multiaddr>) -> io::Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+
+    let mut file = File::create(path)?;
+    for (peer_id, address) in peers {
+        writeln!(file, "{} {}", peer_id.to_base58(), address)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn known_peers_round_trip() {
+        let path = std::env::temp_dir()
+            .join(format!("known_peers_test_{}", std::process::id()))
+            .join(KNOWN_PEERS_FILENAME);
+
+        let mut peers = HashMap::new();
+        peers.insert(PeerId::random(), "/ip4/127.0.0.1/tcp/9000".parse().unwrap());
+        peers.insert(PeerId::random(), "/ip4/10.0.0.1/tcp/9001".parse().unwrap());
+
+        save_known_peers(&path, &peers).unwrap();
+        let loaded: HashMap<PeerId, Multiaddr> =
+            load_known_peers(&path).unwrap().into_iter().collect();
+
+        assert_eq!(loaded, peers);
+
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn missing_known_peers_file_is_empty() {
+        let path = std::env::temp_dir().join("known_peers_test_does_not_exist");
+        assert!(load_known_peers(&path).unwrap().is_empty());
+    }
+
+    fn discovery() -> Discovery<tokio::net::TcpStream> {
+        let log = slog::Logger::root(slog::Discard, o!());
+        Discovery::new(PeerId::random(), &NetworkConfig::new(vec![]), &log)
+    }
+
+    fn dialer(port: usize) -> ConnectedPoint {
+        ConnectedPoint::Dialer {
+            address: format!("/ip4/127.0.0.1/tcp/{}", port).parse().unwrap(),
+        }
+    }
+
+    #[test]
+    fn known_peers_are_bounded() {
+        let mut discovery = discovery();
+        let address: Multiaddr = "/ip4/127.0.0.1/tcp/9000".parse().unwrap();
+
+        for _ in 0..MAX_KNOWN_PEERS + 10 {
+            discovery.add_known_address(&PeerId::random(), address.clone());
+        }
+
+        assert_eq!(discovery.known_peers.len(), MAX_KNOWN_PEERS);
+    }
+
+    #[test]
+    fn good_peers_are_bounded_without_forgetting_connected_peers() {
+        let mut discovery = discovery();
+
+        let peers: Vec<PeerId> = (0..MAX_GOOD_PEERS).map(|_| PeerId::random()).collect();
+        for (i, peer_id) in peers.iter().enumerate() {
+            discovery.inject_connected(peer_id.clone(), dialer(i));
+        }
+        assert_eq!(discovery.good_peers.len(), MAX_GOOD_PEERS);
+
+        // Every stored peer is connected, so there is no room for another.
+        let extra = PeerId::random();
+        discovery.inject_connected(extra.clone(), dialer(MAX_GOOD_PEERS));
+        assert!(!discovery.good_peers.contains_key(&extra));
+
+        // Once a stored peer disconnects it may be replaced.
+        discovery.inject_disconnected(&peers[0], dialer(0));
+        let replacement = PeerId::random();
+        discovery.inject_connected(replacement.clone(), dialer(MAX_GOOD_PEERS + 1));
+        assert!(discovery.good_peers.contains_key(&replacement));
+        assert!(!discovery.good_peers.contains_key(&peers[0]));
+        assert_eq!(discovery.good_peers.len(), MAX_GOOD_PEERS);
+    }
+}
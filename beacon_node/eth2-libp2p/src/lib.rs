@@ -4,18 +4,26 @@
 /// This crate builds and manages the libp2p services required by the beacon node.
 pub mod behaviour;
 mod config;
+pub mod discovery;
 pub mod error;
+mod node_key;
 pub mod rpc;
 mod service;
+mod snappy;
+pub mod topics;
+pub mod validated_gossipsub;
 
-pub use behaviour::PubsubMessage;
+pub use behaviour::{MessageAcceptance, PubsubMessage};
 pub use config::Config as NetworkConfig;
 pub use libp2p::{
     gossipsub::{GossipsubConfig, GossipsubConfigBuilder},
     PeerId,
 };
+pub use node_key::NodeKeyType;
 pub use rpc::RPCEvent;
 pub use service::Libp2pEvent;
 pub use service::Service;
+pub use topics::{GossipEncoding, GossipKind, GossipTopic};
 pub use types::multiaddr;
 pub use types::Multiaddr;
+pub use validated_gossipsub::MessageId;
@@ -0,0 +1,195 @@
+/// Loads the key which determines the `PeerId` of this node, generating and storing one if
+/// required.
+///
+/// Keys are stored as raw bytes: the 32 byte secret key of a secp256k1 key pair, or the 64 byte
+/// encoding of an ed25519 key pair.
+use crate::error;
+use crate::NetworkConfig;
+use libp2p::core::identity::{self, Keypair};
+use slog::{debug, info};
+use std::fs::{self, File, OpenOptions};
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// The name of the file in the network directory which stores the node key.
+pub const NODE_KEY_FILENAME: &str = "key";
+
+/// The length of a stored secp256k1 key.
+const SECP256K1_KEY_LENGTH: usize = 32;
+/// The length of a stored ed25519 key.
+const ED25519_KEY_LENGTH: usize = 64;
+
+/// The type of key generated when there is no stored key.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum NodeKeyType {
+    Secp256k1,
+    Ed25519,
+}
+
+impl FromStr for NodeKeyType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "secp256k1" => Ok(NodeKeyType::Secp256k1),
+            "ed25519" => Ok(NodeKeyType::Ed25519),
+            other => Err(format!("Unknown node key type: {}", other)),
+        }
+    }
+}
+
+/// Returns the path of the key file for `config`, or `None` if the key should not be stored.
+fn key_file(config: &NetworkConfig) -> Option<PathBuf> {
+    config.node_key_file.clone().or_else(|| {
+        config
+            .network_dir
+            .as_ref()
+            .map(|dir| dir.join(NODE_KEY_FILENAME))
+    })
+}
+
+/// Loads the node key from the key file of `config`, if it exists. Otherwise, a key of type
+/// `config.node_key_type` is generated and written to the key file.
+///
+/// Fails if the key file exists but does not contain a valid key, rather than replacing the
+/// identity of the node.
+pub fn load_or_generate(config: &NetworkConfig, log: &slog::Logger) -> error::Result<Keypair> {
+    let path = match key_file(config) {
+        Some(path) => path,
+        None => {
+            debug!(log, "No network directory, using a temporary node key");
+            return Ok(generate(config.node_key_type));
+        }
+    };
+
+    if path.exists() {
+        let mut bytes = vec![];
+        File::open(&path)
+            .and_then(|mut file| file.read_to_end(&mut bytes))
+            .map_err(|e| format!("Unable to read node key {:?}: {:?}", path, e))?;
+        let key = decode(bytes).map_err(|e| format!("Invalid node key {:?}: {}", path, e))?;
+
+        info!(log, "Loaded node key"; "file" => format!("{:?}", path));
+        Ok(key)
+    } else {
+        let key = generate(config.node_key_type);
+        let bytes = encode(&key)?;
+        write_key_file(&path, &bytes)
+            .map_err(|e| format!("Unable to write node key {:?}: {:?}", path, e))?;
+
+        info!(log, "Generated new node key"; "file" => format!("{:?}", path));
+        Ok(key)
+    }
+}
+
+/// Generates a new key of type `key_type`.
+fn generate(key_type: NodeKeyType) -> Keypair {
+    match key_type {
+        NodeKeyType::Secp256k1 => Keypair::generate_secp256k1(),
+        NodeKeyType::Ed25519 => Keypair::generate_ed25519(),
+    }
+}
+
+/// Returns the bytes stored for `key`.
+fn encode(key: &Keypair) -> Result<Vec<u8>, String> {
+    match key {
+        Keypair::Secp256k1(key) => Ok(key.secret().to_bytes().to_vec()),
+        Keypair::Ed25519(key) => Ok(key.encode().to_vec()),
+        _ => Err("Only secp256k1 and ed25519 node keys can be stored".to_string()),
+    }
+}
+
+/// Decodes a key stored by `encode`. The type of the key is determined by its length.
+fn decode(mut bytes: Vec<u8>) -> Result<Keypair, String> {
+    match bytes.len() {
+        SECP256K1_KEY_LENGTH => identity::secp256k1::SecretKey::from_bytes(&mut bytes[..])
+            .map(|secret| Keypair::Secp256k1(secret.into()))
+            .map_err(|e| format!("Invalid secp256k1 key: {:?}", e)),
+        ED25519_KEY_LENGTH => identity::ed25519::Keypair::decode(&mut bytes[..])
+            .map(Keypair::Ed25519)
+            .map_err(|e| format!("Invalid ed25519 key: {:?}", e)),
+        length => Err(format!("Unexpected key length: {}", length)),
+    }
+}
+
+/// Writes `bytes` to a new file at `path`, readable only by the owner.
+fn write_key_file(path: &Path, bytes: &[u8]) -> std::io::Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+
+    options.open(path)?.write_all(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use slog::{o, Discard, Logger};
+
+    fn config(dir: &Path, key_type: NodeKeyType) -> NetworkConfig {
+        let mut config = NetworkConfig::default();
+        config.network_dir = Some(dir.to_path_buf());
+        config.node_key_type = key_type;
+        config
+    }
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("node_key_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn keys_are_reloaded() {
+        let log = Logger::root(Discard, o!());
+
+        for (name, key_type) in &[
+            ("secp256k1", NodeKeyType::Secp256k1),
+            ("ed25519", NodeKeyType::Ed25519),
+        ] {
+            let dir = test_dir(name);
+            let config = config(&dir, *key_type);
+
+            let generated = load_or_generate(&config, &log).unwrap();
+            let loaded = load_or_generate(&config, &log).unwrap();
+            assert_eq!(
+                generated.public().into_peer_id(),
+                loaded.public().into_peer_id()
+            );
+
+            #[cfg(unix)]
+            {
+                use std::os::unix::fs::PermissionsExt;
+                let mode = fs::metadata(dir.join(NODE_KEY_FILENAME))
+                    .unwrap()
+                    .permissions()
+                    .mode();
+                assert_eq!(mode & 0o777, 0o600);
+            }
+
+            fs::remove_dir_all(&dir).unwrap();
+        }
+    }
+
+    #[test]
+    fn invalid_key_is_not_replaced() {
+        let log = Logger::root(Discard, o!());
+        let dir = test_dir("invalid");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join(NODE_KEY_FILENAME), b"not a key").unwrap();
+
+        assert!(load_or_generate(&config(&dir, NodeKeyType::Secp256k1), &log).is_err());
+        assert_eq!(fs::read(dir.join(NODE_KEY_FILENAME)).unwrap(), b"not a key");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
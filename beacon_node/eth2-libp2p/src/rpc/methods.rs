@@ -1,7 +1,23 @@
 use ssz::{Decodable, DecodeError, Encodable, SszStream};
 /// Available RPC methods types and ids.
 use ssz_derive::{Decode, Encode};
-use types::{BeaconBlockBody, BeaconBlockHeader, Epoch, Hash256, Slot};
+use types::{BeaconBlock, BeaconBlockBody, BeaconBlockHeader, Epoch, Hash256, Slot};
+
+/// The maximum `count` of a `BeaconBlockRoots` request, four epochs' worth.
+///
+/// Each root costs the responder a lookup in the database, and nothing we send requires more than
+/// a few epochs of roots at a time.
+pub const MAX_BLOCK_ROOTS_COUNT: u64 = 256;
+/// The maximum `max_headers` of a `BeaconBlockHeaders` request.
+pub const MAX_BLOCK_HEADERS: u64 = 1_024;
+/// The maximum number of roots in a `BeaconBlockBodies` request.
+pub const MAX_BLOCK_BODIES: u64 = 128;
+/// The maximum number of blocks in a `BeaconBlocksByRange` or `BeaconBlocksByRoot` request.
+pub const MAX_REQUEST_BLOCKS: u64 = 64;
+/// The number of bytes of the SSZ encoded state in each chunk of a `BeaconChainState` response.
+pub const STATE_CHUNK_SIZE: u64 = 65_536;
+/// The maximum `count` of a `BeaconChainState` request.
+pub const MAX_STATE_CHUNKS: u64 = 32;
 
 #[derive(Debug)]
 /// Available Serenity Libp2p RPC methods
@@ -16,8 +32,12 @@ pub enum RPCMethod {
     BeaconBlockHeaders,
     /// Requests a number of beacon block bodies.
     BeaconBlockBodies,
-    /// Requests values for a merkle proof for the current blocks state root.
-    BeaconChainState, // Note: experimental, not complete.
+    /// Requests chunks of the SSZ encoded beacon state with some state root.
+    BeaconChainState,
+    /// Requests the beacon blocks in a range of slots.
+    BeaconBlocksByRange,
+    /// Requests a number of beacon blocks by their roots.
+    BeaconBlocksByRoot,
     /// Unknown method received.
     Unknown,
 }
@@ -31,6 +51,8 @@ impl From<u16> for RPCMethod {
             11 => RPCMethod::BeaconBlockHeaders,
             12 => RPCMethod::BeaconBlockBodies,
             13 => RPCMethod::BeaconChainState,
+            14 => RPCMethod::BeaconBlocksByRange,
+            15 => RPCMethod::BeaconBlocksByRoot,
 
             _ => RPCMethod::Unknown,
         }
@@ -46,6 +68,8 @@ impl Into<u16> for RPCMethod {
             RPCMethod::BeaconBlockHeaders => 11,
             RPCMethod::BeaconBlockBodies => 12,
             RPCMethod::BeaconChainState => 13,
+            RPCMethod::BeaconBlocksByRange => 14,
+            RPCMethod::BeaconBlocksByRoot => 15,
             _ => 0,
         }
     }
@@ -59,6 +83,8 @@ pub enum RPCRequest {
     BeaconBlockHeaders(BeaconBlockHeadersRequest),
     BeaconBlockBodies(BeaconBlockBodiesRequest),
     BeaconChainState(BeaconChainStateRequest),
+    BeaconBlocksByRange(BeaconBlocksByRangeRequest),
+    BeaconBlocksByRoot(BeaconBlocksByRootRequest),
 }
 
 impl RPCRequest {
@@ -70,9 +96,19 @@ impl RPCRequest {
             RPCRequest::BeaconBlockHeaders(_) => RPCMethod::BeaconBlockHeaders,
             RPCRequest::BeaconBlockBodies(_) => RPCMethod::BeaconBlockBodies,
             RPCRequest::BeaconChainState(_) => RPCMethod::BeaconChainState,
+            RPCRequest::BeaconBlocksByRange(_) => RPCMethod::BeaconBlocksByRange,
+            RPCRequest::BeaconBlocksByRoot(_) => RPCMethod::BeaconBlocksByRoot,
         };
         method.into()
     }
+
+    /// Returns `true` if the peer is expected to respond to this request.
+    pub fn expects_response(&self) -> bool {
+        match self {
+            RPCRequest::Goodbye(_) => false,
+            _ => true,
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -82,6 +118,10 @@ pub enum RPCResponse {
     BeaconBlockHeaders(BeaconBlockHeadersResponse),
     BeaconBlockBodies(BeaconBlockBodiesResponse),
     BeaconChainState(BeaconChainStateResponse),
+    BeaconBlocksByRange(BeaconBlocksResponse),
+    BeaconBlocksByRoot(BeaconBlocksResponse),
+    /// The request could not be fulfilled.
+    Error(ErrorResponse),
 }
 
 impl RPCResponse {
@@ -92,9 +132,84 @@ impl RPCResponse {
             RPCResponse::BeaconBlockHeaders(_) => RPCMethod::BeaconBlockHeaders,
             RPCResponse::BeaconBlockBodies(_) => RPCMethod::BeaconBlockBodies,
             RPCResponse::BeaconChainState(_) => RPCMethod::BeaconChainState,
+            RPCResponse::BeaconBlocksByRange(_) => RPCMethod::BeaconBlocksByRange,
+            RPCResponse::BeaconBlocksByRoot(_) => RPCMethod::BeaconBlocksByRoot,
+            RPCResponse::Error(error) => return error.method_id,
         };
         method.into()
     }
+
+    /// Returns the `ResponseCode` sent with this response.
+    pub fn response_code(&self) -> ResponseCode {
+        match self {
+            RPCResponse::Error(error) => error.code,
+            _ => ResponseCode::Success,
+        }
+    }
+}
+
+/// The status of an RPC response, sent before the body of the response.
+///
+/// Note: any unknown `u16::into(n)` will resolve to `ResponseCode::Unknown`, which is treated as
+/// an error.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ResponseCode {
+    /// The response contains the requested data.
+    Success,
+    /// The request was malformed or exceeded the limits of the protocol.
+    InvalidRequest,
+    /// The responder failed whilst processing a valid request.
+    ServerError,
+    /// The responder does not have the requested data.
+    ResourceUnavailable,
+    /// The requester has exceeded its allowance for the method and should retry later.
+    RateLimited,
+    Unknown,
+}
+
+impl From<u16> for ResponseCode {
+    fn from(code: u16) -> ResponseCode {
+        match code {
+            0 => ResponseCode::Success,
+            20 => ResponseCode::InvalidRequest,
+            40 => ResponseCode::ServerError,
+            50 => ResponseCode::ResourceUnavailable,
+            60 => ResponseCode::RateLimited,
+            _ => ResponseCode::Unknown,
+        }
+    }
+}
+
+impl Into<u16> for ResponseCode {
+    fn into(self) -> u16 {
+        match self {
+            ResponseCode::Success => 0,
+            ResponseCode::InvalidRequest => 20,
+            ResponseCode::ServerError => 40,
+            ResponseCode::ResourceUnavailable => 50,
+            ResponseCode::RateLimited => 60,
+            ResponseCode::Unknown => u16::max_value(),
+        }
+    }
+}
+
+/// An RPC response with a non-success `ResponseCode`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ErrorResponse {
+    /// The method of the request being responded to. This is sent in the response header, rather
+    /// than as part of the error.
+    pub method_id: u16,
+    /// The reason the request failed.
+    pub code: ResponseCode,
+    /// A human-readable description of the error.
+    pub message: String,
+}
+
+impl ErrorResponse {
+    /// Encodes the error message, which forms the body of an error response.
+    pub fn message_bytes(&self) -> Vec<u8> {
+        self.message.as_bytes().to_vec()
+    }
 }
 
 /* Request/Response data structures for RPC methods */
@@ -119,11 +234,22 @@ pub struct HelloMessage {
 /// Note: any unknown `u64::into(n)` will resolve to `GoodbyeReason::Unknown` for any unknown `n`,
 /// however `GoodbyeReason::Unknown.into()` will go into `0_u64`. Therefore de-serializing then
 /// re-serializing may not return the same bytes.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum GoodbyeReason {
+    /// The node is shutting down.
     ClientShutdown,
+    /// The peer is on a different network or chain.
     IrreleventNetwork,
+    /// The peer misbehaved or an error occurred.
     Fault,
+    /// The peer did not complete the `Hello` handshake.
+    UnableToVerifyNetwork,
+    /// The node has too many peers.
+    TooManyPeers,
+    /// The score of the peer fell below the ban threshold.
+    BadScore,
+    /// The peer is banned.
+    Banned,
     Unknown,
 }
 
@@ -133,6 +259,10 @@ impl From<u64> for GoodbyeReason {
             1 => GoodbyeReason::ClientShutdown,
             2 => GoodbyeReason::IrreleventNetwork,
             3 => GoodbyeReason::Fault,
+            128 => GoodbyeReason::UnableToVerifyNetwork,
+            129 => GoodbyeReason::TooManyPeers,
+            250 => GoodbyeReason::BadScore,
+            251 => GoodbyeReason::Banned,
             _ => GoodbyeReason::Unknown,
         }
     }
@@ -145,13 +275,17 @@ impl Into<u64> for GoodbyeReason {
             GoodbyeReason::ClientShutdown => 1,
             GoodbyeReason::IrreleventNetwork => 2,
             GoodbyeReason::Fault => 3,
+            GoodbyeReason::UnableToVerifyNetwork => 128,
+            GoodbyeReason::TooManyPeers => 129,
+            GoodbyeReason::BadScore => 250,
+            GoodbyeReason::Banned => 251,
         }
     }
 }
 
 impl Encodable for GoodbyeReason {
     fn ssz_append(&self, s: &mut SszStream) {
-        let id: u64 = (*self).clone().into();
+        let id: u64 = (*self).into();
         id.ssz_append(s);
     }
 }
@@ -169,7 +303,7 @@ pub struct BeaconBlockRootsRequest {
     /// The starting slot of the requested blocks.
     pub start_slot: Slot,
     /// The number of blocks from the start slot.
-    pub count: u64, // this must be less than 32768. //TODO: Enforce this in the lower layers
+    pub count: u64, // this must not exceed `MAX_BLOCK_ROOTS_COUNT`.
 }
 
 /// Response containing a number of beacon block roots from a peer.
@@ -235,17 +369,98 @@ pub struct BeaconBlockBodiesResponse {
     pub block_bodies: Vec<BeaconBlockBody>,
 }
 
-/// Request values for tree hashes which yield a blocks `state_root`.
+/// Request a range of chunks of the SSZ encoded `BeaconState` with `state_root`.
+///
+/// Chunk `i` holds the bytes `i * STATE_CHUNK_SIZE..(i + 1) * STATE_CHUNK_SIZE` of the encoding. A
+/// state may therefore be downloaded over several requests, then verified against the
+/// `state_root` of a block once it is complete.
 #[derive(Encode, Decode, Clone, Debug, PartialEq)]
 pub struct BeaconChainStateRequest {
-    /// The tree hashes that a value is requested for.
-    pub hashes: Vec<Hash256>,
+    /// The tree hash root of the requested state.
+    pub state_root: Hash256,
+    /// The index of the first requested chunk.
+    pub start_chunk: u64,
+    /// The number of chunks requested.
+    pub count: u64, // this must not exceed `MAX_STATE_CHUNKS`.
+}
+
+impl BeaconChainStateRequest {
+    /// Returns the requested chunks of the SSZ encoded state `ssz`, or `None` if the first chunk is
+    /// beyond the end of the encoding.
+    pub fn select<'a>(&self, ssz: &'a [u8]) -> Option<&'a [u8]> {
+        let start = self.start_chunk.saturating_mul(STATE_CHUNK_SIZE);
+        let end = self
+            .start_chunk
+            .saturating_add(self.count)
+            .saturating_mul(STATE_CHUNK_SIZE)
+            .min(ssz.len() as u64);
+
+        if start >= ssz.len() as u64 {
+            None
+        } else {
+            Some(&ssz[start as usize..end as usize])
+        }
+    }
 }
 
-/// Request values for tree hashes which yield a blocks `state_root`.
-// Note: TBD
+/// Response containing chunks of an SSZ encoded `BeaconState`.
 #[derive(Encode, Decode, Clone, Debug, PartialEq)]
 pub struct BeaconChainStateResponse {
-    /// The values corresponding the to the requested tree hashes.
-    pub values: bool, //TBD - stubbed with encodeable bool
+    /// The tree hash root of the state.
+    pub state_root: Hash256,
+    /// The length of the whole SSZ encoded state, in bytes.
+    pub state_len: u64,
+    /// The index of the first chunk in `bytes`.
+    pub start_chunk: u64,
+    /// The bytes of the requested chunks. Only the last chunk of the state may be shorter than
+    /// `STATE_CHUNK_SIZE`.
+    pub bytes: Vec<u8>,
+}
+
+impl BeaconChainStateResponse {
+    /// The number of chunks the whole state is split into.
+    pub fn total_chunks(&self) -> u64 {
+        (self.state_len + STATE_CHUNK_SIZE - 1) / STATE_CHUNK_SIZE
+    }
+}
+
+/// Request the beacon blocks at the slots `start_slot + i * step`, for `i` in `0..count`.
+///
+/// Slots without a block are omitted from the response.
+#[derive(Encode, Decode, Clone, Debug, PartialEq)]
+pub struct BeaconBlocksByRangeRequest {
+    /// The first slot of the requested blocks.
+    pub start_slot: Slot,
+    /// The number of slots in the range.
+    pub count: u64, // this must not exceed `MAX_REQUEST_BLOCKS`.
+    /// The number of slots between each requested slot. Must be at least 1.
+    pub step: u64,
+}
+
+/// Request a number of beacon blocks by their roots.
+///
+/// Blocks unknown to the peer are omitted from the response.
+#[derive(Encode, Decode, Clone, Debug, PartialEq)]
+pub struct BeaconBlocksByRootRequest {
+    /// The roots of the requested blocks.
+    pub block_roots: Vec<Hash256>, // this must not exceed `MAX_REQUEST_BLOCKS`.
+}
+
+/// Response containing the requested beacon blocks.
+///
+/// Blocks requested by range are in ascending slot order, blocks requested by root are in the
+/// order they were requested.
+#[derive(Encode, Decode, Clone, Debug, PartialEq)]
+pub struct BeaconBlocksResponse {
+    /// The list of requested beacon blocks.
+    pub blocks: Vec<BeaconBlock>,
+}
+
+impl BeaconBlocksResponse {
+    /// Returns `true` if each `self.blocks[i].slot` is higher than the preceeding `i`.
+    pub fn slots_are_ascending(&self) -> bool {
+        self.blocks
+            .windows(2)
+            .all(|pair| pair[0].slot < pair[1].slot)
+    }
 }
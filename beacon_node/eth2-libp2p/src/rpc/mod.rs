@@ -1,9 +1,11 @@
 /// RPC Protocol over libp2p.
 ///
 /// This is purpose built for Ethereum 2.0 serenity and the protocol listens on
-/// `/eth/serenity/rpc/1.0.0`
+/// `/eth/serenity/rpc/1.0.0/ssz_snappy`, falling back to the uncompressed `/eth/serenity/rpc/1.0.0`
+/// for peers which do not support snappy.
 pub mod methods;
 mod protocol;
+mod rate_limiter;
 
 use futures::prelude::*;
 use libp2p::core::protocols_handler::{OneShotHandler, ProtocolsHandler};
@@ -11,11 +13,19 @@ use libp2p::core::swarm::{
     ConnectedPoint, NetworkBehaviour, NetworkBehaviourAction, PollParameters,
 };
 use libp2p::{Multiaddr, PeerId};
-pub use methods::{HelloMessage, RPCMethod, RPCRequest, RPCResponse};
+pub use methods::{ErrorResponse, HelloMessage, RPCMethod, RPCRequest, RPCResponse, ResponseCode};
 pub use protocol::{RPCEvent, RPCProtocol, RequestId};
-use slog::o;
+use rate_limiter::RateLimiter;
+pub use rate_limiter::{Quota, RateLimiterConfig};
+use slog::{debug, o, warn};
+use std::collections::{HashMap, HashSet};
 use std::marker::PhantomData;
+use std::time::Duration;
 use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::timer::{delay_queue, DelayQueue};
+
+/// The time a peer has to respond to a request before the request times out.
+pub const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
 
 /// The network behaviour handles RPC requests/responses as specified in the Eth 2.0 phase 0
 /// specification.
@@ -23,29 +33,64 @@ use tokio::io::{AsyncRead, AsyncWrite};
 pub struct Rpc<TSubstream> {
     /// Queue of events to processed.
     events: Vec<NetworkBehaviourAction<RPCEvent, RPCMessage>>,
+    /// Requests we have sent which have not yet been responded to, along with their method ids.
+    pending_requests: HashMap<(PeerId, RequestId), (u16, delay_queue::Key)>,
+    /// Fires when a pending request times out.
+    request_timeouts: DelayQueue<(PeerId, RequestId)>,
+    /// Limits the requests each peer can make.
+    rate_limiter: RateLimiter,
+    /// The peers we are connected to, whether they dialed us or we dialed them.
+    connected_peers: HashSet<PeerId>,
     /// Pins the generic substream.
     marker: PhantomData<TSubstream>,
     /// Slog logger for RPC behaviour.
-    _log: slog::Logger,
+    log: slog::Logger,
 }
 
 impl<TSubstream> Rpc<TSubstream> {
-    pub fn new(log: &slog::Logger) -> Self {
+    pub fn new(rate_limits: RateLimiterConfig, log: &slog::Logger) -> Self {
         let log = log.new(o!("Service" => "Libp2p-RPC"));
         Rpc {
             events: Vec::new(),
+            pending_requests: HashMap::new(),
+            request_timeouts: DelayQueue::new(),
+            rate_limiter: RateLimiter::new(rate_limits),
+            connected_peers: HashSet::new(),
             marker: PhantomData,
-            _log: log,
+            log,
         }
     }
 
     /// Submits and RPC request.
+    ///
+    /// Requests which expect a response (i.e., all but `Goodbye`) time out if no response is
+    /// received within `REQUEST_TIMEOUT`.
     pub fn send_rpc(&mut self, peer_id: PeerId, rpc_event: RPCEvent) {
+        if let RPCEvent::Request { id, body, .. } = &rpc_event {
+            if body.expects_response() {
+                let request = (peer_id.clone(), id.clone());
+                let key = self
+                    .request_timeouts
+                    .insert(request.clone(), REQUEST_TIMEOUT);
+                if let Some((_, old_key)) = self
+                    .pending_requests
+                    .insert(request, (body.method_id(), key))
+                {
+                    self.request_timeouts.remove(&old_key);
+                }
+            }
+        }
+
         self.events.push(NetworkBehaviourAction::SendEvent {
             peer_id,
             event: rpc_event,
         });
     }
+
+    /// Returns the peers we are currently connected to.
+    pub fn connected_peers(&self) -> impl Iterator<Item = &PeerId> {
+        self.connected_peers.iter()
+    }
 }
 
 impl<TSubstream> NetworkBehaviour for Rpc<TSubstream>
@@ -64,6 +109,8 @@ where
     }
 
     fn inject_connected(&mut self, peer_id: PeerId, connected_point: ConnectedPoint) {
+        self.connected_peers.insert(peer_id.clone());
+
         // if initialised the connection, report this upwards to send the HELLO request
         if let ConnectedPoint::Dialer { .. } = connected_point {
             self.events.push(NetworkBehaviourAction::GenerateEvent(
@@ -72,7 +119,27 @@ where
         }
     }
 
-    fn inject_disconnected(&mut self, _: &PeerId, _: ConnectedPoint) {}
+    fn inject_disconnected(&mut self, peer_id: &PeerId, _: ConnectedPoint) {
+        self.connected_peers.remove(peer_id);
+        self.rate_limiter.prune();
+
+        // requests to the peer will never be responded to, the user is informed of the
+        // disconnection instead of each request timing out
+        let request_timeouts = &mut self.request_timeouts;
+        self.pending_requests
+            .retain(|(pending_peer_id, _), (_, key)| {
+                if pending_peer_id == peer_id {
+                    request_timeouts.remove(key);
+                    false
+                } else {
+                    true
+                }
+            });
+
+        self.events.push(NetworkBehaviourAction::GenerateEvent(
+            RPCMessage::PeerDisconnected(peer_id.clone()),
+        ));
+    }
 
     fn inject_node_event(
         &mut self,
@@ -85,6 +152,47 @@ where
             OneShotEvent::Sent => return,
         };
 
+        match &event {
+            // a response completes the pending request
+            RPCEvent::Response { id, .. } => {
+                if let Some((_, key)) = self.pending_requests.remove(&(source.clone(), id.clone()))
+                {
+                    self.request_timeouts.remove(&key);
+                }
+            }
+            // requests exceeding the limits are responded to here, rather than by the user
+            RPCEvent::Request { id, body, .. } => {
+                if let Err(e) = self.rate_limiter.allows(&source, body) {
+                    debug!(
+                        self.log,
+                        "Rejected RPC request";
+                        "peer" => format!("{:?}", source),
+                        "method" => format!("{:?}", RPCMethod::from(body.method_id())),
+                        "reason" => format!("{:?}", e)
+                    );
+                    let code = e.response_code();
+                    if body.expects_response() {
+                        self.events.push(NetworkBehaviourAction::SendEvent {
+                            peer_id: source.clone(),
+                            event: RPCEvent::Response {
+                                id: id.clone(),
+                                method_id: body.method_id(),
+                                result: RPCResponse::Error(ErrorResponse {
+                                    method_id: body.method_id(),
+                                    code,
+                                    message: e.message(),
+                                }),
+                            },
+                        });
+                    }
+                    self.events.push(NetworkBehaviourAction::GenerateEvent(
+                        RPCMessage::RequestRejected(source, code),
+                    ));
+                    return;
+                }
+            }
+        }
+
         // send the event to the user
         self.events
             .push(NetworkBehaviourAction::GenerateEvent(RPCMessage::RPC(
@@ -101,6 +209,30 @@ where
             Self::OutEvent,
         >,
     > {
+        loop {
+            match self.request_timeouts.poll() {
+                Ok(Async::Ready(Some(expired))) => {
+                    let (peer_id, id) = expired.into_inner();
+                    if let Some((method_id, _)) =
+                        self.pending_requests.remove(&(peer_id.clone(), id.clone()))
+                    {
+                        self.events.push(NetworkBehaviourAction::GenerateEvent(
+                            RPCMessage::RequestTimeout {
+                                peer_id,
+                                id,
+                                method_id,
+                            },
+                        ));
+                    }
+                }
+                Ok(Async::Ready(None)) | Ok(Async::NotReady) => break,
+                Err(e) => {
+                    warn!(self.log, "Request timer failed"; "error" => format!("{:?}", e));
+                    break;
+                }
+            }
+        }
+
         if !self.events.is_empty() {
             return Async::Ready(self.events.remove(0));
         }
@@ -112,6 +244,17 @@ where
 pub enum RPCMessage {
     RPC(PeerId, RPCEvent),
     PeerDialed(PeerId),
+    /// The connection to the peer was closed. Its pending requests are dropped.
+    PeerDisconnected(PeerId),
+    /// A request from the peer was rejected for exceeding the protocol maximums or rate limits,
+    /// and responded to with the given code.
+    RequestRejected(PeerId, ResponseCode),
+    /// A request sent to the peer was not responded to within `REQUEST_TIMEOUT`.
+    RequestTimeout {
+        peer_id: PeerId,
+        id: RequestId,
+        method_id: u16,
+    },
 }
 
 /// Transmission between the `OneShotHandler` and the `RPCEvent`.
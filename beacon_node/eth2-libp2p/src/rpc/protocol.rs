@@ -1,14 +1,26 @@
 use super::methods::*;
+use crate::snappy;
 use libp2p::core::{upgrade, InboundUpgrade, OutboundUpgrade, UpgradeInfo};
 use ssz::{ssz_encode, Decodable, DecodeError as SSZDecodeError, Encodable, SszStream};
 use std::hash::{Hash, Hasher};
 use std::io;
-use std::iter;
 use tokio::io::{AsyncRead, AsyncWrite};
 
-/// The maximum bytes that can be sent across the RPC.
+/// The maximum bytes that can be sent across the RPC, before decompression.
 const MAX_READ_SIZE: usize = 4_194_304; // 4M
 
+/// The protocol ID of the RPC with snappy compressed SSZ messages.
+const PROTOCOL_SSZ_SNAPPY: &[u8] = b"/eth/serenity/rpc/1.0.0/ssz_snappy";
+
+/// The protocol ID of the RPC with uncompressed SSZ messages, used with peers which do not
+/// support snappy.
+const PROTOCOL_SSZ: &[u8] = b"/eth/serenity/rpc/1.0.0";
+
+/// The protocol IDs we support, in order of preference.
+fn protocol_ids() -> Vec<&'static [u8]> {
+    vec![PROTOCOL_SSZ_SNAPPY, PROTOCOL_SSZ]
+}
+
 /// Implementation of the `ConnectionUpgrade` for the rpc protocol.
 
 #[derive(Debug, Clone)]
@@ -16,11 +28,11 @@ pub struct RPCProtocol;
 
 impl UpgradeInfo for RPCProtocol {
     type Info = &'static [u8];
-    type InfoIter = iter::Once<Self::Info>;
+    type InfoIter = Vec<Self::Info>;
 
     #[inline]
     fn protocol_info(&self) -> Self::InfoIter {
-        iter::once(b"/eth/serenity/rpc/1.0.0")
+        protocol_ids()
     }
 }
 
@@ -102,15 +114,15 @@ pub enum RPCEvent {
 
 impl UpgradeInfo for RPCEvent {
     type Info = &'static [u8];
-    type InfoIter = iter::Once<Self::Info>;
+    type InfoIter = Vec<Self::Info>;
 
     #[inline]
     fn protocol_info(&self) -> Self::InfoIter {
-        iter::once(b"/eth/serenity/rpc/1.0.0")
+        protocol_ids()
     }
 }
 
-type FnDecodeRPCEvent = fn(Vec<u8>, ()) -> Result<RPCEvent, DecodeError>;
+type FnDecodeRPCEvent = fn(Vec<u8>, &'static [u8]) -> Result<RPCEvent, DecodeError>;
 
 impl<TSocket> InboundUpgrade<TSocket> for RPCProtocol
 where
@@ -118,10 +130,35 @@ where
 {
     type Output = RPCEvent;
     type Error = DecodeError;
-    type Future = upgrade::ReadOneThen<upgrade::Negotiated<TSocket>, (), FnDecodeRPCEvent>;
+    type Future =
+        upgrade::ReadOneThen<upgrade::Negotiated<TSocket>, &'static [u8], FnDecodeRPCEvent>;
+
+    fn upgrade_inbound(
+        self,
+        socket: upgrade::Negotiated<TSocket>,
+        protocol: Self::Info,
+    ) -> Self::Future {
+        upgrade::read_one_then(socket, MAX_READ_SIZE, protocol, |packet, protocol| {
+            Ok(decode(unpack(packet, protocol)?)?)
+        })
+    }
+}
+
+/// Decompresses a packet received with the snappy protocol.
+fn unpack(packet: Vec<u8>, protocol: &[u8]) -> Result<Vec<u8>, DecodeError> {
+    if protocol == PROTOCOL_SSZ_SNAPPY {
+        Ok(snappy::decompress(&packet)?)
+    } else {
+        Ok(packet)
+    }
+}
 
-    fn upgrade_inbound(self, socket: upgrade::Negotiated<TSocket>, _: Self::Info) -> Self::Future {
-        upgrade::read_one_then(socket, MAX_READ_SIZE, (), |packet, ()| Ok(decode(packet)?))
+/// Compresses a packet to be sent with the snappy protocol.
+fn pack(packet: Vec<u8>, protocol: &[u8]) -> Vec<u8> {
+    if protocol == PROTOCOL_SSZ_SNAPPY {
+        snappy::compress(&packet)
+    } else {
+        packet
     }
 }
 
@@ -162,6 +199,16 @@ fn decode(packet: Vec<u8>) -> Result<RPCEvent, DecodeError> {
                     BeaconChainStateRequest::ssz_decode(&packet, index)?;
                 RPCRequest::BeaconChainState(chain_state_request)
             }
+            RPCMethod::BeaconBlocksByRange => {
+                let (blocks_by_range_request, _index) =
+                    BeaconBlocksByRangeRequest::ssz_decode(&packet, index)?;
+                RPCRequest::BeaconBlocksByRange(blocks_by_range_request)
+            }
+            RPCMethod::BeaconBlocksByRoot => {
+                let (blocks_by_root_request, _index) =
+                    BeaconBlocksByRootRequest::ssz_decode(&packet, index)?;
+                RPCRequest::BeaconBlocksByRoot(blocks_by_root_request)
+            }
             RPCMethod::Unknown => return Err(DecodeError::UnknownRPCMethod),
         };
 
@@ -173,12 +220,28 @@ fn decode(packet: Vec<u8>) -> Result<RPCEvent, DecodeError> {
     }
     // we have received a response
     else {
+        let (code, index) = u16::ssz_decode(&packet, index)?;
+        let code = ResponseCode::from(code);
+
+        if code != ResponseCode::Success {
+            let (message, _index) = <Vec<u8>>::ssz_decode(&packet, index)?;
+            return Ok(RPCEvent::Response {
+                id,
+                method_id,
+                result: RPCResponse::Error(ErrorResponse {
+                    method_id,
+                    code,
+                    message: String::from_utf8_lossy(&message).into_owned(),
+                }),
+            });
+        }
+
         let result = match RPCMethod::from(method_id) {
             RPCMethod::Hello => {
                 let (body, _index) = HelloMessage::ssz_decode(&packet, index)?;
                 RPCResponse::Hello(body)
             }
-            RPCMethod::Goodbye => unreachable!("Should never receive a goodbye response"),
+            RPCMethod::Goodbye => return Err(DecodeError::UnexpectedGoodbyeResponse),
             RPCMethod::BeaconBlockRoots => {
                 let (body, _index) = BeaconBlockRootsResponse::ssz_decode(&packet, index)?;
                 RPCResponse::BeaconBlockRoots(body)
@@ -195,6 +258,14 @@ fn decode(packet: Vec<u8>) -> Result<RPCEvent, DecodeError> {
                 let (body, _index) = BeaconChainStateResponse::ssz_decode(&packet, index)?;
                 RPCResponse::BeaconChainState(body)
             }
+            RPCMethod::BeaconBlocksByRange => {
+                let (body, _index) = BeaconBlocksResponse::ssz_decode(&packet, index)?;
+                RPCResponse::BeaconBlocksByRange(body)
+            }
+            RPCMethod::BeaconBlocksByRoot => {
+                let (body, _index) = BeaconBlocksResponse::ssz_decode(&packet, index)?;
+                RPCResponse::BeaconBlocksByRoot(body)
+            }
             RPCMethod::Unknown => return Err(DecodeError::UnknownRPCMethod),
         };
         Ok(RPCEvent::Response {
@@ -214,8 +285,12 @@ where
     type Future = upgrade::WriteOne<upgrade::Negotiated<TSocket>>;
 
     #[inline]
-    fn upgrade_outbound(self, socket: upgrade::Negotiated<TSocket>, _: Self::Info) -> Self::Future {
-        let bytes = ssz_encode(&self);
+    fn upgrade_outbound(
+        self,
+        socket: upgrade::Negotiated<TSocket>,
+        protocol: Self::Info,
+    ) -> Self::Future {
+        let bytes = pack(ssz_encode(&self), protocol);
         upgrade::write_one(socket, bytes)
     }
 }
@@ -250,6 +325,12 @@ impl Encodable for RPCEvent {
                     RPCRequest::BeaconChainState(body) => {
                         s.append(body);
                     }
+                    RPCRequest::BeaconBlocksByRange(body) => {
+                        s.append(body);
+                    }
+                    RPCRequest::BeaconBlocksByRoot(body) => {
+                        s.append(body);
+                    }
                 }
             }
             RPCEvent::Response {
@@ -260,6 +341,8 @@ impl Encodable for RPCEvent {
                 s.append(&false);
                 s.append(id);
                 s.append(method_id);
+                let code: u16 = result.response_code().into();
+                s.append(&code);
                 match result {
                     RPCResponse::Hello(response) => {
                         s.append(response);
@@ -276,6 +359,15 @@ impl Encodable for RPCEvent {
                     RPCResponse::BeaconChainState(response) => {
                         s.append(response);
                     }
+                    RPCResponse::BeaconBlocksByRange(response) => {
+                        s.append(response);
+                    }
+                    RPCResponse::BeaconBlocksByRoot(response) => {
+                        s.append(response);
+                    }
+                    RPCResponse::Error(error) => {
+                        s.append(&error.message_bytes());
+                    }
                 }
             }
         }
@@ -285,8 +377,10 @@ impl Encodable for RPCEvent {
 #[derive(Debug)]
 pub enum DecodeError {
     ReadError(upgrade::ReadOneError),
+    DecompressionError(io::Error),
     SSZDecodeError(ssz::DecodeError),
     UnknownRPCMethod,
+    UnexpectedGoodbyeResponse,
 }
 
 impl From<upgrade::ReadOneError> for DecodeError {
@@ -296,9 +390,175 @@ impl From<upgrade::ReadOneError> for DecodeError {
     }
 }
 
+impl From<io::Error> for DecodeError {
+    #[inline]
+    fn from(err: io::Error) -> Self {
+        DecodeError::DecompressionError(err)
+    }
+}
+
 impl From<ssz::DecodeError> for DecodeError {
     #[inline]
     fn from(err: ssz::DecodeError) -> Self {
         DecodeError::SSZDecodeError(err)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use types::{Hash256, Slot};
+
+    #[test]
+    fn error_response_round_trip() {
+        let method_id: u16 = RPCMethod::BeaconBlockRoots.into();
+        let event = RPCEvent::Response {
+            id: RequestId::from(7),
+            method_id,
+            result: RPCResponse::Error(ErrorResponse {
+                method_id,
+                code: ResponseCode::ResourceUnavailable,
+                message: "slot out of bounds".to_string(),
+            }),
+        };
+
+        match decode(ssz_encode(&event)).unwrap() {
+            RPCEvent::Response {
+                id,
+                method_id: decoded_method_id,
+                result: RPCResponse::Error(error),
+            } => {
+                assert_eq!(id, RequestId::from(7));
+                assert_eq!(decoded_method_id, method_id);
+                assert_eq!(error.code, ResponseCode::ResourceUnavailable);
+                assert_eq!(error.message, "slot out of bounds");
+            }
+            other => panic!("unexpected event {:?}", other),
+        }
+    }
+
+    #[test]
+    fn success_response_round_trip() {
+        let event = RPCEvent::Response {
+            id: RequestId::from(1),
+            method_id: RPCMethod::BeaconBlockRoots.into(),
+            result: RPCResponse::BeaconBlockRoots(BeaconBlockRootsResponse { roots: vec![] }),
+        };
+
+        match decode(ssz_encode(&event)).unwrap() {
+            RPCEvent::Response {
+                result: RPCResponse::BeaconBlockRoots(response),
+                ..
+            } => assert!(response.roots.is_empty()),
+            other => panic!("unexpected event {:?}", other),
+        }
+    }
+
+    #[test]
+    fn blocks_by_range_round_trip() {
+        let request = BeaconBlocksByRangeRequest {
+            start_slot: Slot::new(64),
+            count: 32,
+            step: 2,
+        };
+        let event = RPCEvent::Request {
+            id: RequestId::from(3),
+            method_id: RPCMethod::BeaconBlocksByRange.into(),
+            body: RPCRequest::BeaconBlocksByRange(request.clone()),
+        };
+
+        match decode(ssz_encode(&event)).unwrap() {
+            RPCEvent::Request {
+                body: RPCRequest::BeaconBlocksByRange(decoded),
+                ..
+            } => assert_eq!(decoded, request),
+            other => panic!("unexpected event {:?}", other),
+        }
+
+        let event = RPCEvent::Response {
+            id: RequestId::from(3),
+            method_id: RPCMethod::BeaconBlocksByRange.into(),
+            result: RPCResponse::BeaconBlocksByRange(BeaconBlocksResponse { blocks: vec![] }),
+        };
+
+        match decode(ssz_encode(&event)).unwrap() {
+            RPCEvent::Response {
+                result: RPCResponse::BeaconBlocksByRange(response),
+                ..
+            } => assert!(response.blocks.is_empty()),
+            other => panic!("unexpected event {:?}", other),
+        }
+    }
+
+    #[test]
+    fn chain_state_round_trip() {
+        let ssz: Vec<u8> = (0..STATE_CHUNK_SIZE * 2 + 10).map(|i| i as u8).collect();
+        let request = BeaconChainStateRequest {
+            state_root: Hash256::from_low_u64_be(42),
+            start_chunk: 1,
+            count: 4,
+        };
+
+        // The response is truncated at the end of the state.
+        let bytes = request.select(&ssz).unwrap().to_vec();
+        assert_eq!(bytes.len() as u64, STATE_CHUNK_SIZE + 10);
+
+        let response = BeaconChainStateResponse {
+            state_root: request.state_root,
+            state_len: ssz.len() as u64,
+            start_chunk: request.start_chunk,
+            bytes,
+        };
+        assert_eq!(response.total_chunks(), 3);
+
+        let event = RPCEvent::Response {
+            id: RequestId::from(9),
+            method_id: RPCMethod::BeaconChainState.into(),
+            result: RPCResponse::BeaconChainState(response.clone()),
+        };
+
+        match decode(ssz_encode(&event)).unwrap() {
+            RPCEvent::Response {
+                result: RPCResponse::BeaconChainState(decoded),
+                ..
+            } => assert_eq!(decoded, response),
+            other => panic!("unexpected event {:?}", other),
+        }
+
+        // Chunks beyond the end of the state cannot be selected.
+        let request = BeaconChainStateRequest {
+            start_chunk: 3,
+            ..request
+        };
+        assert_eq!(request.select(&ssz), None);
+    }
+
+    #[test]
+    fn snappy_round_trip() {
+        let event = RPCEvent::Response {
+            id: RequestId::from(5),
+            method_id: RPCMethod::BeaconBlocksByRoot.into(),
+            result: RPCResponse::BeaconBlocksByRoot(BeaconBlocksResponse { blocks: vec![] }),
+        };
+
+        let packet = pack(ssz_encode(&event), PROTOCOL_SSZ_SNAPPY);
+        assert_ne!(packet, ssz_encode(&event));
+
+        match decode(unpack(packet, PROTOCOL_SSZ_SNAPPY).unwrap()).unwrap() {
+            RPCEvent::Response {
+                id,
+                result: RPCResponse::BeaconBlocksByRoot(response),
+                ..
+            } => {
+                assert_eq!(id, RequestId::from(5));
+                assert!(response.blocks.is_empty());
+            }
+            other => panic!("unexpected event {:?}", other),
+        }
+
+        // The uncompressed protocol leaves packets untouched.
+        let packet = pack(ssz_encode(&event), PROTOCOL_SSZ);
+        assert_eq!(packet, ssz_encode(&event));
+        assert_eq!(unpack(packet.clone(), PROTOCOL_SSZ).unwrap(), packet);
+    }
+}
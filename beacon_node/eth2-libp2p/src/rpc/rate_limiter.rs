@@ -0,0 +1,381 @@
+/// Per-peer rate limiting of inbound RPC requests.
+///
+/// Each peer has a token bucket for each RPC method. A request costs one token per item it
+/// requests (e.g., per block root), so large requests use up the allowance of a peer faster than
+/// small ones.
+use super::methods::*;
+use libp2p::PeerId;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// The allowance of a single RPC method.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Quota {
+    /// The maximum number of tokens a peer can accumulate. Requests costing more than this are
+    /// always rejected.
+    pub capacity: u64,
+    /// The time taken for an empty bucket to refill completely.
+    pub refill_period: Duration,
+}
+
+impl Quota {
+    pub fn new(capacity: u64, refill_period: Duration) -> Self {
+        Self {
+            capacity,
+            refill_period,
+        }
+    }
+}
+
+/// The quotas of each RPC method. `Goodbye` requests are never limited.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RateLimiterConfig {
+    pub hello: Quota,
+    pub block_roots: Quota,
+    pub block_headers: Quota,
+    pub block_bodies: Quota,
+    pub chain_state: Quota,
+    pub blocks_by_range: Quota,
+    pub blocks_by_root: Quota,
+}
+
+impl Default for RateLimiterConfig {
+    fn default() -> Self {
+        let period = Duration::from_secs(10);
+        Self {
+            hello: Quota::new(16, period),
+            block_roots: Quota::new(MAX_BLOCK_ROOTS_COUNT, period),
+            block_headers: Quota::new(MAX_BLOCK_HEADERS * 4, period),
+            block_bodies: Quota::new(MAX_BLOCK_BODIES * 4, period),
+            chain_state: Quota::new(MAX_STATE_CHUNKS * 4, period),
+            blocks_by_range: Quota::new(MAX_REQUEST_BLOCKS * 4, period),
+            blocks_by_root: Quota::new(MAX_REQUEST_BLOCKS * 4, period),
+        }
+    }
+}
+
+/// The reason a request was rejected.
+#[derive(Debug, Clone, PartialEq)]
+pub enum RateLimitError {
+    /// The request exceeds the maximums of the protocol.
+    ExceedsMaximum(String),
+    /// The peer has used up its allowance for the method.
+    RateLimited,
+}
+
+impl RateLimitError {
+    /// The code used to respond to the rejected request.
+    pub fn response_code(&self) -> ResponseCode {
+        match self {
+            RateLimitError::ExceedsMaximum(_) => ResponseCode::InvalidRequest,
+            RateLimitError::RateLimited => ResponseCode::RateLimited,
+        }
+    }
+
+    /// The message used to respond to the rejected request.
+    pub fn message(&self) -> String {
+        match self {
+            RateLimitError::ExceedsMaximum(message) => message.clone(),
+            RateLimitError::RateLimited => "Rate limited".to_string(),
+        }
+    }
+}
+
+/// The tokens available to a peer for a single method.
+#[derive(Debug, Clone, Copy)]
+struct Bucket {
+    /// The tokens available at `last_updated`.
+    tokens: f64,
+    /// The time `tokens` was last calculated.
+    last_updated: Instant,
+}
+
+/// Tracks the token buckets of every peer which has recently made a request.
+pub struct RateLimiter {
+    config: RateLimiterConfig,
+    buckets: HashMap<(PeerId, u16), Bucket>,
+}
+
+impl RateLimiter {
+    pub fn new(config: RateLimiterConfig) -> Self {
+        Self {
+            config,
+            buckets: HashMap::new(),
+        }
+    }
+
+    /// Checks that `request` from `peer_id` is within the protocol maximums and the allowance of
+    /// the peer, consuming tokens if it is.
+    pub fn allows(&mut self, peer_id: &PeerId, request: &RPCRequest) -> Result<(), RateLimitError> {
+        self.allows_at(peer_id, request, Instant::now())
+    }
+
+    fn allows_at(
+        &mut self,
+        peer_id: &PeerId,
+        request: &RPCRequest,
+        now: Instant,
+    ) -> Result<(), RateLimitError> {
+        check_maximums(request)?;
+
+        let quota = match self.quota(request) {
+            Some(quota) => quota,
+            None => return Ok(()),
+        };
+        let cost = request_cost(request) as f64;
+
+        let bucket = self
+            .buckets
+            .entry((peer_id.clone(), request.method_id()))
+            .or_insert(Bucket {
+                tokens: quota.capacity as f64,
+                last_updated: now,
+            });
+
+        bucket.tokens = refilled(bucket, &quota, now);
+        bucket.last_updated = now;
+
+        if bucket.tokens >= cost {
+            bucket.tokens -= cost;
+            Ok(())
+        } else {
+            Err(RateLimitError::RateLimited)
+        }
+    }
+
+    /// Forgets buckets which have refilled completely, as they are equivalent to new buckets.
+    pub fn prune(&mut self) {
+        self.prune_at(Instant::now())
+    }
+
+    fn prune_at(&mut self, now: Instant) {
+        let config = &self.config;
+        self.buckets.retain(|(_, method_id), bucket| {
+            match quota_for_method(config, RPCMethod::from(*method_id)) {
+                Some(quota) => refilled(bucket, &quota, now) < quota.capacity as f64,
+                None => false,
+            }
+        });
+    }
+
+    fn quota(&self, request: &RPCRequest) -> Option<Quota> {
+        quota_for_method(&self.config, RPCMethod::from(request.method_id()))
+    }
+}
+
+/// Returns the quota of `method`, or `None` if the method is not limited.
+fn quota_for_method(config: &RateLimiterConfig, method: RPCMethod) -> Option<Quota> {
+    match method {
+        RPCMethod::Hello => Some(config.hello),
+        RPCMethod::BeaconBlockRoots => Some(config.block_roots),
+        RPCMethod::BeaconBlockHeaders => Some(config.block_headers),
+        RPCMethod::BeaconBlockBodies => Some(config.block_bodies),
+        RPCMethod::BeaconChainState => Some(config.chain_state),
+        RPCMethod::BeaconBlocksByRange => Some(config.blocks_by_range),
+        RPCMethod::BeaconBlocksByRoot => Some(config.blocks_by_root),
+        RPCMethod::Goodbye | RPCMethod::Unknown => None,
+    }
+}
+
+/// Returns the tokens in `bucket` at `now`.
+fn refilled(bucket: &Bucket, quota: &Quota, now: Instant) -> f64 {
+    let elapsed = now.duration_since(bucket.last_updated).as_millis() as f64;
+    let period = quota.refill_period.as_millis().max(1) as f64;
+    let capacity = quota.capacity as f64;
+
+    (bucket.tokens + capacity * elapsed / period).min(capacity)
+}
+
+/// The number of tokens consumed by `request`.
+fn request_cost(request: &RPCRequest) -> u64 {
+    let items = match request {
+        RPCRequest::BeaconBlockRoots(req) => req.count,
+        RPCRequest::BeaconBlockHeaders(req) => req.max_headers,
+        RPCRequest::BeaconBlockBodies(req) => req.block_roots.len() as u64,
+        RPCRequest::BeaconChainState(req) => req.count,
+        RPCRequest::BeaconBlocksByRange(req) => req.count,
+        RPCRequest::BeaconBlocksByRoot(req) => req.block_roots.len() as u64,
+        RPCRequest::Hello(_) | RPCRequest::Goodbye(_) => 1,
+    };
+    items.max(1)
+}
+
+/// Checks that `request` does not exceed the maximums of the protocol.
+fn check_maximums(request: &RPCRequest) -> Result<(), RateLimitError> {
+    let (items, maximum, name) = match request {
+        RPCRequest::BeaconBlockRoots(req) => (req.count, MAX_BLOCK_ROOTS_COUNT, "block roots"),
+        RPCRequest::BeaconBlockHeaders(req) => (req.max_headers, MAX_BLOCK_HEADERS, "headers"),
+        RPCRequest::BeaconBlockBodies(req) => (
+            req.block_roots.len() as u64,
+            MAX_BLOCK_BODIES,
+            "block bodies",
+        ),
+        RPCRequest::BeaconBlocksByRange(req) => {
+            // The range is bounded as well as the number of blocks, so the responder never has to
+            // look further back than for a `BeaconBlockRoots` request.
+            let span = req.count.saturating_mul(req.step);
+            if span > MAX_BLOCK_ROOTS_COUNT {
+                return Err(RateLimitError::ExceedsMaximum(format!(
+                    "Requested a range of {} slots, the maximum is {}",
+                    span, MAX_BLOCK_ROOTS_COUNT
+                )));
+            }
+            (req.count, MAX_REQUEST_BLOCKS, "blocks")
+        }
+        RPCRequest::BeaconBlocksByRoot(req) => {
+            (req.block_roots.len() as u64, MAX_REQUEST_BLOCKS, "blocks")
+        }
+        RPCRequest::BeaconChainState(req) => (req.count, MAX_STATE_CHUNKS, "state chunks"),
+        _ => return Ok(()),
+    };
+
+    if items > maximum {
+        Err(RateLimitError::ExceedsMaximum(format!(
+            "Requested {} {}, the maximum is {}",
+            items, name, maximum
+        )))
+    } else {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use types::{Hash256, Slot};
+
+    fn roots_request(count: u64) -> RPCRequest {
+        RPCRequest::BeaconBlockRoots(BeaconBlockRootsRequest {
+            start_slot: Slot::new(0),
+            count,
+        })
+    }
+
+    fn bodies_request(count: usize) -> RPCRequest {
+        RPCRequest::BeaconBlockBodies(BeaconBlockBodiesRequest {
+            block_roots: vec![Hash256::zero(); count],
+        })
+    }
+
+    fn limiter() -> RateLimiter {
+        let mut config = RateLimiterConfig::default();
+        config.block_roots = Quota::new(100, Duration::from_secs(10));
+        RateLimiter::new(config)
+    }
+
+    #[test]
+    fn costs_depend_on_request_size() {
+        let mut limiter = limiter();
+        let peer_id = PeerId::random();
+        let now = Instant::now();
+
+        assert_eq!(limiter.allows_at(&peer_id, &roots_request(60), now), Ok(()));
+        assert_eq!(limiter.allows_at(&peer_id, &roots_request(40), now), Ok(()));
+        assert_eq!(
+            limiter.allows_at(&peer_id, &roots_request(1), now),
+            Err(RateLimitError::RateLimited)
+        );
+
+        // other peers and methods have their own allowance.
+        assert_eq!(
+            limiter.allows_at(&PeerId::random(), &roots_request(100), now),
+            Ok(())
+        );
+        assert_eq!(limiter.allows_at(&peer_id, &bodies_request(1), now), Ok(()));
+    }
+
+    #[test]
+    fn buckets_refill() {
+        let mut limiter = limiter();
+        let peer_id = PeerId::random();
+        let now = Instant::now();
+
+        assert_eq!(
+            limiter.allows_at(&peer_id, &roots_request(100), now),
+            Ok(())
+        );
+
+        let later = now + Duration::from_secs(5);
+        assert_eq!(
+            limiter.allows_at(&peer_id, &roots_request(50), later),
+            Ok(())
+        );
+        assert_eq!(
+            limiter.allows_at(&peer_id, &roots_request(1), later),
+            Err(RateLimitError::RateLimited)
+        );
+
+        // full buckets are pruned.
+        limiter.prune_at(later + Duration::from_secs(10));
+        assert!(limiter.buckets.is_empty());
+    }
+
+    #[test]
+    fn back_to_back_large_roots_requests_are_rejected() {
+        let mut limiter = RateLimiter::new(RateLimiterConfig::default());
+        let peer_id = PeerId::random();
+        let now = Instant::now();
+
+        assert_eq!(
+            limiter.allows_at(&peer_id, &roots_request(MAX_BLOCK_ROOTS_COUNT), now),
+            Ok(())
+        );
+        assert_eq!(
+            limiter.allows_at(&peer_id, &roots_request(MAX_BLOCK_ROOTS_COUNT), now),
+            Err(RateLimitError::RateLimited)
+        );
+
+        // a request of the protocol maximum can always be afforded by a peer with a full bucket.
+        let later = now + Duration::from_secs(10);
+        assert_eq!(
+            limiter.allows_at(&peer_id, &roots_request(MAX_BLOCK_ROOTS_COUNT), later),
+            Ok(())
+        );
+
+        // larger requests are invalid, rather than rate limited.
+        let err = limiter
+            .allows_at(
+                &PeerId::random(),
+                &roots_request(MAX_BLOCK_ROOTS_COUNT + 1),
+                now,
+            )
+            .unwrap_err();
+        assert_eq!(err.response_code(), ResponseCode::InvalidRequest);
+    }
+
+    #[test]
+    fn maximums_are_enforced() {
+        let mut limiter = limiter();
+        let peer_id = PeerId::random();
+
+        let err = limiter
+            .allows(&peer_id, &bodies_request(MAX_BLOCK_BODIES as usize + 1))
+            .unwrap_err();
+        assert_eq!(err.response_code(), ResponseCode::InvalidRequest);
+
+        assert!(limiter
+            .allows(&peer_id, &roots_request(MAX_BLOCK_ROOTS_COUNT + 1))
+            .is_err());
+
+        let range_request = |count, step| {
+            RPCRequest::BeaconBlocksByRange(BeaconBlocksByRangeRequest {
+                start_slot: Slot::new(0),
+                count,
+                step,
+            })
+        };
+        assert!(limiter
+            .allows(&peer_id, &range_request(MAX_REQUEST_BLOCKS + 1, 1))
+            .is_err());
+        assert!(limiter
+            .allows(
+                &peer_id,
+                &range_request(MAX_REQUEST_BLOCKS, u64::max_value())
+            )
+            .is_err());
+        assert_eq!(
+            limiter.allows(&peer_id, &range_request(MAX_REQUEST_BLOCKS, 2)),
+            Ok(())
+        );
+    }
+}
@@ -1,15 +1,18 @@
 use crate::behaviour::{Behaviour, BehaviourEvent, PubsubMessage};
 use crate::error;
 use crate::multiaddr::Protocol;
-use crate::rpc::RPCEvent;
-use crate::NetworkConfig;
+use crate::node_key;
+use crate::rpc::{RPCEvent, RequestId, ResponseCode};
+use crate::topics::GossipTopic;
+use crate::validated_gossipsub::MessageId;
+use crate::{Multiaddr, NetworkConfig};
 use futures::prelude::*;
 use futures::Stream;
 use libp2p::core::{
     identity,
     muxing::StreamMuxerBox,
     nodes::Substream,
-    transport::boxed::Boxed,
+    transport::{boxed::Boxed, MemoryTransport},
     upgrade::{InboundUpgradeExt, OutboundUpgradeExt},
 };
 use libp2p::identify::protocol::IdentifyInfo;
@@ -17,18 +20,27 @@ use libp2p::{core, secio, PeerId, Swarm, Transport};
 use slog::{debug, info, trace, warn};
 use std::io::{Error, ErrorKind};
 use std::time::Duration;
-use types::{TopicBuilder, TopicHash};
+use tokio::timer::DelayQueue;
+use types::TopicHash;
 
 type Libp2pStream = Boxed<(PeerId, StreamMuxerBox), Error>;
 type Libp2pBehaviour = Behaviour<Substream<StreamMuxerBox>>;
 
+/// The time allowed for messages queued for a peer (e.g., a `Goodbye`) to be sent before it is
+/// disconnected.
+const DISCONNECT_DELAY: Duration = Duration::from_secs(1);
+
 /// The configuration and state of the libp2p components for the beacon node.
 pub struct Service {
     /// The libp2p Swarm handler.
     //TODO: Make this private
     pub swarm: Swarm<Libp2pStream, Libp2pBehaviour>,
     /// This node's PeerId.
-    _local_peer_id: PeerId,
+    local_peer_id: PeerId,
+    /// Peers which will be disconnected, along with the duration of their ban, if any.
+    peers_to_disconnect: DelayQueue<(PeerId, Option<Duration>)>,
+    /// Banned peers, which are unbanned when their ban expires.
+    banned_peers: DelayQueue<PeerId>,
     /// The libp2p logger handle.
     pub log: slog::Logger,
 }
@@ -37,10 +49,9 @@ impl Service {
     pub fn new(config: NetworkConfig, log: slog::Logger) -> error::Result<Self> {
         debug!(log, "Libp2p Service starting");
 
-        // TODO: Currently using secp256k1 key pairs. Wire protocol specifies RSA. Waiting for this
-        // PR to be merged to generate RSA keys: https://github.com/briansmith/ring/pull/733
-        // TODO: Save and recover node key from disk
-        let local_private_key = identity::Keypair::generate_secp256k1();
+        // TODO: Wire protocol specifies RSA. Waiting for this PR to be merged to generate RSA
+        // keys: https://github.com/briansmith/ring/pull/733
+        let local_private_key = node_key::load_or_generate(&config, &log)?;
 
         let local_public_key = local_private_key.public();
         let local_peer_id = PeerId::from(local_private_key.public());
@@ -78,32 +89,101 @@ impl Service {
             };
         }
 
-        // subscribe to default gossipsub topics
-        let mut subscribed_topics = vec![];
-        for topic in config.topics {
-            let t = TopicBuilder::new(topic.to_string()).build();
-            if swarm.subscribe(t) {
-                trace!(log, "Subscribed to topic: {:?}", topic);
-                subscribed_topics.push(topic);
-            } else {
-                warn!(log, "Could not subscribe to topic: {:?}", topic)
-            }
-        }
-        info!(log, "Subscribed to topics: {:?}", subscribed_topics);
-
         Ok(Service {
-            _local_peer_id: local_peer_id,
+            local_peer_id,
+            peers_to_disconnect: DelayQueue::new(),
+            banned_peers: DelayQueue::new(),
             swarm,
             log,
         })
     }
 }
 
+impl Service {
+    /// Returns this node's PeerId.
+    pub fn local_peer_id(&self) -> &PeerId {
+        &self.local_peer_id
+    }
+
+    /// Dials `address`, e.g., a peer we learned of after the service was started.
+    pub fn dial(&mut self, address: Multiaddr) -> error::Result<()> {
+        Swarm::dial_addr(&mut self.swarm, address.clone())
+            .map_err(|e| format!("Could not dial {}: {:?}", address, e))?;
+        debug!(self.log, "Dialing peer: {}", address);
+        Ok(())
+    }
+
+    /// Subscribes to a gossip topic, returning `false` if we are already subscribed.
+    pub fn subscribe(&mut self, topic: GossipTopic) -> bool {
+        let subscribed = self.swarm.subscribe(topic);
+        if subscribed {
+            info!(self.log, "Subscribed to topic"; "topic" => topic.to_string());
+        }
+        subscribed
+    }
+
+    /// Unsubscribes from a gossip topic, returning `false` if we were not subscribed.
+    pub fn unsubscribe(&mut self, topic: GossipTopic) -> bool {
+        let unsubscribed = self.swarm.unsubscribe(topic);
+        if unsubscribed {
+            info!(self.log, "Unsubscribed from topic"; "topic" => topic.to_string());
+        }
+        unsubscribed
+    }
+
+    /// Disconnects `peer_id`, banning it for `ban_duration` if one is given.
+    ///
+    /// The peer is disconnected after a short delay, so that any messages already queued for it
+    /// can be sent.
+    pub fn disconnect_peer(&mut self, peer_id: PeerId, ban_duration: Option<Duration>) {
+        self.peers_to_disconnect
+            .insert((peer_id, ban_duration), DISCONNECT_DELAY);
+    }
+
+    /// Disconnects the peers whose disconnect delay has passed, and unbans peers whose ban has
+    /// expired.
+    fn poll_bans(&mut self) -> Result<(), crate::error::Error> {
+        while let Async::Ready(Some(expired)) = self
+            .peers_to_disconnect
+            .poll()
+            .map_err(|e| format!("Ban timer failed: {:?}", e))?
+        {
+            // Banning a peer id closes its connections, so a peer which is not banned is
+            // unbanned immediately.
+            let (peer_id, ban_duration) = expired.into_inner();
+            Swarm::ban_peer_id(&mut self.swarm, peer_id.clone());
+            match ban_duration {
+                Some(ban_duration) => {
+                    debug!(self.log, "Banning peer"; "peer" => format!("{:?}", peer_id));
+                    self.swarm.ban_peer(&peer_id);
+                    self.banned_peers.insert(peer_id, ban_duration);
+                }
+                None => Swarm::unban_peer_id(&mut self.swarm, peer_id),
+            }
+        }
+
+        while let Async::Ready(Some(expired)) = self
+            .banned_peers
+            .poll()
+            .map_err(|e| format!("Ban timer failed: {:?}", e))?
+        {
+            let peer_id = expired.into_inner();
+            debug!(self.log, "Unbanning peer"; "peer" => format!("{:?}", peer_id));
+            Swarm::unban_peer_id(&mut self.swarm, peer_id.clone());
+            self.swarm.unban_peer(&peer_id);
+        }
+
+        Ok(())
+    }
+}
+
 impl Stream for Service {
     type Item = Libp2pEvent;
     type Error = crate::error::Error;
 
     fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        self.poll_bans()?;
+
         loop {
             // TODO: Currently only gossipsub events passed here.
             // Build a type for more generic events
@@ -112,12 +192,14 @@ impl Stream for Service {
                 Ok(Async::Ready(Some(event))) => match event {
                     // TODO: Stub here for debugging
                     BehaviourEvent::GossipMessage {
+                        id,
                         source,
                         topics,
                         message,
                     } => {
                         trace!(self.log, "Pubsub message received: {:?}", message);
                         return Ok(Async::Ready(Some(Libp2pEvent::PubsubMessage {
+                            id,
                             source,
                             topics,
                             message,
@@ -126,12 +208,34 @@ impl Stream for Service {
                     BehaviourEvent::RPC(peer_id, event) => {
                         return Ok(Async::Ready(Some(Libp2pEvent::RPC(peer_id, event))));
                     }
+                    BehaviourEvent::RequestRejected(peer_id, code) => {
+                        return Ok(Async::Ready(Some(Libp2pEvent::RequestRejected(
+                            peer_id, code,
+                        ))));
+                    }
+                    BehaviourEvent::RequestTimeout {
+                        peer_id,
+                        id,
+                        method_id,
+                    } => {
+                        return Ok(Async::Ready(Some(Libp2pEvent::RequestTimeout {
+                            peer_id,
+                            id,
+                            method_id,
+                        })));
+                    }
                     BehaviourEvent::PeerDialed(peer_id) => {
                         return Ok(Async::Ready(Some(Libp2pEvent::PeerDialed(peer_id))));
                     }
+                    BehaviourEvent::PeerDisconnected(peer_id) => {
+                        return Ok(Async::Ready(Some(Libp2pEvent::PeerDisconnected(peer_id))));
+                    }
                     BehaviourEvent::Identified(peer_id, info) => {
                         return Ok(Async::Ready(Some(Libp2pEvent::Identified(peer_id, info))));
                     }
+                    BehaviourEvent::UndecodableGossip(peer_id) => {
+                        return Ok(Async::Ready(Some(Libp2pEvent::UndecodableGossip(peer_id))));
+                    }
                 },
                 Ok(Async::Ready(None)) => unreachable!("Swarm stream shouldn't end"),
                 Ok(Async::NotReady) => break,
@@ -142,8 +246,8 @@ impl Stream for Service {
     }
 }
 
-/// The implementation supports TCP/IP, WebSockets over TCP/IP, secio as the encryption layer, and
-/// mplex or yamux as the multiplexing layer.
+/// The implementation supports TCP/IP, WebSockets over TCP/IP and in-process memory connections,
+/// secio as the encryption layer, and mplex or yamux as the multiplexing layer.
 fn build_transport(local_private_key: identity::Keypair) -> Boxed<(PeerId, StreamMuxerBox), Error> {
     // TODO: The Wire protocol currently doesn't specify encryption and this will need to be customised
     // in the future.
@@ -154,6 +258,8 @@ fn build_transport(local_private_key: identity::Keypair) -> Boxed<(PeerId, Strea
         let trans_clone = transport.clone();
         transport.or_transport(websocket::WsConfig::new(trans_clone))
     };
+    // `/memory/<port>` addresses connect nodes running in the same process, e.g., in simulations.
+    let transport = transport.or_transport(MemoryTransport::default());
     transport
         .with_upgrade(secio::SecioConfig::new(local_private_key))
         .and_then(move |out, endpoint| {
@@ -179,14 +285,27 @@ fn build_transport(local_private_key: identity::Keypair) -> Boxed<(PeerId, Strea
 pub enum Libp2pEvent {
     /// An RPC response request has been received on the swarm.
     RPC(PeerId, RPCEvent),
+    /// A request from a peer was rejected for exceeding the protocol maximums or rate limits.
+    RequestRejected(PeerId, ResponseCode),
+    /// A peer did not respond to an RPC request in time.
+    RequestTimeout {
+        peer_id: PeerId,
+        id: RequestId,
+        method_id: u16,
+    },
     /// Initiated the connection to a new peer.
     PeerDialed(PeerId),
+    /// The connection to a peer was closed.
+    PeerDisconnected(PeerId),
     /// Received information about a peer on the network.
     Identified(PeerId, Box<IdentifyInfo>),
-    /// Received pubsub message.
+    /// Received a pubsub message, which is propagated once it has been validated.
     PubsubMessage {
+        id: MessageId,
         source: PeerId,
         topics: Vec<TopicHash>,
         message: Box<PubsubMessage>,
     },
+    /// Received a pubsub message which could not be decoded.
+    UndecodableGossip(PeerId),
 }
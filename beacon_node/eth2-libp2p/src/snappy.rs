@@ -0,0 +1,59 @@
+use std::io::{self, Read, Write};
+
+/// The maximum size of a decompressed payload. Larger payloads are rejected, so that a small
+/// compressed message cannot exhaust our memory.
+pub const MAX_DECOMPRESSED_SIZE: usize = 16_777_216; // 16M
+
+/// Compresses `bytes` with the snappy framing format.
+pub fn compress(bytes: &[u8]) -> Vec<u8> {
+    let mut writer = snap::Writer::new(vec![]);
+    writer
+        .write_all(bytes)
+        .expect("snappy compression into a Vec cannot fail");
+    writer
+        .into_inner()
+        .expect("snappy compression into a Vec cannot fail")
+}
+
+/// Decompresses snappy framed `bytes`, failing if they are malformed or the decompressed payload
+/// exceeds `MAX_DECOMPRESSED_SIZE`.
+pub fn decompress(bytes: &[u8]) -> Result<Vec<u8>, io::Error> {
+    let mut decompressed = vec![];
+    snap::Reader::new(bytes)
+        .take(MAX_DECOMPRESSED_SIZE as u64 + 1)
+        .read_to_end(&mut decompressed)?;
+
+    if decompressed.len() > MAX_DECOMPRESSED_SIZE {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "decompressed payload exceeds the maximum size",
+        ));
+    }
+
+    Ok(decompressed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let bytes = vec![0; 100_000];
+
+        let compressed = compress(&bytes);
+        assert!(compressed.len() < bytes.len());
+        assert_eq!(decompress(&compressed).unwrap(), bytes);
+    }
+
+    #[test]
+    fn rejects_uncompressed_bytes() {
+        assert!(decompress(&[1, 2, 3, 4]).is_err());
+    }
+
+    #[test]
+    fn rejects_oversized_payloads() {
+        let compressed = compress(&vec![0; MAX_DECOMPRESSED_SIZE + 1]);
+        assert!(decompress(&compressed).is_err());
+    }
+}
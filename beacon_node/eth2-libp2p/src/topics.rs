@@ -0,0 +1,248 @@
+use std::fmt;
+use types::{Topic, TopicBuilder};
+
+/// The first component of every eth2 gossip topic.
+pub const TOPIC_PREFIX: &str = "eth2";
+/// The last component of a topic whose messages are SSZ encoded.
+pub const SSZ_ENCODING_POSTFIX: &str = "ssz";
+/// The last component of a topic whose messages are SSZ encoded and snappy compressed.
+pub const SSZ_SNAPPY_ENCODING_POSTFIX: &str = "ssz_snappy";
+/// The topic for beacon blocks.
+pub const BEACON_BLOCK_TOPIC: &str = "beacon_block";
+/// The topic for attestations from every shard, including unaggregated attestations until
+/// aggregation is implemented.
+pub const BEACON_ATTESTATION_TOPIC: &str = "beacon_attestation";
+/// The prefix of the attestation subnet topics, which is followed by the subnet id.
+pub const ATTESTATION_SUBNET_TOPIC_PREFIX: &str = "beacon_attestation_subnet_";
+
+/// The number of attestation subnets. Shards are mapped to subnets by `subnet_for_shard`.
+pub const ATTESTATION_SUBNET_COUNT: u64 = 64;
+
+/// Returns the attestation subnet which carries the unaggregated attestations of `shard`.
+pub fn subnet_for_shard(shard: u64) -> u64 {
+    shard % ATTESTATION_SUBNET_COUNT
+}
+
+/// The kinds of gossip topic.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum GossipKind {
+    /// Beacon blocks.
+    BeaconBlock,
+    /// Attestations from any shard.
+    BeaconAttestation,
+    /// Unaggregated attestations from the shards mapped to the given subnet.
+    AttestationSubnet(u64),
+}
+
+/// The encoding of the messages on a topic.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum GossipEncoding {
+    /// SSZ encoded.
+    SSZ,
+    /// SSZ encoded, then compressed with the snappy framing format.
+    SSZSnappy,
+}
+
+impl GossipEncoding {
+    /// All supported encodings, preferred first.
+    pub fn all() -> [GossipEncoding; 2] {
+        [GossipEncoding::SSZSnappy, GossipEncoding::SSZ]
+    }
+
+    fn postfix(self) -> &'static str {
+        match self {
+            GossipEncoding::SSZ => SSZ_ENCODING_POSTFIX,
+            GossipEncoding::SSZSnappy => SSZ_SNAPPY_ENCODING_POSTFIX,
+        }
+    }
+}
+
+impl Default for GossipEncoding {
+    fn default() -> Self {
+        GossipEncoding::SSZSnappy
+    }
+}
+
+/// A gossip topic, namespaced by fork version.
+///
+/// The topic string has the form `/eth2/{fork_version}/{kind}/{encoding}`, where the fork version
+/// is hex encoded. Nodes on different forks therefore never share topics.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct GossipTopic {
+    kind: GossipKind,
+    fork_version: [u8; 4],
+    encoding: GossipEncoding,
+}
+
+impl GossipTopic {
+    /// Returns a topic with the default encoding.
+    pub fn new(kind: GossipKind, fork_version: [u8; 4]) -> Self {
+        Self {
+            kind,
+            fork_version,
+            encoding: GossipEncoding::default(),
+        }
+    }
+
+    /// Returns the same topic with the given encoding.
+    pub fn with_encoding(self, encoding: GossipEncoding) -> Self {
+        Self { encoding, ..self }
+    }
+
+    /// Returns the subnet topic which carries the unaggregated attestations of `shard`.
+    pub fn attestation_subnet(shard: u64, fork_version: [u8; 4]) -> Self {
+        Self::new(
+            GossipKind::AttestationSubnet(subnet_for_shard(shard)),
+            fork_version,
+        )
+    }
+
+    pub fn kind(&self) -> GossipKind {
+        self.kind
+    }
+
+    pub fn fork_version(&self) -> [u8; 4] {
+        self.fork_version
+    }
+
+    pub fn encoding(&self) -> GossipEncoding {
+        self.encoding
+    }
+
+    /// Parses a topic string produced by `GossipTopic::to_string`.
+    pub fn decode(topic: &str) -> Result<Self, String> {
+        let parts: Vec<&str> = topic.split('/').collect();
+
+        match parts.as_slice() {
+            ["", TOPIC_PREFIX, fork_version, kind, encoding] => Ok(Self {
+                kind: decode_kind(kind)?,
+                fork_version: decode_fork_version(fork_version)?,
+                encoding: decode_encoding(encoding)?,
+            }),
+            _ => Err(format!("Unknown topic: {}", topic)),
+        }
+    }
+}
+
+impl fmt::Display for GossipTopic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let kind = match self.kind {
+            GossipKind::BeaconBlock => BEACON_BLOCK_TOPIC.to_string(),
+            GossipKind::BeaconAttestation => BEACON_ATTESTATION_TOPIC.to_string(),
+            GossipKind::AttestationSubnet(subnet_id) => {
+                format!("{}{}", ATTESTATION_SUBNET_TOPIC_PREFIX, subnet_id)
+            }
+        };
+
+        write!(f, "/{}/", TOPIC_PREFIX)?;
+        for byte in self.fork_version.iter() {
+            write!(f, "{:02x}", byte)?;
+        }
+        write!(f, "/{}/{}", kind, self.encoding.postfix())
+    }
+}
+
+impl Into<Topic> for GossipTopic {
+    fn into(self) -> Topic {
+        TopicBuilder::new(self.to_string()).build()
+    }
+}
+
+fn decode_kind(kind: &str) -> Result<GossipKind, String> {
+    match kind {
+        BEACON_BLOCK_TOPIC => Ok(GossipKind::BeaconBlock),
+        BEACON_ATTESTATION_TOPIC => Ok(GossipKind::BeaconAttestation),
+        _ if kind.starts_with(ATTESTATION_SUBNET_TOPIC_PREFIX) => {
+            let subnet_id = kind[ATTESTATION_SUBNET_TOPIC_PREFIX.len()..]
+                .parse::<u64>()
+                .map_err(|_| format!("Invalid subnet id: {}", kind))?;
+
+            if subnet_id < ATTESTATION_SUBNET_COUNT {
+                Ok(GossipKind::AttestationSubnet(subnet_id))
+            } else {
+                Err(format!("Subnet id out of range: {}", subnet_id))
+            }
+        }
+        _ => Err(format!("Unknown topic kind: {}", kind)),
+    }
+}
+
+fn decode_encoding(encoding: &str) -> Result<GossipEncoding, String> {
+    match encoding {
+        SSZ_ENCODING_POSTFIX => Ok(GossipEncoding::SSZ),
+        SSZ_SNAPPY_ENCODING_POSTFIX => Ok(GossipEncoding::SSZSnappy),
+        _ => Err(format!("Unknown encoding: {}", encoding)),
+    }
+}
+
+fn decode_fork_version(fork_version: &str) -> Result<[u8; 4], String> {
+    if fork_version.len() != 8 || !fork_version.is_ascii() {
+        return Err(format!("Invalid fork version: {}", fork_version));
+    }
+
+    let mut bytes = [0; 4];
+    for (i, byte) in bytes.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&fork_version[i * 2..i * 2 + 2], 16)
+            .map_err(|_| format!("Invalid fork version: {}", fork_version))?;
+    }
+
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FORK_VERSION: [u8; 4] = [0, 1, 0xab, 0xff];
+
+    #[test]
+    fn topic_strings() {
+        assert_eq!(
+            GossipTopic::new(GossipKind::BeaconBlock, FORK_VERSION).to_string(),
+            "/eth2/0001abff/beacon_block/ssz_snappy"
+        );
+        assert_eq!(
+            GossipTopic::new(GossipKind::BeaconAttestation, FORK_VERSION).to_string(),
+            "/eth2/0001abff/beacon_attestation/ssz_snappy"
+        );
+        assert_eq!(
+            GossipTopic::attestation_subnet(70, FORK_VERSION)
+                .with_encoding(GossipEncoding::SSZ)
+                .to_string(),
+            "/eth2/0001abff/beacon_attestation_subnet_6/ssz"
+        );
+    }
+
+    #[test]
+    fn decode_round_trip() {
+        let topics = vec![
+            GossipTopic::new(GossipKind::BeaconBlock, FORK_VERSION),
+            GossipTopic::new(GossipKind::BeaconAttestation, [0; 4])
+                .with_encoding(GossipEncoding::SSZ),
+            GossipTopic::attestation_subnet(3, FORK_VERSION),
+        ];
+
+        for topic in topics {
+            assert_eq!(GossipTopic::decode(&topic.to_string()), Ok(topic));
+        }
+    }
+
+    #[test]
+    fn decode_invalid_topics() {
+        let invalid = vec![
+            "beacon_chain",
+            "/eth2/0001abff/beacon_block",
+            "/eth2/0001ab/beacon_block/ssz",
+            "/eth2/0001abzz/beacon_block/ssz",
+            "/eth2/0001abff/beacon_block/json",
+            "/eth2/0001abff/beacon_block/snappy",
+            "/eth2/0001abff/voluntary_exit/ssz",
+            "/eth2/0001abff/beacon_attestation_subnet_64/ssz",
+            "/eth2/0001abff/beacon_attestation_subnet_x/ssz",
+        ];
+
+        for topic in invalid {
+            assert!(GossipTopic::decode(topic).is_err(), "{}", topic);
+        }
+    }
+}
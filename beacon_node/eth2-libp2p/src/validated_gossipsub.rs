@@ -0,0 +1,439 @@
+//! Gossipsub which only forwards messages once the beacon node has validated them.
+//!
+//! Gossipsub forwards every message to its mesh peers as soon as it is received. This behaviour
+//! wraps it, holding back forwarded messages (including those served to peers from the message
+//! cache) until `report_validation_result` accepts them. Messages which are ignored or rejected
+//! are never forwarded.
+//!
+//! It also records the peer each message was first received from, which gossipsub does not
+//! expose. The `source` of a message is its claimed author and is not authenticated, so it must
+//! not be used to attribute a message to a peer.
+use crate::behaviour::MessageAcceptance;
+use futures::prelude::*;
+use libp2p::core::protocols_handler::ProtocolsHandler;
+use libp2p::core::swarm::{
+    ConnectedPoint, NetworkBehaviour, NetworkBehaviourAction, PollParameters,
+};
+use libp2p::gossipsub::{
+    Gossipsub, GossipsubConfig, GossipsubEvent, GossipsubMessage, GossipsubRpc,
+};
+use libp2p::{Multiaddr, PeerId};
+use slog::{o, trace, warn};
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::timer::Delay;
+use types::{Topic, TopicHash};
+
+/// The time a received message is remembered for. Messages which are not validated within this
+/// time are never forwarded.
+const MESSAGE_RETENTION: Duration = Duration::from_secs(60);
+
+/// The interval at which expired messages are forgotten.
+const PRUNE_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Identifies a gossip message by its author and sequence number, as gossipsub does.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct MessageId {
+    source: PeerId,
+    sequence_number: Vec<u8>,
+}
+
+impl<'a> From<&'a GossipsubMessage> for MessageId {
+    fn from(message: &'a GossipsubMessage) -> Self {
+        Self {
+            source: message.source.clone(),
+            sequence_number: message.sequence_number.clone(),
+        }
+    }
+}
+
+/// The events emitted by `ValidatedGossipsub`.
+#[derive(Debug)]
+pub enum ValidatedGossipsubEvent {
+    /// A message which is not forwarded until it has been validated. `propagation_source` is the
+    /// peer we first received the message from.
+    Message(PeerId, MessageId, GossipsubMessage),
+    /// A remote peer subscribed to a topic.
+    Subscribed { peer_id: PeerId, topic: TopicHash },
+    /// A remote peer unsubscribed from a topic.
+    Unsubscribed { peer_id: PeerId, topic: TopicHash },
+}
+
+/// The validation state of a received message.
+#[derive(Debug)]
+enum Validation {
+    /// The message has not been validated. The messages gossipsub has tried to send since it was
+    /// received are held here.
+    Pending(Vec<(PeerId, GossipsubMessage)>),
+    /// The message may be forwarded.
+    Accepted,
+    /// The message must not be forwarded.
+    Rejected,
+}
+
+/// A message received from a remote peer.
+#[derive(Debug)]
+struct ReceivedMessage {
+    /// The peer we first received the message from.
+    propagation_source: PeerId,
+    validation: Validation,
+    /// The time the message was first received.
+    received: Instant,
+}
+
+/// Gossipsub which holds back forwarded messages until they are validated.
+pub struct ValidatedGossipsub<TSubstream> {
+    /// The underlying gossipsub behaviour.
+    gossipsub: Gossipsub<TSubstream>,
+    /// Our own peer id. Messages we publish are sent without validation.
+    local_peer_id: PeerId,
+    /// Messages received from remote peers within `MESSAGE_RETENTION`.
+    received: HashMap<MessageId, ReceivedMessage>,
+    /// Messages which have been accepted and are ready to be forwarded.
+    ready: VecDeque<(PeerId, GossipsubMessage)>,
+    /// Fires when it is time to forget expired messages.
+    prune_delay: Delay,
+    /// Logger for the validated gossipsub behaviour.
+    log: slog::Logger,
+}
+
+impl<TSubstream> ValidatedGossipsub<TSubstream> {
+    pub fn new(local_peer_id: PeerId, config: GossipsubConfig, log: &slog::Logger) -> Self {
+        Self {
+            gossipsub: Gossipsub::new(local_peer_id.clone(), config),
+            local_peer_id,
+            received: HashMap::new(),
+            ready: VecDeque::new(),
+            prune_delay: Delay::new(Instant::now() + PRUNE_INTERVAL),
+            log: log.new(o!("Service" => "Libp2p-Gossipsub")),
+        }
+    }
+
+    /// Subscribes to a topic.
+    pub fn subscribe(&mut self, topic: Topic) -> bool {
+        self.gossipsub.subscribe(topic)
+    }
+
+    /// Unsubscribes from a topic.
+    pub fn unsubscribe(&mut self, topic: Topic) -> bool {
+        self.gossipsub.unsubscribe(topic)
+    }
+
+    /// Publishes `data` on `topic`. Our own messages are not validated.
+    pub fn publish(&mut self, topic: Topic, data: Vec<u8>) {
+        self.gossipsub.publish(topic, data)
+    }
+
+    /// Forwards the message `message_id`, received from `propagation_source`, if it was accepted.
+    ///
+    /// Messages which are ignored or rejected are never forwarded, including to peers which
+    /// request them from the message cache.
+    pub fn report_validation_result(
+        &mut self,
+        message_id: &MessageId,
+        propagation_source: &PeerId,
+        acceptance: MessageAcceptance,
+    ) {
+        let message = match self.received.get_mut(message_id) {
+            Some(message) => message,
+            None => {
+                trace!(
+                    self.log,
+                    "Validated an expired message";
+                    "message_id" => format!("{:?}", message_id)
+                );
+                return;
+            }
+        };
+
+        let validation = match acceptance {
+            MessageAcceptance::Accept => Validation::Accepted,
+            MessageAcceptance::Ignore | MessageAcceptance::Reject => Validation::Rejected,
+        };
+
+        if let Validation::Pending(held) = std::mem::replace(&mut message.validation, validation) {
+            if acceptance == MessageAcceptance::Accept {
+                self.ready.extend(
+                    held.into_iter()
+                        .filter(|(peer_id, _)| peer_id != propagation_source),
+                );
+            }
+        }
+    }
+
+    /// Records the messages in `rpc`, the first time each is received.
+    fn record_received(&mut self, propagation_source: &PeerId, rpc: &GossipsubRpc) {
+        let now = Instant::now();
+        for message in &rpc.messages {
+            self.received
+                .entry(MessageId::from(message))
+                .or_insert_with(|| ReceivedMessage {
+                    propagation_source: propagation_source.clone(),
+                    validation: Validation::Pending(vec![]),
+                    received: now,
+                });
+        }
+    }
+
+    /// Removes the messages from `rpc` which may not yet be sent to `peer_id`, holding back those
+    /// which are awaiting validation. Returns `None` if nothing is left to send.
+    fn filter_outgoing(&mut self, peer_id: &PeerId, mut rpc: GossipsubRpc) -> Option<GossipsubRpc> {
+        let local_peer_id = &self.local_peer_id;
+        let received = &mut self.received;
+
+        rpc.messages.retain(|message| {
+            if message.source == *local_peer_id {
+                return true;
+            }
+
+            match received.get_mut(&MessageId::from(message)) {
+                Some(ReceivedMessage {
+                    validation: Validation::Accepted,
+                    ..
+                }) => true,
+                Some(ReceivedMessage {
+                    validation: Validation::Pending(held),
+                    ..
+                }) => {
+                    held.push((peer_id.clone(), message.clone()));
+                    false
+                }
+                // rejected or expired messages are never sent.
+                _ => false,
+            }
+        });
+
+        if rpc.messages.is_empty() && rpc.subscriptions.is_empty() && rpc.control_msgs.is_empty() {
+            None
+        } else {
+            Some(rpc)
+        }
+    }
+
+    /// Forgets messages received more than `MESSAGE_RETENTION` ago.
+    fn prune(&mut self) {
+        let now = Instant::now();
+        self.received
+            .retain(|_, message| now.duration_since(message.received) < MESSAGE_RETENTION);
+    }
+}
+
+impl<TSubstream> NetworkBehaviour for ValidatedGossipsub<TSubstream>
+where
+    TSubstream: AsyncRead + AsyncWrite,
+{
+    type ProtocolsHandler = <Gossipsub<TSubstream> as NetworkBehaviour>::ProtocolsHandler;
+    type OutEvent = ValidatedGossipsubEvent;
+
+    fn new_handler(&mut self) -> Self::ProtocolsHandler {
+        NetworkBehaviour::new_handler(&mut self.gossipsub)
+    }
+
+    fn addresses_of_peer(&mut self, peer_id: &PeerId) -> Vec<Multiaddr> {
+        NetworkBehaviour::addresses_of_peer(&mut self.gossipsub, peer_id)
+    }
+
+    fn inject_connected(&mut self, peer_id: PeerId, endpoint: ConnectedPoint) {
+        NetworkBehaviour::inject_connected(&mut self.gossipsub, peer_id, endpoint)
+    }
+
+    fn inject_disconnected(&mut self, peer_id: &PeerId, endpoint: ConnectedPoint) {
+        NetworkBehaviour::inject_disconnected(&mut self.gossipsub, peer_id, endpoint)
+    }
+
+    fn inject_replaced(&mut self, peer_id: PeerId, closed: ConnectedPoint, opened: ConnectedPoint) {
+        NetworkBehaviour::inject_replaced(&mut self.gossipsub, peer_id, closed, opened)
+    }
+
+    fn inject_node_event(
+        &mut self,
+        peer_id: PeerId,
+        event: <Self::ProtocolsHandler as ProtocolsHandler>::OutEvent,
+    ) {
+        self.record_received(&peer_id, &event);
+
+        NetworkBehaviour::inject_node_event(&mut self.gossipsub, peer_id, event)
+    }
+
+    fn poll(
+        &mut self,
+        params: &mut PollParameters<'_>,
+    ) -> Async<
+        NetworkBehaviourAction<
+            <Self::ProtocolsHandler as ProtocolsHandler>::InEvent,
+            Self::OutEvent,
+        >,
+    > {
+        loop {
+            match self.prune_delay.poll() {
+                Ok(Async::Ready(_)) => {
+                    self.prune();
+                    self.prune_delay.reset(Instant::now() + PRUNE_INTERVAL);
+                }
+                Ok(Async::NotReady) => break,
+                Err(e) => {
+                    warn!(self.log, "Gossipsub prune timer failed"; "error" => format!("{:?}", e));
+                    break;
+                }
+            }
+        }
+
+        if let Some((peer_id, message)) = self.ready.pop_front() {
+            return Async::Ready(NetworkBehaviourAction::SendEvent {
+                peer_id,
+                event: GossipsubRpc {
+                    messages: vec![message],
+                    subscriptions: vec![],
+                    control_msgs: vec![],
+                },
+            });
+        }
+
+        loop {
+            match self.gossipsub.poll(params) {
+                Async::Ready(NetworkBehaviourAction::SendEvent { peer_id, event }) => {
+                    if let Some(event) = self.filter_outgoing(&peer_id, event) {
+                        return Async::Ready(NetworkBehaviourAction::SendEvent { peer_id, event });
+                    }
+                }
+                Async::Ready(NetworkBehaviourAction::GenerateEvent(event)) => {
+                    let event = match event {
+                        GossipsubEvent::Message(message) => {
+                            let id = MessageId::from(&message);
+                            let propagation_source = match self.received.get(&id) {
+                                Some(received) => received.propagation_source.clone(),
+                                None => continue,
+                            };
+                            ValidatedGossipsubEvent::Message(propagation_source, id, message)
+                        }
+                        GossipsubEvent::Subscribed { peer_id, topic } => {
+                            ValidatedGossipsubEvent::Subscribed { peer_id, topic }
+                        }
+                        GossipsubEvent::Unsubscribed { peer_id, topic } => {
+                            ValidatedGossipsubEvent::Unsubscribed { peer_id, topic }
+                        }
+                    };
+                    return Async::Ready(NetworkBehaviourAction::GenerateEvent(event));
+                }
+                Async::Ready(NetworkBehaviourAction::DialAddress { address }) => {
+                    return Async::Ready(NetworkBehaviourAction::DialAddress { address });
+                }
+                Async::Ready(NetworkBehaviourAction::DialPeer { peer_id }) => {
+                    return Async::Ready(NetworkBehaviourAction::DialPeer { peer_id });
+                }
+                Async::Ready(NetworkBehaviourAction::ReportObservedAddr { address }) => {
+                    return Async::Ready(NetworkBehaviourAction::ReportObservedAddr { address });
+                }
+                Async::NotReady => break,
+            }
+        }
+
+        Async::NotReady
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use libp2p::gossipsub::GossipsubConfigBuilder;
+    use tokio::net::TcpStream;
+
+    fn gossipsub() -> ValidatedGossipsub<TcpStream> {
+        let log = slog::Logger::root(slog::Discard, o!());
+        ValidatedGossipsub::new(
+            PeerId::random(),
+            GossipsubConfigBuilder::new().build(),
+            &log,
+        )
+    }
+
+    fn message(source: &PeerId, sequence_number: u8) -> GossipsubMessage {
+        GossipsubMessage {
+            source: source.clone(),
+            data: vec![sequence_number],
+            sequence_number: vec![sequence_number],
+            topics: vec![],
+        }
+    }
+
+    fn rpc(message: &GossipsubMessage) -> GossipsubRpc {
+        GossipsubRpc {
+            messages: vec![message.clone()],
+            subscriptions: vec![],
+            control_msgs: vec![],
+        }
+    }
+
+    #[test]
+    fn forwards_are_held_until_accepted() {
+        let mut gossipsub = gossipsub();
+        let (author, forwarder, mesh_peer) = (PeerId::random(), PeerId::random(), PeerId::random());
+        let message = message(&author, 1);
+        let id = MessageId::from(&message);
+
+        gossipsub.record_received(&forwarder, &rpc(&message));
+        assert_eq!(
+            gossipsub.received[&id].propagation_source, forwarder,
+            "the forwarding peer is recorded, not the author"
+        );
+
+        assert!(gossipsub
+            .filter_outgoing(&mesh_peer, rpc(&message))
+            .is_none());
+        assert!(gossipsub.ready.is_empty());
+
+        gossipsub.report_validation_result(&id, &forwarder, MessageAcceptance::Accept);
+        assert_eq!(
+            gossipsub.ready.pop_front(),
+            Some((mesh_peer.clone(), message.clone()))
+        );
+
+        // once accepted, the message is sent immediately.
+        assert!(gossipsub
+            .filter_outgoing(&mesh_peer, rpc(&message))
+            .is_some());
+    }
+
+    #[test]
+    fn rejected_messages_are_never_forwarded() {
+        for &acceptance in &[MessageAcceptance::Ignore, MessageAcceptance::Reject] {
+            let mut gossipsub = gossipsub();
+            let (forwarder, mesh_peer) = (PeerId::random(), PeerId::random());
+            let message = message(&PeerId::random(), 1);
+            let id = MessageId::from(&message);
+
+            gossipsub.record_received(&forwarder, &rpc(&message));
+            assert!(gossipsub
+                .filter_outgoing(&mesh_peer, rpc(&message))
+                .is_none());
+
+            gossipsub.report_validation_result(&id, &forwarder, acceptance);
+            assert!(gossipsub.ready.is_empty());
+            assert!(gossipsub
+                .filter_outgoing(&mesh_peer, rpc(&message))
+                .is_none());
+        }
+    }
+
+    #[test]
+    fn unknown_messages_are_not_forwarded() {
+        let mut gossipsub = gossipsub();
+        let message = message(&PeerId::random(), 1);
+
+        assert!(gossipsub
+            .filter_outgoing(&PeerId::random(), rpc(&message))
+            .is_none());
+    }
+
+    #[test]
+    fn own_messages_are_sent_without_validation() {
+        let mut gossipsub = gossipsub();
+        let local_peer_id = gossipsub.local_peer_id.clone();
+        let message = message(&local_peer_id, 1);
+
+        assert!(gossipsub
+            .filter_outgoing(&PeerId::random(), rpc(&message))
+            .is_some());
+    }
+}
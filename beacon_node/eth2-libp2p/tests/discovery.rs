@@ -0,0 +1,66 @@
+// Runs several in-process nodes on localhost and checks that they find each other through a
+// single boot node.
+use eth2_libp2p::{Multiaddr, NetworkConfig, Service};
+use futures::prelude::*;
+use slog::{o, Discard, Logger};
+use std::net::TcpListener;
+use std::time::Duration;
+use tokio::runtime::Runtime;
+use tokio::timer::Timeout;
+
+/// Returns a localhost port which is currently free, as chosen by the OS.
+fn unused_port() -> u16 {
+    TcpListener::bind("127.0.0.1:0")
+        .and_then(|listener| listener.local_addr())
+        .map(|address| address.port())
+        .expect("should find an unused port")
+}
+
+/// Builds a node listening on `port` of localhost.
+fn build_node(port: u16, boot_nodes: Vec<Multiaddr>, target_peers: usize) -> Service {
+    let mut config = NetworkConfig::new(boot_nodes);
+    config.listen_port = port;
+    config.listen_addresses = vec![format!("/ip4/127.0.0.1/tcp/{}", port).parse().unwrap()];
+    config.target_peers = target_peers;
+    config.discovery_interval = Duration::from_secs(1);
+
+    Service::new(config, Logger::root(Discard, o!())).expect("should build the service")
+}
+
+#[test]
+fn nodes_discover_each_other_through_boot_node() {
+    let num_nodes = 4;
+    let ports: Vec<u16> = (0..num_nodes).map(|_| unused_port()).collect();
+
+    // The nodes are built on the runtime, so that their listeners are bound to its reactor.
+    let fully_connected = futures::future::lazy(move || {
+        let boot_address: Multiaddr = format!("/ip4/127.0.0.1/tcp/{}", ports[0]).parse().unwrap();
+
+        let mut nodes = vec![build_node(ports[0], vec![], num_nodes - 1)];
+        for port in &ports[1..] {
+            nodes.push(build_node(*port, vec![boot_address.clone()], num_nodes - 1));
+        }
+
+        // Every node only knows the boot node, so they must discover the others to be fully
+        // connected.
+        futures::future::poll_fn(move || -> Result<_, ()> {
+            for node in nodes.iter_mut() {
+                while let Ok(Async::Ready(Some(_))) = node.poll() {}
+            }
+
+            if nodes
+                .iter()
+                .all(|node| node.swarm.connected_peers() == num_nodes - 1)
+            {
+                Ok(Async::Ready(()))
+            } else {
+                Ok(Async::NotReady)
+            }
+        })
+    });
+
+    let mut runtime = Runtime::new().unwrap();
+    runtime
+        .block_on(Timeout::new(fully_connected, Duration::from_secs(30)))
+        .expect("nodes should discover each other before the timeout");
+}
@@ -8,7 +8,10 @@ use beacon_chain::{
     AttestationValidationError, CheckPoint,
 };
 use eth2_libp2p::rpc::HelloMessage;
-use types::{Attestation, BeaconBlock, BeaconBlockBody, BeaconBlockHeader, Epoch, Hash256, Slot};
+use types::{
+    Attestation, BeaconBlock, BeaconBlockBody, BeaconBlockHeader, BeaconStateError, Epoch, Hash256,
+    Slot,
+};
 
 pub use beacon_chain::{BeaconChainError, BlockProcessingOutcome, InvalidBlock};
 
@@ -20,10 +23,20 @@ pub trait BeaconChain: Send + Sync {
 
     fn slot(&self) -> Slot;
 
+    fn fork_version(&self) -> [u8; 4];
+
     fn head(&self) -> RwLockReadGuard<CheckPoint>;
 
+    fn block_proposer(&self, slot: Slot) -> Result<usize, BeaconStateError>;
+
     fn get_block(&self, block_root: &Hash256) -> Result<Option<BeaconBlock>, BeaconChainError>;
 
+    fn get_state_ssz(&self, state_root: &Hash256) -> Result<Option<Vec<u8>>, BeaconChainError>;
+
+    fn store_state(&self, state: &BeaconState) -> Result<Hash256, BeaconChainError>;
+
+    fn store_block(&self, block: &BeaconBlock) -> Result<Hash256, BeaconChainError>;
+
     fn best_slot(&self) -> Slot;
 
     fn best_block_root(&self) -> Hash256;
@@ -59,6 +72,15 @@ pub trait BeaconChain: Send + Sync {
     fn get_block_bodies(&self, roots: &[Hash256])
         -> Result<Vec<BeaconBlockBody>, BeaconChainError>;
 
+    fn get_blocks_by_range(
+        &self,
+        start_slot: Slot,
+        count: usize,
+        step: usize,
+    ) -> Result<Vec<BeaconBlock>, BeaconChainError>;
+
+    fn get_blocks_by_root(&self, roots: &[Hash256]) -> Result<Vec<BeaconBlock>, BeaconChainError>;
+
     fn is_new_block_root(&self, beacon_block_root: &Hash256) -> Result<bool, BeaconChainError>;
 }
 
@@ -80,14 +102,36 @@ where
         self.get_state().slot
     }
 
+    /// The fork version of the present epoch, which namespaces our gossip topics.
+    fn fork_version(&self) -> [u8; 4] {
+        let state = self.get_state();
+        state.fork.get_fork_version(state.current_epoch(&self.spec))
+    }
+
     fn head(&self) -> RwLockReadGuard<CheckPoint> {
         self.head()
     }
 
+    fn block_proposer(&self, slot: Slot) -> Result<usize, BeaconStateError> {
+        self.block_proposer(slot)
+    }
+
     fn get_block(&self, block_root: &Hash256) -> Result<Option<BeaconBlock>, BeaconChainError> {
         self.get_block(block_root)
     }
 
+    fn get_state_ssz(&self, state_root: &Hash256) -> Result<Option<Vec<u8>>, BeaconChainError> {
+        self.get_state_ssz(state_root)
+    }
+
+    fn store_state(&self, state: &BeaconState) -> Result<Hash256, BeaconChainError> {
+        self.store_state(state)
+    }
+
+    fn store_block(&self, block: &BeaconBlock) -> Result<Hash256, BeaconChainError> {
+        self.store_block(block)
+    }
+
     fn finalized_epoch(&self) -> Epoch {
         self.get_state().finalized_epoch
     }
@@ -157,6 +201,49 @@ where
         self.get_block_bodies(roots)
     }
 
+    /// Returns the blocks at the slots `start_slot + i * step` for `i` in `0..count`, omitting
+    /// slots without a block.
+    ///
+    /// A `step` of zero selects no slots, so no blocks are returned.
+    fn get_blocks_by_range(
+        &self,
+        start_slot: Slot,
+        count: usize,
+        step: usize,
+    ) -> Result<Vec<BeaconBlock>, BeaconChainError> {
+        if step == 0 {
+            return Ok(vec![]);
+        }
+
+        let roots = self.get_block_roots(start_slot, count, step - 1)?;
+
+        let mut blocks: Vec<BeaconBlock> = vec![];
+        for (i, root) in roots.iter().enumerate() {
+            let block = self
+                .get_block(root)?
+                .ok_or_else(|| BeaconChainError::MissingBeaconBlock(*root))?;
+
+            // A skip slot has the root of an earlier block, which is not in the range.
+            if block.slot == start_slot + Slot::from(i * step) {
+                blocks.push(block);
+            }
+        }
+
+        Ok(blocks)
+    }
+
+    /// Returns the blocks of `roots` which are known, in the same order.
+    fn get_blocks_by_root(&self, roots: &[Hash256]) -> Result<Vec<BeaconBlock>, BeaconChainError> {
+        let mut blocks: Vec<BeaconBlock> = vec![];
+        for root in roots {
+            if let Some(block) = self.get_block(root)? {
+                blocks.push(block);
+            }
+        }
+
+        Ok(blocks)
+    }
+
     fn is_new_block_root(&self, beacon_block_root: &Hash256) -> Result<bool, BeaconChainError> {
         self.is_new_block_root(beacon_block_root)
     }
@@ -2,9 +2,11 @@
 pub mod beacon_chain;
 pub mod error;
 pub mod message_handler;
+pub mod peer_manager;
 pub mod service;
+pub mod subnets;
 pub mod sync;
 
-pub use eth2_libp2p::NetworkConfig;
+pub use eth2_libp2p::{NetworkConfig, NodeKeyType};
 pub use service::NetworkMessage;
 pub use service::Service;
@@ -1,21 +1,26 @@
 use crate::beacon_chain::BeaconChain;
 use crate::error;
+use crate::peer_manager::{PeerAction, PeerManager, BAN_DURATION};
 use crate::service::{NetworkMessage, OutgoingMessage};
 use crate::sync::SimpleSync;
-use crossbeam_channel::{unbounded as channel, Sender};
+use crossbeam_channel::{unbounded as channel, RecvTimeoutError, Sender};
 use eth2_libp2p::{
     behaviour::PubsubMessage,
-    rpc::{methods::GoodbyeReason, RPCRequest, RPCResponse, RequestId},
-    PeerId, RPCEvent,
+    rpc::{
+        methods::GoodbyeReason, ErrorResponse, RPCMethod, RPCRequest, RPCResponse, RequestId,
+        ResponseCode,
+    },
+    MessageAcceptance, MessageId, PeerId, RPCEvent,
 };
 use futures::future;
-use slog::{debug, warn};
+use parking_lot::RwLock;
+use slog::{debug, info, warn};
 use std::collections::HashMap;
 use std::sync::Arc;
-use std::time::Instant;
+use std::time::{Duration, Instant};
 
-/// Timeout for RPC requests.
-// const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+/// The interval at which peer scores are maintained.
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(5);
 /// Timeout before banning a peer for non-identification.
 // const HELLO_TIMEOUT: Duration = Duration::from_secs(30);
 
@@ -40,8 +45,20 @@ pub enum HandlerMessage {
     PeerDisconnected(PeerId),
     /// An RPC response/request has been received.
     RPC(PeerId, RPCEvent),
-    /// A gossip message has been received.
-    PubsubMessage(PeerId, Box<PubsubMessage>),
+    /// A request from a peer was rejected by the RPC rate limiter, and responded to with the
+    /// given code.
+    RequestRejected(PeerId, ResponseCode),
+    /// A peer did not respond to an RPC request in time.
+    RequestTimeout {
+        peer_id: PeerId,
+        id: RequestId,
+        method_id: u16,
+    },
+    /// A gossip message has been received from the given peer, and must be validated before it
+    /// is propagated.
+    PubsubMessage(MessageId, PeerId, Box<PubsubMessage>),
+    /// A gossip message which could not be decoded has been received.
+    UndecodableGossip(PeerId),
 }
 
 impl MessageHandler {
@@ -49,6 +66,7 @@ impl MessageHandler {
     pub fn spawn(
         beacon_chain: Arc<BeaconChain>,
         network_send: crossbeam_channel::Sender<NetworkMessage>,
+        peer_manager: Arc<RwLock<PeerManager>>,
         executor: &tokio::runtime::TaskExecutor,
         log: slog::Logger,
     ) -> error::Result<Sender<HandlerMessage>> {
@@ -57,49 +75,117 @@ impl MessageHandler {
         let (handler_send, handler_recv) = channel();
 
         // Initialise sync and begin processing in thread
-        // generate the Message handler
-        let sync = SimpleSync::new(beacon_chain.clone(), &log);
-
-        let mut handler = MessageHandler {
-            _chain: beacon_chain.clone(),
-            sync,
-            network_context: NetworkContext::new(network_send, log.clone()),
-            log: log.clone(),
-        };
+        let mut handler =
+            MessageHandler::new(beacon_chain, network_send, peer_manager, log.clone());
 
         // spawn handler task
         // TODO: Handle manual termination of thread
+        let mut last_heartbeat = Instant::now();
         executor.spawn(future::poll_fn(move || -> Result<_, _> {
             loop {
-                handler.handle_message(handler_recv.recv().map_err(|_| {
-                    debug!(log, "Network message handler terminated.");
-                })?);
+                match handler_recv.recv_timeout(HEARTBEAT_INTERVAL) {
+                    Ok(message) => handler.handle_message(message),
+                    Err(RecvTimeoutError::Timeout) => {}
+                    Err(RecvTimeoutError::Disconnected) => {
+                        debug!(log, "Network message handler terminated.");
+                        return Err(());
+                    }
+                }
+
+                if last_heartbeat.elapsed() >= HEARTBEAT_INTERVAL {
+                    handler.heartbeat();
+                    last_heartbeat = Instant::now();
+                }
             }
         }));
 
         Ok(handler_send)
     }
 
+    /// Builds a `MessageHandler` which is driven by calling `handle_message`, rather than by a
+    /// spawned task. Useful for stepping many handlers deterministically, e.g., in a simulation.
+    pub fn new(
+        beacon_chain: Arc<BeaconChain>,
+        network_send: crossbeam_channel::Sender<NetworkMessage>,
+        peer_manager: Arc<RwLock<PeerManager>>,
+        log: slog::Logger,
+    ) -> Self {
+        let sync = SimpleSync::new(beacon_chain.clone(), &log);
+
+        MessageHandler {
+            _chain: beacon_chain,
+            sync,
+            network_context: NetworkContext::new(network_send, peer_manager, log.clone()),
+            log,
+        }
+    }
+
     /// Handle all messages incoming from the network service.
-    fn handle_message(&mut self, message: HandlerMessage) {
+    pub fn handle_message(&mut self, message: HandlerMessage) {
+        // Any message from a peer shows we are connected to it, including peers which dialed us.
+        match &message {
+            HandlerMessage::PeerDialed(peer_id) | HandlerMessage::RPC(peer_id, _) => {
+                self.network_context.peer_connected(peer_id)
+            }
+            HandlerMessage::PeerDisconnected(peer_id) => {
+                self.network_context.peer_disconnected(peer_id)
+            }
+            _ => {}
+        }
+
         match message {
             // we have initiated a connection to a peer
             HandlerMessage::PeerDialed(peer_id) => {
-                self.sync.on_connect(peer_id, &mut self.network_context);
+                // The peer's ban is already recorded, so it is not banned again.
+                if self.network_context.is_banned(&peer_id) {
+                    self.network_context
+                        .disconnect(peer_id, GoodbyeReason::Banned, None);
+                } else {
+                    self.sync.on_connect(peer_id, &mut self.network_context);
+                }
+            }
+            // a peer has disconnected
+            HandlerMessage::PeerDisconnected(peer_id) => {
+                self.sync.on_disconnect(peer_id, &mut self.network_context);
             }
             // we have received an RPC message request/response
             HandlerMessage::RPC(peer_id, rpc_event) => {
                 self.handle_rpc_message(peer_id, rpc_event);
             }
+            // an RPC request from a peer exceeded its limits
+            HandlerMessage::RequestRejected(peer_id, code) => {
+                let action = match code {
+                    ResponseCode::RateLimited => PeerAction::RateLimited,
+                    _ => PeerAction::InvalidRequest,
+                };
+                self.network_context.report_peer(peer_id, action);
+            }
+            // an RPC request we sent has not been responded to
+            HandlerMessage::RequestTimeout {
+                peer_id,
+                id,
+                method_id,
+            } => {
+                self.handle_request_timeout(peer_id, id, method_id);
+            }
             // we have received an RPC message request/response
-            HandlerMessage::PubsubMessage(peer_id, gossip) => {
-                self.handle_gossip(peer_id, *gossip);
+            HandlerMessage::PubsubMessage(id, peer_id, gossip) => {
+                self.handle_gossip(id, peer_id, *gossip);
+            }
+            // we have received a gossip message which could not be decoded
+            HandlerMessage::UndecodableGossip(peer_id) => {
+                self.network_context
+                    .report_peer(peer_id, PeerAction::UndecodableGossip);
             }
-            //TODO: Handle all messages
-            _ => {}
         }
     }
 
+    /// Maintains the scores of our peers and the sync state. Called every `HEARTBEAT_INTERVAL`.
+    pub fn heartbeat(&mut self) {
+        self.network_context.heartbeat();
+        self.sync.on_heartbeat(&mut self.network_context);
+    }
+
     /* RPC - Related functionality */
 
     /// Handle RPC messages
@@ -121,7 +207,10 @@ impl MessageHandler {
                 hello_message,
                 &mut self.network_context,
             ),
-            RPCRequest::Goodbye(goodbye_reason) => self.sync.on_goodbye(peer_id, goodbye_reason),
+            RPCRequest::Goodbye(goodbye_reason) => {
+                self.sync
+                    .on_goodbye(peer_id, goodbye_reason, &mut self.network_context)
+            }
             RPCRequest::BeaconBlockRoots(request) => self.sync.on_beacon_block_roots_request(
                 peer_id,
                 request_id,
@@ -140,11 +229,26 @@ impl MessageHandler {
                 request,
                 &mut self.network_context,
             ),
-            RPCRequest::BeaconChainState(_) => {
-                // We do not implement this endpoint, it is not required and will only likely be
-                // useful for light-client support in later phases.
-                warn!(self.log, "BeaconChainState RPC call is not supported.");
+            RPCRequest::BeaconBlocksByRange(request) => {
+                self.sync.on_beacon_blocks_by_range_request(
+                    peer_id,
+                    request_id,
+                    request,
+                    &mut self.network_context,
+                )
             }
+            RPCRequest::BeaconBlocksByRoot(request) => self.sync.on_beacon_blocks_by_root_request(
+                peer_id,
+                request_id,
+                request,
+                &mut self.network_context,
+            ),
+            RPCRequest::BeaconChainState(request) => self.sync.on_beacon_chain_state_request(
+                peer_id,
+                request_id,
+                request,
+                &mut self.network_context,
+            ),
         }
     }
 
@@ -168,49 +272,111 @@ impl MessageHandler {
         }
 
         match response {
+            RPCResponse::Error(error) => {
+                warn!(
+                    self.log,
+                    "RPCErrorResponse";
+                    "peer" => format!("{:?}", peer_id),
+                    "method" => format!("{:?}", RPCMethod::from(error.method_id)),
+                    "code" => format!("{:?}", error.code),
+                    "message" => error.message,
+                );
+                self.sync
+                    .on_error_response(peer_id, id, &mut self.network_context);
+            }
             RPCResponse::Hello(hello_message) => {
                 self.sync
                     .on_hello_response(peer_id, hello_message, &mut self.network_context);
             }
-            RPCResponse::BeaconBlockRoots(response) => {
-                self.sync.on_beacon_block_roots_response(
+            RPCResponse::BeaconBlocksByRange(response) => {
+                self.sync.on_beacon_blocks_by_range_response(
                     peer_id,
+                    id,
                     response,
                     &mut self.network_context,
                 );
             }
-            RPCResponse::BeaconBlockHeaders(response) => {
-                self.sync.on_beacon_block_headers_response(
+            RPCResponse::BeaconBlocksByRoot(response) => {
+                self.sync.on_beacon_blocks_by_root_response(
                     peer_id,
+                    id,
                     response,
                     &mut self.network_context,
                 );
             }
-            RPCResponse::BeaconBlockBodies(response) => {
-                self.sync.on_beacon_block_bodies_response(
+            RPCResponse::BeaconBlockRoots(_)
+            | RPCResponse::BeaconBlockHeaders(_)
+            | RPCResponse::BeaconBlockBodies(_) => {
+                // Blocks are downloaded with `BeaconBlocksByRange` and `BeaconBlocksByRoot`, so we
+                // never request these.
+                warn!(
+                    self.log,
+                    "Unexpected RPC response";
+                    "peer" => format!("{:?}", peer_id),
+                    "method" => format!("{:?}", RPCMethod::from(response.method_id())),
+                );
+            }
+            RPCResponse::BeaconChainState(response) => {
+                self.sync.on_beacon_chain_state_response(
                     peer_id,
+                    id,
                     response,
                     &mut self.network_context,
                 );
             }
-            RPCResponse::BeaconChainState(_) => {
-                // We do not implement this endpoint, it is not required and will only likely be
-                // useful for light-client support in later phases.
-                //
-                // Theoretically, we shouldn't reach this code because we should never send a
-                // beacon state RPC request.
-                warn!(self.log, "BeaconChainState RPC call is not supported.");
-            }
         };
     }
 
-    /// Handle RPC messages
-    fn handle_gossip(&mut self, peer_id: PeerId, gossip_message: PubsubMessage) {
+    /// An RPC request we sent has timed out.
+    fn handle_request_timeout(&mut self, peer_id: PeerId, id: RequestId, method_id: u16) {
+        // if the request is no longer outstanding, it has already been responded to.
+        if self
+            .network_context
+            .outstanding_outgoing_request_ids
+            .remove(&(peer_id.clone(), id.clone()))
+            .is_none()
+        {
+            return;
+        }
+
+        let method = RPCMethod::from(method_id);
+        debug!(
+            self.log,
+            "RPCRequestTimeout";
+            "peer" => format!("{:?}", peer_id),
+            "request_id" => format!("{:?}", id),
+            "method" => format!("{:?}", method),
+        );
+
+        self.sync
+            .on_request_timeout(peer_id.clone(), id, method, &mut self.network_context);
+        self.network_context
+            .report_peer(peer_id, PeerAction::RequestTimeout);
+    }
+
+    /// Handle gossip messages.
+    ///
+    /// Each message is validated and the result is reported to the network service, which only
+    /// propagates accepted messages. Accepted messages are then processed.
+    fn handle_gossip(&mut self, id: MessageId, peer_id: PeerId, gossip_message: PubsubMessage) {
+        let acceptance = match &gossip_message {
+            PubsubMessage::Block(block) => self.sync.validate_block_gossip(block),
+            PubsubMessage::Attestation(attestation) => {
+                self.sync.validate_attestation_gossip(attestation)
+            }
+        };
+
+        self.network_context
+            .report_gossip_validation(id, peer_id.clone(), acceptance);
+
+        if acceptance != MessageAcceptance::Accept {
+            return;
+        }
+
         match gossip_message {
             PubsubMessage::Block(message) => {
-                let _should_foward_on =
-                    self.sync
-                        .on_block_gossip(peer_id, message, &mut self.network_context);
+                self.sync
+                    .on_block_gossip(peer_id, message, &mut self.network_context)
             }
             PubsubMessage::Attestation(message) => {
                 self.sync
@@ -223,6 +389,8 @@ impl MessageHandler {
 pub struct NetworkContext {
     /// The network channel to relay messages to the Network service.
     network_send: crossbeam_channel::Sender<NetworkMessage>,
+    /// The scores and bans of peers, shared with the network service.
+    peer_manager: Arc<RwLock<PeerManager>>,
     /// A mapping of peers and the RPC id we have sent an RPC request to.
     outstanding_outgoing_request_ids: HashMap<(PeerId, RequestId), Instant>,
     /// Stores the next `RequestId` we should include on an outgoing `RPCRequest` to a `PeerId`.
@@ -232,21 +400,134 @@ pub struct NetworkContext {
 }
 
 impl NetworkContext {
-    pub fn new(network_send: crossbeam_channel::Sender<NetworkMessage>, log: slog::Logger) -> Self {
+    pub fn new(
+        network_send: crossbeam_channel::Sender<NetworkMessage>,
+        peer_manager: Arc<RwLock<PeerManager>>,
+        log: slog::Logger,
+    ) -> Self {
         Self {
             network_send,
+            peer_manager,
             outstanding_outgoing_request_ids: HashMap::new(),
             outgoing_request_ids: HashMap::new(),
             log,
         }
     }
 
-    pub fn disconnect(&mut self, peer_id: PeerId, reason: GoodbyeReason) {
-        self.send_rpc_request(peer_id, RPCRequest::Goodbye(reason))
-        // TODO: disconnect peers.
+    /// Reduces the score of `peer_id` for `action`, disconnecting and banning the peer if its
+    /// score falls below the ban threshold.
+    pub fn report_peer(&mut self, peer_id: PeerId, action: PeerAction) {
+        let banned = self.peer_manager.write().report_peer(&peer_id, action);
+
+        debug!(
+            self.log, "PeerReported";
+            "peer" => format!("{:?}", peer_id),
+            "action" => format!("{:?}", action),
+            "score" => self.peer_manager.read().score(&peer_id),
+        );
+
+        if banned {
+            info!(
+                self.log, "PeerBanned";
+                "peer" => format!("{:?}", peer_id),
+                "action" => format!("{:?}", action),
+            );
+            self.disconnect(peer_id, action.goodbye_reason(), Some(BAN_DURATION));
+        }
+    }
+
+    /// Returns `true` if `peer_id` is currently banned.
+    pub fn is_banned(&self, peer_id: &PeerId) -> bool {
+        self.peer_manager.read().is_banned(peer_id)
+    }
+
+    /// Records that we are connected to `peer_id`.
+    ///
+    /// This is called for every message from a peer, so the write lock is only taken the first
+    /// time.
+    pub fn peer_connected(&mut self, peer_id: &PeerId) {
+        if !self.peer_manager.read().is_connected(peer_id) {
+            self.peer_manager.write().peer_connected(peer_id);
+        }
+    }
+
+    /// Records that `peer_id` has disconnected, and forgets our requests to it, which will never
+    /// be responded to.
+    pub fn peer_disconnected(&mut self, peer_id: &PeerId) {
+        self.peer_manager.write().peer_disconnected(peer_id);
+        self.outstanding_outgoing_request_ids
+            .retain(|(request_peer_id, _), _| request_peer_id != peer_id);
     }
 
-    pub fn send_rpc_request(&mut self, peer_id: PeerId, rpc_request: RPCRequest) {
+    /// Sends a `Goodbye` to `peer_id`, then disconnects it.
+    ///
+    /// If `ban_duration` is given the peer is also banned for that long, and the ban is recorded
+    /// in the `PeerManager`.
+    pub fn disconnect(
+        &mut self,
+        peer_id: PeerId,
+        reason: GoodbyeReason,
+        ban_duration: Option<Duration>,
+    ) {
+        if let Some(ban_duration) = ban_duration {
+            self.peer_manager.write().ban(&peer_id, ban_duration);
+        }
+
+        // A `Goodbye` has no response, so it is not tracked as an outstanding request.
+        let id = self.generate_request_id(&peer_id);
+        let goodbye = RPCRequest::Goodbye(reason);
+        self.send_rpc_event(
+            peer_id.clone(),
+            RPCEvent::Request {
+                id,
+                method_id: goodbye.method_id(),
+                body: goodbye,
+            },
+        );
+
+        self.network_send
+            .send(NetworkMessage::Disconnect {
+                peer_id,
+                ban_duration,
+            })
+            .unwrap_or_else(|_| {
+                warn!(self.log, "Could not send disconnect to the network service")
+            });
+    }
+
+    /// Informs the network service of the result of validating a gossip message, penalising the
+    /// peer which sent it if it was rejected.
+    pub fn report_gossip_validation(
+        &mut self,
+        message_id: MessageId,
+        propagation_source: PeerId,
+        acceptance: MessageAcceptance,
+    ) {
+        if acceptance == MessageAcceptance::Reject {
+            self.report_peer(propagation_source.clone(), PeerAction::InvalidGossip);
+        }
+
+        self.network_send
+            .send(NetworkMessage::Validate {
+                message_id,
+                propagation_source,
+                acceptance,
+            })
+            .unwrap_or_else(|_| {
+                warn!(
+                    self.log,
+                    "Could not send gossip validation result to the network service"
+                )
+            });
+    }
+
+    /// Maintains peer scores and bans.
+    pub fn heartbeat(&mut self) {
+        self.peer_manager.write().heartbeat();
+    }
+
+    /// Sends `rpc_request` to `peer_id`, returning the `RequestId` its response will carry.
+    pub fn send_rpc_request(&mut self, peer_id: PeerId, rpc_request: RPCRequest) -> RequestId {
         let id = self.generate_request_id(&peer_id);
 
         self.outstanding_outgoing_request_ids
@@ -255,11 +536,13 @@ impl NetworkContext {
         self.send_rpc_event(
             peer_id,
             RPCEvent::Request {
-                id,
+                id: id.clone(),
                 method_id: rpc_request.method_id(),
                 body: rpc_request,
             },
         );
+
+        id
     }
 
     pub fn send_rpc_response(
@@ -278,6 +561,26 @@ impl NetworkContext {
         );
     }
 
+    /// Responds to the request `request_id` with an error, rather than the requested data.
+    pub fn send_rpc_error_response(
+        &mut self,
+        peer_id: PeerId,
+        request_id: RequestId,
+        method: RPCMethod,
+        code: ResponseCode,
+        message: String,
+    ) {
+        self.send_rpc_response(
+            peer_id,
+            request_id,
+            RPCResponse::Error(ErrorResponse {
+                method_id: method.into(),
+                code,
+                message,
+            }),
+        )
+    }
+
     fn send_rpc_event(&self, peer_id: PeerId, rpc_event: RPCEvent) {
         self.send(peer_id, OutgoingMessage::RPC(rpc_event))
     }
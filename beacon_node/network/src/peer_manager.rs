@@ -0,0 +1,328 @@
+//! Tracks the reputation of connected peers.
+//!
+//! Each peer has a score which starts at zero and is reduced whenever the peer misbehaves. Scores
+//! decay back towards zero over time, so that occasional faults are forgiven. A peer whose score
+//! falls below `BAN_THRESHOLD` is disconnected and banned for `BAN_DURATION`.
+use eth2_libp2p::rpc::methods::GoodbyeReason;
+use eth2_libp2p::PeerId;
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, Instant};
+
+/// The lowest score a peer can have.
+const MIN_SCORE: f64 = -100.0;
+
+/// Peers with a score below this value are disconnected and banned.
+pub const BAN_THRESHOLD: f64 = -50.0;
+
+/// The time taken for a score to decay to half of its value.
+const SCORE_HALF_LIFE: Duration = Duration::from_secs(600);
+
+/// Scores closer to zero than this are forgotten.
+const SCORE_EPSILON: f64 = 0.1;
+
+/// The time a peer remains banned after falling below `BAN_THRESHOLD`.
+pub const BAN_DURATION: Duration = Duration::from_secs(1800);
+
+/// Misbehaviour which reduces the score of a peer.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PeerAction {
+    /// The peer sent a block which failed validation.
+    InvalidBlock,
+    /// The peer sent a gossip message which could not be decoded.
+    UndecodableGossip,
+    /// The peer sent a gossip message which was rejected by gossip validation.
+    InvalidGossip,
+    /// The peer sent a malformed RPC response.
+    InvalidResponse,
+    /// The peer did not respond to an RPC request in time.
+    RequestTimeout,
+    /// The peer sent an RPC request exceeding the protocol maximums.
+    InvalidRequest,
+    /// The peer sent RPC requests faster than its rate limit allows.
+    RateLimited,
+    /// The peer is on a different network to us.
+    WrongNetwork,
+    /// The peer sent a `Hello` which contradicts the finalized checkpoint of its previous one.
+    InconsistentHello,
+}
+
+impl PeerAction {
+    /// The amount the score of a peer is reduced by for this action.
+    pub fn penalty(self) -> f64 {
+        match self {
+            PeerAction::InvalidBlock => 20.0,
+            PeerAction::UndecodableGossip => 10.0,
+            PeerAction::InvalidGossip => 10.0,
+            PeerAction::InvalidResponse => 10.0,
+            PeerAction::RequestTimeout => 5.0,
+            PeerAction::InvalidRequest => 10.0,
+            PeerAction::RateLimited => 5.0,
+            PeerAction::WrongNetwork => -MIN_SCORE,
+            PeerAction::InconsistentHello => -MIN_SCORE,
+        }
+    }
+
+    /// The reason sent to the peer if this action causes it to be banned.
+    pub fn goodbye_reason(self) -> GoodbyeReason {
+        match self {
+            PeerAction::WrongNetwork => GoodbyeReason::IrreleventNetwork,
+            PeerAction::InconsistentHello => GoodbyeReason::Fault,
+            _ => GoodbyeReason::BadScore,
+        }
+    }
+}
+
+/// The score of a single peer.
+#[derive(Debug, Clone, Copy)]
+struct PeerScore {
+    /// The score at the time of `last_updated`.
+    score: f64,
+    /// The time from which `score` decays.
+    last_updated: Instant,
+}
+
+impl PeerScore {
+    /// Returns the score at `now`, after decay.
+    fn at(&self, now: Instant) -> f64 {
+        decay(self.score, now.duration_since(self.last_updated))
+    }
+}
+
+/// Returns `score` after decaying for `elapsed`.
+fn decay(score: f64, elapsed: Duration) -> f64 {
+    let half_lives = elapsed.as_millis() as f64 / SCORE_HALF_LIFE.as_millis() as f64;
+    score * 0.5_f64.powf(half_lives)
+}
+
+/// Maintains the scores and bans of peers.
+#[derive(Default)]
+pub struct PeerManager {
+    /// Peers with a non-zero score.
+    scores: HashMap<PeerId, PeerScore>,
+    /// Banned peers and the time their ban expires.
+    banned: HashMap<PeerId, Instant>,
+    /// The peers we are connected to.
+    connected: HashSet<PeerId>,
+}
+
+impl PeerManager {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Reduces the score of `peer_id` for `action`.
+    ///
+    /// Returns `true` if the peer fell below `BAN_THRESHOLD` and has been banned. The caller is
+    /// responsible for disconnecting it.
+    pub fn report_peer(&mut self, peer_id: &PeerId, action: PeerAction) -> bool {
+        self.report_peer_at(peer_id, action, Instant::now())
+    }
+
+    fn report_peer_at(&mut self, peer_id: &PeerId, action: PeerAction, now: Instant) -> bool {
+        if self.is_banned_at(peer_id, now) {
+            return false;
+        }
+
+        let score = self.score_at(peer_id, now) - action.penalty();
+
+        if score < BAN_THRESHOLD {
+            self.ban_at(peer_id, BAN_DURATION, now);
+            true
+        } else {
+            self.scores.insert(
+                peer_id.clone(),
+                PeerScore {
+                    score: score.max(MIN_SCORE),
+                    last_updated: now,
+                },
+            );
+            false
+        }
+    }
+
+    /// Bans `peer_id` for `ban_duration`, forgetting its score.
+    pub fn ban(&mut self, peer_id: &PeerId, ban_duration: Duration) {
+        self.ban_at(peer_id, ban_duration, Instant::now())
+    }
+
+    fn ban_at(&mut self, peer_id: &PeerId, ban_duration: Duration, now: Instant) {
+        self.scores.remove(peer_id);
+        self.banned.insert(peer_id.clone(), now + ban_duration);
+    }
+
+    /// Returns the current score of `peer_id`. Unknown peers have a score of zero.
+    pub fn score(&self, peer_id: &PeerId) -> f64 {
+        self.score_at(peer_id, Instant::now())
+    }
+
+    fn score_at(&self, peer_id: &PeerId, now: Instant) -> f64 {
+        self.scores
+            .get(peer_id)
+            .map(|score| score.at(now))
+            .unwrap_or(0.0)
+    }
+
+    /// Returns the current score of every peer with a non-zero score, lowest first.
+    pub fn scores(&self) -> Vec<(PeerId, f64)> {
+        let now = Instant::now();
+        let mut scores: Vec<(PeerId, f64)> = self
+            .scores
+            .iter()
+            .map(|(peer_id, score)| (peer_id.clone(), score.at(now)))
+            .collect();
+        scores.sort_by(|a, b| a.1.partial_cmp(&b.1).unwrap_or(std::cmp::Ordering::Equal));
+        scores
+    }
+
+    /// Returns `true` if `peer_id` is currently banned.
+    pub fn is_banned(&self, peer_id: &PeerId) -> bool {
+        self.is_banned_at(peer_id, Instant::now())
+    }
+
+    fn is_banned_at(&self, peer_id: &PeerId, now: Instant) -> bool {
+        self.banned
+            .get(peer_id)
+            .map_or(false, |expiry| *expiry > now)
+    }
+
+    /// Returns the peers which are currently banned.
+    pub fn banned_peers(&self) -> Vec<PeerId> {
+        let now = Instant::now();
+        self.banned
+            .iter()
+            .filter(|(_, expiry)| **expiry > now)
+            .map(|(peer_id, _)| peer_id.clone())
+            .collect()
+    }
+
+    /// Records that we are connected to `peer_id`.
+    pub fn peer_connected(&mut self, peer_id: &PeerId) {
+        self.connected.insert(peer_id.clone());
+    }
+
+    /// Returns `true` if we are connected to `peer_id`.
+    pub fn is_connected(&self, peer_id: &PeerId) -> bool {
+        self.connected.contains(peer_id)
+    }
+
+    /// Records that `peer_id` has disconnected.
+    pub fn peer_disconnected(&mut self, peer_id: &PeerId) {
+        self.connected.remove(peer_id);
+    }
+
+    /// Returns the peers we are connected to.
+    pub fn connected_peers(&self) -> Vec<PeerId> {
+        self.connected.iter().cloned().collect()
+    }
+
+    /// Removes expired bans and forgets scores which have decayed to zero.
+    pub fn heartbeat(&mut self) {
+        self.heartbeat_at(Instant::now())
+    }
+
+    fn heartbeat_at(&mut self, now: Instant) {
+        self.banned.retain(|_, expiry| *expiry > now);
+        self.scores
+            .retain(|_, score| score.at(now).abs() >= SCORE_EPSILON);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scores_decay() {
+        assert_eq!(decay(-40.0, Duration::from_secs(0)), -40.0);
+        assert_eq!(decay(-40.0, SCORE_HALF_LIFE), -20.0);
+        assert_eq!(decay(-40.0, SCORE_HALF_LIFE * 2), -10.0);
+    }
+
+    #[test]
+    fn repeated_faults_ban_peer() {
+        let mut manager = PeerManager::new();
+        let peer_id = PeerId::random();
+        let now = Instant::now();
+
+        assert!(!manager.report_peer_at(&peer_id, PeerAction::InvalidBlock, now));
+        assert!(!manager.report_peer_at(&peer_id, PeerAction::InvalidBlock, now));
+        assert_eq!(manager.score_at(&peer_id, now), -40.0);
+
+        assert!(manager.report_peer_at(&peer_id, PeerAction::InvalidBlock, now));
+        assert!(manager.is_banned_at(&peer_id, now));
+
+        // further reports do not ban the peer again.
+        assert!(!manager.report_peer_at(&peer_id, PeerAction::InvalidBlock, now));
+    }
+
+    #[test]
+    fn wrong_network_bans_immediately() {
+        let mut manager = PeerManager::new();
+        let peer_id = PeerId::random();
+
+        assert!(manager.report_peer(&peer_id, PeerAction::WrongNetwork));
+        assert!(manager.is_banned(&peer_id));
+        assert_eq!(manager.banned_peers(), vec![peer_id]);
+    }
+
+    #[test]
+    fn bans_expire_after_their_duration() {
+        let mut manager = PeerManager::new();
+        let peer_id = PeerId::random();
+        let now = Instant::now();
+
+        manager.report_peer_at(&peer_id, PeerAction::InvalidBlock, now);
+        manager.ban_at(&peer_id, Duration::from_secs(60), now);
+
+        assert!(manager.is_banned_at(&peer_id, now + Duration::from_secs(59)));
+        assert!(!manager.is_banned_at(&peer_id, now + Duration::from_secs(60)));
+        assert_eq!(manager.score_at(&peer_id, now), 0.0);
+    }
+
+    #[test]
+    fn tracks_connected_peers() {
+        let mut manager = PeerManager::new();
+        let peer_id = PeerId::random();
+
+        manager.peer_connected(&peer_id);
+        manager.peer_connected(&peer_id);
+        assert!(manager.is_connected(&peer_id));
+        assert_eq!(manager.connected_peers(), vec![peer_id.clone()]);
+
+        manager.peer_disconnected(&peer_id);
+        assert!(!manager.is_connected(&peer_id));
+        assert!(manager.connected_peers().is_empty());
+    }
+
+    #[test]
+    fn decayed_faults_are_forgiven() {
+        let mut manager = PeerManager::new();
+        let peer_id = PeerId::random();
+        let now = Instant::now();
+
+        manager.report_peer_at(&peer_id, PeerAction::InvalidBlock, now);
+        manager.report_peer_at(&peer_id, PeerAction::InvalidBlock, now);
+
+        // after a half-life the score has recovered enough to tolerate another fault.
+        let later = now + SCORE_HALF_LIFE;
+        assert!(!manager.report_peer_at(&peer_id, PeerAction::InvalidBlock, later));
+        assert_eq!(manager.score_at(&peer_id, later), -40.0);
+    }
+
+    #[test]
+    fn heartbeat_expires_bans_and_scores() {
+        let mut manager = PeerManager::new();
+        let banned = PeerId::random();
+        let faulty = PeerId::random();
+        let now = Instant::now();
+
+        manager.report_peer_at(&banned, PeerAction::WrongNetwork, now);
+        manager.report_peer_at(&faulty, PeerAction::RequestTimeout, now);
+
+        let later = now + SCORE_HALF_LIFE * 6;
+        manager.heartbeat_at(later);
+
+        assert!(!manager.is_banned_at(&banned, later));
+        assert!(manager.scores.is_empty());
+    }
+}
@@ -1,24 +1,33 @@
 use crate::beacon_chain::BeaconChain;
 use crate::error;
 use crate::message_handler::{HandlerMessage, MessageHandler};
+use crate::peer_manager::PeerManager;
 use crate::NetworkConfig;
 use crossbeam_channel::{unbounded as channel, Sender, TryRecvError};
+use eth2_libp2p::rpc::methods::GoodbyeReason;
 use eth2_libp2p::Service as LibP2PService;
-use eth2_libp2p::{Libp2pEvent, PeerId};
+use eth2_libp2p::{GossipKind, GossipTopic, Libp2pEvent, MessageAcceptance, MessageId, PeerId};
 use eth2_libp2p::{PubsubMessage, RPCEvent};
 use futures::prelude::*;
 use futures::sync::oneshot;
 use futures::Stream;
+use parking_lot::RwLock;
 use slog::{debug, info, o, trace};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 use tokio::runtime::TaskExecutor;
-use types::Topic;
+use tokio::timer::Delay;
+
+/// The time allowed for the `Goodbye` messages sent on shutdown to reach our peers.
+const SHUTDOWN_DELAY: Duration = Duration::from_secs(2);
 
 /// Service that handles communication between internal services and the eth2_libp2p network service.
 pub struct Service {
     //libp2p_service: Arc<Mutex<LibP2PService>>,
     _libp2p_exit: oneshot::Sender<()>,
     network_send: crossbeam_channel::Sender<NetworkMessage>,
+    /// The scores and bans of peers.
+    peer_manager: Arc<RwLock<PeerManager>>,
     //message_handler: MessageHandler,
     //message_handler_send: Sender<HandlerMessage>,
 }
@@ -32,18 +41,26 @@ impl Service {
     ) -> error::Result<(Arc<Self>, Sender<NetworkMessage>)> {
         // build the network channel
         let (network_send, network_recv) = channel::<NetworkMessage>();
+        let peer_manager = Arc::new(RwLock::new(PeerManager::new()));
         // launch message handler thread
         let message_handler_log = log.new(o!("Service" => "MessageHandler"));
+        let fork_version = beacon_chain.fork_version();
         let message_handler_send = MessageHandler::spawn(
             beacon_chain,
             network_send.clone(),
+            peer_manager.clone(),
             executor,
             message_handler_log,
         )?;
 
         // launch libp2p service
         let libp2p_log = log.new(o!("Service" => "Libp2p"));
-        let libp2p_service = LibP2PService::new(config.clone(), libp2p_log)?;
+        let mut libp2p_service = LibP2PService::new(config.clone(), libp2p_log)?;
+
+        // Attestation subnets are subscribed to as validators request their duties.
+        for kind in &[GossipKind::BeaconBlock, GossipKind::BeaconAttestation] {
+            libp2p_service.subscribe(GossipTopic::new(*kind, fork_version));
+        }
 
         // TODO: Spawn thread to handle libp2p messages and pass to message handler thread.
         let libp2p_exit = spawn_service(
@@ -56,11 +73,35 @@ impl Service {
         let network_service = Service {
             _libp2p_exit: libp2p_exit,
             network_send: network_send.clone(),
+            peer_manager,
         };
 
         Ok((Arc::new(network_service), network_send))
     }
 
+    /// Returns the current score of every peer with a non-zero score, lowest first.
+    pub fn peer_scores(&self) -> Vec<(PeerId, f64)> {
+        self.peer_manager.read().scores()
+    }
+
+    /// Returns the peers which are currently banned.
+    pub fn banned_peers(&self) -> Vec<PeerId> {
+        self.peer_manager.read().banned_peers()
+    }
+
+    /// Returns the peers we are connected to, with their current scores.
+    pub fn connected_peers(&self) -> Vec<(PeerId, f64)> {
+        let peer_manager = self.peer_manager.read();
+        peer_manager
+            .connected_peers()
+            .into_iter()
+            .map(|peer_id| {
+                let score = peer_manager.score(&peer_id);
+                (peer_id, score)
+            })
+            .collect()
+    }
+
     // TODO: Testing only
     pub fn send_message(&self) {
         self.network_send
@@ -87,10 +128,9 @@ fn spawn_service(
             libp2p_service,
             network_recv,
             message_handler_send,
+            exit_rx,
             log.clone(),
         )
-        // allow for manual termination
-        .select(exit_rx.then(|_| Ok(())))
         .then(move |_| {
             info!(log.clone(), "Network service shutdown");
             Ok(())
@@ -100,46 +140,55 @@ fn spawn_service(
     Ok(network_exit)
 }
 
+/// Runs the network service until `exit` fires or is dropped.
+///
+/// On exit every connected peer is sent a `Goodbye`, and the swarm is polled for a further
+/// `SHUTDOWN_DELAY` so that the messages are sent.
 //TODO: Potentially handle channel errors
 fn network_service(
     mut libp2p_service: LibP2PService,
     network_recv: crossbeam_channel::Receiver<NetworkMessage>,
     message_handler_send: crossbeam_channel::Sender<HandlerMessage>,
+    mut exit: oneshot::Receiver<()>,
     log: slog::Logger,
 ) -> impl futures::Future<Item = (), Error = eth2_libp2p::error::Error> {
+    let mut shutdown: Option<Delay> = None;
+
     futures::future::poll_fn(move || -> Result<_, eth2_libp2p::error::Error> {
+        // say goodbye to our peers once the service is told to exit
+        match shutdown.as_mut() {
+            None => match exit.poll() {
+                Ok(Async::NotReady) => {}
+                // the exit signal was sent, or the service dropped
+                Ok(Async::Ready(())) | Err(_) => {
+                    info!(log, "Saying goodbye to peers");
+                    libp2p_service
+                        .swarm
+                        .goodbye_peers(GoodbyeReason::ClientShutdown);
+                    let mut delay = Delay::new(Instant::now() + SHUTDOWN_DELAY);
+                    // register interest in the delay, so that we are polled when it elapses
+                    if let Ok(Async::Ready(())) = delay.poll() {
+                        return Ok(Async::Ready(()));
+                    }
+                    shutdown = Some(delay);
+                }
+            },
+            Some(delay) => match delay.poll() {
+                Ok(Async::NotReady) => {}
+                Ok(Async::Ready(())) | Err(_) => return Ok(Async::Ready(())),
+            },
+        }
+
         // poll the swarm
         loop {
             match libp2p_service.poll() {
-                Ok(Async::Ready(Some(event))) => match event {
-                    Libp2pEvent::RPC(peer_id, rpc_event) => {
-                        trace!(log, "RPC Event: RPC message received: {:?}", rpc_event);
-                        message_handler_send
-                            .send(HandlerMessage::RPC(peer_id, rpc_event))
-                            .map_err(|_| "failed to send rpc to handler")?;
-                    }
-                    Libp2pEvent::PeerDialed(peer_id) => {
-                        debug!(log, "Peer Dialed: {:?}", peer_id);
-                        message_handler_send
-                            .send(HandlerMessage::PeerDialed(peer_id))
-                            .map_err(|_| "failed to send rpc to handler")?;
-                    }
-                    Libp2pEvent::Identified(peer_id, info) => {
-                        debug!(
-                            log,
-                            "We have identified peer: {:?} with {:?}", peer_id, info
-                        );
-                    }
-                    Libp2pEvent::PubsubMessage {
-                        source, message, ..
-                    } => {
-                        //TODO: Decide if we need to propagate the topic upwards. (Potentially for
-                        //attestations)
+                Ok(Async::Ready(Some(event))) => {
+                    if let Some(message) = handler_message(event, &log) {
                         message_handler_send
-                            .send(HandlerMessage::PubsubMessage(source, message))
-                            .map_err(|_| " failed to send pubsub message to handler")?;
+                            .send(message)
+                            .map_err(|_| "failed to send message to handler")?;
                     }
-                },
+                }
                 Ok(Async::Ready(None)) => unreachable!("Stream never ends"),
                 Ok(Async::NotReady) => break,
                 Err(_) => break,
@@ -149,24 +198,7 @@ fn network_service(
         // TODO: refactor - combine poll_fn's?
         loop {
             match network_recv.try_recv() {
-                // TODO: Testing message - remove
-                Ok(NetworkMessage::Send(peer_id, outgoing_message)) => {
-                    match outgoing_message {
-                        OutgoingMessage::RPC(rpc_event) => {
-                            trace!(log, "Sending RPC Event: {:?}", rpc_event);
-                            //TODO: Make swarm private
-                            //TODO: Implement correct peer id topic message handling
-                            libp2p_service.swarm.send_rpc(peer_id, rpc_event);
-                        }
-                        OutgoingMessage::NotifierTest => {
-                            // debug!(log, "Received message from notifier");
-                        }
-                    };
-                }
-                Ok(NetworkMessage::Publish { topics, message }) => {
-                    debug!(log, "Sending pubsub message on topics {:?}", topics);
-                    libp2p_service.swarm.publish(topics, *message);
-                }
+                Ok(message) => send_to_libp2p(&mut libp2p_service, message, &log),
                 Err(TryRecvError::Empty) => break,
                 Err(TryRecvError::Disconnected) => {
                     return Err(eth2_libp2p::error::Error::from(
@@ -179,6 +211,116 @@ fn network_service(
     })
 }
 
+/// Converts an event of the libp2p service into a message for the message handler, or `None` if
+/// the handler has no use for it.
+pub fn handler_message(event: Libp2pEvent, log: &slog::Logger) -> Option<HandlerMessage> {
+    match event {
+        Libp2pEvent::RPC(peer_id, rpc_event) => {
+            trace!(log, "RPC Event: RPC message received: {:?}", rpc_event);
+            Some(HandlerMessage::RPC(peer_id, rpc_event))
+        }
+        Libp2pEvent::RequestRejected(peer_id, code) => {
+            Some(HandlerMessage::RequestRejected(peer_id, code))
+        }
+        Libp2pEvent::RequestTimeout {
+            peer_id,
+            id,
+            method_id,
+        } => Some(HandlerMessage::RequestTimeout {
+            peer_id,
+            id,
+            method_id,
+        }),
+        Libp2pEvent::PeerDialed(peer_id) => {
+            debug!(log, "Peer Dialed: {:?}", peer_id);
+            Some(HandlerMessage::PeerDialed(peer_id))
+        }
+        Libp2pEvent::PeerDisconnected(peer_id) => {
+            debug!(log, "Peer Disconnected: {:?}", peer_id);
+            Some(HandlerMessage::PeerDisconnected(peer_id))
+        }
+        Libp2pEvent::Identified(peer_id, info) => {
+            debug!(
+                log,
+                "We have identified peer: {:?} with {:?}", peer_id, info
+            );
+            None
+        }
+        Libp2pEvent::PubsubMessage {
+            id,
+            source,
+            message,
+            ..
+        } => {
+            //TODO: Decide if we need to propagate the topic upwards. (Potentially for
+            //attestations)
+            Some(HandlerMessage::PubsubMessage(id, source, message))
+        }
+        Libp2pEvent::UndecodableGossip(source) => Some(HandlerMessage::UndecodableGossip(source)),
+    }
+}
+
+/// Carries out a message from the message handler or another service on the libp2p service.
+pub fn send_to_libp2p(
+    libp2p_service: &mut LibP2PService,
+    message: NetworkMessage,
+    log: &slog::Logger,
+) {
+    match message {
+        // TODO: Testing message - remove
+        NetworkMessage::Send(peer_id, outgoing_message) => {
+            match outgoing_message {
+                OutgoingMessage::RPC(rpc_event) => {
+                    trace!(log, "Sending RPC Event: {:?}", rpc_event);
+                    //TODO: Make swarm private
+                    //TODO: Implement correct peer id topic message handling
+                    libp2p_service.swarm.send_rpc(peer_id, rpc_event);
+                }
+                OutgoingMessage::NotifierTest => {
+                    // debug!(log, "Received message from notifier");
+                }
+            };
+        }
+        NetworkMessage::Publish { topics, message } => {
+            debug!(log, "Sending pubsub message on topics {:?}", topics);
+            libp2p_service.swarm.publish(topics, *message);
+        }
+        NetworkMessage::Subscribe { topics } => {
+            for topic in topics {
+                libp2p_service.subscribe(topic);
+            }
+        }
+        NetworkMessage::Unsubscribe { topics } => {
+            for topic in topics {
+                libp2p_service.unsubscribe(topic);
+            }
+        }
+        NetworkMessage::Validate {
+            message_id,
+            propagation_source,
+            acceptance,
+        } => {
+            trace!(
+                log, "Gossip message validated";
+                "message_id" => format!("{:?}", message_id),
+                "acceptance" => format!("{:?}", acceptance),
+            );
+            libp2p_service.swarm.report_message_validation_result(
+                &message_id,
+                &propagation_source,
+                acceptance,
+            );
+        }
+        NetworkMessage::Disconnect {
+            peer_id,
+            ban_duration,
+        } => {
+            debug!(log, "Disconnecting peer"; "peer" => format!("{:?}", peer_id));
+            libp2p_service.disconnect_peer(peer_id, ban_duration);
+        }
+    }
+}
+
 /// Types of messages that the network service can receive.
 #[derive(Debug, Clone)]
 pub enum NetworkMessage {
@@ -187,9 +329,25 @@ pub enum NetworkMessage {
     Send(PeerId, OutgoingMessage),
     /// Publish a message to pubsub mechanism.
     Publish {
-        topics: Vec<Topic>,
+        topics: Vec<GossipTopic>,
         message: Box<PubsubMessage>,
     },
+    /// Subscribe to gossip topics, e.g., the attestation subnets of our validators.
+    Subscribe { topics: Vec<GossipTopic> },
+    /// Unsubscribe from gossip topics, e.g., attestation subnets which are no longer needed.
+    Unsubscribe { topics: Vec<GossipTopic> },
+    /// The result of validating a gossip message, which is propagated only if it was accepted.
+    Validate {
+        message_id: MessageId,
+        propagation_source: PeerId,
+        acceptance: MessageAcceptance,
+    },
+    /// Disconnect a peer, once any queued messages have been sent, and ban it for
+    /// `ban_duration` if one is given.
+    Disconnect {
+        peer_id: PeerId,
+        ban_duration: Option<Duration>,
+    },
 }
 
 /// Type of outgoing messages that can be sent through the network service.
@@ -0,0 +1,96 @@
+//! Tracks the attestation subnets our validators need.
+//!
+//! A validator needs the subnet of its shard until the end of the epoch it attests in. Each
+//! request for duties extends the subscriptions of the validator's subnets, and subnets which are
+//! no longer needed by any validator are unsubscribed from.
+use eth2_libp2p::GossipTopic;
+use std::collections::HashMap;
+use types::Epoch;
+
+/// The attestation subnets we are subscribed to, and the last epoch each is needed in.
+#[derive(Default)]
+pub struct SubnetSubscriptions {
+    subscriptions: HashMap<GossipTopic, Epoch>,
+}
+
+impl SubnetSubscriptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records that `topic` is needed until the end of `epoch`.
+    ///
+    /// Returns `true` if we were not already subscribed to `topic`.
+    pub fn subscribe(&mut self, topic: GossipTopic, epoch: Epoch) -> bool {
+        match self.subscriptions.get_mut(&topic) {
+            Some(until) => {
+                *until = std::cmp::max(*until, epoch);
+                false
+            }
+            None => {
+                self.subscriptions.insert(topic, epoch);
+                true
+            }
+        }
+    }
+
+    /// Forgets the subnets which are not needed in `current_epoch` or later, returning them.
+    pub fn expire(&mut self, current_epoch: Epoch) -> Vec<GossipTopic> {
+        let expired: Vec<GossipTopic> = self
+            .subscriptions
+            .iter()
+            .filter(|(_, until)| **until < current_epoch)
+            .map(|(topic, _)| *topic)
+            .collect();
+
+        for topic in &expired {
+            self.subscriptions.remove(topic);
+        }
+
+        expired
+    }
+
+    /// Returns `true` if we are subscribed to `topic`.
+    pub fn is_subscribed(&self, topic: &GossipTopic) -> bool {
+        self.subscriptions.contains_key(topic)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn subnet(shard: u64) -> GossipTopic {
+        GossipTopic::attestation_subnet(shard, [0; 4])
+    }
+
+    #[test]
+    fn subnets_are_subscribed_to_once() {
+        let mut subscriptions = SubnetSubscriptions::new();
+
+        assert!(subscriptions.subscribe(subnet(1), Epoch::new(3)));
+        assert!(!subscriptions.subscribe(subnet(1), Epoch::new(3)));
+        assert!(subscriptions.subscribe(subnet(2), Epoch::new(3)));
+        assert!(subscriptions.is_subscribed(&subnet(1)));
+    }
+
+    #[test]
+    fn subnets_expire_after_their_last_duty() {
+        let mut subscriptions = SubnetSubscriptions::new();
+        subscriptions.subscribe(subnet(1), Epoch::new(3));
+        subscriptions.subscribe(subnet(2), Epoch::new(3));
+        // a later duty extends the subscription.
+        subscriptions.subscribe(subnet(2), Epoch::new(4));
+
+        assert!(subscriptions.expire(Epoch::new(3)).is_empty());
+        assert_eq!(subscriptions.expire(Epoch::new(4)), vec![subnet(1)]);
+        assert!(!subscriptions.is_subscribed(&subnet(1)));
+        assert!(subscriptions.is_subscribed(&subnet(2)));
+
+        assert_eq!(subscriptions.expire(Epoch::new(5)), vec![subnet(2)]);
+        assert!(subscriptions.expire(Epoch::new(6)).is_empty());
+
+        // an expired subnet is subscribed to again when it is needed.
+        assert!(subscriptions.subscribe(subnet(1), Epoch::new(6)));
+    }
+}
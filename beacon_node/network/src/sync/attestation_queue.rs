@@ -0,0 +1,418 @@
+use eth2_libp2p::rpc::RequestId;
+use eth2_libp2p::PeerId;
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, Instant};
+use types::{Attestation, BeaconBlock, Hash256};
+
+/// The maximum number of attestations held in the queue. Further attestations are dropped.
+const MAX_QUEUED_ATTESTATIONS: usize = 1_024;
+/// The maximum number of queued attestations received from a single peer, so that one peer cannot
+/// fill the queue.
+const MAX_QUEUED_ATTESTATIONS_PER_PEER: usize = 128;
+/// The time an attestation is held for before it is dropped.
+const QUEUED_ATTESTATION_EXPIRY: Duration = Duration::from_secs(30);
+/// The time we wait for a block to arrive via gossip before requesting it by root.
+const BLOCK_LOOKUP_DELAY: Duration = Duration::from_secs(4);
+
+/// The progress of fetching a block which queued attestations vote for.
+#[derive(Debug, Clone, PartialEq)]
+enum Lookup {
+    /// The block has not been requested.
+    NotRequested,
+    /// The block has been requested, and the request is outstanding.
+    Requested {
+        peer_id: PeerId,
+        request_id: RequestId,
+    },
+    /// The block has been received in response to a request. The block is not requested again.
+    Responded,
+}
+
+/// The queued attestations which vote for a single unknown block.
+struct QueuedBlockVotes {
+    /// The attestations, with the peer each was received from.
+    attestations: Vec<(Attestation, PeerId)>,
+    /// The time the first attestation was queued.
+    first_queued: Instant,
+    lookup: Lookup,
+    /// Peers which failed to provide the block. It is requested from other peers instead.
+    failed_peers: HashSet<PeerId>,
+}
+
+/// Holds gossip attestations whose `beacon_block_root` is unknown, which commonly arrive shortly
+/// before the block they vote for.
+///
+/// The attestations are processed once the block is imported. If it has not arrived via gossip
+/// within `BLOCK_LOOKUP_DELAY`, the block is requested by root from a peer which sent one of its
+/// attestations, trying each such peer in turn until one provides it. Attestations are dropped
+/// after `QUEUED_ATTESTATION_EXPIRY`, and no more than `MAX_QUEUED_ATTESTATIONS` are held, of
+/// which at most `MAX_QUEUED_ATTESTATIONS_PER_PEER` are from any one peer.
+#[derive(Default)]
+pub struct AttestationQueue {
+    /// The queued attestations, by the root of the block they vote for.
+    blocks: HashMap<Hash256, QueuedBlockVotes>,
+    /// The number of queued attestations.
+    len: usize,
+    /// The number of queued attestations from each peer.
+    peer_counts: HashMap<PeerId, usize>,
+}
+
+impl AttestationQueue {
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Queues an attestation from `peer_id`, returning `false` if the queue is full or `peer_id`
+    /// has used up its share of it.
+    pub fn enqueue(&mut self, attestation: Attestation, peer_id: PeerId, now: Instant) -> bool {
+        let peer_count = self.peer_counts.get(&peer_id).cloned().unwrap_or(0);
+        if self.len >= MAX_QUEUED_ATTESTATIONS || peer_count >= MAX_QUEUED_ATTESTATIONS_PER_PEER {
+            return false;
+        }
+
+        self.peer_counts.insert(peer_id.clone(), peer_count + 1);
+        self.blocks
+            .entry(attestation.data.beacon_block_root)
+            .or_insert_with(|| QueuedBlockVotes {
+                attestations: vec![],
+                first_queued: now,
+                lookup: Lookup::NotRequested,
+                failed_peers: HashSet::new(),
+            })
+            .attestations
+            .push((attestation, peer_id));
+        self.len += 1;
+
+        true
+    }
+
+    /// Removes and returns the attestations for each block for which `is_known` is `true`.
+    pub fn take_known<F>(&mut self, is_known: F) -> Vec<(Attestation, PeerId)>
+    where
+        F: Fn(&Hash256) -> bool,
+    {
+        let known: Vec<Hash256> = self
+            .blocks
+            .keys()
+            .filter(|block_root| is_known(block_root))
+            .cloned()
+            .collect();
+
+        let mut attestations = vec![];
+        for block_root in known {
+            if let Some(votes) = self.blocks.remove(&block_root) {
+                attestations.extend(votes.attestations);
+            }
+        }
+        self.forget(&attestations);
+
+        attestations
+    }
+
+    /// Drops the attestations for blocks which were first queued more than
+    /// `QUEUED_ATTESTATION_EXPIRY` before `now`, returning the number dropped.
+    pub fn remove_expired(&mut self, now: Instant) -> usize {
+        let expired: Vec<Hash256> = self
+            .blocks
+            .iter()
+            .filter(|(_, votes)| {
+                now.duration_since(votes.first_queued) >= QUEUED_ATTESTATION_EXPIRY
+            })
+            .map(|(block_root, _)| *block_root)
+            .collect();
+
+        let mut attestations = vec![];
+        for block_root in expired {
+            if let Some(votes) = self.blocks.remove(&block_root) {
+                attestations.extend(votes.attestations);
+            }
+        }
+        self.forget(&attestations);
+
+        attestations.len()
+    }
+
+    /// Updates the counts of queued attestations for `attestations`, which have been removed.
+    fn forget(&mut self, attestations: &[(Attestation, PeerId)]) {
+        self.len -= attestations.len();
+        for (_, peer_id) in attestations {
+            if let Some(count) = self.peer_counts.get_mut(peer_id) {
+                *count -= 1;
+                if *count == 0 {
+                    self.peer_counts.remove(peer_id);
+                }
+            }
+        }
+    }
+
+    /// Returns each block which has not been requested and has been awaited for at least
+    /// `BLOCK_LOOKUP_DELAY`, along with the latest peer to send one of its attestations which has
+    /// not failed to provide it. The caller is responsible for requesting them and calling
+    /// `on_block_requested`.
+    pub fn blocks_to_request(&self, now: Instant) -> Vec<(Hash256, PeerId)> {
+        self.blocks
+            .iter()
+            .filter(|(_, votes)| {
+                votes.lookup == Lookup::NotRequested
+                    && now.duration_since(votes.first_queued) >= BLOCK_LOOKUP_DELAY
+            })
+            .filter_map(|(block_root, votes)| {
+                let peer_id = votes
+                    .attestations
+                    .iter()
+                    .rev()
+                    .map(|(_, peer_id)| peer_id)
+                    .find(|peer_id| !votes.failed_peers.contains(peer_id))?;
+                Some((*block_root, peer_id.clone()))
+            })
+            .collect()
+    }
+
+    /// Records that the block with `block_root` has been requested from `peer_id`.
+    pub fn on_block_requested(
+        &mut self,
+        block_root: &Hash256,
+        peer_id: PeerId,
+        request_id: RequestId,
+    ) {
+        if let Some(votes) = self.blocks.get_mut(block_root) {
+            votes.lookup = Lookup::Requested {
+                peer_id,
+                request_id,
+            };
+        }
+    }
+
+    /// Handles the response to a block request, returning `false` if the request was not made by
+    /// the queue.
+    ///
+    /// If `blocks` does not contain the requested block, it is requested again from another peer.
+    pub fn on_block_response(
+        &mut self,
+        peer_id: &PeerId,
+        request_id: &RequestId,
+        blocks: &[BeaconBlock],
+    ) -> bool {
+        match self.find_request(peer_id, request_id) {
+            Some((block_root, votes)) => {
+                if blocks
+                    .iter()
+                    .any(|block| block.canonical_root() == *block_root)
+                {
+                    votes.lookup = Lookup::Responded;
+                } else {
+                    votes.failed_peers.insert(peer_id.clone());
+                    votes.lookup = Lookup::NotRequested;
+                }
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Handles a block request which failed, so that the block is requested again from another
+    /// peer. Returns `false` if the request was not made by the queue.
+    pub fn on_request_failed(&mut self, peer_id: &PeerId, request_id: &RequestId) -> bool {
+        match self.find_request(peer_id, request_id) {
+            Some((_, votes)) => {
+                votes.failed_peers.insert(peer_id.clone());
+                votes.lookup = Lookup::NotRequested;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Handles the disconnection of `peer_id`. Blocks requested from it are requested again from
+    /// another peer, as the requests will never be responded to.
+    pub fn remove_peer(&mut self, peer_id: &PeerId) {
+        for votes in self.blocks.values_mut() {
+            if let Lookup::Requested {
+                peer_id: requested_from,
+                ..
+            } = &votes.lookup
+            {
+                if requested_from == peer_id {
+                    votes.lookup = Lookup::NotRequested;
+                }
+            }
+            votes.failed_peers.insert(peer_id.clone());
+        }
+    }
+
+    /// Returns the block which was requested with `request_id` from `peer_id`.
+    fn find_request(
+        &mut self,
+        peer_id: &PeerId,
+        request_id: &RequestId,
+    ) -> Option<(&Hash256, &mut QueuedBlockVotes)> {
+        let request = Lookup::Requested {
+            peer_id: peer_id.clone(),
+            request_id: request_id.clone(),
+        };
+
+        self.blocks
+            .iter_mut()
+            .find(|(_, votes)| votes.lookup == request)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use types::test_utils::{SeedableRng, TestRandom, XorShiftRng};
+    use types::ChainSpec;
+
+    fn attestation(block_root: u64) -> Attestation {
+        let mut rng = XorShiftRng::from_seed([42; 16]);
+        let mut attestation = Attestation::random_for_test(&mut rng);
+        attestation.data.beacon_block_root = Hash256::from_low_u64_be(block_root);
+        attestation
+    }
+
+    #[test]
+    fn attestations_are_released_once_their_block_is_known() {
+        let mut queue = AttestationQueue::default();
+        let now = Instant::now();
+
+        assert!(queue.enqueue(attestation(1), PeerId::random(), now));
+        assert!(queue.enqueue(attestation(1), PeerId::random(), now));
+        assert!(queue.enqueue(attestation(2), PeerId::random(), now));
+        assert_eq!(queue.len(), 3);
+
+        assert!(queue.take_known(|_| false).is_empty());
+
+        let released = queue.take_known(|root| *root == Hash256::from_low_u64_be(1));
+        assert_eq!(released.len(), 2);
+        assert_eq!(queue.len(), 1);
+    }
+
+    #[test]
+    fn queue_is_bounded_and_expires() {
+        let mut queue = AttestationQueue::default();
+        let now = Instant::now();
+
+        for i in 0..MAX_QUEUED_ATTESTATIONS {
+            assert!(queue.enqueue(attestation(i as u64), PeerId::random(), now));
+        }
+        assert!(!queue.enqueue(attestation(0), PeerId::random(), now));
+
+        assert_eq!(queue.remove_expired(now), 0);
+        assert_eq!(
+            queue.remove_expired(now + QUEUED_ATTESTATION_EXPIRY),
+            MAX_QUEUED_ATTESTATIONS
+        );
+        assert!(queue.is_empty());
+        assert!(queue.peer_counts.is_empty());
+    }
+
+    #[test]
+    fn a_single_peer_cannot_fill_the_queue() {
+        let mut queue = AttestationQueue::default();
+        let now = Instant::now();
+        let peer_id = PeerId::random();
+
+        for i in 0..MAX_QUEUED_ATTESTATIONS_PER_PEER {
+            assert!(queue.enqueue(attestation(i as u64), peer_id.clone(), now));
+        }
+        assert!(!queue.enqueue(attestation(0), peer_id.clone(), now));
+        assert!(queue.enqueue(attestation(0), PeerId::random(), now));
+
+        // the peer may queue attestations again once its earlier ones are released.
+        queue.take_known(|root| *root == Hash256::from_low_u64_be(1));
+        assert!(queue.enqueue(attestation(1), peer_id, now));
+    }
+
+    #[test]
+    fn blocks_are_requested_after_a_delay() {
+        let mut queue = AttestationQueue::default();
+        let now = Instant::now();
+        let peer_id = PeerId::random();
+        let block_root = Hash256::from_low_u64_be(1);
+
+        queue.enqueue(attestation(1), peer_id.clone(), now);
+        assert!(queue.blocks_to_request(now).is_empty());
+
+        let later = now + BLOCK_LOOKUP_DELAY;
+        assert_eq!(
+            queue.blocks_to_request(later),
+            vec![(block_root, peer_id.clone())]
+        );
+
+        queue.on_block_requested(&block_root, peer_id.clone(), RequestId::from(5));
+        assert!(queue.blocks_to_request(later).is_empty());
+    }
+
+    #[test]
+    fn blocks_are_requested_from_another_peer_until_received() {
+        let mut queue = AttestationQueue::default();
+        let now = Instant::now();
+        let later = now + BLOCK_LOOKUP_DELAY;
+        let (first, second, third) = (PeerId::random(), PeerId::random(), PeerId::random());
+
+        let block = BeaconBlock::empty(&ChainSpec::foundation());
+        let block_root = block.canonical_root();
+        let mut vote = attestation(0);
+        vote.data.beacon_block_root = block_root;
+
+        queue.enqueue(vote.clone(), first.clone(), now);
+        queue.enqueue(vote.clone(), second.clone(), now);
+        queue.enqueue(vote, third.clone(), now);
+
+        // the latest peer is tried first.
+        assert_eq!(
+            queue.blocks_to_request(later),
+            vec![(block_root, third.clone())]
+        );
+        queue.on_block_requested(&block_root, third.clone(), RequestId::from(1));
+
+        // a failed request is retried with another peer.
+        assert!(!queue.on_request_failed(&third, &RequestId::from(2)));
+        assert!(queue.on_request_failed(&third, &RequestId::from(1)));
+        assert_eq!(
+            queue.blocks_to_request(later),
+            vec![(block_root, second.clone())]
+        );
+        queue.on_block_requested(&block_root, second.clone(), RequestId::from(1));
+
+        // as is an empty response.
+        assert!(queue.on_block_response(&second, &RequestId::from(1), &[]));
+        assert_eq!(
+            queue.blocks_to_request(later),
+            vec![(block_root, first.clone())]
+        );
+        queue.on_block_requested(&block_root, first.clone(), RequestId::from(1));
+
+        // the block is not requested again once it has been received.
+        assert!(queue.on_block_response(&first, &RequestId::from(1), &[block]));
+        assert!(queue.blocks_to_request(later).is_empty());
+    }
+
+    #[test]
+    fn blocks_requested_from_a_disconnected_peer_are_requested_again() {
+        let mut queue = AttestationQueue::default();
+        let now = Instant::now();
+        let later = now + BLOCK_LOOKUP_DELAY;
+        let (first, second) = (PeerId::random(), PeerId::random());
+        let block_root = Hash256::from_low_u64_be(1);
+
+        queue.enqueue(attestation(1), first.clone(), now);
+        queue.enqueue(attestation(1), second.clone(), now);
+        queue.on_block_requested(&block_root, second.clone(), RequestId::from(1));
+        assert!(queue.blocks_to_request(later).is_empty());
+
+        queue.remove_peer(&second);
+        assert_eq!(
+            queue.blocks_to_request(later),
+            vec![(block_root, first.clone())]
+        );
+
+        // a disconnected peer which was not asked for the block is not asked later.
+        queue.remove_peer(&first);
+        assert!(queue.blocks_to_request(later).is_empty());
+    }
+}
@@ -0,0 +1,325 @@
+use crate::beacon_chain::BeaconChain;
+use eth2_libp2p::MessageAcceptance;
+use slog::{debug, error};
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use tree_hash::SignedRoot;
+use types::{Attestation, BeaconBlock, BeaconStateError, Domain, Hash256, Slot};
+
+/// If a gossip message is more than `FUTURE_SLOT_TOLERANCE` slots ahead of our slot clock, it is
+/// ignored.
+const FUTURE_SLOT_TOLERANCE: u64 = 1;
+
+/// Records the gossip messages which have already been validated, so that duplicates are ignored.
+///
+/// Blocks are recorded by root and by `(proposer_index, slot)`, attestations by
+/// `(validator_index, slot)` for each participating validator.
+#[derive(Default)]
+pub struct SeenCache {
+    block_roots: HashMap<Hash256, Slot>,
+    block_proposers: HashSet<(usize, Slot)>,
+    attesters: HashSet<(usize, Slot)>,
+}
+
+impl SeenCache {
+    /// Returns `true` if a block with `block_root` has been recorded.
+    pub fn has_seen_block_root(&self, block_root: &Hash256) -> bool {
+        self.block_roots.contains_key(block_root)
+    }
+
+    /// Records a block, returning `false` if a block with the same root, or from the same proposer
+    /// at the same slot, has already been recorded.
+    pub fn observe_block(
+        &mut self,
+        block_root: Hash256,
+        proposer_index: usize,
+        slot: Slot,
+    ) -> bool {
+        if self.has_seen_block_root(&block_root)
+            || !self.block_proposers.insert((proposer_index, slot))
+        {
+            return false;
+        }
+
+        self.block_roots.insert(block_root, slot);
+        true
+    }
+
+    /// Records an attestation by `attesters` at `slot`, returning `false` if every one of them has
+    /// already been recorded at `slot`.
+    pub fn observe_attesters(&mut self, attesters: &[usize], slot: Slot) -> bool {
+        attesters.iter().fold(false, |is_new, &validator_index| {
+            self.attesters.insert((validator_index, slot)) || is_new
+        })
+    }
+
+    /// Forgets the blocks with a slot lower than `block_slot`, and the attestations with a slot
+    /// lower than `attestation_slot`.
+    pub fn prune(&mut self, block_slot: Slot, attestation_slot: Slot) {
+        self.block_roots.retain(|_, slot| *slot >= block_slot);
+        self.block_proposers.retain(|(_, slot)| *slot >= block_slot);
+        self.attesters.retain(|(_, slot)| *slot >= attestation_slot);
+    }
+}
+
+/// A fast check of gossip messages, performed before they are propagated to our peers.
+///
+/// Only the slot, the proposer signature and whether the message has been seen before are
+/// checked. Messages which are accepted must still be fully processed by the chain.
+pub struct GossipValidator {
+    chain: Arc<BeaconChain>,
+    seen: SeenCache,
+    log: slog::Logger,
+}
+
+impl GossipValidator {
+    pub fn new(chain: Arc<BeaconChain>, log: slog::Logger) -> Self {
+        Self {
+            chain,
+            seen: SeenCache::default(),
+            log,
+        }
+    }
+
+    /// Validates a gossip block.
+    ///
+    /// Blocks from a finalized or distant future slot, or which have been seen before, are
+    /// ignored. Blocks without a valid proposer signature are rejected.
+    pub fn validate_block(&mut self, block: &BeaconBlock) -> MessageAcceptance {
+        self.prune();
+
+        let present_slot = self.chain.slot();
+        if block.slot > present_slot + FUTURE_SLOT_TOLERANCE || block.slot <= self.finalized_slot()
+        {
+            debug!(
+                self.log, "GossipBlockIgnored";
+                "msg" => "outside of slot window",
+                "present_slot" => present_slot,
+                "block_slot" => block.slot,
+            );
+            return MessageAcceptance::Ignore;
+        }
+
+        let block_root = block.canonical_root();
+        if self.seen.has_seen_block_root(&block_root) || !self.is_new_block_root(&block_root) {
+            return MessageAcceptance::Ignore;
+        }
+
+        // The proposer is only known for slots in the epoch of our present state.
+        let proposer_index = match self.chain.block_proposer(block.slot) {
+            Ok(index) => index,
+            Err(e) => {
+                debug!(
+                    self.log, "GossipBlockIgnored";
+                    "msg" => "unable to determine proposer",
+                    "block_slot" => block.slot,
+                    "error" => format!("{:?}", e),
+                );
+                return MessageAcceptance::Ignore;
+            }
+        };
+
+        if !self.has_valid_proposer_signature(block, proposer_index) {
+            debug!(
+                self.log, "GossipBlockRejected";
+                "msg" => "invalid proposer signature",
+                "block_slot" => block.slot,
+                "proposer_index" => proposer_index,
+            );
+            return MessageAcceptance::Reject;
+        }
+
+        // Only the first block from a proposer at each slot is propagated.
+        if !self
+            .seen
+            .observe_block(block_root, proposer_index, block.slot)
+        {
+            return MessageAcceptance::Ignore;
+        }
+
+        MessageAcceptance::Accept
+    }
+
+    /// Validates a gossip attestation.
+    ///
+    /// Attestations from outside of the last epoch, or whose attesters have all attested at the
+    /// same slot before, are ignored. Attestations without a valid set of attesters are rejected.
+    pub fn validate_attestation(&mut self, attestation: &Attestation) -> MessageAcceptance {
+        self.prune();
+
+        let present_slot = self.chain.slot();
+        let attestation_slot = attestation.data.slot;
+        if attestation_slot > present_slot + FUTURE_SLOT_TOLERANCE
+            || attestation_slot < self.earliest_attestation_slot()
+        {
+            debug!(
+                self.log, "GossipAttestationIgnored";
+                "msg" => "outside of slot window",
+                "present_slot" => present_slot,
+                "attestation_slot" => attestation_slot,
+            );
+            return MessageAcceptance::Ignore;
+        }
+
+        let attesters = match self.attesters(attestation) {
+            Ok(attesters) => attesters,
+            Err(BeaconStateError::NoCommitteeForShard) | Err(BeaconStateError::InvalidBitfield) => {
+                return MessageAcceptance::Reject;
+            }
+            Err(e) => {
+                debug!(
+                    self.log, "GossipAttestationIgnored";
+                    "msg" => "unable to determine committee",
+                    "attestation_slot" => attestation_slot,
+                    "error" => format!("{:?}", e),
+                );
+                return MessageAcceptance::Ignore;
+            }
+        };
+
+        if attesters.is_empty() {
+            return MessageAcceptance::Reject;
+        }
+
+        if !self.seen.observe_attesters(&attesters, attestation_slot) {
+            return MessageAcceptance::Ignore;
+        }
+
+        MessageAcceptance::Accept
+    }
+
+    /// Returns `true` if `block` is signed by the validator with `proposer_index`.
+    fn has_valid_proposer_signature(&self, block: &BeaconBlock, proposer_index: usize) -> bool {
+        let spec = self.chain.get_spec();
+        let state = self.chain.get_state();
+
+        let proposer = match state.validator_registry.get(proposer_index) {
+            Some(proposer) => proposer,
+            None => return false,
+        };
+
+        let domain = spec.get_domain(
+            block.slot.epoch(spec.slots_per_epoch),
+            Domain::BeaconBlock,
+            &state.fork,
+        );
+
+        block
+            .signature
+            .verify(&block.signed_root()[..], domain, &proposer.pubkey)
+    }
+
+    /// Returns the indices of the validators which participated in `attestation`.
+    ///
+    /// Returns `InvalidBitfield` if bits are set for validators outside of the committee.
+    fn attesters(&self, attestation: &Attestation) -> Result<Vec<usize>, BeaconStateError> {
+        let spec = self.chain.get_spec();
+        let state = self.chain.get_state();
+        let data = &attestation.data;
+
+        let crosslink_committee = state.get_crosslink_committee_for_shard(
+            data.slot.epoch(spec.slots_per_epoch),
+            data.shard,
+            spec,
+        )?;
+
+        if crosslink_committee.slot != data.slot {
+            return Err(BeaconStateError::NoCommitteeForShard);
+        }
+
+        let bitfield = &attestation.aggregation_bitfield;
+        let attesters: Vec<usize> = crosslink_committee
+            .committee
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| bitfield.get(*i).unwrap_or(false))
+            .map(|(_, validator_index)| *validator_index)
+            .collect();
+
+        if attesters.len() != bitfield.num_set_bits() {
+            return Err(BeaconStateError::InvalidBitfield);
+        }
+
+        Ok(attesters)
+    }
+
+    /// Forgets messages which are now outside of the slot window.
+    fn prune(&mut self) {
+        let finalized_slot = self.finalized_slot();
+        let earliest_attestation_slot = self.earliest_attestation_slot();
+        self.seen.prune(finalized_slot, earliest_attestation_slot);
+    }
+
+    /// The start slot of the finalized epoch.
+    fn finalized_slot(&self) -> Slot {
+        self.chain
+            .finalized_epoch()
+            .start_slot(self.chain.get_spec().slots_per_epoch)
+    }
+
+    /// The lowest slot of an attestation which is not ignored.
+    fn earliest_attestation_slot(&self) -> Slot {
+        self.chain.slot() - self.chain.get_spec().slots_per_epoch
+    }
+
+    /// Returns `true` if `self.chain` has not yet processed this block.
+    fn is_new_block_root(&self, block_root: &Hash256) -> bool {
+        self.chain
+            .is_new_block_root(block_root)
+            .unwrap_or_else(|_| {
+                error!(self.log, "Unable to determine if block is new.");
+                true
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn blocks_are_seen_by_root_and_proposer() {
+        let mut seen = SeenCache::default();
+        let slot = Slot::new(10);
+
+        assert!(seen.observe_block(Hash256::from_low_u64_be(1), 3, slot));
+        assert!(seen.has_seen_block_root(&Hash256::from_low_u64_be(1)));
+        // The same block again.
+        assert!(!seen.observe_block(Hash256::from_low_u64_be(1), 3, slot));
+        // A different block from the same proposer and slot.
+        assert!(!seen.observe_block(Hash256::from_low_u64_be(2), 3, slot));
+        assert!(!seen.has_seen_block_root(&Hash256::from_low_u64_be(2)));
+        // A different proposer at the same slot.
+        assert!(seen.observe_block(Hash256::from_low_u64_be(3), 4, slot));
+    }
+
+    #[test]
+    fn attestations_are_seen_by_validator_and_slot() {
+        let mut seen = SeenCache::default();
+
+        assert!(seen.observe_attesters(&[1, 2], Slot::new(10)));
+        assert!(!seen.observe_attesters(&[1], Slot::new(10)));
+        assert!(!seen.observe_attesters(&[2, 1], Slot::new(10)));
+        // An aggregate including a new attester.
+        assert!(seen.observe_attesters(&[1, 2, 3], Slot::new(10)));
+        // The same attester at another slot.
+        assert!(seen.observe_attesters(&[1], Slot::new(11)));
+    }
+
+    #[test]
+    fn prune_forgets_old_slots() {
+        let mut seen = SeenCache::default();
+        seen.observe_block(Hash256::from_low_u64_be(1), 1, Slot::new(5));
+        seen.observe_block(Hash256::from_low_u64_be(2), 2, Slot::new(15));
+        seen.observe_attesters(&[1], Slot::new(5));
+        seen.observe_attesters(&[1], Slot::new(15));
+
+        seen.prune(Slot::new(10), Slot::new(10));
+
+        assert!(!seen.has_seen_block_root(&Hash256::from_low_u64_be(1)));
+        assert!(seen.has_seen_block_root(&Hash256::from_low_u64_be(2)));
+        assert!(seen.observe_block(Hash256::from_low_u64_be(3), 1, Slot::new(5)));
+        assert!(seen.observe_attesters(&[1], Slot::new(5)));
+        assert!(!seen.observe_attesters(&[1], Slot::new(15)));
+    }
+}
@@ -1,8 +1,15 @@
+mod attestation_queue;
+mod gossip_validation;
 mod import_queue;
+mod parent_lookup;
+mod range_sync;
 /// Syncing for lighthouse.
 ///
 /// Stores the various syncing methods for the beacon chain.
 mod simple_sync;
+mod state_download;
+#[cfg(test)]
+mod test_utils;
 
 pub use simple_sync::SimpleSync;
 
@@ -0,0 +1,474 @@
+use crate::beacon_chain::BeaconChain;
+use crate::message_handler::NetworkContext;
+use crate::peer_manager::PeerAction;
+use eth2_libp2p::rpc::methods::*;
+use eth2_libp2p::rpc::{RPCRequest, RequestId};
+use eth2_libp2p::PeerId;
+use slog::{debug, error, info, warn};
+use std::sync::Arc;
+use types::{BeaconBlock, Hash256};
+
+/// The maximum number of ancestors fetched for a single block. Blocks further behind our chain
+/// than this are left for range sync.
+const PARENT_DEPTH_TOLERANCE: usize = 32;
+
+/// The number of times a parent is requested before the lookup is abandoned.
+const MAX_PARENT_ATTEMPTS: usize = 3;
+
+/// The maximum number of lookups in progress at any time.
+const MAX_PARENT_LOOKUPS: usize = 16;
+
+/// The search for the unknown ancestors of a single block.
+struct ParentLookup {
+    /// The block with an unknown parent, followed by each of its downloaded ancestors. The parent
+    /// of the last block is being requested.
+    blocks: Vec<BeaconBlock>,
+    /// The peer which sent the original block, which is expected to have its ancestors.
+    peer_id: PeerId,
+    /// The outstanding request for the parent of the last block.
+    request_id: RequestId,
+    /// The number of times the current parent has been requested.
+    attempts: usize,
+}
+
+impl ParentLookup {
+    /// The root of the block being requested.
+    fn parent_root(&self) -> Hash256 {
+        self.blocks
+            .last()
+            .expect("a lookup has at least one block")
+            .previous_block_root
+    }
+
+    /// Returns `true` if `block_root` is the root of a block in the lookup, or of the block being
+    /// requested.
+    fn contains(&self, block_root: &Hash256) -> bool {
+        self.parent_root() == *block_root
+            || self
+                .blocks
+                .iter()
+                .any(|block| block.canonical_root() == *block_root)
+    }
+}
+
+/// Fetches the unknown ancestors of gossip blocks.
+///
+/// When a gossip block has an unknown parent, the parent is requested by root from the peer which
+/// sent the block. This is repeated for each ancestor until one builds upon a block we know, or
+/// `PARENT_DEPTH_TOLERANCE` ancestors have been fetched. The ancestors and the block are then
+/// imported, oldest first. Lookups which exceed the tolerance are left to range sync.
+///
+/// A parent which is not provided after `MAX_PARENT_ATTEMPTS` requests causes the lookup to be
+/// abandoned, and the peer to be penalised.
+pub struct ParentLookups {
+    chain: Arc<BeaconChain>,
+    lookups: Vec<ParentLookup>,
+    log: slog::Logger,
+}
+
+impl ParentLookups {
+    pub fn new(chain: Arc<BeaconChain>, log: slog::Logger) -> Self {
+        Self {
+            chain,
+            lookups: vec![],
+            log,
+        }
+    }
+
+    /// Returns `true` if `block_root` is part of, or is being requested by, a lookup in progress.
+    pub fn is_looking_up(&self, block_root: &Hash256) -> bool {
+        self.lookups
+            .iter()
+            .any(|lookup| lookup.contains(block_root))
+    }
+
+    /// Starts looking up the ancestors of `block`, which was sent by `peer_id`.
+    ///
+    /// Returns the block if there are too many lookups in progress to start another.
+    pub fn start(
+        &mut self,
+        block: BeaconBlock,
+        peer_id: PeerId,
+        network: &mut NetworkContext,
+    ) -> Result<(), BeaconBlock> {
+        if self.lookups.len() >= MAX_PARENT_LOOKUPS {
+            debug!(
+                self.log, "ParentLookupRejected";
+                "msg" => "too many lookups",
+                "block_slot" => block.slot,
+            );
+            return Err(block);
+        }
+
+        let request_id = request_parent(peer_id.clone(), block.previous_block_root, network);
+
+        debug!(
+            self.log, "ParentLookupStarted";
+            "peer" => format!("{:?}", peer_id),
+            "block_slot" => block.slot,
+            "parent_root" => format!("{}", block.previous_block_root),
+        );
+
+        self.lookups.push(ParentLookup {
+            blocks: vec![block],
+            peer_id,
+            request_id,
+            attempts: 1,
+        });
+
+        Ok(())
+    }
+
+    /// Abandons the lookups of `peer_id`, which is no longer able to provide the ancestors.
+    pub fn remove_peer(&mut self, peer_id: &PeerId) {
+        self.lookups.retain(|lookup| lookup.peer_id != *peer_id);
+    }
+
+    /// Handles a request which was responded to with an error, or not responded to at all.
+    ///
+    /// Returns `false` if the request was not made by a lookup.
+    pub fn on_request_failed(
+        &mut self,
+        peer_id: &PeerId,
+        request_id: &RequestId,
+        network: &mut NetworkContext,
+    ) -> bool {
+        match self.lookup_index(peer_id, request_id) {
+            Some(index) => {
+                self.retry(index, network);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Handles a `BeaconBlocksByRoot` response, requesting the next ancestor or importing the
+    /// blocks of the lookup.
+    ///
+    /// Returns `false` if the request was not made by a lookup.
+    pub fn on_blocks_by_root_response(
+        &mut self,
+        peer_id: &PeerId,
+        request_id: &RequestId,
+        res: BeaconBlocksResponse,
+        network: &mut NetworkContext,
+    ) -> bool {
+        let index = match self.lookup_index(peer_id, request_id) {
+            Some(index) => index,
+            None => return false,
+        };
+
+        let parent_root = self.lookups[index].parent_root();
+        let parent = match res
+            .blocks
+            .into_iter()
+            .find(|block| block.canonical_root() == parent_root)
+        {
+            Some(parent) => parent,
+            None => {
+                debug!(
+                    self.log, "ParentLookupMissingBlock";
+                    "peer" => format!("{:?}", peer_id),
+                    "parent_root" => format!("{}", parent_root),
+                );
+                self.retry(index, network);
+                return true;
+            }
+        };
+
+        let builds_on_chain = !self.is_new_block_root(&parent.previous_block_root);
+        self.lookups[index].blocks.push(parent);
+
+        if builds_on_chain {
+            // The oldest block builds upon our chain, the lookup is complete.
+            let lookup = self.lookups.remove(index);
+            self.import(lookup, network);
+        } else if self.lookups[index].blocks.len() > PARENT_DEPTH_TOLERANCE {
+            let lookup = self.lookups.remove(index);
+            info!(
+                self.log, "ParentLookupAbandoned";
+                "msg" => "too many ancestors, syncing from peer",
+                "peer" => format!("{:?}", lookup.peer_id),
+                "depth" => lookup.blocks.len(),
+            );
+            // The peer's `Hello` response starts range sync if it is ahead of us.
+            network.send_rpc_request(
+                lookup.peer_id,
+                RPCRequest::Hello(self.chain.hello_message()),
+            );
+        } else {
+            let lookup = &mut self.lookups[index];
+            lookup.request_id =
+                request_parent(lookup.peer_id.clone(), lookup.parent_root(), network);
+            lookup.attempts = 1;
+        }
+
+        true
+    }
+
+    /// Returns the index of the lookup awaiting the response to `request_id` from `peer_id`.
+    fn lookup_index(&self, peer_id: &PeerId, request_id: &RequestId) -> Option<usize> {
+        self.lookups
+            .iter()
+            .position(|lookup| lookup.peer_id == *peer_id && lookup.request_id == *request_id)
+    }
+
+    /// Requests the parent of the lookup at `index` again, abandoning the lookup and penalising
+    /// its peer if it has been requested too many times.
+    fn retry(&mut self, index: usize, network: &mut NetworkContext) {
+        let lookup = &mut self.lookups[index];
+
+        if lookup.attempts >= MAX_PARENT_ATTEMPTS {
+            let lookup = self.lookups.remove(index);
+            warn!(
+                self.log, "ParentLookupFailed";
+                "peer" => format!("{:?}", lookup.peer_id),
+                "parent_root" => format!("{}", lookup.parent_root()),
+                "attempts" => lookup.attempts,
+            );
+            network.report_peer(lookup.peer_id, PeerAction::InvalidResponse);
+            return;
+        }
+
+        lookup.attempts += 1;
+        lookup.request_id = request_parent(lookup.peer_id.clone(), lookup.parent_root(), network);
+    }
+
+    /// Imports the blocks of a complete lookup, oldest first.
+    fn import(&mut self, lookup: ParentLookup, network: &mut NetworkContext) {
+        let mut imported = 0;
+
+        for block in lookup.blocks.into_iter().rev() {
+            match self.chain.process_block(block) {
+                Ok(outcome) if outcome.sucessfully_processed() => imported += 1,
+                Ok(outcome) => {
+                    warn!(
+                        self.log, "ParentLookupBlockNotImported";
+                        "peer" => format!("{:?}", lookup.peer_id),
+                        "outcome" => format!("{:?}", outcome),
+                    );
+                    if outcome.is_invalid() {
+                        network.report_peer(lookup.peer_id, PeerAction::InvalidBlock);
+                    }
+                    // The remaining blocks descend from this one, they cannot be imported.
+                    break;
+                }
+                Err(e) => {
+                    error!(
+                        self.log, "ParentLookupBlockProcessingError";
+                        "error" => format!("{:?}", e),
+                    );
+                    break;
+                }
+            }
+        }
+
+        if imported > 0 {
+            info!(self.log, "ParentLookupImported"; "blocks" => imported);
+        }
+    }
+
+    /// Returns `true` if `self.chain` has not yet processed this block.
+    fn is_new_block_root(&self, block_root: &Hash256) -> bool {
+        self.chain
+            .is_new_block_root(block_root)
+            .unwrap_or_else(|_| {
+                error!(self.log, "Unable to determine if block is new.");
+                true
+            })
+    }
+}
+
+/// Requests the block with `block_root` from `peer_id`.
+fn request_parent(peer_id: PeerId, block_root: Hash256, network: &mut NetworkContext) -> RequestId {
+    network.send_rpc_request(
+        peer_id,
+        RPCRequest::BeaconBlocksByRoot(BeaconBlocksByRootRequest {
+            block_roots: vec![block_root],
+        }),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::service::NetworkMessage;
+    use crate::sync::test_utils::*;
+    use crossbeam_channel::Receiver;
+    use test_harness::BeaconChainHarness;
+    use types::ChainSpec;
+
+    fn parent_lookups(harness: &BeaconChainHarness) -> ParentLookups {
+        ParentLookups::new(harness.beacon_chain.clone(), logger())
+    }
+
+    /// Returns a syncing harness and three blocks it does not have, each the parent of the next.
+    fn unknown_chain() -> (BeaconChainHarness, Vec<BeaconBlock>) {
+        let mut harnesses = harnesses(2);
+        let blocks = (0..3)
+            .map(|_| harnesses[0].advance_chain_with_block())
+            .collect();
+        let mut syncing = harnesses.pop().expect("two harnesses were built");
+        for _ in 0..3 {
+            syncing.increment_beacon_chain_slot();
+        }
+        (syncing, blocks)
+    }
+
+    /// Returns `depth` blocks, each the parent of the next, the first having an unknown parent.
+    fn fake_chain(depth: usize, spec: &ChainSpec) -> Vec<BeaconBlock> {
+        let mut parent_root = Hash256::from_low_u64_be(42);
+        (0..depth)
+            .map(|i| {
+                let mut block = BeaconBlock::empty(spec);
+                block.slot = spec.genesis_slot + i as u64 + 1;
+                block.previous_block_root = parent_root;
+                parent_root = block.canonical_root();
+                block
+            })
+            .collect()
+    }
+
+    /// Returns the roots requested through `BeaconBlocksByRoot` since this was last called.
+    fn requested_roots(receiver: &Receiver<NetworkMessage>) -> Vec<Hash256> {
+        sent_requests(receiver)
+            .into_iter()
+            .flat_map(|(_, _, request)| match request {
+                RPCRequest::BeaconBlocksByRoot(req) => req.block_roots,
+                request => panic!("unexpected request: {:?}", request),
+            })
+            .collect()
+    }
+
+    fn respond(
+        lookups: &mut ParentLookups,
+        peer_id: &PeerId,
+        blocks: Vec<BeaconBlock>,
+        network: &mut NetworkContext,
+    ) {
+        let request_id = lookups.lookups[0].request_id.clone();
+        assert!(lookups.on_blocks_by_root_response(
+            peer_id,
+            &request_id,
+            BeaconBlocksResponse { blocks },
+            network
+        ));
+    }
+
+    #[test]
+    fn walk_ends_at_a_known_block() {
+        let (syncing, blocks) = unknown_chain();
+        let (mut network, receiver, _) = network();
+        let mut lookups = parent_lookups(&syncing);
+        let peer_id = PeerId::random();
+
+        lookups
+            .start(blocks[2].clone(), peer_id.clone(), &mut network)
+            .unwrap();
+        assert_eq!(requested_roots(&receiver), vec![blocks[1].canonical_root()]);
+
+        respond(
+            &mut lookups,
+            &peer_id,
+            vec![blocks[1].clone()],
+            &mut network,
+        );
+        assert_eq!(requested_roots(&receiver), vec![blocks[0].canonical_root()]);
+
+        // the parent of the oldest block is genesis, which is known.
+        respond(
+            &mut lookups,
+            &peer_id,
+            vec![blocks[0].clone()],
+            &mut network,
+        );
+        assert!(requested_roots(&receiver).is_empty());
+        assert!(lookups.lookups.is_empty());
+    }
+
+    #[test]
+    fn ancestors_are_imported_oldest_first() {
+        let (syncing, blocks) = unknown_chain();
+        let (mut network, _, _) = network();
+        let mut lookups = parent_lookups(&syncing);
+        let peer_id = PeerId::random();
+
+        lookups
+            .start(blocks[2].clone(), peer_id.clone(), &mut network)
+            .unwrap();
+        respond(
+            &mut lookups,
+            &peer_id,
+            vec![blocks[1].clone()],
+            &mut network,
+        );
+        respond(
+            &mut lookups,
+            &peer_id,
+            vec![blocks[0].clone()],
+            &mut network,
+        );
+
+        // each block can only be imported after its parent.
+        for block in &blocks {
+            assert!(!syncing
+                .beacon_chain
+                .is_new_block_root(&block.canonical_root())
+                .unwrap());
+        }
+    }
+
+    #[test]
+    fn walk_stops_at_depth_tolerance() {
+        let harnesses = harnesses(1);
+        let harness = &harnesses[0];
+        let (mut network, receiver, _) = network();
+        let mut lookups = parent_lookups(harness);
+        let peer_id = PeerId::random();
+
+        let mut blocks = fake_chain(PARENT_DEPTH_TOLERANCE + 1, &harness.spec);
+        let block = blocks.pop().unwrap();
+        lookups.start(block, peer_id.clone(), &mut network).unwrap();
+
+        for _ in 0..PARENT_DEPTH_TOLERANCE - 1 {
+            let parent = blocks.pop().unwrap();
+            respond(&mut lookups, &peer_id, vec![parent], &mut network);
+        }
+        assert_eq!(requested_roots(&receiver).len(), PARENT_DEPTH_TOLERANCE);
+
+        // the last ancestor exceeds the tolerance, so the peer is asked for its `Hello` instead.
+        let parent = blocks.pop().unwrap();
+        respond(&mut lookups, &peer_id, vec![parent], &mut network);
+        assert!(lookups.lookups.is_empty());
+        match sent_requests(&receiver).as_slice() {
+            [(to, _, RPCRequest::Hello(_))] => assert_eq!(*to, peer_id),
+            requests => panic!("unexpected requests: {:?}", requests),
+        }
+    }
+
+    #[test]
+    fn lookup_is_abandoned_after_max_attempts() {
+        let harnesses = harnesses(1);
+        let harness = &harnesses[0];
+        let (mut network, receiver, peer_manager) = network();
+        let mut lookups = parent_lookups(harness);
+        let peer_id = PeerId::random();
+
+        let block = fake_chain(1, &harness.spec).pop().unwrap();
+        lookups.start(block, peer_id.clone(), &mut network).unwrap();
+
+        // an empty response is retried, as is a failed request.
+        respond(&mut lookups, &peer_id, vec![], &mut network);
+        for _ in 1..MAX_PARENT_ATTEMPTS {
+            let request_id = lookups.lookups[0].request_id.clone();
+            assert!(lookups.on_request_failed(&peer_id, &request_id, &mut network));
+        }
+
+        assert_eq!(requested_roots(&receiver).len(), MAX_PARENT_ATTEMPTS);
+        assert!(lookups.lookups.is_empty());
+        assert_eq!(
+            peer_manager.read().score(&peer_id),
+            -PeerAction::InvalidResponse.penalty()
+        );
+    }
+}
@@ -0,0 +1,689 @@
+use crate::beacon_chain::BeaconChain;
+use crate::message_handler::NetworkContext;
+use crate::peer_manager::PeerAction;
+use eth2_libp2p::rpc::methods::*;
+use eth2_libp2p::rpc::{RPCRequest, RequestId};
+use eth2_libp2p::PeerId;
+use slog::{debug, error, info, warn};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::Arc;
+use types::{BeaconBlock, Hash256, Slot};
+
+/// The number of slots in each batch. Must not exceed `MAX_REQUEST_BLOCKS`.
+const BATCH_SIZE: u64 = MAX_REQUEST_BLOCKS;
+
+/// The number of times a batch may fail to download or import before the sync is abandoned.
+const MAX_BATCH_ATTEMPTS: usize = 5;
+
+/// The maximum number of batches being downloaded or awaiting import at any time.
+const MAX_PENDING_BATCHES: usize = 8;
+
+#[derive(Debug)]
+enum BatchState {
+    /// The batch is waiting for a peer to download it from.
+    Pending,
+    /// The batch is being downloaded from `peer_id`.
+    Downloading {
+        peer_id: PeerId,
+        request_id: RequestId,
+    },
+    /// The blocks of the batch have been downloaded from `peer_id` and are waiting to be imported.
+    Downloaded {
+        peer_id: PeerId,
+        blocks: Vec<BeaconBlock>,
+    },
+}
+
+/// A range of slots downloaded from a single peer.
+#[derive(Debug)]
+struct Batch {
+    /// The first slot of the batch.
+    start_slot: Slot,
+    /// The number of slots in the batch.
+    count: u64,
+    state: BatchState,
+    /// Peers which have failed to provide this batch. They are only used again if no other peer
+    /// can provide it.
+    failed_peers: HashSet<PeerId>,
+    /// The number of times the batch has failed.
+    attempts: usize,
+}
+
+impl Batch {
+    fn new(start_slot: Slot, count: u64) -> Self {
+        Self {
+            start_slot,
+            count,
+            state: BatchState::Pending,
+            failed_peers: HashSet::new(),
+            attempts: 0,
+        }
+    }
+
+    /// The slot following the last slot of the batch.
+    fn end_slot(&self) -> Slot {
+        self.start_slot + self.count
+    }
+
+    /// Returns `true` if `slot` is within the batch.
+    fn contains(&self, slot: Slot) -> bool {
+        slot >= self.start_slot && slot < self.end_slot()
+    }
+
+    /// Returns `true` if the batch is being downloaded with the request `request_id` to `peer_id`.
+    fn is_awaiting(&self, peer_id: &PeerId, request_id: &RequestId) -> bool {
+        match &self.state {
+            BatchState::Downloading {
+                peer_id: batch_peer,
+                request_id: batch_request,
+                ..
+            } => batch_peer == peer_id && batch_request == request_id,
+            _ => false,
+        }
+    }
+
+    /// Returns the peer the batch is being downloaded from, if any.
+    fn downloading_from(&self) -> Option<&PeerId> {
+        match &self.state {
+            BatchState::Downloading { peer_id, .. } => Some(peer_id),
+            _ => None,
+        }
+    }
+}
+
+/// Downloads a range of slots from multiple peers in parallel.
+///
+/// The slots between our best slot and the best slot of our peers are split into batches of
+/// `BATCH_SIZE` slots. Each batch is downloaded with a single `BeaconBlocksByRange` request to a
+/// peer whose `Hello` shows it has the whole batch. A peer downloads one batch at a time.
+///
+/// Batches may finish downloading in any order, but are imported in slot order. A batch which
+/// fails to download or import is retried with another peer, and peers which serve invalid blocks
+/// or responses are penalised.
+pub struct RangeSync {
+    chain: Arc<BeaconChain>,
+    /// The best slot each peer claimed in its latest `Hello`.
+    peers: HashMap<PeerId, Slot>,
+    /// The batches being downloaded or awaiting import, in ascending slot order.
+    batches: VecDeque<Batch>,
+    /// The first slot of the next batch to be created.
+    next_batch_start: Slot,
+    log: slog::Logger,
+}
+
+impl RangeSync {
+    pub fn new(chain: Arc<BeaconChain>, log: slog::Logger) -> Self {
+        let next_batch_start = chain.best_slot() + 1;
+        Self {
+            chain,
+            peers: HashMap::new(),
+            batches: VecDeque::new(),
+            next_batch_start,
+            log,
+        }
+    }
+
+    /// Returns `true` if there are batches being downloaded or awaiting import.
+    pub fn is_syncing(&self) -> bool {
+        !self.batches.is_empty()
+    }
+
+    /// Adds (or updates) a peer which has the blocks up to and including `best_slot`.
+    pub fn add_peer(&mut self, peer_id: PeerId, best_slot: Slot, network: &mut NetworkContext) {
+        if !self.is_syncing() {
+            self.next_batch_start = self.chain.best_slot() + 1;
+        }
+
+        debug!(
+            self.log, "RangeSyncAddPeer";
+            "peer" => format!("{:?}", peer_id),
+            "best_slot" => best_slot,
+        );
+
+        self.peers.insert(peer_id, best_slot);
+        self.update(network);
+    }
+
+    /// Removes a peer, returning any batch it was downloading to the pending batches.
+    pub fn remove_peer(&mut self, peer_id: &PeerId, network: &mut NetworkContext) {
+        if self.peers.remove(peer_id).is_none() {
+            return;
+        }
+
+        for batch in self.batches.iter_mut() {
+            if batch.downloading_from() == Some(peer_id) {
+                batch.state = BatchState::Pending;
+            }
+        }
+
+        self.update(network);
+    }
+
+    /// Handles a request which was responded to with an error, or not responded to at all.
+    ///
+    /// The batch is retried with another peer. Returns `false` if the request was not made by
+    /// range sync.
+    pub fn on_request_failed(
+        &mut self,
+        peer_id: &PeerId,
+        request_id: &RequestId,
+        network: &mut NetworkContext,
+    ) -> bool {
+        match self.awaiting_batch(peer_id, request_id) {
+            Some(index) => {
+                self.fail_batch(index, network);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Handles a `BeaconBlocksByRange` response, importing any batches which are ready.
+    ///
+    /// Returns `false` if the request was not made by range sync.
+    pub fn on_blocks_by_range_response(
+        &mut self,
+        peer_id: &PeerId,
+        request_id: &RequestId,
+        res: BeaconBlocksResponse,
+        network: &mut NetworkContext,
+    ) -> bool {
+        let index = match self.awaiting_batch(peer_id, request_id) {
+            Some(index) => index,
+            None => return false,
+        };
+
+        let batch = &self.batches[index];
+        if !res.slots_are_ascending()
+            || res.blocks.iter().any(|block| !batch.contains(block.slot))
+            || !blocks_are_chained(&res.blocks)
+        {
+            warn!(
+                self.log, "InvalidBlocksByRangeResponse";
+                "peer" => format!("{:?}", peer_id),
+                "start_slot" => batch.start_slot,
+                "count" => res.blocks.len(),
+            );
+            self.reject_batch(index, PeerAction::InvalidResponse, network);
+            return true;
+        }
+
+        debug!(
+            self.log, "BatchDownloaded";
+            "peer" => format!("{:?}", peer_id),
+            "start_slot" => batch.start_slot,
+            "blocks" => res.blocks.len(),
+        );
+
+        self.batches[index].state = BatchState::Downloaded {
+            peer_id: peer_id.clone(),
+            blocks: res.blocks,
+        };
+        self.update(network);
+
+        true
+    }
+
+    /// Returns the index of the batch awaiting the response to `request_id` from `peer_id`.
+    fn awaiting_batch(&self, peer_id: &PeerId, request_id: &RequestId) -> Option<usize> {
+        self.batches
+            .iter()
+            .position(|batch| batch.is_awaiting(peer_id, request_id))
+    }
+
+    /// Creates new batches up to the best slot of our peers, assigns pending batches to idle peers
+    /// and imports the batches which are ready.
+    fn update(&mut self, network: &mut NetworkContext) {
+        self.import_batches(network);
+
+        let target_slot = match self.peers.values().max() {
+            Some(slot) => *slot,
+            None => return,
+        };
+
+        while self.batches.len() < MAX_PENDING_BATCHES && self.next_batch_start <= target_slot {
+            let count = std::cmp::min(
+                BATCH_SIZE,
+                (target_slot + 1 - self.next_batch_start).as_u64(),
+            );
+            self.batches
+                .push_back(Batch::new(self.next_batch_start, count));
+            self.next_batch_start += count;
+        }
+
+        for index in 0..self.batches.len() {
+            if let BatchState::Pending = self.batches[index].state {
+                if let Some(peer_id) = self.idle_peer_for(&self.batches[index]) {
+                    self.request(index, peer_id, network);
+                }
+            }
+        }
+    }
+
+    /// Returns a peer which is not downloading a batch and claims to have all of `batch`.
+    ///
+    /// Peers which have not failed the batch before are preferred.
+    fn idle_peer_for(&self, batch: &Batch) -> Option<PeerId> {
+        let busy: HashSet<&PeerId> = self
+            .batches
+            .iter()
+            .filter_map(|batch| batch.downloading_from())
+            .collect();
+
+        let mut fallback = None;
+        for (peer_id, best_slot) in &self.peers {
+            if busy.contains(peer_id) || *best_slot + 1 < batch.end_slot() {
+                continue;
+            }
+            if !batch.failed_peers.contains(peer_id) {
+                return Some(peer_id.clone());
+            }
+            if fallback.is_none() {
+                fallback = Some(peer_id.clone());
+            }
+        }
+        fallback
+    }
+
+    /// Requests the blocks of the batch at `index` from `peer_id`.
+    fn request(&mut self, index: usize, peer_id: PeerId, network: &mut NetworkContext) {
+        let batch = &self.batches[index];
+        let req = BeaconBlocksByRangeRequest {
+            start_slot: batch.start_slot,
+            count: batch.count,
+            step: 1,
+        };
+
+        debug!(
+            self.log, "RangeSyncRequest";
+            "peer" => format!("{:?}", peer_id),
+            "start_slot" => req.start_slot,
+            "count" => req.count,
+        );
+
+        let request_id =
+            network.send_rpc_request(peer_id.clone(), RPCRequest::BeaconBlocksByRange(req));
+        self.batches[index].state = BatchState::Downloading {
+            peer_id,
+            request_id,
+        };
+    }
+
+    /// Penalises the peer downloading or having downloaded the batch at `index` for `action`, then
+    /// retries the batch.
+    fn reject_batch(&mut self, index: usize, action: PeerAction, network: &mut NetworkContext) {
+        if let Some(peer_id) = self.batch_peer(index) {
+            network.report_peer(peer_id, action);
+        }
+        self.fail_batch(index, network);
+    }
+
+    /// Returns the batch at `index` to the pending batches so that it may be retried with another
+    /// peer, abandoning the sync if the batch has failed too many times.
+    fn fail_batch(&mut self, index: usize, network: &mut NetworkContext) {
+        let peer_id = self.batch_peer(index);
+        let batch = &mut self.batches[index];
+
+        batch.attempts += 1;
+        batch.state = BatchState::Pending;
+        if let Some(peer_id) = peer_id {
+            batch.failed_peers.insert(peer_id);
+        }
+
+        if batch.attempts >= MAX_BATCH_ATTEMPTS {
+            warn!(
+                self.log, "RangeSyncAbandoned";
+                "start_slot" => batch.start_slot,
+                "attempts" => batch.attempts,
+            );
+            // Peers are forgotten, so the sync restarts when they next say `Hello`.
+            self.batches.clear();
+            self.peers.clear();
+            self.next_batch_start = self.chain.best_slot() + 1;
+            return;
+        }
+
+        self.update(network);
+    }
+
+    /// Returns the peer downloading or having downloaded the batch at `index`.
+    fn batch_peer(&self, index: usize) -> Option<PeerId> {
+        match &self.batches[index].state {
+            BatchState::Downloading { peer_id, .. } | BatchState::Downloaded { peer_id, .. } => {
+                Some(peer_id.clone())
+            }
+            BatchState::Pending => None,
+        }
+    }
+
+    /// Imports the downloaded batches at the front of the queue, in slot order.
+    fn import_batches(&mut self, network: &mut NetworkContext) {
+        while let Some(BatchState::Downloaded { .. }) = self.batches.front().map(|b| &b.state) {
+            let mut batch = self.batches.pop_front().expect("front batch exists");
+            let state = std::mem::replace(&mut batch.state, BatchState::Pending);
+            let (peer_id, blocks) = match state {
+                BatchState::Downloaded { peer_id, blocks } => (peer_id, blocks),
+                _ => unreachable!("front batch is downloaded"),
+            };
+
+            let mut imported = 0;
+            let mut failure = None;
+
+            for block in blocks {
+                if !self.is_new_block_root(&block.canonical_root()) {
+                    continue;
+                }
+
+                match self.chain.process_block(block) {
+                    Ok(outcome) if outcome.sucessfully_processed() => imported += 1,
+                    Ok(outcome) => {
+                        warn!(
+                            self.log, "RangeSyncBlockNotImported";
+                            "peer" => format!("{:?}", peer_id),
+                            "outcome" => format!("{:?}", outcome),
+                        );
+                        failure = Some(outcome.is_invalid());
+                        break;
+                    }
+                    Err(e) => {
+                        error!(
+                            self.log, "RangeSyncBlockProcessingError";
+                            "error" => format!("{:?}", e),
+                        );
+                        failure = Some(false);
+                        break;
+                    }
+                }
+            }
+
+            if imported > 0 {
+                info!(
+                    self.log, "ImportedBatch";
+                    "start_slot" => batch.start_slot,
+                    "blocks" => imported,
+                );
+            }
+
+            if let Some(invalid) = failure {
+                // Return the batch to the queue so that it is downloaded again. Blocks which were
+                // imported are skipped when it is.
+                batch.state = BatchState::Downloaded {
+                    peer_id,
+                    blocks: vec![],
+                };
+                self.batches.push_front(batch);
+                if invalid {
+                    self.reject_batch(0, PeerAction::InvalidBlock, network);
+                } else {
+                    self.fail_batch(0, network);
+                }
+                return;
+            }
+        }
+    }
+
+    /// Returns `true` if `self.chain` has not yet processed this block.
+    fn is_new_block_root(&self, block_root: &Hash256) -> bool {
+        self.chain
+            .is_new_block_root(block_root)
+            .unwrap_or_else(|_| {
+                error!(self.log, "Unable to determine if block is new.");
+                true
+            })
+    }
+}
+
+/// Returns `true` if each block in `blocks` is the parent of the next.
+fn blocks_are_chained(blocks: &[BeaconBlock]) -> bool {
+    blocks
+        .windows(2)
+        .all(|pair| pair[1].previous_block_root == pair[0].canonical_root())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sync::test_utils::*;
+    use test_harness::BeaconChainHarness;
+
+    fn range_sync(harness: &BeaconChainHarness) -> RangeSync {
+        RangeSync::new(harness.beacon_chain.clone(), logger())
+    }
+
+    /// Returns the id of the request for the batch at `index`.
+    fn request_id(sync: &RangeSync, index: usize) -> RequestId {
+        match &sync.batches[index].state {
+            BatchState::Downloading { request_id, .. } => request_id.clone(),
+            state => panic!("batch is not downloading: {:?}", state),
+        }
+    }
+
+    #[test]
+    fn slots_are_split_into_batches() {
+        let harnesses = harnesses(1);
+        let harness = &harnesses[0];
+        let genesis_slot = harness.spec.genesis_slot;
+        let (mut network, receiver, _) = network();
+        let mut sync = range_sync(harness);
+        let peer_id = PeerId::random();
+
+        sync.add_peer(
+            peer_id.clone(),
+            genesis_slot + BATCH_SIZE * 2 + 10,
+            &mut network,
+        );
+
+        let batches: Vec<(Slot, u64)> = sync
+            .batches
+            .iter()
+            .map(|batch| (batch.start_slot, batch.count))
+            .collect();
+        assert_eq!(
+            batches,
+            vec![
+                (genesis_slot + 1, BATCH_SIZE),
+                (genesis_slot + BATCH_SIZE + 1, BATCH_SIZE),
+                (genesis_slot + BATCH_SIZE * 2 + 1, 10),
+            ]
+        );
+
+        // the peer downloads one batch at a time.
+        let requests = sent_requests(&receiver);
+        assert_eq!(requests.len(), 1);
+        match &requests[0] {
+            (to, _, RPCRequest::BeaconBlocksByRange(req)) => {
+                assert_eq!(*to, peer_id);
+                assert_eq!(
+                    *req,
+                    BeaconBlocksByRangeRequest {
+                        start_slot: genesis_slot + 1,
+                        count: BATCH_SIZE,
+                        step: 1,
+                    }
+                );
+            }
+            request => panic!("unexpected request: {:?}", request),
+        }
+    }
+
+    #[test]
+    fn batches_are_assigned_to_peers_which_have_them() {
+        let harnesses = harnesses(1);
+        let harness = &harnesses[0];
+        let genesis_slot = harness.spec.genesis_slot;
+        let (mut network, _, _) = network();
+        let mut sync = range_sync(harness);
+        let (short, long) = (PeerId::random(), PeerId::random());
+
+        sync.add_peer(short.clone(), genesis_slot + 10, &mut network);
+        sync.add_peer(long.clone(), genesis_slot + 100, &mut network);
+
+        assert_eq!(sync.batches.len(), 3);
+        assert_eq!(sync.batches[0].downloading_from(), Some(&short));
+        assert_eq!(sync.batches[1].downloading_from(), Some(&long));
+        // only `long` has the last batch, and it is busy.
+        assert_eq!(sync.batches[2].downloading_from(), None);
+    }
+
+    #[test]
+    fn failed_batches_are_retried_with_another_peer() {
+        let harnesses = harnesses(1);
+        let harness = &harnesses[0];
+        let genesis_slot = harness.spec.genesis_slot;
+        let (mut network, _, _) = network();
+        let mut sync = range_sync(harness);
+        let (first, second) = (PeerId::random(), PeerId::random());
+
+        sync.add_peer(first.clone(), genesis_slot + 10, &mut network);
+        sync.add_peer(second.clone(), genesis_slot + 10, &mut network);
+        assert_eq!(sync.batches[0].downloading_from(), Some(&first));
+
+        let id = request_id(&sync, 0);
+        assert!(!sync.on_request_failed(&second, &id, &mut network));
+        assert!(sync.on_request_failed(&first, &id, &mut network));
+
+        assert_eq!(sync.batches[0].downloading_from(), Some(&second));
+        assert_eq!(sync.batches[0].attempts, 1);
+    }
+
+    #[test]
+    fn batches_are_imported_in_slot_order() {
+        let mut harnesses = harnesses(2);
+        let genesis_slot = harnesses[0].spec.genesis_slot;
+        let blocks: Vec<BeaconBlock> = (0..2)
+            .map(|_| harnesses[0].advance_chain_with_block())
+            .collect();
+        let syncing = &mut harnesses[1];
+        syncing.increment_beacon_chain_slot();
+        syncing.increment_beacon_chain_slot();
+
+        let (mut network, _, _) = network();
+        let mut sync = range_sync(syncing);
+        let (first, second) = (PeerId::random(), PeerId::random());
+
+        // each peer is assigned a batch of one slot.
+        sync.add_peer(first.clone(), genesis_slot + 1, &mut network);
+        sync.add_peer(second.clone(), genesis_slot + 2, &mut network);
+        let (first_id, second_id) = (request_id(&sync, 0), request_id(&sync, 1));
+
+        let response = |block: &BeaconBlock| BeaconBlocksResponse {
+            blocks: vec![block.clone()],
+        };
+        let is_known = |block: &BeaconBlock| {
+            !syncing
+                .beacon_chain
+                .is_new_block_root(&block.canonical_root())
+                .unwrap()
+        };
+
+        // the later batch waits for the earlier one.
+        assert!(sync.on_blocks_by_range_response(
+            &second,
+            &second_id,
+            response(&blocks[1]),
+            &mut network
+        ));
+        assert!(!is_known(&blocks[1]));
+        assert_eq!(sync.batches.len(), 2);
+
+        assert!(sync.on_blocks_by_range_response(
+            &first,
+            &first_id,
+            response(&blocks[0]),
+            &mut network
+        ));
+        assert!(blocks.iter().all(is_known));
+        assert!(!sync.is_syncing());
+    }
+
+    #[test]
+    fn invalid_responses_are_penalised() {
+        let harnesses = harnesses(1);
+        let harness = &harnesses[0];
+        let genesis_slot = harness.spec.genesis_slot;
+        let (mut network, _, peer_manager) = network();
+        let mut sync = range_sync(harness);
+        let peer_id = PeerId::random();
+
+        sync.add_peer(peer_id.clone(), genesis_slot + 10, &mut network);
+
+        // a block outside of the requested range.
+        let mut block = BeaconBlock::empty(&harness.spec);
+        block.slot = genesis_slot + 50;
+        let id = request_id(&sync, 0);
+        assert!(sync.on_blocks_by_range_response(
+            &peer_id,
+            &id,
+            BeaconBlocksResponse {
+                blocks: vec![block]
+            },
+            &mut network
+        ));
+
+        assert_eq!(
+            peer_manager.read().score(&peer_id),
+            -PeerAction::InvalidResponse.penalty()
+        );
+        // the peer is the only one with the batch, so it is retried with the same peer.
+        assert_eq!(sync.batches[0].attempts, 1);
+        assert_eq!(sync.batches[0].downloading_from(), Some(&peer_id));
+        assert_ne!(request_id(&sync, 0), id);
+    }
+
+    #[test]
+    fn unchained_blocks_are_rejected() {
+        let harnesses = harnesses(1);
+        let harness = &harnesses[0];
+        let genesis_slot = harness.spec.genesis_slot;
+        let (mut network, _, peer_manager) = network();
+        let mut sync = range_sync(harness);
+        let peer_id = PeerId::random();
+
+        sync.add_peer(peer_id.clone(), genesis_slot + 10, &mut network);
+
+        // two blocks within the range, where the second is not a child of the first.
+        let blocks: Vec<BeaconBlock> = (1..3)
+            .map(|i| {
+                let mut block = BeaconBlock::empty(&harness.spec);
+                block.slot = genesis_slot + i;
+                block
+            })
+            .collect();
+        let id = request_id(&sync, 0);
+        assert!(sync.on_blocks_by_range_response(
+            &peer_id,
+            &id,
+            BeaconBlocksResponse { blocks },
+            &mut network
+        ));
+
+        assert_eq!(
+            peer_manager.read().score(&peer_id),
+            -PeerAction::InvalidResponse.penalty()
+        );
+        assert_eq!(sync.batches[0].attempts, 1);
+    }
+
+    #[test]
+    fn batches_of_skipped_slots_are_completed() {
+        let harnesses = harnesses(1);
+        let harness = &harnesses[0];
+        let genesis_slot = harness.spec.genesis_slot;
+        let (mut network, _, peer_manager) = network();
+        let mut sync = range_sync(harness);
+        let peer_id = PeerId::random();
+
+        sync.add_peer(peer_id.clone(), genesis_slot + 1, &mut network);
+
+        let id = request_id(&sync, 0);
+        assert!(sync.on_blocks_by_range_response(
+            &peer_id,
+            &id,
+            BeaconBlocksResponse { blocks: vec![] },
+            &mut network
+        ));
+
+        assert!(!sync.is_syncing());
+        assert_eq!(peer_manager.read().score(&peer_id), 0.0);
+    }
+}
@@ -1,25 +1,32 @@
+use super::attestation_queue::AttestationQueue;
+use super::gossip_validation::GossipValidator;
 use super::import_queue::ImportQueue;
-use crate::beacon_chain::{BeaconChain, BlockProcessingOutcome, InvalidBlock};
+use super::parent_lookup::ParentLookups;
+use super::range_sync::RangeSync;
+use super::state_download::StateDownload;
+use crate::beacon_chain::{BeaconChain, BeaconChainError, BlockProcessingOutcome, InvalidBlock};
 use crate::message_handler::NetworkContext;
+use crate::peer_manager::PeerAction;
 use eth2_libp2p::rpc::methods::*;
-use eth2_libp2p::rpc::{RPCRequest, RPCResponse, RequestId};
-use eth2_libp2p::PeerId;
+use eth2_libp2p::rpc::{RPCMethod, RPCRequest, RPCResponse, RequestId, ResponseCode};
+use eth2_libp2p::{MessageAcceptance, PeerId};
 use slog::{debug, error, info, o, warn};
 use std::collections::HashMap;
 use std::sync::Arc;
-use std::time::Duration;
-use tree_hash::TreeHash;
-use types::{Attestation, BeaconBlock, Epoch, Hash256, Slot};
-
-/// The number of slots that we can import blocks ahead of us, before going into full Sync mode.
-const SLOT_IMPORT_TOLERANCE: u64 = 100;
+use std::time::{Duration, Instant};
+use types::{Attestation, BeaconBlock, BeaconStateError, Epoch, Hash256, Slot};
 
 /// The amount of seconds a block (or partial block) may exist in the import queue.
 const QUEUE_STALE_SECS: u64 = 600;
 
-/// If a block is more than `FUTURE_SLOT_TOLERANCE` slots ahead of our slot clock, we drop it.
-/// Otherwise we queue it.
-const FUTURE_SLOT_TOLERANCE: u64 = 1;
+/// The interval at which the `Hello` handshake is repeated with each peer, so that our view of its
+/// chain stays current.
+const REHANDSHAKE_INTERVAL: Duration = Duration::from_secs(300);
+
+/// The state of the finalized block of a peer is downloaded if the peer has finalized at least
+/// this many epochs beyond us, so that blocks building upon it can be imported without replaying
+/// the chain up to it.
+const STATE_DOWNLOAD_DISTANCE: u64 = 4;
 
 /// Keeps track of syncing information for known connected peers.
 #[derive(Clone, Copy, Debug)]
@@ -29,22 +36,37 @@ pub struct PeerSyncInfo {
     latest_finalized_epoch: Epoch,
     best_root: Hash256,
     best_slot: Slot,
+    /// The status of the peer relative to us, as of its latest `Hello`.
+    status: PeerStatus,
+    /// The time we last sent the peer a `Hello`, or received one from it.
+    last_hello: Instant,
 }
 
 impl PeerSyncInfo {
-    /// Returns `true` if the has a different network ID to `other`.
-    fn has_different_network_id_to(&self, other: Self) -> bool {
-        self.network_id != other.network_id
+    /// Builds the sync info of a peer from its `Hello`, which was received at `now`.
+    fn new(hello: HelloMessage, status: PeerStatus, now: Instant) -> Self {
+        PeerSyncInfo {
+            network_id: hello.network_id,
+            latest_finalized_root: hello.latest_finalized_root,
+            latest_finalized_epoch: hello.latest_finalized_epoch,
+            best_root: hello.best_root,
+            best_slot: hello.best_slot,
+            status,
+            last_hello: now,
+        }
     }
 
-    /// Returns `true` if the peer has a higher finalized epoch than `other`.
-    fn has_higher_finalized_epoch_than(&self, other: Self) -> bool {
-        self.latest_finalized_epoch > other.latest_finalized_epoch
+    /// Returns `true` if the `Hello` handshake should be repeated with the peer.
+    fn is_due_rehandshake(&self, now: Instant) -> bool {
+        now.duration_since(self.last_hello) >= REHANDSHAKE_INTERVAL
     }
 
-    /// Returns `true` if the peer has a higher best slot than `other`.
-    fn has_higher_best_slot_than(&self, other: Self) -> bool {
-        self.best_slot > other.best_slot
+    /// Returns `true` if `hello` contradicts the finalized checkpoint the peer previously gave
+    /// us, i.e., finality went backwards or the finalized root changed.
+    fn is_contradicted_by(&self, hello: &HelloMessage) -> bool {
+        hello.latest_finalized_epoch < self.latest_finalized_epoch
+            || (hello.latest_finalized_epoch == self.latest_finalized_epoch
+                && hello.latest_finalized_root != self.latest_finalized_root)
     }
 }
 
@@ -56,6 +78,8 @@ pub enum PeerStatus {
     DifferentNetworkId,
     /// The peer lists a finalized epoch for which we have a different root.
     FinalizedEpochNotInChain,
+    /// The peer lists a finalized epoch which is later than the present epoch.
+    FinalizedEpochInFuture,
     /// The peer has a higher finalized epoch.
     HigherFinalizedEpoch,
     /// The peer has a higher best slot.
@@ -69,6 +93,7 @@ impl PeerStatus {
         match self {
             PeerStatus::DifferentNetworkId => false,
             PeerStatus::FinalizedEpochNotInChain => false,
+            PeerStatus::FinalizedEpochInFuture => false,
             PeerStatus::HigherFinalizedEpoch => true,
             PeerStatus::HigherBestSlot => true,
             PeerStatus::NotInteresting => true,
@@ -76,32 +101,6 @@ impl PeerStatus {
     }
 }
 
-impl From<HelloMessage> for PeerSyncInfo {
-    fn from(hello: HelloMessage) -> PeerSyncInfo {
-        PeerSyncInfo {
-            network_id: hello.network_id,
-            latest_finalized_root: hello.latest_finalized_root,
-            latest_finalized_epoch: hello.latest_finalized_epoch,
-            best_root: hello.best_root,
-            best_slot: hello.best_slot,
-        }
-    }
-}
-
-impl From<&Arc<BeaconChain>> for PeerSyncInfo {
-    fn from(chain: &Arc<BeaconChain>) -> PeerSyncInfo {
-        Self::from(chain.hello_message())
-    }
-}
-
-/// The current syncing state.
-#[derive(PartialEq)]
-pub enum SyncState {
-    Idle,
-    Downloading,
-    _Stopped,
-}
-
 /// Simple Syncing protocol.
 pub struct SimpleSync {
     /// A reference to the underlying beacon chain.
@@ -110,8 +109,18 @@ pub struct SimpleSync {
     known_peers: HashMap<PeerId, PeerSyncInfo>,
     /// A queue to allow importing of blocks
     import_queue: ImportQueue,
-    /// The current state of the syncing protocol.
-    state: SyncState,
+    /// Downloads blocks from peers with a higher best slot.
+    range_sync: RangeSync,
+    /// Fetches the unknown ancestors of gossip blocks.
+    parent_lookups: ParentLookups,
+    /// Decides which gossip messages are propagated.
+    gossip_validator: GossipValidator,
+    /// Attestations which vote for blocks we have not yet imported.
+    attestation_queue: AttestationQueue,
+    /// The request for a block whose state is to be downloaded, if any.
+    state_block_request: Option<(PeerId, RequestId, Hash256)>,
+    /// The download of the state of a block from a peer, if any.
+    state_download: Option<(BeaconBlock, StateDownload)>,
     /// Sync logger.
     log: slog::Logger,
 }
@@ -123,28 +132,136 @@ impl SimpleSync {
 
         let queue_item_stale_time = Duration::from_secs(QUEUE_STALE_SECS);
 
-        let import_queue =
-            ImportQueue::new(beacon_chain.clone(), queue_item_stale_time, log.clone());
+        let import_queue = ImportQueue::new(queue_item_stale_time, log.clone());
+        let range_sync = RangeSync::new(beacon_chain.clone(), sync_logger.clone());
+        let parent_lookups = ParentLookups::new(beacon_chain.clone(), sync_logger.clone());
+        let gossip_validator = GossipValidator::new(beacon_chain.clone(), sync_logger.clone());
         SimpleSync {
             chain: beacon_chain.clone(),
             known_peers: HashMap::new(),
             import_queue,
-            state: SyncState::Idle,
+            range_sync,
+            parent_lookups,
+            gossip_validator,
+            attestation_queue: AttestationQueue::default(),
+            state_block_request: None,
+            state_download: None,
             log: sync_logger,
         }
     }
 
-    /// Handle a `Goodbye` message from a peer.
-    ///
-    /// Removes the peer from `known_peers`.
-    pub fn on_goodbye(&mut self, peer_id: PeerId, reason: GoodbyeReason) {
+    /// Handle a `Goodbye` message from a peer, which is about to disconnect.
+    pub fn on_goodbye(
+        &mut self,
+        peer_id: PeerId,
+        reason: GoodbyeReason,
+        network: &mut NetworkContext,
+    ) {
         info!(
             self.log, "PeerGoodbye";
             "peer" => format!("{:?}", peer_id),
             "reason" => format!("{:?}", reason),
         );
 
-        self.known_peers.remove(&peer_id);
+        self.remove_peer(&peer_id, network);
+    }
+
+    /// Handle the disconnection of a peer, whether or not it said `Goodbye`.
+    pub fn on_disconnect(&mut self, peer_id: PeerId, network: &mut NetworkContext) {
+        debug!(self.log, "PeerDisconnect"; "peer" => format!("{:?}", peer_id));
+
+        self.remove_peer(&peer_id, network);
+    }
+
+    /// Removes the peer from `known_peers`, range sync, parent lookups and the attestation queue,
+    /// and abandons a state download from the peer.
+    fn remove_peer(&mut self, peer_id: &PeerId, network: &mut NetworkContext) {
+        self.known_peers.remove(peer_id);
+        self.range_sync.remove_peer(peer_id, network);
+        self.parent_lookups.remove_peer(peer_id);
+        self.attestation_queue.remove_peer(peer_id);
+
+        if self
+            .state_block_request
+            .as_ref()
+            .map_or(false, |(request_peer, _, _)| request_peer == peer_id)
+        {
+            self.state_block_request = None;
+        }
+
+        if self
+            .state_download
+            .as_ref()
+            .map_or(false, |(_, download)| download.peer_id() == peer_id)
+        {
+            self.abandon_state_download("peer disconnected");
+        }
+    }
+
+    /// Handle a request to `peer_id` which was not responded to in time.
+    ///
+    /// A peer which does not respond to a `Hello` cannot be verified to be on our chain, so it is
+    /// forgotten and disconnected. Blocks that were being downloaded are requested from another
+    /// peer.
+    pub fn on_request_timeout(
+        &mut self,
+        peer_id: PeerId,
+        request_id: RequestId,
+        method: RPCMethod,
+        network: &mut NetworkContext,
+    ) {
+        warn!(
+            self.log, "RequestTimeout";
+            "peer" => format!("{:?}", peer_id),
+            "method" => format!("{:?}", method),
+        );
+
+        if let RPCMethod::Hello = method {
+            self.remove_peer(&peer_id, network);
+            network.disconnect(peer_id, GoodbyeReason::UnableToVerifyNetwork, None);
+        } else {
+            self.on_request_failed(peer_id, request_id, network);
+        }
+    }
+
+    /// Handle an error response to a request we sent to `peer_id`.
+    ///
+    /// Blocks that were being downloaded are requested from another peer.
+    pub fn on_error_response(
+        &mut self,
+        peer_id: PeerId,
+        request_id: RequestId,
+        network: &mut NetworkContext,
+    ) {
+        self.on_request_failed(peer_id, request_id, network);
+    }
+
+    /// Retries the download which the failed request `request_id` was part of.
+    fn on_request_failed(
+        &mut self,
+        peer_id: PeerId,
+        request_id: RequestId,
+        network: &mut NetworkContext,
+    ) {
+        let handled = self
+            .range_sync
+            .on_request_failed(&peer_id, &request_id, network)
+            || self
+                .parent_lookups
+                .on_request_failed(&peer_id, &request_id, network)
+            || self
+                .attestation_queue
+                .on_request_failed(&peer_id, &request_id);
+
+        if handled {
+            return;
+        }
+
+        if self.is_state_block_request(&peer_id, &request_id) {
+            self.state_block_request = None;
+        } else if self.is_state_download_request(&peer_id, &request_id) {
+            self.abandon_state_download("request failed");
+        }
     }
 
     /// Handle the connection of a new peer.
@@ -191,24 +308,34 @@ impl SimpleSync {
         self.process_hello(peer_id, hello, network);
     }
 
-    /// Returns a `PeerStatus` for some peer.
-    fn peer_status(&self, peer: PeerSyncInfo) -> PeerStatus {
-        let local = PeerSyncInfo::from(&self.chain);
+    /// Returns a `PeerStatus` for a peer which sent us `hello`.
+    fn peer_status(&self, hello: &HelloMessage) -> PeerStatus {
+        let local = self.chain.hello_message();
+        let slots_per_epoch = self.chain.get_spec().slots_per_epoch;
 
-        if peer.has_different_network_id_to(local) {
+        if hello.network_id != local.network_id {
             return PeerStatus::DifferentNetworkId;
         }
 
-        if local.has_higher_finalized_epoch_than(peer) {
-            let peer_finalized_slot = peer
-                .latest_finalized_epoch
-                .start_slot(self.chain.get_spec().slots_per_epoch);
+        // No epoch can be finalized before it has begun.
+        if hello.latest_finalized_epoch > self.chain.slot().epoch(slots_per_epoch) {
+            return PeerStatus::FinalizedEpochInFuture;
+        }
+
+        if local.latest_finalized_epoch == hello.latest_finalized_epoch
+            && local.latest_finalized_root != hello.latest_finalized_root
+        {
+            return PeerStatus::FinalizedEpochNotInChain;
+        }
+
+        if local.latest_finalized_epoch > hello.latest_finalized_epoch {
+            let peer_finalized_slot = hello.latest_finalized_epoch.start_slot(slots_per_epoch);
 
             let local_roots = self.chain.get_block_roots(peer_finalized_slot, 1, 0);
 
             if let Ok(local_roots) = local_roots {
                 if let Some(local_root) = local_roots.get(0) {
-                    if *local_root != peer.latest_finalized_root {
+                    if *local_root != hello.latest_finalized_root {
                         return PeerStatus::FinalizedEpochNotInChain;
                     }
                 } else {
@@ -227,9 +354,9 @@ impl SimpleSync {
             }
         }
 
-        if peer.has_higher_finalized_epoch_than(local) {
+        if hello.latest_finalized_epoch > local.latest_finalized_epoch {
             PeerStatus::HigherFinalizedEpoch
-        } else if peer.has_higher_best_slot_than(local) {
+        } else if hello.best_slot > local.best_slot {
             PeerStatus::HigherBestSlot
         } else {
             PeerStatus::NotInteresting
@@ -238,66 +365,130 @@ impl SimpleSync {
 
     /// Process a `Hello` message, requesting new blocks if appropriate.
     ///
-    /// Disconnects the peer if required.
+    /// A peer on another network or chain, or with a finalized epoch in the future, is forgotten
+    /// and disconnected with a `Goodbye`. Only a peer on another network, or which contradicts the
+    /// finalized checkpoint of its previous `Hello`, is banned: a peer on another fork, or whose
+    /// clock is ahead of ours, may be honest.
     fn process_hello(
         &mut self,
         peer_id: PeerId,
         hello: HelloMessage,
         network: &mut NetworkContext,
     ) {
-        let spec = self.chain.get_spec();
-
-        let remote = PeerSyncInfo::from(hello);
-        let local = PeerSyncInfo::from(&self.chain);
-        let remote_status = self.peer_status(remote);
+        let status = self.peer_status(&hello);
+        let previous = self.known_peers.get(&peer_id).cloned();
+        let contradicted = previous.map_or(false, |previous| previous.is_contradicted_by(&hello));
 
-        if remote_status.should_handshake() {
-            info!(self.log, "HandshakeSuccess"; "peer" => format!("{:?}", peer_id));
-            self.known_peers.insert(peer_id.clone(), remote);
-        } else {
+        if contradicted || !status.should_handshake() {
+            let reason = if contradicted {
+                "finalized checkpoint changed".to_string()
+            } else {
+                format!("{:?}", status)
+            };
             info!(
                 self.log, "HandshakeFailure";
                 "peer" => format!("{:?}", peer_id),
-                "reason" => "network_id"
+                "reason" => reason,
             );
-            network.disconnect(peer_id.clone(), GoodbyeReason::IrreleventNetwork);
+            self.remove_peer(&peer_id, network);
+
+            // A penalty bans the peer, which sends it a `Goodbye` and disconnects it.
+            match status {
+                _ if contradicted => network.report_peer(peer_id, PeerAction::InconsistentHello),
+                PeerStatus::DifferentNetworkId => {
+                    network.report_peer(peer_id, PeerAction::WrongNetwork)
+                }
+                PeerStatus::FinalizedEpochNotInChain => {
+                    network.disconnect(peer_id, GoodbyeReason::IrreleventNetwork, None)
+                }
+                _ => network.disconnect(peer_id, GoodbyeReason::UnableToVerifyNetwork, None),
+            }
+            return;
         }
 
-        // If required, send additional requests.
-        match remote_status {
-            PeerStatus::HigherFinalizedEpoch => {
-                let start_slot = remote
-                    .latest_finalized_epoch
-                    .start_slot(spec.slots_per_epoch);
-                let required_slots = start_slot - local.best_slot;
+        match previous {
+            None => info!(self.log, "HandshakeSuccess"; "peer" => format!("{:?}", peer_id)),
+            Some(previous) if previous.status != status => debug!(
+                self.log, "PeerStatusChanged";
+                "peer" => format!("{:?}", peer_id),
+                "status" => format!("{:?}", status),
+            ),
+            Some(_) => {}
+        }
 
-                self.request_block_roots(
-                    peer_id,
-                    BeaconBlockRootsRequest {
-                        start_slot,
-                        count: required_slots.into(),
-                    },
-                    network,
-                );
+        let remote = PeerSyncInfo::new(hello, status, Instant::now());
+        self.known_peers.insert(peer_id.clone(), remote);
+
+        // If the peer has blocks we do not, download them.
+        match status {
+            PeerStatus::HigherFinalizedEpoch => {
+                self.request_state_block(&peer_id, &remote, network);
+                self.range_sync.add_peer(peer_id, remote.best_slot, network);
             }
             PeerStatus::HigherBestSlot => {
-                let required_slots = remote.best_slot - local.best_slot;
-
-                self.request_block_roots(
-                    peer_id,
-                    BeaconBlockRootsRequest {
-                        start_slot: local.best_slot + 1,
-                        count: required_slots.into(),
-                    },
-                    network,
-                );
+                self.range_sync.add_peer(peer_id, remote.best_slot, network);
             }
             PeerStatus::FinalizedEpochNotInChain => {}
+            PeerStatus::FinalizedEpochInFuture => {}
             PeerStatus::DifferentNetworkId => {}
             PeerStatus::NotInteresting => {}
         }
     }
 
+    /// Requests the finalized block of a peer which has finalized at least
+    /// `STATE_DOWNLOAD_DISTANCE` epochs beyond us, so that its state can be downloaded.
+    ///
+    /// Only one state is downloaded at a time.
+    fn request_state_block(
+        &mut self,
+        peer_id: &PeerId,
+        remote: &PeerSyncInfo,
+        network: &mut NetworkContext,
+    ) {
+        let local_finalized_epoch = self.chain.finalized_epoch();
+        if remote.latest_finalized_epoch < local_finalized_epoch + STATE_DOWNLOAD_DISTANCE
+            || self.state_block_request.is_some()
+            || self.state_download.is_some()
+            || self.chain_has_seen_block(&remote.latest_finalized_root)
+        {
+            return;
+        }
+
+        let block_root = remote.latest_finalized_root;
+        let request_id = network.send_rpc_request(
+            peer_id.clone(),
+            RPCRequest::BeaconBlocksByRoot(BeaconBlocksByRootRequest {
+                block_roots: vec![block_root],
+            }),
+        );
+        self.state_block_request = Some((peer_id.clone(), request_id, block_root));
+    }
+
+    /// Returns `true` if `request_id` to `peer_id` is the request for a block whose state is to
+    /// be downloaded.
+    fn is_state_block_request(&self, peer_id: &PeerId, request_id: &RequestId) -> bool {
+        self.state_block_request
+            .as_ref()
+            .map_or(false, |(request_peer, id, _)| {
+                request_peer == peer_id && id == request_id
+            })
+    }
+
+    /// Repeats the `Hello` handshake with each peer whose last `Hello` is older than
+    /// `REHANDSHAKE_INTERVAL`. A peer which does not respond is forgotten.
+    fn rehandshake_peers(&mut self, network: &mut NetworkContext) {
+        let now = Instant::now();
+        let hello = self.chain.hello_message();
+
+        for (peer_id, info) in self.known_peers.iter_mut() {
+            if info.is_due_rehandshake(now) {
+                debug!(self.log, "Rehandshake"; "peer" => format!("{:?}", peer_id));
+                info.last_hello = now;
+                network.send_rpc_request(peer_id.clone(), RPCRequest::Hello(hello.clone()));
+            }
+        }
+    }
+
     /// Handle a `BeaconBlockRoots` request from the peer.
     pub fn on_beacon_block_roots_request(
         &mut self,
@@ -319,13 +510,19 @@ impl SimpleSync {
         {
             Ok(roots) => roots,
             Err(e) => {
-                // TODO: return RPC error.
                 warn!(
                     self.log,
                     "RPCRequest"; "peer" => format!("{:?}", peer_id),
                     "req" => "BeaconBlockRoots",
                     "error" => format!("{:?}", e)
                 );
+                network.send_rpc_error_response(
+                    peer_id,
+                    request_id,
+                    RPCMethod::BeaconBlockRoots,
+                    error_response_code(&e),
+                    format!("{:?}", e),
+                );
                 return;
             }
         };
@@ -346,98 +543,142 @@ impl SimpleSync {
         )
     }
 
-    /// Handle a `BeaconBlockRoots` response from the peer.
-    pub fn on_beacon_block_roots_response(
+    /// Handle a `BeaconBlockHeaders` request from the peer.
+    pub fn on_beacon_block_headers_request(
         &mut self,
         peer_id: PeerId,
-        res: BeaconBlockRootsResponse,
+        request_id: RequestId,
+        req: BeaconBlockHeadersRequest,
         network: &mut NetworkContext,
     ) {
         debug!(
             self.log,
-            "BlockRootsResponse";
+            "BlockHeadersRequest";
             "peer" => format!("{:?}", peer_id),
-            "count" => res.roots.len(),
+            "count" => req.max_headers,
         );
 
-        if res.roots.is_empty() {
-            warn!(
-                self.log,
-                "Peer returned empty block roots response";
-                "peer_id" => format!("{:?}", peer_id)
-            );
-            return;
-        }
-
-        // The wire protocol specifies that slots must be in ascending order.
-        if !res.slots_are_ascending() {
-            warn!(
-                self.log,
-                "Peer returned block roots response with bad slot ordering";
-                "peer_id" => format!("{:?}", peer_id)
-            );
-            return;
-        }
+        let headers = match self.chain.get_block_headers(
+            req.start_slot,
+            req.max_headers as usize,
+            req.skip_slots as usize,
+        ) {
+            Ok(headers) => headers,
+            Err(e) => {
+                warn!(
+                    self.log,
+                    "RPCRequest"; "peer" => format!("{:?}", peer_id),
+                    "req" => "BeaconBlockHeaders",
+                    "error" => format!("{:?}", e)
+                );
+                network.send_rpc_error_response(
+                    peer_id,
+                    request_id,
+                    RPCMethod::BeaconBlockHeaders,
+                    error_response_code(&e),
+                    format!("{:?}", e),
+                );
+                return;
+            }
+        };
 
-        let new_roots = self
-            .import_queue
-            .enqueue_block_roots(&res.roots, peer_id.clone());
+        network.send_rpc_response(
+            peer_id,
+            request_id,
+            RPCResponse::BeaconBlockHeaders(BeaconBlockHeadersResponse { headers }),
+        )
+    }
 
-        // No new roots means nothing to do.
-        //
-        // This check protects against future panics.
-        if new_roots.is_empty() {
-            return;
-        }
+    /// Handle a `BeaconBlockBodies` request from the peer.
+    pub fn on_beacon_block_bodies_request(
+        &mut self,
+        peer_id: PeerId,
+        request_id: RequestId,
+        req: BeaconBlockBodiesRequest,
+        network: &mut NetworkContext,
+    ) {
+        debug!(
+            self.log,
+            "BlockBodiesRequest";
+            "peer" => format!("{:?}", peer_id),
+            "count" => req.block_roots.len(),
+        );
 
-        // Determine the first (earliest) and last (latest) `BlockRootSlot` items.
-        //
-        // This logic relies upon slots to be in ascending order, which is enforced earlier.
-        let first = new_roots.first().expect("Non-empty list must have first");
-        let last = new_roots.last().expect("Non-empty list must have last");
+        let block_bodies = match self.chain.get_block_bodies(&req.block_roots) {
+            Ok(bodies) => bodies,
+            Err(e) => {
+                warn!(
+                    self.log,
+                    "RPCRequest"; "peer" => format!("{:?}", peer_id),
+                    "req" => "BeaconBlockBodies",
+                    "error" => format!("{:?}", e)
+                );
+                network.send_rpc_error_response(
+                    peer_id,
+                    request_id,
+                    RPCMethod::BeaconBlockBodies,
+                    error_response_code(&e),
+                    format!("{:?}", e),
+                );
+                return;
+            }
+        };
 
-        // Request all headers between the earliest and latest new `BlockRootSlot` items.
-        self.request_block_headers(
+        network.send_rpc_response(
             peer_id,
-            BeaconBlockHeadersRequest {
-                start_root: first.block_root,
-                start_slot: first.slot,
-                max_headers: (last.slot - first.slot + 1).as_u64(),
-                skip_slots: 0,
-            },
-            network,
+            request_id,
+            RPCResponse::BeaconBlockBodies(BeaconBlockBodiesResponse { block_bodies }),
         )
     }
 
-    /// Handle a `BeaconBlockHeaders` request from the peer.
-    pub fn on_beacon_block_headers_request(
+    /// Handle a `BeaconBlocksByRange` request from the peer.
+    pub fn on_beacon_blocks_by_range_request(
         &mut self,
         peer_id: PeerId,
         request_id: RequestId,
-        req: BeaconBlockHeadersRequest,
+        req: BeaconBlocksByRangeRequest,
         network: &mut NetworkContext,
     ) {
         debug!(
             self.log,
-            "BlockHeadersRequest";
+            "BlocksByRangeRequest";
             "peer" => format!("{:?}", peer_id),
-            "count" => req.max_headers,
+            "start_slot" => req.start_slot,
+            "count" => req.count,
+            "step" => req.step,
         );
 
-        let headers = match self.chain.get_block_headers(
+        if req.step == 0 {
+            network.send_rpc_error_response(
+                peer_id,
+                request_id,
+                RPCMethod::BeaconBlocksByRange,
+                ResponseCode::InvalidRequest,
+                "Step must be at least 1".to_string(),
+            );
+            return;
+        }
+
+        let blocks = match self.chain.get_blocks_by_range(
             req.start_slot,
-            req.max_headers as usize,
-            req.skip_slots as usize,
+            req.count as usize,
+            req.step as usize,
         ) {
-            Ok(headers) => headers,
+            Ok(blocks) => blocks,
             Err(e) => {
-                // TODO: return RPC error.
                 warn!(
                     self.log,
                     "RPCRequest"; "peer" => format!("{:?}", peer_id),
-                    "req" => "BeaconBlockHeaders",
+                    "req" => "BeaconBlocksByRange",
                     "error" => format!("{:?}", e)
                 );
+                network.send_rpc_error_response(
+                    peer_id,
+                    request_id,
+                    RPCMethod::BeaconBlocksByRange,
+                    error_response_code(&e),
+                    format!("{:?}", e),
+                );
                 return;
             }
         };
@@ -445,66 +686,68 @@ impl SimpleSync {
         network.send_rpc_response(
             peer_id,
             request_id,
-            RPCResponse::BeaconBlockHeaders(BeaconBlockHeadersResponse { headers }),
+            RPCResponse::BeaconBlocksByRange(BeaconBlocksResponse { blocks }),
         )
     }
 
-    /// Handle a `BeaconBlockHeaders` response from the peer.
-    pub fn on_beacon_block_headers_response(
+    /// Handle a `BeaconBlocksByRange` response from the peer.
+    pub fn on_beacon_blocks_by_range_response(
         &mut self,
         peer_id: PeerId,
-        res: BeaconBlockHeadersResponse,
+        request_id: RequestId,
+        res: BeaconBlocksResponse,
         network: &mut NetworkContext,
     ) {
         debug!(
             self.log,
-            "BlockHeadersResponse";
+            "BlocksByRangeResponse";
             "peer" => format!("{:?}", peer_id),
-            "count" => res.headers.len(),
+            "count" => res.blocks.len(),
         );
 
-        if res.headers.is_empty() {
-            warn!(
-                self.log,
-                "Peer returned empty block headers response. PeerId: {:?}", peer_id
-            );
+        if !self
+            .range_sync
+            .on_blocks_by_range_response(&peer_id, &request_id, res, network)
+        {
+            debug!(self.log, "UnexpectedBlocksByRangeResponse"; "peer" => format!("{:?}", peer_id));
             return;
         }
 
-        // Enqueue the headers, obtaining a list of the roots of the headers which were newly added
-        // to the queue.
-        let block_roots = self
-            .import_queue
-            .enqueue_headers(res.headers, peer_id.clone());
-
-        self.request_block_bodies(peer_id, BeaconBlockBodiesRequest { block_roots }, network);
+        // Queued gossip blocks may build upon the blocks which were imported.
+        self.process_import_queue(network);
     }
 
-    /// Handle a `BeaconBlockBodies` request from the peer.
-    pub fn on_beacon_block_bodies_request(
+    /// Handle a `BeaconBlocksByRoot` request from the peer.
+    pub fn on_beacon_blocks_by_root_request(
         &mut self,
         peer_id: PeerId,
         request_id: RequestId,
-        req: BeaconBlockBodiesRequest,
+        req: BeaconBlocksByRootRequest,
         network: &mut NetworkContext,
     ) {
         debug!(
             self.log,
-            "BlockBodiesRequest";
+            "BlocksByRootRequest";
             "peer" => format!("{:?}", peer_id),
             "count" => req.block_roots.len(),
         );
 
-        let block_bodies = match self.chain.get_block_bodies(&req.block_roots) {
-            Ok(bodies) => bodies,
+        let blocks = match self.chain.get_blocks_by_root(&req.block_roots) {
+            Ok(blocks) => blocks,
             Err(e) => {
-                // TODO: return RPC error.
                 warn!(
                     self.log,
                     "RPCRequest"; "peer" => format!("{:?}", peer_id),
-                    "req" => "BeaconBlockBodies",
+                    "req" => "BeaconBlocksByRoot",
                     "error" => format!("{:?}", e)
                 );
+                network.send_rpc_error_response(
+                    peer_id,
+                    request_id,
+                    RPCMethod::BeaconBlocksByRoot,
+                    error_response_code(&e),
+                    format!("{:?}", e),
+                );
                 return;
             }
         };
@@ -512,68 +755,256 @@ impl SimpleSync {
         network.send_rpc_response(
             peer_id,
             request_id,
-            RPCResponse::BeaconBlockBodies(BeaconBlockBodiesResponse { block_bodies }),
+            RPCResponse::BeaconBlocksByRoot(BeaconBlocksResponse { blocks }),
         )
     }
 
-    /// Handle a `BeaconBlockBodies` response from the peer.
-    pub fn on_beacon_block_bodies_response(
+    /// Handle a `BeaconBlocksByRoot` response from the peer.
+    pub fn on_beacon_blocks_by_root_response(
         &mut self,
         peer_id: PeerId,
-        res: BeaconBlockBodiesResponse,
+        request_id: RequestId,
+        res: BeaconBlocksResponse,
         network: &mut NetworkContext,
     ) {
         debug!(
             self.log,
-            "BlockBodiesResponse";
+            "BlocksByRootResponse";
             "peer" => format!("{:?}", peer_id),
-            "count" => res.block_bodies.len(),
+            "count" => res.blocks.len(),
         );
 
-        self.import_queue
-            .enqueue_bodies(res.block_bodies, peer_id.clone());
+        if self.is_state_block_request(&peer_id, &request_id) {
+            let (_, _, block_root) = self
+                .state_block_request
+                .take()
+                .expect("the request is for a state block");
+            match res
+                .blocks
+                .into_iter()
+                .find(|block| block.block_header().canonical_root() == block_root)
+            {
+                Some(block) => self.download_state(peer_id, block, network),
+                None => network.report_peer(peer_id, PeerAction::InvalidResponse),
+            }
+            return;
+        }
 
-        // Clear out old entries
-        self.import_queue.remove_stale();
+        if self
+            .attestation_queue
+            .on_block_response(&peer_id, &request_id, &res.blocks)
+        {
+            // The blocks were requested for queued attestations. They are processed as though
+            // they arrived via gossip, so that unknown parents are looked up.
+            for block in res.blocks {
+                self.on_block_gossip(peer_id.clone(), block, network);
+            }
+        } else if !self.parent_lookups.on_blocks_by_root_response(
+            &peer_id,
+            &request_id,
+            res,
+            network,
+        ) {
+            debug!(self.log, "UnexpectedBlocksByRootResponse"; "peer" => format!("{:?}", peer_id));
+            return;
+        }
 
-        // Import blocks, if possible.
+        // Queued gossip blocks may build upon the blocks which were imported.
         self.process_import_queue(network);
     }
 
-    /// Process a gossip message declaring a new block.
+    /// Handle a `BeaconChainState` request from the peer.
     ///
-    /// Returns a `bool` which, if `true`, indicates we should forward the block to our peers.
-    pub fn on_block_gossip(
+    /// Responds with the requested chunks of the SSZ encoded state, which is not decoded.
+    pub fn on_beacon_chain_state_request(
+        &mut self,
+        peer_id: PeerId,
+        request_id: RequestId,
+        req: BeaconChainStateRequest,
+        network: &mut NetworkContext,
+    ) {
+        debug!(
+            self.log,
+            "BeaconChainStateRequest";
+            "peer" => format!("{:?}", peer_id),
+            "state_root" => format!("{}", req.state_root),
+            "start_chunk" => req.start_chunk,
+            "count" => req.count,
+        );
+
+        let (code, message) = match self.chain.get_state_ssz(&req.state_root) {
+            Ok(Some(ssz)) => match req.select(&ssz) {
+                Some(bytes) => {
+                    let response = BeaconChainStateResponse {
+                        state_root: req.state_root,
+                        state_len: ssz.len() as u64,
+                        start_chunk: req.start_chunk,
+                        bytes: bytes.to_vec(),
+                    };
+
+                    return network.send_rpc_response(
+                        peer_id,
+                        request_id,
+                        RPCResponse::BeaconChainState(response),
+                    );
+                }
+                None => (
+                    ResponseCode::InvalidRequest,
+                    "Chunks are beyond the end of the state".to_string(),
+                ),
+            },
+            Ok(None) => (
+                ResponseCode::ResourceUnavailable,
+                "Unknown state root".to_string(),
+            ),
+            Err(e) => {
+                warn!(
+                    self.log,
+                    "RPCRequest"; "peer" => format!("{:?}", peer_id),
+                    "req" => "BeaconChainState",
+                    "error" => format!("{:?}", e)
+                );
+                (error_response_code(&e), format!("{:?}", e))
+            }
+        };
+
+        network.send_rpc_error_response(
+            peer_id,
+            request_id,
+            RPCMethod::BeaconChainState,
+            code,
+            message,
+        );
+    }
+
+    /// Starts downloading the state of `block` from `peer_id`, replacing any download in
+    /// progress.
+    ///
+    /// Once the state has been downloaded and verified against the `state_root` of `block`, the
+    /// state and block are written to the stores of the chain. Blocks building upon `block` can
+    /// then be imported.
+    pub fn download_state(
         &mut self,
         peer_id: PeerId,
         block: BeaconBlock,
         network: &mut NetworkContext,
-    ) -> bool {
+    ) {
         info!(
+            self.log, "StateDownloadStarted";
+            "peer" => format!("{:?}", peer_id),
+            "slot" => block.slot,
+            "state_root" => format!("{}", block.state_root),
+        );
+
+        let download = StateDownload::start(block.state_root, peer_id, network);
+        self.state_download = Some((block, download));
+    }
+
+    /// Handle a `BeaconChainState` response from the peer.
+    pub fn on_beacon_chain_state_response(
+        &mut self,
+        peer_id: PeerId,
+        request_id: RequestId,
+        res: BeaconChainStateResponse,
+        network: &mut NetworkContext,
+    ) {
+        debug!(
             self.log,
-            "NewGossipBlock";
+            "BeaconChainStateResponse";
             "peer" => format!("{:?}", peer_id),
+            "start_chunk" => res.start_chunk,
+            "total_chunks" => res.total_chunks(),
         );
 
-        // Ignore any block from a finalized slot.
-        if self.slot_is_finalized(block.slot) {
+        if !self.is_state_download_request(&peer_id, &request_id) {
+            debug!(self.log, "UnexpectedBeaconChainStateResponse"; "peer" => format!("{:?}", peer_id));
+            return;
+        }
+
+        let (_, download) = self
+            .state_download
+            .as_mut()
+            .expect("the request belongs to a download");
+
+        match download.on_response(res, network) {
+            Ok(None) => {}
+            Ok(Some(state)) => {
+                let (block, _) = self
+                    .state_download
+                    .take()
+                    .expect("the request belongs to a download");
+
+                // The state is stored first, as the block is useless without it.
+                match self
+                    .chain
+                    .store_state(&state)
+                    .and_then(|_| self.chain.store_block(&block))
+                {
+                    Ok(block_root) => info!(
+                        self.log, "StateDownloaded";
+                        "block_root" => format!("{}", block_root),
+                        "state_root" => format!("{}", block.state_root),
+                        "slot" => state.slot,
+                    ),
+                    Err(e) => error!(
+                        self.log, "StateDownloadFailed";
+                        "msg" => "unable to store state",
+                        "error" => format!("{:?}", e),
+                    ),
+                }
+            }
+            Err(e) => {
+                warn!(
+                    self.log, "StateDownloadFailed";
+                    "peer" => format!("{:?}", peer_id),
+                    "error" => format!("{:?}", e),
+                );
+                self.state_download = None;
+                network.report_peer(peer_id, PeerAction::InvalidResponse);
+            }
+        }
+    }
+
+    /// Returns `true` if `request_id` to `peer_id` is the outstanding request of the state
+    /// download.
+    fn is_state_download_request(&self, peer_id: &PeerId, request_id: &RequestId) -> bool {
+        self.state_download.as_ref().map_or(false, |(_, download)| {
+            download.is_request(peer_id, request_id)
+        })
+    }
+
+    /// Abandons the state download in progress.
+    fn abandon_state_download(&mut self, reason: &str) {
+        if let Some((_, download)) = self.state_download.take() {
             warn!(
-                self.log, "NewGossipBlock";
-                "msg" => "new block slot is finalized.",
-                "block_slot" => block.slot,
+                self.log, "StateDownloadAbandoned";
+                "peer" => format!("{:?}", download.peer_id()),
+                "state_root" => format!("{}", download.state_root()),
+                "reason" => reason,
             );
-            return false;
         }
+    }
 
-        let block_root = Hash256::from_slice(&block.tree_hash_root());
+    /// Performs the fast checks on a gossip block which decide whether it is propagated to our
+    /// peers. Only accepted blocks should be passed to `on_block_gossip`.
+    pub fn validate_block_gossip(&mut self, block: &BeaconBlock) -> MessageAcceptance {
+        self.gossip_validator.validate_block(block)
+    }
 
-        // Ignore any block that the chain already knows about.
-        if self.chain_has_seen_block(&block_root) {
-            println!("this happened");
-            // TODO: Age confirm that we shouldn't forward a block if we already know of it.
-            return false;
-        }
+    /// Performs the fast checks on a gossip attestation which decide whether it is propagated to
+    /// our peers. Only accepted attestations should be passed to `on_attestation_gossip`.
+    pub fn validate_attestation_gossip(&mut self, attestation: &Attestation) -> MessageAcceptance {
+        self.gossip_validator.validate_attestation(attestation)
+    }
 
+    /// Process a gossip message declaring a new block, which has been accepted by
+    /// `validate_block_gossip`.
+    pub fn on_block_gossip(
+        &mut self,
+        peer_id: PeerId,
+        block: BeaconBlock,
+        network: &mut NetworkContext,
+    ) {
         debug!(
             self.log,
             "NewGossipBlock";
@@ -582,59 +1013,43 @@ impl SimpleSync {
         );
         match self.chain.process_block(block.clone()) {
             Ok(BlockProcessingOutcome::InvalidBlock(InvalidBlock::ParentUnknown)) => {
-                // The block was valid and we processed it successfully.
                 debug!(
                     self.log, "NewGossipBlock";
                     "msg" => "parent block unknown",
                     "parent_root" => format!("{}", block.previous_block_root),
                     "peer" => format!("{:?}", peer_id),
                 );
-                // Queue the block for later processing.
-                self.import_queue
-                    .enqueue_full_blocks(vec![block], peer_id.clone());
-                // Send a hello to learn of the clients best slot so we can then sync the require
-                // parent(s).
-                network.send_rpc_request(
-                    peer_id.clone(),
-                    RPCRequest::Hello(self.chain.hello_message()),
-                );
-                // Forward the block onto our peers.
-                //
-                // Note: this may need to be changed if we decide to only forward blocks if we have
-                // all required info.
-                true
+                // Look up the ancestors of the block, unless they are already being looked up.
+                // In that case, or if there are too many lookups, queue the block so that it is
+                // processed once its parent is imported.
+                if self
+                    .parent_lookups
+                    .is_looking_up(&block.previous_block_root)
+                {
+                    self.import_queue
+                        .enqueue_full_blocks(vec![block], peer_id.clone());
+                } else if let Err(block) =
+                    self.parent_lookups.start(block, peer_id.clone(), network)
+                {
+                    self.import_queue
+                        .enqueue_full_blocks(vec![block], peer_id.clone());
+                }
             }
             Ok(BlockProcessingOutcome::InvalidBlock(InvalidBlock::FutureSlot {
                 present_slot,
                 block_slot,
             })) => {
-                if block_slot - present_slot > FUTURE_SLOT_TOLERANCE {
-                    // The block is too far in the future, drop it.
-                    warn!(
-                        self.log, "NewGossipBlock";
-                        "msg" => "future block rejected",
-                        "present_slot" => present_slot,
-                        "block_slot" => block_slot,
-                        "FUTURE_SLOT_TOLERANCE" => FUTURE_SLOT_TOLERANCE,
-                        "peer" => format!("{:?}", peer_id),
-                    );
-                    // Do not forward the block around to peers.
-                    false
-                } else {
-                    // The block is in the future, but not too far.
-                    warn!(
-                        self.log, "NewGossipBlock";
-                        "msg" => "queuing future block",
-                        "present_slot" => present_slot,
-                        "block_slot" => block_slot,
-                        "FUTURE_SLOT_TOLERANCE" => FUTURE_SLOT_TOLERANCE,
-                        "peer" => format!("{:?}", peer_id),
-                    );
-                    // Queue the block for later processing.
-                    self.import_queue.enqueue_full_blocks(vec![block], peer_id);
-                    // Forward the block around to peers.
-                    true
-                }
+                // Gossip validation ignores blocks too far in the future, so this block is only
+                // slightly ahead of our slot clock.
+                debug!(
+                    self.log, "NewGossipBlock";
+                    "msg" => "queuing future block",
+                    "present_slot" => present_slot,
+                    "block_slot" => block_slot,
+                    "peer" => format!("{:?}", peer_id),
+                );
+                // Queue the block for later processing.
+                self.import_queue.enqueue_full_blocks(vec![block], peer_id);
             }
             Ok(outcome) => {
                 if outcome.is_invalid() {
@@ -645,10 +1060,8 @@ impl SimpleSync {
                         "outcome" => format!("{:?}", outcome),
                         "peer" => format!("{:?}", peer_id),
                     );
-                    // Disconnect the peer
-                    network.disconnect(peer_id, GoodbyeReason::Fault);
-                    // Do not forward the block to peers.
-                    false
+                    // Penalise the peer
+                    network.report_peer(peer_id, PeerAction::InvalidBlock);
                 } else if outcome.sucessfully_processed() {
                     // The block was valid and we processed it successfully.
                     info!(
@@ -656,8 +1069,7 @@ impl SimpleSync {
                         "msg" => "block import successful",
                         "peer" => format!("{:?}", peer_id),
                     );
-                    // Forward the block to peers
-                    true
+                    self.process_queued_attestations();
                 } else {
                     // The block wasn't necessarily invalid but we didn't process it successfully.
                     // This condition shouldn't be reached.
@@ -666,8 +1078,6 @@ impl SimpleSync {
                         "msg" => "unexpected condition in processing block.",
                         "outcome" => format!("{:?}", outcome),
                     );
-                    // Do not forward the block on.
-                    false
                 }
             }
             Err(e) => {
@@ -680,15 +1090,14 @@ impl SimpleSync {
                     "msg" => "internal error in processing block.",
                     "error" => format!("{:?}", e),
                 );
-                // Do not forward the block to peers.
-                false
             }
         }
     }
 
-    /// Process a gossip message declaring a new attestation.
+    /// Process a gossip message declaring a new attestation, which has been accepted by
+    /// `validate_attestation_gossip`.
     ///
-    /// Not currently implemented.
+    /// An attestation for a block we have not imported is queued until the block is imported.
     pub fn on_attestation_gossip(
         &mut self,
         peer_id: PeerId,
@@ -701,23 +1110,101 @@ impl SimpleSync {
             "peer" => format!("{:?}", peer_id),
         );
 
-        match self.chain.process_attestation(msg) {
+        let block_root = msg.data.beacon_block_root;
+        if self.chain_has_seen_block(&block_root) {
+            self.import_attestation(msg);
+        } else if self
+            .attestation_queue
+            .enqueue(msg, peer_id.clone(), Instant::now())
+        {
+            debug!(
+                self.log, "QueuedAttestation";
+                "block_root" => format!("{}", block_root),
+                "queued" => self.attestation_queue.len(),
+                "peer" => format!("{:?}", peer_id),
+            );
+        } else {
+            warn!(
+                self.log, "DroppedAttestation";
+                "msg" => "attestation queue full",
+                "block_root" => format!("{}", block_root),
+                "peer" => format!("{:?}", peer_id),
+            );
+        }
+    }
+
+    /// Processes an attestation whose block has been imported.
+    fn import_attestation(&self, attestation: Attestation) {
+        match self.chain.process_attestation(attestation) {
             Ok(()) => info!(self.log, "ImportedAttestation"),
             Err(e) => warn!(self.log, "InvalidAttestation"; "error" => format!("{:?}", e)),
         }
     }
 
+    /// Processes the queued attestations whose blocks have since been imported.
+    fn process_queued_attestations(&mut self) {
+        if self.attestation_queue.is_empty() {
+            return;
+        }
+
+        let chain = &self.chain;
+        let attestations = self
+            .attestation_queue
+            .take_known(|block_root| !chain.is_new_block_root(block_root).unwrap_or(true));
+
+        for (attestation, _peer_id) in attestations {
+            self.import_attestation(attestation);
+        }
+    }
+
+    /// Maintains the attestation queue and repeats due handshakes. Called every
+    /// `HEARTBEAT_INTERVAL`.
+    ///
+    /// Drops expired attestations, processes those whose blocks have been imported and requests
+    /// the blocks which have not arrived via gossip.
+    pub fn on_heartbeat(&mut self, network: &mut NetworkContext) {
+        self.rehandshake_peers(network);
+
+        let now = Instant::now();
+
+        let expired = self.attestation_queue.remove_expired(now);
+        if expired > 0 {
+            debug!(self.log, "Dropped {} expired queued attestations", expired);
+        }
+
+        self.process_queued_attestations();
+
+        for (block_root, peer_id) in self.attestation_queue.blocks_to_request(now) {
+            debug!(
+                self.log, "RequestAttestedBlock";
+                "block_root" => format!("{}", block_root),
+                "peer" => format!("{:?}", peer_id),
+            );
+            let request_id = network.send_rpc_request(
+                peer_id.clone(),
+                RPCRequest::BeaconBlocksByRoot(BeaconBlocksByRootRequest {
+                    block_roots: vec![block_root],
+                }),
+            );
+            self.attestation_queue
+                .on_block_requested(&block_root, peer_id, request_id);
+        }
+    }
+
     /// Iterate through the `import_queue` and process any complete blocks.
     ///
     /// If a block is successfully processed it is removed from the queue, otherwise it remains in
     /// the queue.
     pub fn process_import_queue(&mut self, network: &mut NetworkContext) {
+        // Clear out old entries
+        self.import_queue.remove_stale();
+
         let mut successful = 0;
         let mut invalid = 0;
         let mut errored = 0;
 
         // Loop through all of the complete blocks in the queue.
-        for (block_root, block, sender) in self.import_queue.complete_blocks() {
+        for (block_root, block, sender) in self.import_queue.queued_blocks() {
             match self.chain.process_block(block) {
                 Ok(outcome) => {
                     if outcome.is_invalid() {
@@ -728,7 +1215,7 @@ impl SimpleSync {
                             "sender_peer_id" => format!("{:?}", sender),
                             "reason" => format!("{:?}", outcome),
                         );
-                        network.disconnect(sender, GoodbyeReason::Fault);
+                        network.report_peer(sender, PeerAction::InvalidBlock);
                         break;
                     }
 
@@ -762,64 +1249,10 @@ impl SimpleSync {
         if errored > 0 {
             warn!(self.log, "Failed to process {} blocks", errored)
         }
-    }
 
-    /// Request some `BeaconBlockRoots` from the remote peer.
-    fn request_block_roots(
-        &mut self,
-        peer_id: PeerId,
-        req: BeaconBlockRootsRequest,
-        network: &mut NetworkContext,
-    ) {
-        // Potentially set state to sync.
-        if self.state == SyncState::Idle && req.count > SLOT_IMPORT_TOLERANCE {
-            debug!(self.log, "Entering downloading sync state.");
-            self.state = SyncState::Downloading;
+        if successful > 0 {
+            self.process_queued_attestations();
         }
-
-        debug!(
-            self.log,
-            "RPCRequest(BeaconBlockRoots)";
-            "count" => req.count,
-            "peer" => format!("{:?}", peer_id)
-        );
-
-        // TODO: handle count > max count.
-        network.send_rpc_request(peer_id.clone(), RPCRequest::BeaconBlockRoots(req));
-    }
-
-    /// Request some `BeaconBlockHeaders` from the remote peer.
-    fn request_block_headers(
-        &mut self,
-        peer_id: PeerId,
-        req: BeaconBlockHeadersRequest,
-        network: &mut NetworkContext,
-    ) {
-        debug!(
-            self.log,
-            "RPCRequest(BeaconBlockHeaders)";
-            "max_headers" => req.max_headers,
-            "peer" => format!("{:?}", peer_id)
-        );
-
-        network.send_rpc_request(peer_id.clone(), RPCRequest::BeaconBlockHeaders(req));
-    }
-
-    /// Request some `BeaconBlockBodies` from the remote peer.
-    fn request_block_bodies(
-        &mut self,
-        peer_id: PeerId,
-        req: BeaconBlockBodiesRequest,
-        network: &mut NetworkContext,
-    ) {
-        debug!(
-            self.log,
-            "RPCRequest(BeaconBlockBodies)";
-            "count" => req.block_roots.len(),
-            "peer" => format!("{:?}", peer_id)
-        );
-
-        network.send_rpc_request(peer_id.clone(), RPCRequest::BeaconBlockBodies(req));
     }
 
     /// Returns `true` if `self.chain` has not yet processed this block.
@@ -833,17 +1266,130 @@ impl SimpleSync {
             })
     }
 
-    /// Returns `true` if the given slot is finalized in our chain.
-    fn slot_is_finalized(&self, slot: Slot) -> bool {
-        slot <= self
-            .chain
-            .hello_message()
-            .latest_finalized_epoch
-            .start_slot(self.chain.get_spec().slots_per_epoch)
-    }
-
     /// Generates our current state in the form of a HELLO RPC message.
     pub fn generate_hello(&self) -> HelloMessage {
         self.chain.hello_message()
     }
 }
+
+/// Returns the `ResponseCode` sent to a peer whose request failed with `error`.
+///
+/// Requests for slots or blocks we do not have are not our fault, all other errors are.
+fn error_response_code(error: &BeaconChainError) -> ResponseCode {
+    match error {
+        BeaconChainError::BeaconStateError(BeaconStateError::SlotOutOfBounds)
+        | BeaconChainError::MissingBeaconBlock(_) => ResponseCode::ResourceUnavailable,
+        _ => ResponseCode::ServerError,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::service::NetworkMessage;
+    use crate::sync::test_utils::*;
+    use ssz::ssz_encode;
+
+    fn hello(finalized_epoch: u64, finalized_root: u64) -> HelloMessage {
+        HelloMessage {
+            network_id: 1,
+            latest_finalized_root: Hash256::from_low_u64_be(finalized_root),
+            latest_finalized_epoch: Epoch::new(finalized_epoch),
+            best_root: Hash256::zero(),
+            best_slot: Slot::new(0),
+        }
+    }
+
+    #[test]
+    fn finalized_checkpoint_must_not_change() {
+        let info = PeerSyncInfo::new(hello(2, 1), PeerStatus::NotInteresting, Instant::now());
+
+        assert!(!info.is_contradicted_by(&hello(2, 1)));
+        assert!(!info.is_contradicted_by(&hello(3, 2)));
+        assert!(info.is_contradicted_by(&hello(2, 2)));
+        assert!(info.is_contradicted_by(&hello(1, 1)));
+    }
+
+    #[test]
+    fn rehandshake_is_due_after_interval() {
+        let now = Instant::now();
+        let info = PeerSyncInfo::new(hello(0, 0), PeerStatus::NotInteresting, now);
+
+        assert!(!info.is_due_rehandshake(now));
+        assert!(info.is_due_rehandshake(now + REHANDSHAKE_INTERVAL));
+    }
+
+    #[test]
+    fn downloaded_states_are_stored_with_their_block() {
+        let mut harnesses = harnesses(2);
+        harnesses[0].advance_chain_with_block();
+        let (block, block_root, state) = {
+            let head = harnesses[0].beacon_chain.head();
+            (
+                head.beacon_block.clone(),
+                head.beacon_block_root,
+                head.beacon_state.clone(),
+            )
+        };
+        let syncing = harnesses.pop().expect("two harnesses were built");
+        let mut sync = SimpleSync::new(syncing.beacon_chain.clone(), &logger());
+        let (mut network, receiver, _) = network();
+        let peer_id = PeerId::random();
+
+        sync.download_state(peer_id.clone(), block.clone(), &mut network);
+        let (request_id, request) = match sent_requests(&receiver).pop() {
+            Some((_, id, RPCRequest::BeaconChainState(request))) => (id, request),
+            other => panic!("unexpected request: {:?}", other),
+        };
+        let ssz = ssz_encode(&state);
+        let response = BeaconChainStateResponse {
+            state_root: block.state_root,
+            state_len: ssz.len() as u64,
+            start_chunk: 0,
+            bytes: request.select(&ssz).unwrap().to_vec(),
+        };
+        sync.on_beacon_chain_state_response(peer_id, request_id, response, &mut network);
+
+        assert!(sync.state_download.is_none());
+        assert_eq!(syncing.beacon_chain.get_block(&block_root), Ok(Some(block)));
+        assert!(syncing
+            .beacon_chain
+            .get_state_ssz(&state.canonical_root())
+            .unwrap()
+            .is_some());
+    }
+
+    #[test]
+    fn only_peers_on_another_network_are_banned() {
+        let harness = harnesses(1).pop().expect("one harness was built");
+        let mut sync = SimpleSync::new(harness.beacon_chain.clone(), &logger());
+        let (mut network, receiver, peer_manager) = network();
+        let (ahead, elsewhere) = (PeerId::random(), PeerId::random());
+
+        // a finalized epoch in the future may be due to clock skew.
+        let mut future = sync.generate_hello();
+        future.latest_finalized_epoch += 100;
+        sync.process_hello(ahead.clone(), future, &mut network);
+
+        let mut other_network = sync.generate_hello();
+        other_network.network_id += 1;
+        sync.process_hello(elsewhere.clone(), other_network, &mut network);
+
+        let disconnects: Vec<(PeerId, bool)> = receiver
+            .try_iter()
+            .filter_map(|message| match message {
+                NetworkMessage::Disconnect {
+                    peer_id,
+                    ban_duration,
+                } => Some((peer_id, ban_duration.is_some())),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            disconnects,
+            vec![(ahead.clone(), false), (elsewhere.clone(), true)]
+        );
+        assert!(!peer_manager.read().is_banned(&ahead));
+        assert!(peer_manager.read().is_banned(&elsewhere));
+    }
+}
@@ -0,0 +1,217 @@
+use crate::message_handler::NetworkContext;
+use eth2_libp2p::rpc::methods::*;
+use eth2_libp2p::rpc::{RPCRequest, RequestId};
+use eth2_libp2p::PeerId;
+use ssz::decode;
+use types::{BeaconState, Hash256};
+
+/// The largest encoded state which is downloaded, so that a peer cannot make us buffer an
+/// unbounded amount of data.
+const MAX_STATE_LEN: u64 = 256 * 1024 * 1024;
+
+/// The reasons a `BeaconChainState` response is not accepted by a `StateDownload`.
+#[derive(Debug, PartialEq)]
+pub enum StateDownloadError {
+    /// The response is for another state, or its chunks do not follow on from those already
+    /// downloaded.
+    UnexpectedChunks,
+    /// The peer claims the encoded state is longer than `MAX_STATE_LEN`.
+    StateTooLarge(u64),
+    /// The downloaded bytes are not a valid SSZ encoded `BeaconState`.
+    InvalidSsz,
+    /// The downloaded state has a different root to the one requested.
+    StateRootMismatch(Hash256),
+}
+
+/// Downloads the SSZ encoded `BeaconState` with some state root from a single peer.
+///
+/// The state is requested `MAX_STATE_CHUNKS` chunks at a time. Once the last chunk has arrived,
+/// the state is decoded and its tree hash root compared to the requested root. Requesting the
+/// `state_root` of a trusted block therefore yields a verified state, without replaying the chain
+/// from genesis.
+pub struct StateDownload {
+    /// The root of the state being downloaded.
+    state_root: Hash256,
+    /// The peer the state is downloaded from.
+    peer_id: PeerId,
+    /// The outstanding request for the next chunks.
+    request_id: RequestId,
+    /// The length of the encoded state, once known.
+    state_len: Option<u64>,
+    /// The bytes of the encoded state downloaded so far.
+    bytes: Vec<u8>,
+}
+
+impl StateDownload {
+    /// Starts downloading the state with `state_root` from `peer_id`.
+    pub fn start(state_root: Hash256, peer_id: PeerId, network: &mut NetworkContext) -> Self {
+        let request_id = request_chunks(peer_id.clone(), state_root, 0, network);
+
+        Self {
+            state_root,
+            peer_id,
+            request_id,
+            state_len: None,
+            bytes: vec![],
+        }
+    }
+
+    pub fn state_root(&self) -> Hash256 {
+        self.state_root
+    }
+
+    pub fn peer_id(&self) -> &PeerId {
+        &self.peer_id
+    }
+
+    /// Returns `true` if `request_id` to `peer_id` is the outstanding request of this download.
+    pub fn is_request(&self, peer_id: &PeerId, request_id: &RequestId) -> bool {
+        self.peer_id == *peer_id && self.request_id == *request_id
+    }
+
+    /// Handles the response to the outstanding request.
+    ///
+    /// Returns the verified state once it is complete, otherwise requests the next chunks and
+    /// returns `None`.
+    pub fn on_response(
+        &mut self,
+        res: BeaconChainStateResponse,
+        network: &mut NetworkContext,
+    ) -> Result<Option<BeaconState>, StateDownloadError> {
+        let state_len = self.state_len.unwrap_or(res.state_len);
+        if state_len > MAX_STATE_LEN {
+            return Err(StateDownloadError::StateTooLarge(state_len));
+        }
+
+        let next_chunk = self.bytes.len() as u64 / STATE_CHUNK_SIZE;
+        let new_len = self.bytes.len() as u64 + res.bytes.len() as u64;
+
+        // Every response but the last must contain whole chunks.
+        if res.state_root != self.state_root
+            || res.state_len != state_len
+            || res.start_chunk != next_chunk
+            || res.bytes.is_empty()
+            || new_len > state_len
+            || (new_len < state_len && res.bytes.len() as u64 % STATE_CHUNK_SIZE != 0)
+        {
+            return Err(StateDownloadError::UnexpectedChunks);
+        }
+
+        self.state_len = Some(state_len);
+        self.bytes.extend_from_slice(&res.bytes);
+
+        if new_len < state_len {
+            self.request_id = request_chunks(
+                self.peer_id.clone(),
+                self.state_root,
+                new_len / STATE_CHUNK_SIZE,
+                network,
+            );
+            return Ok(None);
+        }
+
+        let state: BeaconState = decode(&self.bytes).map_err(|_| StateDownloadError::InvalidSsz)?;
+        let state_root = state.canonical_root();
+
+        if state_root != self.state_root {
+            return Err(StateDownloadError::StateRootMismatch(state_root));
+        }
+
+        Ok(Some(state))
+    }
+}
+
+/// Requests `MAX_STATE_CHUNKS` chunks of the state with `state_root`, starting at `start_chunk`.
+fn request_chunks(
+    peer_id: PeerId,
+    state_root: Hash256,
+    start_chunk: u64,
+    network: &mut NetworkContext,
+) -> RequestId {
+    network.send_rpc_request(
+        peer_id,
+        RPCRequest::BeaconChainState(BeaconChainStateRequest {
+            state_root,
+            start_chunk,
+            count: MAX_STATE_CHUNKS,
+        }),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sync::test_utils::network;
+    use ssz::ssz_encode;
+    use types::test_utils::{SeedableRng, TestRandom, XorShiftRng};
+
+    fn response(state_root: Hash256, ssz: &[u8], start_chunk: u64) -> BeaconChainStateResponse {
+        let request = BeaconChainStateRequest {
+            state_root,
+            start_chunk,
+            count: MAX_STATE_CHUNKS,
+        };
+
+        BeaconChainStateResponse {
+            state_root,
+            state_len: ssz.len() as u64,
+            start_chunk,
+            bytes: request.select(ssz).unwrap().to_vec(),
+        }
+    }
+
+    #[test]
+    fn downloads_and_verifies_state() {
+        let (mut network, _, _) = network();
+        let mut rng = XorShiftRng::from_seed([42; 16]);
+        let state = BeaconState::random_for_test(&mut rng);
+        let state_root = state.canonical_root();
+        let ssz = ssz_encode(&state);
+
+        let mut download = StateDownload::start(state_root, PeerId::random(), &mut network);
+        let downloaded = download
+            .on_response(response(state_root, &ssz, 0), &mut network)
+            .unwrap();
+
+        assert_eq!(downloaded, Some(state));
+    }
+
+    #[test]
+    fn rejects_state_with_wrong_root() {
+        let (mut network, _, _) = network();
+        let mut rng = XorShiftRng::from_seed([42; 16]);
+        let ssz = ssz_encode(&BeaconState::random_for_test(&mut rng));
+        let state_root = Hash256::from_low_u64_be(1);
+
+        let mut download = StateDownload::start(state_root, PeerId::random(), &mut network);
+
+        assert_eq!(
+            download.on_response(response(Hash256::from_low_u64_be(2), &ssz, 0), &mut network),
+            Err(StateDownloadError::UnexpectedChunks)
+        );
+        match download.on_response(response(state_root, &ssz, 0), &mut network) {
+            Err(StateDownloadError::StateRootMismatch(_)) => {}
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn rejects_state_larger_than_maximum() {
+        let (mut network, _, _) = network();
+        let state_root = Hash256::from_low_u64_be(1);
+
+        let mut download = StateDownload::start(state_root, PeerId::random(), &mut network);
+        let res = BeaconChainStateResponse {
+            state_root,
+            state_len: MAX_STATE_LEN + 1,
+            start_chunk: 0,
+            bytes: vec![0; STATE_CHUNK_SIZE as usize],
+        };
+
+        assert_eq!(
+            download.on_response(res, &mut network),
+            Err(StateDownloadError::StateTooLarge(MAX_STATE_LEN + 1))
+        );
+        assert!(download.bytes.is_empty());
+    }
+}
@@ -0,0 +1,56 @@
+/// Helpers shared by the unit tests of the sync modules.
+use crate::message_handler::NetworkContext;
+use crate::peer_manager::PeerManager;
+use crate::service::{NetworkMessage, OutgoingMessage};
+use crossbeam_channel::{unbounded, Receiver};
+use eth2_libp2p::rpc::{RPCRequest, RequestId};
+use eth2_libp2p::{PeerId, RPCEvent};
+use parking_lot::RwLock;
+use std::sync::Arc;
+use test_harness::BeaconChainHarness;
+use types::{test_utils::TestingBeaconStateBuilder, ChainSpec};
+
+const VALIDATOR_COUNT: usize = 8;
+
+/// Builds `count` harnesses which share the same genesis state.
+pub fn harnesses(count: usize) -> Vec<BeaconChainHarness> {
+    let spec = ChainSpec::few_validators();
+    let state_builder =
+        TestingBeaconStateBuilder::from_default_keypairs_file_if_exists(VALIDATOR_COUNT, &spec);
+
+    (0..count)
+        .map(|_| BeaconChainHarness::from_beacon_state_builder(state_builder.clone(), spec.clone()))
+        .collect()
+}
+
+/// Builds a `NetworkContext`, returning the receiver of the messages it sends and its peer
+/// manager.
+pub fn network() -> (
+    NetworkContext,
+    Receiver<NetworkMessage>,
+    Arc<RwLock<PeerManager>>,
+) {
+    let (network_send, network_recv) = unbounded();
+    let peer_manager = Arc::new(RwLock::new(PeerManager::new()));
+    let network = NetworkContext::new(network_send, peer_manager.clone(), logger());
+
+    (network, network_recv, peer_manager)
+}
+
+/// Returns the RPC requests sent through the network since this was last called.
+pub fn sent_requests(receiver: &Receiver<NetworkMessage>) -> Vec<(PeerId, RequestId, RPCRequest)> {
+    receiver
+        .try_iter()
+        .filter_map(|message| match message {
+            NetworkMessage::Send(
+                peer_id,
+                OutgoingMessage::RPC(RPCEvent::Request { id, body, .. }),
+            ) => Some((peer_id, id, body)),
+            _ => None,
+        })
+        .collect()
+}
+
+pub fn logger() -> slog::Logger {
+    slog::Logger::root(slog::Discard, slog::o!())
+}
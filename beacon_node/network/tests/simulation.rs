@@ -0,0 +1,100 @@
+mod simulator;
+
+use simulator::{LinkConfig, Simulator};
+use sloggers::terminal::{Destination, TerminalLoggerBuilder};
+use sloggers::types::Severity;
+use sloggers::Build;
+
+const NODE_COUNT: usize = 4;
+const VALIDATOR_COUNT: usize = 8;
+
+fn get_logger() -> slog::Logger {
+    let mut builder = TerminalLoggerBuilder::new();
+    builder.level(Severity::Warning);
+    builder.destination(Destination::Stderr);
+    builder.build().unwrap()
+}
+
+#[test]
+fn heads_converge_when_connected() {
+    let mut simulator = Simulator::new(NODE_COUNT, VALIDATOR_COUNT, 1, get_logger());
+    simulator.connect_all();
+
+    let slots_per_epoch = simulator.spec().slots_per_epoch;
+    simulator.run_slots(slots_per_epoch * 2);
+
+    simulator.assert_heads_converged();
+}
+
+#[test]
+fn heads_converge_after_partition_heals() {
+    let mut simulator = Simulator::new(NODE_COUNT, VALIDATOR_COUNT, 2, get_logger());
+    simulator.connect_all();
+
+    let slots_per_epoch = simulator.spec().slots_per_epoch;
+    simulator.run_slots(slots_per_epoch);
+
+    // Each side of the partition builds its own fork. Once healed, the minority must reorg onto
+    // the fork of the majority.
+    simulator.partition(&[&[0, 1, 2], &[3]]);
+    simulator.run_slots(4);
+
+    let heads = simulator.heads();
+    assert_eq!(heads[0], heads[1]);
+    assert_eq!(heads[0], heads[2]);
+    assert_ne!(heads[0], heads[3], "the partition should cause a fork");
+
+    simulator.heal();
+    simulator.run_slots(slots_per_epoch * 2);
+
+    simulator.assert_heads_converged();
+}
+
+#[test]
+fn finalizes_with_latency() {
+    let mut simulator = Simulator::new(NODE_COUNT, VALIDATOR_COUNT, 3, get_logger());
+    simulator.set_default_link(LinkConfig {
+        latency_ms: 20,
+        jitter_ms: 30,
+        drop_rate: 0.0,
+    });
+    // The last node is far from the others, so its blocks and attestations arrive late.
+    for node in 0..NODE_COUNT - 1 {
+        simulator.set_link(
+            node,
+            NODE_COUNT - 1,
+            LinkConfig {
+                latency_ms: 100,
+                jitter_ms: 50,
+                drop_rate: 0.0,
+            },
+        );
+    }
+    simulator.connect_all();
+
+    // Justifying two consecutive epochs finalizes the first of them at the next epoch boundary.
+    let spec = simulator.spec().clone();
+    simulator.run_slots(spec.slots_per_epoch * 4);
+
+    simulator.assert_heads_converged();
+    simulator.assert_finalized(spec.genesis_epoch + 1);
+}
+
+// Runs for longer than the other simulations, use `cargo test -- --ignored` to include it.
+#[test]
+#[ignore]
+fn finalizes_with_latency_and_drops() {
+    let mut simulator = Simulator::new(NODE_COUNT, VALIDATOR_COUNT, 3, get_logger());
+    simulator.set_default_link(LinkConfig {
+        latency_ms: 50,
+        jitter_ms: 100,
+        drop_rate: 0.05,
+    });
+    simulator.connect_all();
+
+    let spec = simulator.spec().clone();
+    simulator.run_slots(spec.slots_per_epoch * 6);
+
+    simulator.assert_heads_converged();
+    simulator.assert_finalized(spec.genesis_epoch + 2);
+}
@@ -0,0 +1,622 @@
+//! An in-process simulation of a network of beacon nodes.
+//!
+//! Each node is a `BeaconChainHarness`, with its own `TestingSlotClock` and a share of the
+//! validators, plus a libp2p `Service` listening on a `/memory/<port>` address and a
+//! `MessageHandler` which is stepped by the simulator instead of being spawned. The nodes exchange
+//! gossip and RPC messages over the libp2p memory transport, so every message passes through
+//! gossipsub, the RPC codec, compression and the rate limiter as it would between real nodes.
+//!
+//! The simulator sits between each libp2p service and its handler. A message received by a node
+//! is held back for the latency and jitter of its link before it is handed to the handler, gossip
+//! messages are lost at the drop rate of their link, and messages between nodes which are
+//! partitioned from each other are lost. The conditions of each link are drawn from a seeded RNG,
+//! but the nodes run on a single-threaded runtime in wall-clock time, so a simulation is not
+//! exactly repeatable.
+
+use attester::Signer;
+use crossbeam_channel::{unbounded, Receiver};
+use eth2_libp2p::{
+    GossipKind, GossipTopic, GossipsubConfigBuilder, MessageAcceptance, Multiaddr, NetworkConfig,
+    PeerId, PubsubMessage, Service,
+};
+use futures::prelude::*;
+use network::message_handler::{HandlerMessage, MessageHandler};
+use network::peer_manager::PeerManager;
+use network::service::{handler_message, send_to_libp2p, NetworkMessage};
+use parking_lot::RwLock;
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use test_harness::BeaconChainHarness;
+use tokio::runtime::current_thread::Runtime;
+use tokio::timer::{Delay, DelayQueue};
+use tree_hash::TreeHash;
+use types::test_utils::{RngCore, SeedableRng, TestingBeaconStateBuilder, XorShiftRng};
+use types::*;
+
+/// The wall-clock duration of a slot.
+const SLOT_DURATION: Duration = Duration::from_millis(500);
+/// The interval of the gossipsub heartbeat, which adds newly connected peers to the mesh.
+const GOSSIP_HEARTBEAT: Duration = Duration::from_millis(100);
+/// The time allowed for newly connected nodes to handshake and join each other's gossip mesh.
+const CONNECT_DURATION: Duration = Duration::from_secs(1);
+
+/// The next unused memory transport port. Simulations running in parallel share the transport, so
+/// each node of each simulation listens on its own port.
+static NEXT_PORT: AtomicUsize = AtomicUsize::new(1);
+
+/// The conditions of the link between two nodes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LinkConfig {
+    /// The minimum time taken to deliver a message.
+    pub latency_ms: u64,
+    /// The maximum random delay added to `latency_ms`.
+    pub jitter_ms: u64,
+    /// The probability that a gossip message is dropped, between 0 and 1. RPC messages are sent
+    /// over reliable streams, so they are never dropped.
+    pub drop_rate: f64,
+}
+
+impl Default for LinkConfig {
+    fn default() -> Self {
+        Self {
+            latency_ms: 5,
+            jitter_ms: 0,
+            drop_rate: 0.0,
+        }
+    }
+}
+
+/// A message received by the libp2p service of node `to` from its peer `from`, which is held
+/// back for the latency of their link.
+struct InFlight {
+    from: usize,
+    to: usize,
+    message: HandlerMessage,
+}
+
+/// A beacon node in the simulation.
+pub struct SimNode {
+    pub harness: BeaconChainHarness,
+    pub peer_id: PeerId,
+    /// The memory transport address the node listens on.
+    address: Multiaddr,
+    service: Service,
+    handler: MessageHandler,
+    network_recv: Receiver<NetworkMessage>,
+    /// The indices of the validators run by this node.
+    validators: HashSet<usize>,
+}
+
+impl SimNode {
+    /// Builds node `index` of `node_count`, which runs every `node_count`th of the
+    /// `validator_count` validators, starting at `index`.
+    ///
+    /// Must be called on the runtime of the simulation, which runs the timers of the service.
+    fn new(
+        index: usize,
+        node_count: usize,
+        validator_count: usize,
+        state_builder: &TestingBeaconStateBuilder,
+        spec: &ChainSpec,
+        log: &slog::Logger,
+    ) -> Result<Self, String> {
+        let log = log.new(slog::o!("node" => index));
+        let harness =
+            BeaconChainHarness::from_beacon_state_builder(state_builder.clone(), spec.clone());
+
+        let address: Multiaddr = format!("/memory/{}", NEXT_PORT.fetch_add(1, Ordering::SeqCst))
+            .parse()
+            .map_err(|e| format!("Invalid memory address: {:?}", e))?;
+        let mut config = NetworkConfig::default();
+        config.listen_addresses = vec![address.clone()];
+        config.gs_config = GossipsubConfigBuilder::new()
+            .max_gossip_size(4_000_000)
+            .heartbeat_initial_delay(GOSSIP_HEARTBEAT)
+            .heartbeat_interval(GOSSIP_HEARTBEAT)
+            .build();
+        let service = Service::new(config, log.clone())
+            .map_err(|e| format!("Failed to start the libp2p service: {:?}", e))?;
+
+        let (network_send, network_recv) = unbounded();
+        let handler = MessageHandler::new(
+            harness.beacon_chain.clone(),
+            network_send,
+            Arc::new(RwLock::new(PeerManager::new())),
+            log,
+        );
+
+        let mut node = SimNode {
+            harness,
+            peer_id: service.local_peer_id().clone(),
+            address,
+            service,
+            handler,
+            network_recv,
+            validators: (index..validator_count).step_by(node_count).collect(),
+        };
+
+        // Attestation subnets are not used, every node receives every attestation.
+        for kind in &[GossipKind::BeaconBlock, GossipKind::BeaconAttestation] {
+            let topic = node.topic(*kind);
+            node.service.subscribe(topic);
+        }
+
+        Ok(node)
+    }
+
+    pub fn head(&self) -> Hash256 {
+        self.harness.beacon_chain.head().beacon_block_root
+    }
+
+    pub fn finalized_epoch(&self) -> Epoch {
+        self.harness.beacon_chain.state.read().finalized_epoch
+    }
+
+    /// The gossip topic of messages of `kind`, in the fork of the present epoch.
+    fn topic(&self, kind: GossipKind) -> GossipTopic {
+        let state = self.harness.beacon_chain.state.read();
+        let fork_version = state
+            .fork
+            .get_fork_version(state.current_epoch(&self.harness.spec));
+
+        GossipTopic::new(kind, fork_version)
+    }
+
+    /// Publishes `message` to our peers on its gossip topic.
+    fn publish(&mut self, message: PubsubMessage) {
+        let topic = match message {
+            PubsubMessage::Block(_) => self.topic(GossipKind::BeaconBlock),
+            PubsubMessage::Attestation(_) => self.topic(GossipKind::BeaconAttestation),
+        };
+        self.service.swarm.publish(vec![topic], message);
+    }
+
+    /// Produces a block if one of our validators is the proposer at the present slot.
+    fn produce_block(&mut self) -> Option<BeaconBlock> {
+        let chain = self.harness.beacon_chain.clone();
+        let proposer = chain.block_proposer(chain.present_slot()).ok()?;
+
+        if !self.validators.contains(&proposer) {
+            return None;
+        }
+
+        let block = self.harness.produce_block();
+        chain
+            .process_block(block.clone())
+            .expect("a node can import its own block");
+
+        Some(block)
+    }
+
+    /// Produces an attestation to our head from each of our validators with a duty at the
+    /// present slot, signed by the harness signer of the validator.
+    fn produce_attestations(&self) -> Vec<Attestation> {
+        let chain = &self.harness.beacon_chain;
+        let spec = &self.harness.spec;
+        let present_slot = chain.present_slot();
+
+        let (duties, fork) = {
+            let state = chain.state.read();
+            let mut duties = vec![];
+
+            for committee in state
+                .get_crosslink_committees_at_slot(present_slot, spec)
+                .expect("committees are known for the present slot")
+            {
+                for validator_index in committee.committee.iter() {
+                    if !self.validators.contains(validator_index) {
+                        continue;
+                    }
+
+                    let duty = state
+                        .get_attestation_duties(*validator_index, spec)
+                        .expect("duties are known for the present epoch")
+                        .expect("a committee member has a duty");
+                    duties.push((*validator_index, duty));
+                }
+            }
+
+            (duties, state.fork.clone())
+        };
+
+        duties
+            .into_iter()
+            .map(|(validator_index, duty)| {
+                let data = chain
+                    .produce_attestation_data(duty.shard)
+                    .expect("attestation data can be produced");
+
+                let message = AttestationDataAndCustodyBit {
+                    data: data.clone(),
+                    custody_bit: false,
+                }
+                .tree_hash_root();
+                let domain = spec.get_domain(
+                    data.slot.epoch(spec.slots_per_epoch),
+                    Domain::Attestation,
+                    &fork,
+                );
+                let signature = self.harness.validators[validator_index]
+                    .signer
+                    .sign_attestation_message(&message, domain)
+                    .expect("the local signer always signs");
+
+                let mut aggregate_signature = AggregateSignature::new();
+                aggregate_signature.add(&signature);
+
+                let mut aggregation_bitfield = Bitfield::with_capacity(duty.committee_len);
+                aggregation_bitfield.set(duty.committee_index, true);
+
+                Attestation {
+                    aggregation_bitfield,
+                    data,
+                    custody_bitfield: Bitfield::with_capacity(duty.committee_len),
+                    aggregate_signature,
+                }
+            })
+            .collect()
+    }
+}
+
+/// A network of beacon nodes, and the messages held back by the simulated links between them.
+pub struct Simulator {
+    pub nodes: Vec<SimNode>,
+    spec: ChainSpec,
+    /// Runs the libp2p services of the nodes. Taken while the simulation is running.
+    runtime: Option<Runtime>,
+    /// Messages received by the nodes, which are delivered to their handlers once due.
+    in_flight: DelayQueue<InFlight>,
+    /// When the last message sent over each link is due, so that a link delivers in order, like
+    /// the stream it stands in for.
+    last_delivery: HashMap<(usize, usize), Instant>,
+    rng: XorShiftRng,
+    default_link: LinkConfig,
+    links: HashMap<(usize, usize), LinkConfig>,
+    /// The partition of each node. Nodes in different partitions cannot communicate.
+    partitions: Vec<usize>,
+    log: slog::Logger,
+}
+
+impl Simulator {
+    /// Creates `node_count` unconnected nodes sharing a genesis state with `validator_count`
+    /// validators. Validator `i` is run by node `i % node_count`.
+    pub fn new(node_count: usize, validator_count: usize, seed: u64, log: slog::Logger) -> Self {
+        let spec = ChainSpec::few_validators();
+        let state_builder =
+            TestingBeaconStateBuilder::from_default_keypairs_file_if_exists(validator_count, &spec);
+
+        let mut runtime = Runtime::new().expect("should build the runtime");
+        let nodes = runtime
+            .block_on(futures::future::lazy(|| {
+                (0..node_count)
+                    .map(|index| {
+                        SimNode::new(
+                            index,
+                            node_count,
+                            validator_count,
+                            &state_builder,
+                            &spec,
+                            &log,
+                        )
+                    })
+                    .collect::<Result<Vec<_>, _>>()
+            }))
+            .expect("should build the nodes");
+
+        let mut seed_bytes = [0; 16];
+        seed_bytes[..8].copy_from_slice(&seed.to_le_bytes());
+
+        Self {
+            nodes,
+            spec,
+            runtime: Some(runtime),
+            in_flight: DelayQueue::new(),
+            last_delivery: HashMap::new(),
+            rng: XorShiftRng::from_seed(seed_bytes),
+            default_link: LinkConfig::default(),
+            links: HashMap::new(),
+            partitions: vec![0; node_count],
+            log,
+        }
+    }
+
+    /// Connects every pair of nodes, and waits for them to handshake.
+    pub fn connect_all(&mut self) {
+        for a in 0..self.nodes.len() {
+            for b in a + 1..self.nodes.len() {
+                self.connect(a, b);
+            }
+        }
+        self.run_for(CONNECT_DURATION);
+    }
+
+    /// Node `a` dials node `b`. Their handshake starts once the simulation runs.
+    pub fn connect(&mut self, a: usize, b: usize) {
+        let address = self.nodes[b].address.clone();
+        self.nodes[a]
+            .service
+            .dial(address)
+            .expect("a memory address can be dialled");
+    }
+
+    /// Sets the conditions of every link without its own configuration.
+    pub fn set_default_link(&mut self, link: LinkConfig) {
+        self.default_link = link;
+    }
+
+    /// Sets the conditions of the link between `a` and `b`, in both directions.
+    pub fn set_link(&mut self, a: usize, b: usize, link: LinkConfig) {
+        self.links.insert((a.min(b), a.max(b)), link);
+    }
+
+    /// Splits the nodes into `groups`, which cannot communicate with each other. Messages in
+    /// flight between groups are lost, but the nodes stay connected, as over a network which
+    /// stops routing their packets. Every node must be in exactly one group.
+    pub fn partition(&mut self, groups: &[&[usize]]) {
+        let mut partitions = vec![None; self.nodes.len()];
+        for (partition, group) in groups.iter().enumerate() {
+            for &node in group.iter() {
+                assert!(partitions[node].is_none(), "node {} is in two groups", node);
+                partitions[node] = Some(partition);
+            }
+        }
+
+        self.partitions = partitions
+            .into_iter()
+            .enumerate()
+            .map(|(node, partition)| {
+                partition.unwrap_or_else(|| panic!("node {} is in no group", node))
+            })
+            .collect();
+    }
+
+    /// Removes any partition.
+    pub fn heal(&mut self) {
+        self.partitions = vec![0; self.nodes.len()];
+    }
+
+    /// Runs `slots` slots. In each slot, every node advances its slot clock, the proposer
+    /// publishes a block, then every validator with a duty publishes an attestation a third of a
+    /// slot later. Fork choice is run by every node at the end of the slot.
+    pub fn run_slots(&mut self, slots: u64) {
+        for _ in 0..slots {
+            let slot_start = Instant::now();
+
+            for node in self.nodes.iter_mut() {
+                node.harness.increment_beacon_chain_slot();
+                node.handler.heartbeat();
+            }
+
+            for node in self.nodes.iter_mut() {
+                if let Some(block) = node.produce_block() {
+                    node.publish(PubsubMessage::Block(block));
+                }
+            }
+            self.run_until(slot_start + SLOT_DURATION / 3);
+
+            for node in self.nodes.iter_mut() {
+                for attestation in node.produce_attestations() {
+                    node.harness
+                        .beacon_chain
+                        .process_attestation(attestation.clone())
+                        .expect("a node can import its own attestation");
+                    node.publish(PubsubMessage::Attestation(attestation));
+                }
+            }
+            self.run_until(slot_start + SLOT_DURATION);
+
+            for node in self.nodes.iter_mut() {
+                node.harness.run_fork_choice();
+            }
+        }
+    }
+
+    /// The head block root of each node.
+    pub fn heads(&self) -> Vec<Hash256> {
+        self.nodes.iter().map(SimNode::head).collect()
+    }
+
+    /// The finalized epoch of each node.
+    pub fn finalized_epochs(&self) -> Vec<Epoch> {
+        self.nodes.iter().map(SimNode::finalized_epoch).collect()
+    }
+
+    pub fn spec(&self) -> &ChainSpec {
+        &self.spec
+    }
+
+    /// Panics unless every node has the same head.
+    pub fn assert_heads_converged(&self) {
+        let heads = self.heads();
+        assert!(
+            heads.iter().all(|head| *head == heads[0]),
+            "heads have not converged: {:?}",
+            heads
+        );
+    }
+
+    /// Panics unless every node has finalized at least `epoch`.
+    pub fn assert_finalized(&self, epoch: Epoch) {
+        let finalized_epochs = self.finalized_epochs();
+        assert!(
+            finalized_epochs.iter().all(|finalized| *finalized >= epoch),
+            "not every node has finalized epoch {}: {:?}",
+            epoch,
+            finalized_epochs
+        );
+    }
+
+    /// Runs the network for `duration`.
+    fn run_for(&mut self, duration: Duration) {
+        self.run_until(Instant::now() + duration);
+    }
+
+    /// Runs the network until `deadline`, handling every message which arrives before it.
+    fn run_until(&mut self, deadline: Instant) {
+        let mut runtime = self
+            .runtime
+            .take()
+            .expect("the simulation is not already running");
+
+        // The delay is created on the runtime, which runs its timer.
+        let mut delay = None;
+        let result = runtime.block_on(futures::future::poll_fn(|| -> Result<_, String> {
+            self.poll_network()?;
+            delay
+                .get_or_insert_with(|| Delay::new(deadline))
+                .poll()
+                .map_err(|e| format!("Simulation timer failed: {:?}", e))
+        }));
+
+        self.runtime = Some(runtime);
+        result.expect("the simulated network should run");
+    }
+
+    /// Polls the libp2p service of every node, delivers the messages which are due to the
+    /// handlers, and sends the messages of the handlers, until none of them make progress.
+    fn poll_network(&mut self) -> Result<(), String> {
+        loop {
+            let mut progress = false;
+
+            for to in 0..self.nodes.len() {
+                while let Async::Ready(Some(event)) = self.nodes[to]
+                    .service
+                    .poll()
+                    .map_err(|e| format!("The service of node {} failed: {:?}", to, e))?
+                {
+                    if let Some(message) = handler_message(event, &self.log) {
+                        self.receive(to, message);
+                    }
+                    progress = true;
+                }
+            }
+
+            while let Async::Ready(Some(expired)) = self
+                .in_flight
+                .poll()
+                .map_err(|e| format!("Delivery timer failed: {:?}", e))?
+            {
+                self.deliver(expired.into_inner());
+                progress = true;
+            }
+
+            for node in self.nodes.iter_mut() {
+                while let Ok(message) = node.network_recv.try_recv() {
+                    send_to_libp2p(&mut node.service, message, &self.log);
+                    progress = true;
+                }
+            }
+
+            if !progress {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Holds a message received by node `to` back for the latency of the link to its sender, or
+    /// drops it.
+    fn receive(&mut self, to: usize, message: HandlerMessage) {
+        let from = match self.node_index(peer_of(&message)) {
+            Some(from) => from,
+            None => return self.nodes[to].handler.handle_message(message),
+        };
+
+        // Events of the local service, e.g., a connection or timeout, are not sent over the link,
+        // but are kept in order with the messages which were.
+        let mut due = Instant::now();
+        if is_sent_over_link(&message) {
+            if self.is_dropped(from, to, &message) {
+                return self.drop_message(to, message);
+            }
+            due += self.delay(from, to);
+        }
+
+        let last_delivery = self.last_delivery.entry((from, to)).or_insert(due);
+        *last_delivery = due.max(*last_delivery);
+        self.in_flight
+            .insert_at(InFlight { from, to, message }, *last_delivery);
+    }
+
+    fn deliver(&mut self, in_flight: InFlight) {
+        let InFlight { from, to, message } = in_flight;
+
+        if is_sent_over_link(&message) && !self.can_communicate(from, to) {
+            self.drop_message(to, message);
+        } else {
+            self.nodes[to].handler.handle_message(message);
+        }
+    }
+
+    /// Discards a message received by node `to`. A gossip message is ignored, so that it is not
+    /// propagated.
+    fn drop_message(&mut self, to: usize, message: HandlerMessage) {
+        if let HandlerMessage::PubsubMessage(message_id, source, _) = message {
+            self.nodes[to]
+                .service
+                .swarm
+                .report_message_validation_result(&message_id, &source, MessageAcceptance::Ignore);
+        }
+    }
+
+    fn link(&self, a: usize, b: usize) -> LinkConfig {
+        self.links
+            .get(&(a.min(b), a.max(b)))
+            .cloned()
+            .unwrap_or(self.default_link)
+    }
+
+    fn can_communicate(&self, a: usize, b: usize) -> bool {
+        self.partitions[a] == self.partitions[b]
+    }
+
+    /// Returns `true` if `message` from `from` to `to` is lost, due to a partition or, for a
+    /// gossip message, the drop rate of their link.
+    fn is_dropped(&mut self, from: usize, to: usize, message: &HandlerMessage) -> bool {
+        if !self.can_communicate(from, to) {
+            return true;
+        }
+
+        match message {
+            HandlerMessage::PubsubMessage(..) => {
+                let draw = f64::from(self.rng.next_u32()) / f64::from(u32::max_value());
+                draw < self.link(from, to).drop_rate
+            }
+            _ => false,
+        }
+    }
+
+    /// The time taken by the next message from `from` to `to`.
+    fn delay(&mut self, from: usize, to: usize) -> Duration {
+        let link = self.link(from, to);
+        Duration::from_millis(link.latency_ms + self.rng.next_u64() % (link.jitter_ms + 1))
+    }
+
+    fn node_index(&self, peer_id: &PeerId) -> Option<usize> {
+        self.nodes.iter().position(|node| node.peer_id == *peer_id)
+    }
+}
+
+/// The peer which sent a message for the handler, or which it concerns.
+fn peer_of(message: &HandlerMessage) -> &PeerId {
+    match message {
+        HandlerMessage::PeerDialed(peer_id)
+        | HandlerMessage::PeerDisconnected(peer_id)
+        | HandlerMessage::RPC(peer_id, _)
+        | HandlerMessage::RequestRejected(peer_id, _)
+        | HandlerMessage::RequestTimeout { peer_id, .. }
+        | HandlerMessage::PubsubMessage(_, peer_id, _)
+        | HandlerMessage::UndecodableGossip(peer_id) => peer_id,
+    }
+}
+
+/// Returns `true` if `message` was sent to us by a peer, rather than raised by our own service.
+fn is_sent_over_link(message: &HandlerMessage) -> bool {
+    match message {
+        HandlerMessage::RPC(..)
+        | HandlerMessage::PubsubMessage(..)
+        | HandlerMessage::UndecodableGossip(..) => true,
+        _ => false,
+    }
+}
@@ -0,0 +1,132 @@
+use crate::error::ApiError;
+use crate::response::{json_response, ssz_response, success_response, ApiResult, Encoding};
+use crate::url_query::UrlQuery;
+use crate::ApiContext;
+use serde_derive::Serialize;
+use ssz::decode;
+use ssz_derive::Encode;
+use types::{BeaconState, Epoch, Hash256, Slot};
+
+/// The head of the canonical chain.
+#[derive(Serialize, Encode)]
+pub struct HeadResponse {
+    pub slot: Slot,
+    pub block_root: Hash256,
+    pub state_root: Hash256,
+}
+
+/// The latest finalized checkpoint.
+#[derive(Serialize, Encode)]
+pub struct FinalizedCheckpointResponse {
+    pub epoch: Epoch,
+    pub root: Hash256,
+}
+
+/// `GET /beacon/head`
+pub fn get_head(ctx: &ApiContext, encoding: Encoding) -> ApiResult {
+    let head = ctx.chain.head();
+
+    let response = HeadResponse {
+        slot: head.beacon_block.slot,
+        block_root: head.beacon_block_root,
+        state_root: head.beacon_state_root,
+    };
+
+    success_response(&response, encoding)
+}
+
+/// `GET /beacon/finalized_checkpoint`
+pub fn get_finalized_checkpoint(ctx: &ApiContext, encoding: Encoding) -> ApiResult {
+    let state = ctx.chain.get_state();
+
+    let response = FinalizedCheckpointResponse {
+        epoch: state.finalized_epoch,
+        root: state.finalized_root,
+    };
+
+    success_response(&response, encoding)
+}
+
+/// `GET /beacon/block?root=` or `GET /beacon/block?slot=`
+///
+/// A skipped slot has no block.
+pub fn get_block(ctx: &ApiContext, query: &UrlQuery, encoding: Encoding) -> ApiResult {
+    let (block_root, slot) = match (query.get_root("root")?, query.get_u64("slot")?) {
+        (Some(root), None) => (root, None),
+        (None, Some(slot)) => {
+            let slot = Slot::from(slot);
+            let roots = ctx.chain.get_block_roots(slot, 1, 0)?;
+            let root = roots
+                .first()
+                .cloned()
+                .ok_or_else(|| ApiError::NotFound(format!("No block at slot {}", slot)))?;
+            (root, Some(slot))
+        }
+        _ => {
+            return Err(ApiError::BadRequest(
+                "Exactly one of `root` or `slot` is required".to_string(),
+            ));
+        }
+    };
+
+    let block = ctx
+        .chain
+        .get_block(&block_root)?
+        .ok_or_else(|| ApiError::NotFound(format!("No block with root {:?}", block_root)))?;
+
+    // The block roots of skipped slots repeat the root of the previous block.
+    if let Some(slot) = slot {
+        if block.slot != slot {
+            return Err(ApiError::NotFound(format!("No block at slot {}", slot)));
+        }
+    }
+
+    success_response(&block, encoding)
+}
+
+/// `GET /beacon/state?root=`, optionally with `field=` to serve a single field as JSON.
+///
+/// The SSZ encoding of a whole state is served as stored, without decoding it.
+pub fn get_state(ctx: &ApiContext, query: &UrlQuery, encoding: Encoding) -> ApiResult {
+    let state_root = query
+        .get_root("root")?
+        .ok_or_else(|| ApiError::BadRequest("Missing query parameter `root`".to_string()))?;
+    let ssz = load_state_ssz(ctx, &state_root)?;
+
+    match query.get("field") {
+        None if encoding == Encoding::Ssz => Ok(ssz_response(ssz)),
+        None => json_response(&decode_state(&ssz)?, encoding),
+        Some(field) => {
+            let state = serde_json::to_value(decode_state(&ssz)?).map_err(|e| {
+                ApiError::ServerError(format!("Unable to serialize state: {:?}", e))
+            })?;
+            let value = state
+                .get(field)
+                .ok_or_else(|| ApiError::NotFound(format!("No state field named {}", field)))?;
+
+            json_response(value, encoding)
+        }
+    }
+}
+
+/// Calls `f` with the state with the `root` in `query`, or with the head state if no root is
+/// given.
+pub fn with_state<F>(ctx: &ApiContext, query: &UrlQuery, f: F) -> ApiResult
+where
+    F: FnOnce(&BeaconState) -> ApiResult,
+{
+    match query.get_root("root")? {
+        Some(state_root) => f(&decode_state(&load_state_ssz(ctx, &state_root)?)?),
+        None => f(&ctx.chain.head().beacon_state),
+    }
+}
+
+fn load_state_ssz(ctx: &ApiContext, state_root: &Hash256) -> Result<Vec<u8>, ApiError> {
+    ctx.chain
+        .get_state_ssz(state_root)?
+        .ok_or_else(|| ApiError::NotFound(format!("No state with root {:?}", state_root)))
+}
+
+fn decode_state(ssz: &[u8]) -> Result<BeaconState, ApiError> {
+    decode(ssz).map_err(|e| ApiError::ServerError(format!("Stored state is invalid: {:?}", e)))
+}
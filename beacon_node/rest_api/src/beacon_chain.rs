@@ -0,0 +1,67 @@
+use beacon_chain::BeaconChain as RawBeaconChain;
+pub use beacon_chain::BeaconChainError;
+use beacon_chain::{
+    db::ClientDB,
+    fork_choice::ForkChoice,
+    parking_lot::RwLockReadGuard,
+    slot_clock::SlotClock,
+    types::{BeaconState, ChainSpec},
+    CheckPoint,
+};
+use types::{BeaconBlock, Hash256, Slot};
+
+/// The REST API's API to the beacon chain.
+pub trait BeaconChain: Send + Sync {
+    fn get_spec(&self) -> &ChainSpec;
+
+    fn get_state(&self) -> RwLockReadGuard<BeaconState>;
+
+    fn head(&self) -> RwLockReadGuard<CheckPoint>;
+
+    fn get_block(&self, block_root: &Hash256) -> Result<Option<BeaconBlock>, BeaconChainError>;
+
+    fn get_block_roots(
+        &self,
+        start_slot: Slot,
+        count: usize,
+        skip: usize,
+    ) -> Result<Vec<Hash256>, BeaconChainError>;
+
+    fn get_state_ssz(&self, state_root: &Hash256) -> Result<Option<Vec<u8>>, BeaconChainError>;
+}
+
+impl<T, U, F> BeaconChain for RawBeaconChain<T, U, F>
+where
+    T: ClientDB + Sized,
+    U: SlotClock,
+    F: ForkChoice,
+{
+    fn get_spec(&self) -> &ChainSpec {
+        &self.spec
+    }
+
+    fn get_state(&self) -> RwLockReadGuard<BeaconState> {
+        self.state.read()
+    }
+
+    fn head(&self) -> RwLockReadGuard<CheckPoint> {
+        self.head()
+    }
+
+    fn get_block(&self, block_root: &Hash256) -> Result<Option<BeaconBlock>, BeaconChainError> {
+        self.get_block(block_root)
+    }
+
+    fn get_block_roots(
+        &self,
+        start_slot: Slot,
+        count: usize,
+        skip: usize,
+    ) -> Result<Vec<Hash256>, BeaconChainError> {
+        self.get_block_roots(start_slot, count, skip)
+    }
+
+    fn get_state_ssz(&self, state_root: &Hash256) -> Result<Option<Vec<u8>>, BeaconChainError> {
+        self.get_state_ssz(state_root)
+    }
+}
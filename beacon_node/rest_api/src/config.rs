@@ -0,0 +1,22 @@
+use std::net::Ipv4Addr;
+
+/// REST API Configuration
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// Enable the REST API server.
+    pub enabled: bool,
+    /// The IPv4 address the REST API will listen on.
+    pub listen_address: Ipv4Addr,
+    /// The port the REST API will listen on.
+    pub port: u16,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            enabled: false, // rest api disabled by default
+            listen_address: Ipv4Addr::new(127, 0, 0, 1),
+            port: 5052,
+        }
+    }
+}
@@ -0,0 +1,57 @@
+use crate::beacon_chain::BeaconChainError;
+use hyper::StatusCode;
+use types::BeaconStateError;
+
+/// The reasons an API request fails, each of which is served with its own status code.
+#[derive(Debug, PartialEq)]
+pub enum ApiError {
+    /// The request is malformed, e.g., a query parameter is missing or cannot be parsed.
+    BadRequest(String),
+    /// The requested resource does not exist.
+    NotFound(String),
+    /// The resource cannot be served in the requested encoding.
+    UnsupportedEncoding(String),
+    /// The node failed to serve the request.
+    ServerError(String),
+}
+
+impl ApiError {
+    pub fn status_code(&self) -> StatusCode {
+        match self {
+            ApiError::BadRequest(_) => StatusCode::BAD_REQUEST,
+            ApiError::NotFound(_) => StatusCode::NOT_FOUND,
+            ApiError::UnsupportedEncoding(_) => StatusCode::NOT_ACCEPTABLE,
+            ApiError::ServerError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    pub fn message(&self) -> &str {
+        match self {
+            ApiError::BadRequest(message)
+            | ApiError::NotFound(message)
+            | ApiError::UnsupportedEncoding(message)
+            | ApiError::ServerError(message) => message,
+        }
+    }
+}
+
+impl From<BeaconChainError> for ApiError {
+    fn from(e: BeaconChainError) -> ApiError {
+        match e {
+            BeaconChainError::BeaconStateError(e) => ApiError::from(e),
+            e => ApiError::ServerError(format!("{:?}", e)),
+        }
+    }
+}
+
+impl From<BeaconStateError> for ApiError {
+    fn from(e: BeaconStateError) -> ApiError {
+        match e {
+            // Requests for slots or epochs outside those known to the state are not our fault.
+            BeaconStateError::SlotOutOfBounds | BeaconStateError::EpochOutOfBounds => {
+                ApiError::NotFound(format!("{:?}", e))
+            }
+            e => ApiError::ServerError(format!("{:?}", e)),
+        }
+    }
+}
@@ -0,0 +1,280 @@
+mod beacon;
+pub mod beacon_chain;
+pub mod config;
+mod error;
+pub mod network;
+mod node;
+mod response;
+mod url_query;
+mod validator;
+
+use self::beacon_chain::BeaconChain;
+use self::error::ApiError;
+use self::network::Network;
+use self::response::{error_response, ApiResult, Encoding};
+use self::url_query::UrlQuery;
+pub use config::Config as ApiConfig;
+use futures::Future;
+use hyper::service::service_fn_ok;
+use hyper::{Body, Method, Request, Response, Server};
+use slog::{debug, info, o, warn};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::runtime::TaskExecutor;
+
+/// The state shared by every request handler.
+pub struct ApiContext {
+    pub chain: Arc<BeaconChain>,
+    pub network: Arc<Network>,
+    pub log: slog::Logger,
+}
+
+pub fn start_server(
+    config: &ApiConfig,
+    executor: &TaskExecutor,
+    beacon_chain: Arc<BeaconChain>,
+    network: Arc<Network>,
+    log: &slog::Logger,
+) -> exit_future::Signal {
+    let log = log.new(o!("Service"=>"REST"));
+
+    // build a channel to kill the api server
+    let (api_exit_signal, api_exit) = exit_future::signal();
+
+    let ctx = Arc::new(ApiContext {
+        chain: beacon_chain,
+        network,
+        log: log.clone(),
+    });
+
+    let addr = SocketAddr::new(config.listen_address.into(), config.port);
+    let service = move || {
+        let ctx = ctx.clone();
+        service_fn_ok(move |req: Request<Body>| handle_request(&ctx, req))
+    };
+
+    let shutdown_log = log.clone();
+    let shutdown = api_exit.map(move |_| info!(shutdown_log, "REST API server shutting down"));
+
+    let server_log = log.clone();
+    let server = Server::bind(&addr)
+        .serve(service)
+        .with_graceful_shutdown(shutdown)
+        .map_err(move |e| warn!(server_log, "REST API server failed: {:?}", e));
+
+    info!(log, "REST API listening on {}", addr);
+    executor.spawn(server);
+    api_exit_signal
+}
+
+/// Routes a request to its handler, serving any error as a JSON error response.
+fn handle_request(ctx: &ApiContext, req: Request<Body>) -> Response<Body> {
+    let path = req.uri().path().to_string();
+
+    route(ctx, &req).unwrap_or_else(|e| {
+        debug!(ctx.log, "API request failed"; "path" => &path, "error" => e.message());
+        error_response(&e)
+    })
+}
+
+fn route(ctx: &ApiContext, req: &Request<Body>) -> ApiResult {
+    if *req.method() != Method::GET {
+        return Err(ApiError::BadRequest(format!(
+            "Unsupported method: {}",
+            req.method()
+        )));
+    }
+
+    let query = UrlQuery::from_request(req);
+    let encoding = Encoding::from_request(req, &query)?;
+
+    match req.uri().path() {
+        "/beacon/head" => beacon::get_head(ctx, encoding),
+        "/beacon/finalized_checkpoint" => beacon::get_finalized_checkpoint(ctx, encoding),
+        "/beacon/block" => beacon::get_block(ctx, &query, encoding),
+        "/beacon/state" => beacon::get_state(ctx, &query, encoding),
+        "/beacon/validators" => validator::get_validators(ctx, &query, encoding),
+        "/beacon/validators/balances" => validator::get_balances(ctx, &query, encoding),
+        "/beacon/committees" => validator::get_committees(ctx, &query, encoding),
+        "/node/peers" => node::get_peers(ctx, encoding),
+        path => Err(ApiError::NotFound(format!("Unknown path: {}", path))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::response::{JSON_CONTENT_TYPE, SSZ_CONTENT_TYPE};
+    use eth2_libp2p::PeerId;
+    use futures::Stream;
+    use hyper::header::{ACCEPT, CONTENT_TYPE};
+    use hyper::StatusCode;
+    use ssz::ssz_encode;
+    use test_harness::BeaconChainHarness;
+    use types::{BeaconBlock, ChainSpec, Hash256, Slot};
+
+    /// A network with a fixed set of peers.
+    struct TestNetwork(Vec<(PeerId, f64)>);
+
+    impl Network for TestNetwork {
+        fn connected_peers(&self) -> Vec<(PeerId, f64)> {
+            self.0.clone()
+        }
+    }
+
+    /// A chain with blocks at the first and third slots after genesis, the second being skipped.
+    struct TestChain {
+        harness: BeaconChainHarness,
+        blocks: Vec<BeaconBlock>,
+        peer_id: PeerId,
+    }
+
+    impl TestChain {
+        fn new() -> Self {
+            let mut harness = BeaconChainHarness::new(ChainSpec::few_validators(), 8);
+            let first = harness.advance_chain_with_block();
+            harness.increment_beacon_chain_slot();
+            let third = harness.advance_chain_with_block();
+            harness.run_fork_choice();
+
+            Self {
+                harness,
+                blocks: vec![first, third],
+                peer_id: PeerId::random(),
+            }
+        }
+
+        fn genesis_slot(&self) -> Slot {
+            self.harness.spec.genesis_slot
+        }
+
+        fn get(&self, uri: &str) -> Response<Body> {
+            self.request(Request::get(uri).body(Body::empty()).unwrap())
+        }
+
+        fn request(&self, req: Request<Body>) -> Response<Body> {
+            let ctx = ApiContext {
+                chain: self.harness.beacon_chain.clone(),
+                network: Arc::new(TestNetwork(vec![(self.peer_id.clone(), -10.0)])),
+                log: slog::Logger::root(slog::Discard, o!()),
+            };
+            handle_request(&ctx, req)
+        }
+    }
+
+    fn body(response: Response<Body>) -> Vec<u8> {
+        response.into_body().concat2().wait().unwrap().to_vec()
+    }
+
+    fn json(response: Response<Body>) -> serde_json::Value {
+        assert_eq!(response.headers()[CONTENT_TYPE], JSON_CONTENT_TYPE);
+        serde_json::from_slice(&body(response)).unwrap()
+    }
+
+    #[test]
+    fn rejects_unknown_paths_and_methods() {
+        let chain = TestChain::new();
+
+        assert_eq!(chain.get("/beacon/unknown").status(), StatusCode::NOT_FOUND);
+
+        let post = Request::post("/beacon/head").body(Body::empty()).unwrap();
+        assert_eq!(chain.request(post).status(), StatusCode::BAD_REQUEST);
+
+        let response = chain.get("/beacon/block");
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert!(json(response)["error"].is_string());
+    }
+
+    #[test]
+    fn get_block_by_slot() {
+        let chain = TestChain::new();
+        let slot = chain.genesis_slot() + 1;
+
+        let response = chain.get(&format!("/beacon/block?slot={}", slot));
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            json(response),
+            serde_json::to_value(&chain.blocks[0]).unwrap()
+        );
+
+        let root = chain.blocks[1].canonical_root();
+        let response = chain.get(&format!("/beacon/block?root={:?}", root));
+        assert_eq!(
+            json(response),
+            serde_json::to_value(&chain.blocks[1]).unwrap()
+        );
+    }
+
+    #[test]
+    fn get_block_at_skipped_slot() {
+        let chain = TestChain::new();
+        let slot = chain.genesis_slot() + 2;
+
+        let response = chain.get(&format!("/beacon/block?slot={}", slot));
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[test]
+    fn serves_ssz_or_json() {
+        let chain = TestChain::new();
+        let uri = format!("/beacon/block?slot={}", chain.genesis_slot() + 1);
+        let ssz = ssz_encode(&chain.blocks[0]);
+
+        let response = chain.get(&format!("{}&encoding=ssz", uri));
+        assert_eq!(response.headers()[CONTENT_TYPE], SSZ_CONTENT_TYPE);
+        assert_eq!(body(response), ssz);
+
+        let accept_ssz = Request::get(uri.as_str())
+            .header(ACCEPT, SSZ_CONTENT_TYPE)
+            .body(Body::empty())
+            .unwrap();
+        assert_eq!(body(chain.request(accept_ssz)), ssz);
+
+        // the query parameter takes precedence over the header.
+        let accept_ssz = Request::get(format!("{}&encoding=json", uri).as_str())
+            .header(ACCEPT, SSZ_CONTENT_TYPE)
+            .body(Body::empty())
+            .unwrap();
+        assert_eq!(
+            json(chain.request(accept_ssz)),
+            serde_json::to_value(&chain.blocks[0]).unwrap()
+        );
+
+        let response = chain.get(&format!("{}&encoding=xml", uri));
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        // peers are only available as JSON.
+        let response = chain.get("/node/peers?encoding=ssz");
+        assert_eq!(response.status(), StatusCode::NOT_ACCEPTABLE);
+        assert_eq!(
+            json(chain.get("/node/peers")),
+            serde_json::json!([{ "peer_id": chain.peer_id.to_base58(), "score": -10.0 }])
+        );
+    }
+
+    #[test]
+    fn get_state_and_state_fields() {
+        let chain = TestChain::new();
+        let head = chain.harness.beacon_chain.head().clone();
+        let uri = format!("/beacon/state?root={:?}", head.beacon_state_root);
+
+        let response = chain.get(&format!("{}&encoding=ssz", uri));
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(body(response), ssz_encode(&head.beacon_state));
+
+        assert_eq!(
+            json(chain.get(&format!("{}&field=slot", uri))),
+            serde_json::to_value(head.beacon_state.slot).unwrap()
+        );
+        assert_eq!(
+            json(chain.get(&format!("{}&field=validator_balances", uri))),
+            serde_json::to_value(&head.beacon_state.validator_balances).unwrap()
+        );
+
+        let response = chain.get(&format!("{}&field=unknown", uri));
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        let response = chain.get(&format!("/beacon/state?root={:?}", Hash256::zero()));
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+}
@@ -0,0 +1,14 @@
+use eth2_libp2p::PeerId;
+use network::Service as NetworkService;
+
+/// The REST API's API to the network.
+pub trait Network: Send + Sync {
+    /// Returns the peers we are connected to, with their current scores.
+    fn connected_peers(&self) -> Vec<(PeerId, f64)>;
+}
+
+impl Network for NetworkService {
+    fn connected_peers(&self) -> Vec<(PeerId, f64)> {
+        self.connected_peers()
+    }
+}
@@ -0,0 +1,25 @@
+use crate::response::{json_response, ApiResult, Encoding};
+use crate::ApiContext;
+use serde_derive::Serialize;
+
+/// A peer we are connected to.
+#[derive(Serialize)]
+pub struct PeerResponse {
+    pub peer_id: String,
+    pub score: f64,
+}
+
+/// `GET /node/peers`
+pub fn get_peers(ctx: &ApiContext, encoding: Encoding) -> ApiResult {
+    let peers: Vec<PeerResponse> = ctx
+        .network
+        .connected_peers()
+        .into_iter()
+        .map(|(peer_id, score)| PeerResponse {
+            peer_id: peer_id.to_base58(),
+            score,
+        })
+        .collect();
+
+    json_response(&peers, encoding)
+}
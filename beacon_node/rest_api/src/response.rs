@@ -0,0 +1,92 @@
+use crate::error::ApiError;
+use crate::url_query::UrlQuery;
+use hyper::header::{ACCEPT, CONTENT_TYPE};
+use hyper::{Body, Request, Response, StatusCode};
+use serde::Serialize;
+use ssz::{ssz_encode, Encodable};
+
+/// The content type of JSON responses.
+pub const JSON_CONTENT_TYPE: &str = "application/json";
+/// The content type of SSZ responses.
+pub const SSZ_CONTENT_TYPE: &str = "application/ssz";
+
+/// The result of handling an API request.
+pub type ApiResult = Result<Response<Body>, ApiError>;
+
+/// The encodings a response may be served in.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Encoding {
+    Json,
+    Ssz,
+}
+
+impl Encoding {
+    /// Returns the encoding requested with the `encoding` query parameter, or else the `Accept`
+    /// header. Defaults to JSON.
+    pub fn from_request(req: &Request<Body>, query: &UrlQuery) -> Result<Self, ApiError> {
+        match query.get("encoding") {
+            Some("json") => return Ok(Encoding::Json),
+            Some("ssz") => return Ok(Encoding::Ssz),
+            Some(other) => {
+                return Err(ApiError::BadRequest(format!(
+                    "Unknown encoding: {}, expected `json` or `ssz`",
+                    other
+                )));
+            }
+            None => {}
+        }
+
+        let accepts_ssz = req
+            .headers()
+            .get(ACCEPT)
+            .and_then(|accept| accept.to_str().ok())
+            .map_or(false, |accept| accept.contains(SSZ_CONTENT_TYPE));
+
+        if accepts_ssz {
+            Ok(Encoding::Ssz)
+        } else {
+            Ok(Encoding::Json)
+        }
+    }
+}
+
+/// Serves `item` in the requested encoding.
+pub fn success_response<T: Serialize + Encodable>(item: &T, encoding: Encoding) -> ApiResult {
+    match encoding {
+        Encoding::Json => json_response(item, encoding),
+        Encoding::Ssz => Ok(ssz_response(ssz_encode(item))),
+    }
+}
+
+/// Serves `item` as JSON, failing if SSZ was requested because `item` has no SSZ encoding.
+pub fn json_response<T: Serialize>(item: &T, encoding: Encoding) -> ApiResult {
+    if encoding == Encoding::Ssz {
+        return Err(ApiError::UnsupportedEncoding(
+            "This resource is only available as JSON".to_string(),
+        ));
+    }
+
+    let json = serde_json::to_vec(item)
+        .map_err(|e| ApiError::ServerError(format!("Unable to serialize response: {:?}", e)))?;
+
+    Ok(response(StatusCode::OK, JSON_CONTENT_TYPE, json))
+}
+
+/// Serves bytes which are already SSZ encoded.
+pub fn ssz_response(bytes: Vec<u8>) -> Response<Body> {
+    response(StatusCode::OK, SSZ_CONTENT_TYPE, bytes)
+}
+
+/// Serves `error` as a JSON object with its message, e.g., `{"error": "Missing ..."}`.
+pub fn error_response(error: &ApiError) -> Response<Body> {
+    let json = serde_json::json!({ "error": error.message() });
+    response(error.status_code(), JSON_CONTENT_TYPE, json.to_string())
+}
+
+fn response<B: Into<Body>>(status: StatusCode, content_type: &str, body: B) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header(CONTENT_TYPE, content_type)
+        .body(body.into())
+        .expect("a response with a valid status and content type cannot fail to build")
+}
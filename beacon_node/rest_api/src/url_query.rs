@@ -0,0 +1,113 @@
+use crate::error::ApiError;
+use hyper::{Body, Request};
+use std::str::FromStr;
+use types::Hash256;
+
+/// The query parameters of a request, e.g., `?slot=5&encoding=ssz`.
+///
+/// Values are not percent-decoded, as none of our parameters contain reserved characters.
+#[derive(Debug, Default)]
+pub struct UrlQuery(Vec<(String, String)>);
+
+impl UrlQuery {
+    pub fn from_request(req: &Request<Body>) -> Self {
+        req.uri().query().map(Self::parse).unwrap_or_default()
+    }
+
+    fn parse(query: &str) -> Self {
+        let pairs = query
+            .split('&')
+            .filter(|pair| !pair.is_empty())
+            .map(|pair| {
+                let mut parts = pair.splitn(2, '=');
+                let key = parts.next().unwrap_or_default();
+                let value = parts.next().unwrap_or_default();
+                (key.to_string(), value.to_string())
+            })
+            .collect();
+
+        UrlQuery(pairs)
+    }
+
+    /// Returns the first value of `key`, if any.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.0
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Returns the first value of `key`, failing if there is none.
+    pub fn get_required(&self, key: &str) -> Result<&str, ApiError> {
+        self.get(key)
+            .ok_or_else(|| ApiError::BadRequest(format!("Missing query parameter `{}`", key)))
+    }
+
+    /// Returns the first value of `key` parsed as a `u64`, if any.
+    pub fn get_u64(&self, key: &str) -> Result<Option<u64>, ApiError> {
+        self.get(key)
+            .map(|value| {
+                value.parse::<u64>().map_err(|_| {
+                    ApiError::BadRequest(format!("Invalid `{}`: {} is not an integer", key, value))
+                })
+            })
+            .transpose()
+    }
+
+    /// Returns the first value of `key` parsed as a root, if any.
+    pub fn get_root(&self, key: &str) -> Result<Option<Hash256>, ApiError> {
+        self.get(key).map(parse_root).transpose()
+    }
+}
+
+/// Parses a hex encoded 32 byte root, with or without a `0x` prefix.
+pub fn parse_root(root: &str) -> Result<Hash256, ApiError> {
+    let hex = if root.starts_with("0x") {
+        &root[2..]
+    } else {
+        root
+    };
+
+    if hex.len() != 64 {
+        return Err(ApiError::BadRequest(format!(
+            "Invalid root: {} is not 32 bytes",
+            root
+        )));
+    }
+
+    Hash256::from_str(hex)
+        .map_err(|_| ApiError::BadRequest(format!("Invalid root: {} is not hex", root)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_query_parameters() {
+        let query = UrlQuery::parse("slot=5&encoding=ssz&slot=6&flag");
+
+        assert_eq!(query.get("slot"), Some("5"));
+        assert_eq!(query.get("encoding"), Some("ssz"));
+        assert_eq!(query.get("flag"), Some(""));
+        assert_eq!(query.get("root"), None);
+        assert_eq!(query.get_u64("slot"), Ok(Some(5)));
+        assert!(query.get_u64("encoding").is_err());
+        assert!(query.get_required("root").is_err());
+    }
+
+    #[test]
+    fn parses_roots() {
+        let root = Hash256::from_low_u64_be(42);
+        let hex: String = root
+            .as_bytes()
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect();
+
+        assert_eq!(parse_root(&hex), Ok(root));
+        assert_eq!(parse_root(&format!("0x{}", hex)), Ok(root));
+        assert!(parse_root("0x1234").is_err());
+        assert!(parse_root(&"zz".repeat(32)).is_err());
+    }
+}
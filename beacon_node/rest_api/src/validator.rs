@@ -0,0 +1,54 @@
+use crate::beacon::with_state;
+use crate::error::ApiError;
+use crate::response::{success_response, ApiResult, Encoding};
+use crate::url_query::UrlQuery;
+use crate::ApiContext;
+use types::Slot;
+
+/// `GET /beacon/validators`, optionally with `root=` for the state and `index=` for a single
+/// validator.
+pub fn get_validators(ctx: &ApiContext, query: &UrlQuery, encoding: Encoding) -> ApiResult {
+    let index = query.get_u64("index")?;
+
+    with_state(ctx, query, |state| match index {
+        Some(index) => {
+            let validator = state
+                .validator_registry
+                .get(index as usize)
+                .ok_or_else(|| ApiError::NotFound(format!("No validator with index {}", index)))?;
+            success_response(validator, encoding)
+        }
+        None => success_response(&state.validator_registry, encoding),
+    })
+}
+
+/// `GET /beacon/validators/balances`, optionally with `root=` for the state and `index=` for a
+/// single validator.
+pub fn get_balances(ctx: &ApiContext, query: &UrlQuery, encoding: Encoding) -> ApiResult {
+    let index = query.get_u64("index")?;
+
+    with_state(ctx, query, |state| match index {
+        Some(index) => {
+            let balance = state
+                .validator_balances
+                .get(index as usize)
+                .ok_or_else(|| ApiError::NotFound(format!("No validator with index {}", index)))?;
+            success_response(balance, encoding)
+        }
+        None => success_response(&state.validator_balances, encoding),
+    })
+}
+
+/// `GET /beacon/committees?slot=`
+///
+/// Committees are only known for slots in the previous, present and next epochs.
+pub fn get_committees(ctx: &ApiContext, query: &UrlQuery, encoding: Encoding) -> ApiResult {
+    let state = ctx.chain.get_state();
+    let slot = query.get_u64("slot")?.map(Slot::from).unwrap_or(state.slot);
+
+    let committees = state
+        .get_crosslink_committees_at_slot(slot, ctx.chain.get_spec())
+        .map_err(|e| ApiError::NotFound(format!("No committees at slot {}: {:?}", slot, e)))?;
+
+    success_response(committees, encoding)
+}
@@ -1,6 +1,8 @@
 use crate::beacon_chain::BeaconChain;
+use eth2_libp2p::{GossipKind, GossipTopic, PubsubMessage};
 use futures::Future;
 use grpcio::{RpcContext, RpcStatus, RpcStatusCode, UnarySink};
+use network::NetworkMessage;
 use protos::services::{
     AttestationData as AttestationDataProto, ProduceAttestationDataRequest,
     ProduceAttestationDataResponse, PublishAttestationRequest, PublishAttestationResponse,
@@ -14,6 +16,7 @@ use types::Attestation;
 #[derive(Clone)]
 pub struct AttestationServiceInstance {
     pub chain: Arc<BeaconChain>,
+    pub network_chan: crossbeam_channel::Sender<NetworkMessage>,
     pub log: slog::Logger,
 }
 
@@ -124,7 +127,16 @@ impl AttestationService for AttestationServiceInstance {
             }
         };
 
-        match self.chain.process_attestation(attestation) {
+        // Validators produce unaggregated attestations, which are published on the subnet of
+        // their shard. As attestations are not yet aggregated, they are also published on the
+        // global attestation topic so that every node can include them in blocks.
+        let fork_version = self.chain.fork_version();
+        let topics = vec![
+            GossipTopic::attestation_subnet(attestation.data.shard, fork_version),
+            GossipTopic::new(GossipKind::BeaconAttestation, fork_version),
+        ];
+
+        match self.chain.process_attestation(attestation.clone()) {
             Ok(_) => {
                 // Attestation was successfully processed.
                 info!(
@@ -133,6 +145,21 @@ impl AttestationService for AttestationServiceInstance {
                     "type" => "valid_attestation",
                 );
 
+                // Publish the attestation to the p2p network via gossipsub.
+                self.network_chan
+                    .send(NetworkMessage::Publish {
+                        topics,
+                        message: Box::new(PubsubMessage::Attestation(attestation)),
+                    })
+                    .unwrap_or_else(|e| {
+                        error!(
+                            self.log,
+                            "PublishAttestation";
+                            "type" => "failed to publish to gossipsub",
+                            "error" => format!("{:?}", e)
+                        );
+                    });
+
                 resp.set_success(true);
             }
             Err(e) => {
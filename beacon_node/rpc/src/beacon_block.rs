@@ -1,6 +1,6 @@
 use crate::beacon_chain::BeaconChain;
 use crossbeam_channel;
-use eth2_libp2p::PubsubMessage;
+use eth2_libp2p::{GossipKind, GossipTopic, PubsubMessage};
 use futures::Future;
 use grpcio::{RpcContext, RpcStatus, RpcStatusCode, UnarySink};
 use network::NetworkMessage;
@@ -105,9 +105,10 @@ impl BeaconBlockService for BeaconBlockServiceInstance {
                                 "outcome" => format!("{:?}", outcome)
                             );
 
-                            // TODO: Obtain topics from the network service properly.
-                            let topic =
-                                types::TopicBuilder::new("beacon_chain".to_string()).build();
+                            let topic = GossipTopic::new(
+                                GossipKind::BeaconBlock,
+                                self.chain.fork_version(),
+                            );
                             let message = PubsubMessage::Block(block);
 
                             // Publish the block to the p2p network via gossipsub.
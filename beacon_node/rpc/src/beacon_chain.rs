@@ -4,10 +4,11 @@ use beacon_chain::{
     fork_choice::ForkChoice,
     parking_lot::{RwLockReadGuard, RwLockWriteGuard},
     slot_clock::SlotClock,
-    types::{BeaconState, ChainSpec, Signature},
-    AttestationValidationError, BlockProductionError,
+    types::{BeaconState, ChainSpec, Epoch, Signature},
+    AttestationValidationError, BlockProductionError, EpochDuties,
 };
 pub use beacon_chain::{BeaconChainError, BlockProcessingOutcome};
+use std::sync::Arc;
 use types::{Attestation, AttestationData, BeaconBlock};
 
 /// The RPC's API to the beacon chain.
@@ -18,6 +19,10 @@ pub trait BeaconChain: Send + Sync {
 
     fn get_mut_state(&self) -> RwLockWriteGuard<BeaconState>;
 
+    fn fork_version(&self) -> [u8; 4];
+
+    fn get_epoch_duties(&self, epoch: Epoch) -> Result<Arc<EpochDuties>, BeaconChainError>;
+
     fn process_block(&self, block: BeaconBlock)
         -> Result<BlockProcessingOutcome, BeaconChainError>;
 
@@ -52,6 +57,16 @@ where
         self.state.write()
     }
 
+    /// The fork version of the present epoch, which namespaces gossip topics.
+    fn fork_version(&self) -> [u8; 4] {
+        let state = self.get_state();
+        state.fork.get_fork_version(state.current_epoch(&self.spec))
+    }
+
+    fn get_epoch_duties(&self, epoch: Epoch) -> Result<Arc<EpochDuties>, BeaconChainError> {
+        self.epoch_duties(epoch)
+    }
+
     fn process_block(
         &self,
         block: BeaconBlock,
@@ -10,9 +10,11 @@ use self::beacon_block::BeaconBlockServiceInstance;
 use self::beacon_chain::BeaconChain;
 use self::beacon_node::BeaconNodeServiceInstance;
 use self::validator::ValidatorServiceInstance;
+use ::beacon_chain::parking_lot::Mutex;
 pub use config::Config as RPCConfig;
 use futures::Future;
 use grpcio::{Environment, ServerBuilder};
+use network::subnets::SubnetSubscriptions;
 use network::NetworkMessage;
 use protos::services_grpc::{
     create_attestation_service, create_beacon_block_service, create_beacon_node_service,
@@ -47,7 +49,7 @@ pub fn start_server(
     let beacon_block_service = {
         let instance = BeaconBlockServiceInstance {
             chain: beacon_chain.clone(),
-            network_chan,
+            network_chan: network_chan.clone(),
             log: log.clone(),
         };
         create_beacon_block_service(instance)
@@ -55,6 +57,8 @@ pub fn start_server(
     let validator_service = {
         let instance = ValidatorServiceInstance {
             chain: beacon_chain.clone(),
+            network_chan: network_chan.clone(),
+            subnet_subscriptions: Arc::new(Mutex::new(SubnetSubscriptions::new())),
             log: log.clone(),
         };
         create_validator_service(instance)
@@ -62,6 +66,7 @@ pub fn start_server(
     let attestation_service = {
         let instance = AttestationServiceInstance {
             chain: beacon_chain.clone(),
+            network_chan,
             log: log.clone(),
         };
         create_attestation_service(instance)
@@ -1,17 +1,24 @@
 use crate::beacon_chain::BeaconChain;
+use beacon_chain::parking_lot::Mutex;
 use bls::PublicKey;
+use eth2_libp2p::GossipTopic;
 use futures::Future;
 use grpcio::{RpcContext, RpcStatus, RpcStatusCode, UnarySink};
+use network::subnets::SubnetSubscriptions;
+use network::NetworkMessage;
 use protos::services::{ActiveValidator, GetDutiesRequest, GetDutiesResponse, ValidatorDuty};
 use protos::services_grpc::ValidatorService;
 use slog::{trace, warn};
 use ssz::decode;
 use std::sync::Arc;
-use types::{Epoch, RelativeEpoch};
+use types::Epoch;
 
 #[derive(Clone)]
 pub struct ValidatorServiceInstance {
     pub chain: Arc<BeaconChain>,
+    pub network_chan: crossbeam_channel::Sender<NetworkMessage>,
+    /// The attestation subnets needed by the validators which have requested their duties.
+    pub subnet_subscriptions: Arc<Mutex<SubnetSubscriptions>>,
     pub log: slog::Logger,
 }
 //TODO: Refactor Errors
@@ -30,46 +37,32 @@ impl ValidatorService for ValidatorServiceInstance {
         trace!(self.log, "RPC request"; "endpoint" => "GetValidatorDuties", "epoch" => req.get_epoch());
 
         let spec = self.chain.get_spec();
-        let state = self.chain.get_state();
         let epoch = Epoch::from(req.get_epoch());
         let mut resp = GetDutiesResponse::new();
         let resp_validators = resp.mut_active_validators();
 
-        let relative_epoch =
-            match RelativeEpoch::from_epoch(state.slot.epoch(spec.slots_per_epoch), epoch) {
-                Ok(v) => v,
-                Err(e) => {
-                    // incorrect epoch
-                    let log_clone = self.log.clone();
-                    let f = sink
-                        .fail(RpcStatus::new(
-                            RpcStatusCode::FailedPrecondition,
-                            Some(format!("Invalid epoch: {:?}", e)),
-                        ))
-                        .map_err(move |e| warn!(log_clone, "failed to reply {:?}: {:?}", req, e));
-                    return ctx.spawn(f);
-                }
-            };
-
-        let validator_proposers: Result<Vec<usize>, _> = epoch
-            .slot_iter(spec.slots_per_epoch)
-            .map(|slot| state.get_beacon_proposer_index(slot, relative_epoch, &spec))
-            .collect();
-        let validator_proposers = match validator_proposers {
+        // compute the duties before acquiring the state lock, advancing a copy of the state if
+        // the epoch is in the future.
+        let epoch_duties = match self.chain.get_epoch_duties(epoch) {
             Ok(v) => v,
             Err(e) => {
-                // could not get the validator proposer index
+                // incorrect epoch or unable to compute the duties
                 let log_clone = self.log.clone();
                 let f = sink
                     .fail(RpcStatus::new(
                         RpcStatusCode::FailedPrecondition,
-                        Some(format!("Could not find beacon proposers: {:?}", e)),
+                        Some(format!("Unable to determine duties for epoch: {:?}", e)),
                     ))
-                    .map_err(move |e| warn!(log_clone, "failed to reply {:?} : {:?}", req, e));
+                    .map_err(move |e| warn!(log_clone, "failed to reply {:?}: {:?}", req, e));
                 return ctx.spawn(f);
             }
         };
 
+        let fork_version = self.chain.fork_version();
+        let state = self.chain.get_state();
+        let current_epoch = state.current_epoch(&spec);
+        let mut subnet_topics = vec![];
+
         // get the duties for each validator
         for validator_pk in validators.get_public_keys() {
             let mut active_validator = ActiveValidator::new();
@@ -115,9 +108,9 @@ impl ValidatorService for ValidatorServiceInstance {
             };
 
             // get attestation duties and check if validator is active
-            let attestation_duties = match state.get_attestation_duties(val_index, &spec) {
-                Ok(Some(v)) => v,
-                Ok(_) => {
+            let attestation_duties = match epoch_duties.attestation_duty(val_index) {
+                Some(v) => v,
+                None => {
                     // validator is inactive, go to the next validator
                     warn!(
                         self.log,
@@ -127,27 +120,14 @@ impl ValidatorService for ValidatorServiceInstance {
                     resp_validators.push(active_validator);
                     continue;
                 }
-                // the cache is not built, throw an error
-                Err(e) => {
-                    let log_clone = self.log.clone();
-                    let f = sink
-                        .fail(RpcStatus::new(
-                            RpcStatusCode::FailedPrecondition,
-                            Some(format!("Beacon state error {:?}", e)),
-                        ))
-                        .map_err(move |e| warn!(log_clone, "Failed to reply {:?}: {:?}", req, e));
-                    return ctx.spawn(f);
-                }
             };
 
             // we have an active validator, set its duties
             let mut duty = ValidatorDuty::new();
 
             // check if the validator needs to propose a block
-            if let Some(slot) = validator_proposers.iter().position(|&v| val_index == v) {
-                duty.set_block_production_slot(
-                    epoch.start_slot(spec.slots_per_epoch).as_u64() + slot as u64,
-                );
+            if let Some(slot) = epoch_duties.block_production_slot(val_index, &spec) {
+                duty.set_block_production_slot(slot.as_u64());
             } else {
                 // no blocks to propose this epoch
                 duty.set_none(false)
@@ -158,10 +138,52 @@ impl ValidatorService for ValidatorServiceInstance {
             duty.set_attestation_shard(attestation_duties.shard);
             duty.set_committee_len(attestation_duties.committee_len as u64);
 
+            // the validator's attestations are published on the subnet of its shard
+            subnet_topics.push(GossipTopic::attestation_subnet(
+                attestation_duties.shard,
+                fork_version,
+            ));
+
             active_validator.set_duty(duty);
             resp_validators.push(active_validator);
         }
 
+        // subscribe to the attestation subnets of the validators until their duties are done, so
+        // that we receive the attestations of their committees, and unsubscribe from the subnets
+        // which are no longer needed
+        let (new_topics, expired_topics) = {
+            let mut subscriptions = self.subnet_subscriptions.lock();
+            let new_topics: Vec<GossipTopic> = subnet_topics
+                .into_iter()
+                .filter(|topic| subscriptions.subscribe(*topic, epoch))
+                .collect();
+            (new_topics, subscriptions.expire(current_epoch))
+        };
+        if !new_topics.is_empty() {
+            self.network_chan
+                .send(NetworkMessage::Subscribe { topics: new_topics })
+                .unwrap_or_else(|e| {
+                    warn!(
+                        self.log,
+                        "Failed to subscribe to attestation subnets";
+                        "error" => format!("{:?}", e)
+                    )
+                });
+        }
+        if !expired_topics.is_empty() {
+            self.network_chan
+                .send(NetworkMessage::Unsubscribe {
+                    topics: expired_topics,
+                })
+                .unwrap_or_else(|e| {
+                    warn!(
+                        self.log,
+                        "Failed to unsubscribe from attestation subnets";
+                        "error" => format!("{:?}", e)
+                    )
+                });
+        }
+
         let f = sink
             .success(resp)
             .map_err(move |e| println!("failed to reply {:?}: {:?}", req, e));
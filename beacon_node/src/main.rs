@@ -46,6 +46,30 @@ fn main() {
                 .help("A list of comma separated multi addresses representing bootnodes to connect to.")
                 .takes_value(true),
         )
+        .arg(
+            Arg::with_name("network-key")
+                .long("network-key")
+                .value_name("FILE")
+                .help("File containing the key which determines the peer id of this node. Created if it does not exist. Defaults to a file in the data directory.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("network-key-type")
+                .long("network-key-type")
+                .value_name("TYPE")
+                .help("The type of network key to generate if none is stored.")
+                .possible_values(&["secp256k1", "ed25519"])
+                .takes_value(true),
+        )
+        // beacon chain related arguments
+        .arg(
+            Arg::with_name("fork-choice")
+                .long("fork-choice")
+                .value_name("ALGORITHM")
+                .help("The fork choice algorithm used to find the head of the chain.")
+                .possible_values(&["bitwise", "proto-array"])
+                .takes_value(true),
+        )
         // rpc related arguments
         .arg(
             Arg::with_name("rpc")
@@ -68,6 +92,28 @@ fn main() {
                 .help("Listen port for RPC endpoint.")
                 .takes_value(true),
         )
+        // rest api related arguments
+        .arg(
+            Arg::with_name("api")
+                .long("api")
+                .value_name("API")
+                .help("Enable the REST API server.")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("api-address")
+                .long("api-address")
+                .value_name("APIADDRESS")
+                .help("Listen address for the REST API.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("api-port")
+                .long("api-port")
+                .value_name("APIPORT")
+                .help("Listen port for the REST API.")
+                .takes_value(true),
+        )
         .get_matches();
 
     // invalid arguments, panic
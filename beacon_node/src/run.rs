@@ -1,15 +1,17 @@
-use client::client_types::TestingClientType;
+use client::client_types::{TestingClientType, TestingProtoArrayClientType};
 use client::error;
-use client::{notifier, Client, ClientConfig};
+use client::{notifier, Client, ClientConfig, ClientTypes};
+use exit_future::{Exit, Signal};
+use fork_choice::ForkChoiceAlgorithm;
 use futures::sync::oneshot;
 use futures::Future;
 use slog::info;
 use std::cell::RefCell;
-use tokio::runtime::Builder;
+use tokio::runtime::{Builder, Runtime};
 use tokio_timer::clock::Clock;
 
 pub fn run_beacon_node(config: ClientConfig, log: &slog::Logger) -> error::Result<()> {
-    let mut runtime = Builder::new()
+    let runtime = Builder::new()
         .name_prefix("main-")
         .clock(Clock::system())
         .build()
@@ -32,10 +34,32 @@ pub fn run_beacon_node(config: ClientConfig, log: &slog::Logger) -> error::Resul
 
     let (exit_signal, exit) = exit_future::signal();
 
+    // currently testing - using the in-memory client types
+    match config.fork_choice {
+        ForkChoiceAlgorithm::ProtoArray => run_client::<TestingProtoArrayClientType>(
+            runtime,
+            config,
+            ctrlc,
+            exit_signal,
+            exit,
+            log,
+        ),
+        _ => run_client::<TestingClientType>(runtime, config, ctrlc, exit_signal, exit, log),
+    }
+}
+
+/// Runs a client of type `T` until `ctrlc` fires.
+fn run_client<T: ClientTypes>(
+    mut runtime: Runtime,
+    config: ClientConfig,
+    ctrlc: oneshot::Receiver<()>,
+    exit_signal: Signal,
+    exit: Exit,
+    log: &slog::Logger,
+) -> error::Result<()> {
     let executor = runtime.executor();
 
-    // currently testing - using TestingClientType
-    let client: Client<TestingClientType> = Client::new(config, log.clone(), &executor)?;
+    let client: Client<T> = Client::new(config, log.clone(), &executor)?;
     notifier::run(&client, executor, exit);
 
     runtime
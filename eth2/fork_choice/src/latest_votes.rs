@@ -0,0 +1,206 @@
+//! Tracks the latest vote of each validator, as used by the LMD-GHOST implementations.
+use ssz_derive::{Decode, Encode};
+use std::collections::{HashMap, HashSet};
+use types::{ChainSpec, Epoch, Hash256};
+
+/// The latest message of a single validator.
+#[derive(Debug, Clone, Copy, PartialEq, Encode, Decode)]
+pub struct Vote {
+    /// The block the validator voted for.
+    pub target_root: Hash256,
+    /// The epoch of the attestation which carried the vote.
+    pub target_epoch: Epoch,
+    /// The effective balance of the validator at the time of the vote.
+    pub balance: u64,
+}
+
+impl Vote {
+    /// The weight of this vote.
+    ///
+    /// Note: Votes are weighted by min(balance, MAX_DEPOSIT_AMOUNT) //
+    /// FORK_CHOICE_BALANCE_INCREMENT
+    pub fn weight(&self, spec: &ChainSpec) -> u64 {
+        std::cmp::min(self.balance, spec.max_deposit_amount) / spec.fork_choice_balance_increment
+    }
+}
+
+/// The latest vote of each validator, excluding validators which have been proven to equivocate.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LatestVotes {
+    /// Maps validator index to the latest vote of that validator.
+    votes: HashMap<u64, Vote>,
+    /// Validators whose votes are ignored.
+    equivocating: HashSet<u64>,
+}
+
+impl LatestVotes {
+    /// Record `vote` as the latest vote of `validator_index`, if it is strictly newer than the
+    /// existing vote and the validator has not equivocated.
+    ///
+    /// Returns `true` if the vote was recorded.
+    pub fn insert(&mut self, validator_index: u64, vote: Vote) -> bool {
+        if self.equivocating.contains(&validator_index) {
+            return false;
+        }
+
+        match self.votes.get(&validator_index) {
+            Some(existing) if existing.target_epoch >= vote.target_epoch => false,
+            _ => {
+                self.votes.insert(validator_index, vote);
+                true
+            }
+        }
+    }
+
+    /// Remove the vote of `validator_index` and ignore all future votes from it.
+    ///
+    /// Returns the vote that was removed, if any.
+    pub fn insert_equivocation(&mut self, validator_index: u64) -> Option<Vote> {
+        self.equivocating.insert(validator_index);
+        self.votes.remove(&validator_index)
+    }
+
+    /// Returns the latest vote of `validator_index`.
+    pub fn get(&self, validator_index: u64) -> Option<&Vote> {
+        self.votes.get(&validator_index)
+    }
+
+    /// Returns `true` if `validator_index` has been proven to equivocate.
+    pub fn is_equivocating(&self, validator_index: u64) -> bool {
+        self.equivocating.contains(&validator_index)
+    }
+
+    /// Returns the total weight of the latest votes for each block root.
+    ///
+    /// Blocks without any weighted votes are omitted.
+    pub fn weights(&self, spec: &ChainSpec) -> HashMap<Hash256, u64> {
+        let mut weights = HashMap::new();
+        for vote in self.votes.values() {
+            let weight = vote.weight(spec);
+            if weight > 0 {
+                *weights.entry(vote.target_root).or_insert(0) += weight;
+            }
+        }
+        weights
+    }
+}
+
+/// A single entry of `LatestVotes`, as stored in the database.
+#[derive(Debug, Clone, PartialEq, Encode, Decode)]
+pub struct PersistedVote {
+    pub validator_index: u64,
+    pub vote: Vote,
+}
+
+/// An SSZ-serializable version of `LatestVotes`.
+///
+/// Entries are sorted by validator index.
+#[derive(Debug, Clone, PartialEq, Encode, Decode)]
+pub struct PersistedLatestVotes {
+    pub votes: Vec<PersistedVote>,
+    pub equivocating: Vec<u64>,
+}
+
+impl<'a> From<&'a LatestVotes> for PersistedLatestVotes {
+    fn from(latest_votes: &'a LatestVotes) -> Self {
+        let mut votes: Vec<PersistedVote> = latest_votes
+            .votes
+            .iter()
+            .map(|(validator_index, vote)| PersistedVote {
+                validator_index: *validator_index,
+                vote: *vote,
+            })
+            .collect();
+        votes.sort_by_key(|entry| entry.validator_index);
+
+        let mut equivocating: Vec<u64> = latest_votes.equivocating.iter().cloned().collect();
+        equivocating.sort();
+
+        Self {
+            votes,
+            equivocating,
+        }
+    }
+}
+
+impl From<PersistedLatestVotes> for LatestVotes {
+    fn from(persisted: PersistedLatestVotes) -> Self {
+        Self {
+            votes: persisted
+                .votes
+                .into_iter()
+                .map(|entry| (entry.validator_index, entry.vote))
+                .collect(),
+            equivocating: persisted.equivocating.into_iter().collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vote(root: u64, epoch: u64) -> Vote {
+        Vote {
+            target_root: Hash256::from_low_u64_be(root),
+            target_epoch: Epoch::new(epoch),
+            balance: 32_000_000_000,
+        }
+    }
+
+    #[test]
+    fn only_strictly_newer_votes_replace() {
+        let mut votes = LatestVotes::default();
+
+        assert!(votes.insert(0, vote(1, 2)));
+        assert!(
+            !votes.insert(0, vote(2, 2)),
+            "same epoch should not replace"
+        );
+        assert!(
+            !votes.insert(0, vote(3, 1)),
+            "older epoch should not replace"
+        );
+        assert_eq!(votes.get(0), Some(&vote(1, 2)));
+
+        assert!(votes.insert(0, vote(4, 3)));
+        assert_eq!(votes.get(0), Some(&vote(4, 3)));
+    }
+
+    #[test]
+    fn equivocating_validators_are_ignored() {
+        let mut votes = LatestVotes::default();
+        votes.insert(0, vote(1, 1));
+        votes.insert(1, vote(1, 1));
+
+        assert_eq!(votes.insert_equivocation(0), Some(vote(1, 1)));
+        assert!(votes.is_equivocating(0));
+        assert!(!votes.insert(0, vote(2, 2)));
+        assert_eq!(votes.get(0), None);
+
+        let spec = ChainSpec::foundation();
+        let weights = votes.weights(&spec);
+        assert_eq!(weights.len(), 1);
+        assert_eq!(
+            weights[&Hash256::from_low_u64_be(1)],
+            vote(1, 1).weight(&spec)
+        );
+    }
+
+    #[test]
+    fn persisted_round_trip() {
+        let mut votes = LatestVotes::default();
+        votes.insert(3, vote(1, 1));
+        votes.insert(1, vote(2, 4));
+        votes.insert(2, vote(2, 4));
+        votes.insert_equivocation(2);
+        votes.insert_equivocation(7);
+
+        let persisted = PersistedLatestVotes::from(&votes);
+        let bytes = ssz::ssz_encode(&persisted);
+        let decoded: PersistedLatestVotes = ssz::decode(&bytes).unwrap();
+
+        assert_eq!(decoded, persisted);
+        assert_eq!(LatestVotes::from(decoded), votes);
+    }
+}
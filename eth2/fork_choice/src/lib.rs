@@ -1,8 +1,8 @@
 //! This crate stores the various implementations of fork-choice rules that can be used for the
 //! beacon blockchain.
 //!
-//! There are three implementations. One is the naive longest chain rule (primarily for testing
-//! purposes). The other two are proposed implementations of the LMD-GHOST fork-choice rule with various forms of optimisation.
+//! There are five implementations. One is the naive longest chain rule (primarily for testing
+//! purposes). The others are proposed implementations of the LMD-GHOST fork-choice rule with various forms of optimisation.
 //!
 //! The current implementations are:
 //! - [`longest-chain`]: Simplistic longest-chain fork choice - primarily for testing, **not for
@@ -11,27 +11,37 @@
 //! specifications (https://github.com/ethereum/eth2.0-specs/blob/v0.1/specs/core/0_beacon-chain.md#get_block_root).
 //! - [`bitwise_lmd_ghost`]: This is an optimised version of bitwise LMD-GHOST as proposed
 //! by Vitalik. The reference implementation can be found at: https://github.com/ethereum/research/blob/master/ghost/ghost.py
+//! - [`optimized_lmd_ghost`]: An implementation of LMD-GHOST using a log-lookup of block ancestors.
+//! - [`proto_array`]: An implementation of LMD-GHOST which stores blocks in a flat array with
+//! cumulative weights and applies vote changes incrementally.
 //!
 //! [`longest-chain`]: struct.LongestChain.html
 //! [`slow_lmd_ghost`]: struct.SlowLmdGhost.html
 //! [`bitwise_lmd_ghost`]: struct.OptimisedLmdGhost.html
+//! [`optimized_lmd_ghost`]: struct.OptimizedLMDGhost.html
+//! [`proto_array`]: struct.ProtoArrayForkChoice.html
 
 extern crate db;
 extern crate ssz;
 extern crate types;
 
 pub mod bitwise_lmd_ghost;
+pub mod latest_votes;
 pub mod longest_chain;
 pub mod optimized_lmd_ghost;
+mod persistence;
+pub mod proto_array;
 pub mod slow_lmd_ghost;
 
 use db::stores::BeaconBlockAtSlotError;
 use db::DBError;
-use types::{BeaconBlock, ChainSpec, Hash256};
+use types::{BeaconBlock, ChainSpec, Epoch, Hash256};
 
 pub use bitwise_lmd_ghost::BitwiseLMDGhost;
+pub use latest_votes::{LatestVotes, PersistedLatestVotes, Vote};
 pub use longest_chain::LongestChain;
 pub use optimized_lmd_ghost::OptimizedLMDGhost;
+pub use proto_array::ProtoArrayForkChoice;
 pub use slow_lmd_ghost::SlowLMDGhost;
 
 /// Defines the interface for Fork Choices. Each Fork choice will define their own data structures
@@ -47,11 +57,24 @@ pub trait ForkChoice: Send + Sync {
         spec: &ChainSpec,
     ) -> Result<(), ForkChoiceError>;
     /// Called when an attestation has been added. Allows generic attestation-level data structures to be built for a given fork choice.
+    ///
+    /// The vote of `validator_index` for `target_block_hash` is weighted by the effective
+    /// `balance` of the validator. It only replaces an existing vote from the same validator if
+    /// `target_epoch` is strictly greater than that of the existing vote.
     // This can be generalised to a full attestation if required later.
     fn add_attestation(
         &mut self,
         validator_index: u64,
         target_block_hash: &Hash256,
+        target_epoch: Epoch,
+        balance: u64,
+        spec: &ChainSpec,
+    ) -> Result<(), ForkChoiceError>;
+    /// Called when a validator has been proven to equivocate (e.g., by an attester slashing).
+    /// The weight of the validator is removed and all future votes from it are ignored.
+    fn add_equivocation(
+        &mut self,
+        validator_index: u64,
         spec: &ChainSpec,
     ) -> Result<(), ForkChoiceError>;
     /// The fork-choice algorithm to find the current canonical head of the chain.
@@ -61,10 +84,27 @@ pub trait ForkChoice: Send + Sync {
         justified_start_block: &Hash256,
         spec: &ChainSpec,
     ) -> Result<Hash256, ForkChoiceError>;
+    /// Called when the finalized block has changed. Allows fork choices to prune data structures
+    /// which can no longer affect the head.
+    fn update_finalized_root(
+        &mut self,
+        _finalized_root: &Hash256,
+        _spec: &ChainSpec,
+    ) -> Result<(), ForkChoiceError> {
+        Ok(())
+    }
+    /// Returns the SSZ encoding of the state of the fork choice, so that it can be stored in the
+    /// database. Caches which can be rebuilt from the block store are not included.
+    fn as_ssz_bytes(&self) -> Vec<u8>;
+    /// Replaces the state of the fork choice with one previously returned by `as_ssz_bytes`.
+    ///
+    /// The fork choice is left unmodified if `bytes` is invalid or refers to blocks which are not
+    /// in the block store.
+    fn restore_from_ssz_bytes(&mut self, bytes: &[u8]) -> Result<(), ForkChoiceError>;
 }
 
 /// Possible fork choice errors that can occur.
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone)]
 pub enum ForkChoiceError {
     MissingBeaconBlock(Hash256),
     MissingBeaconState(Hash256),
@@ -73,6 +113,10 @@ pub enum ForkChoiceError {
     ChildrenNotFound,
     StorageError(String),
     HeadNotFound,
+    InvalidDeltaLength(usize),
+    InvalidNodeDelta(Hash256),
+    MissingPersistedBlock(Hash256),
+    InvalidPersistedState(String),
 }
 
 impl From<DBError> for ForkChoiceError {
@@ -106,4 +150,7 @@ pub enum ForkChoiceAlgorithm {
     BitwiseLMDGhost,
     /// An optimised implementation of LMD ghost.
     OptimizedLMDGhost,
+    /// A proto-array implementation of LMD ghost, which does not read the database to find the
+    /// head.
+    ProtoArray,
 }
@@ -1,7 +1,9 @@
+use crate::persistence::{decode_persisted, verify_blocks_exist};
 use crate::{ForkChoice, ForkChoiceError};
 use db::{stores::BeaconBlockStore, ClientDB};
+use ssz::ssz_encode;
 use std::sync::Arc;
-use types::{BeaconBlock, ChainSpec, Hash256, Slot};
+use types::{BeaconBlock, ChainSpec, Epoch, Hash256, Slot};
 
 pub struct LongestChain<T>
 where
@@ -43,12 +45,19 @@ impl<T: ClientDB + Sized> ForkChoice for LongestChain<T> {
         &mut self,
         _: u64,
         _: &Hash256,
+        _: Epoch,
+        _: u64,
         _: &ChainSpec,
     ) -> Result<(), ForkChoiceError> {
         // do nothing
         Ok(())
     }
 
+    fn add_equivocation(&mut self, _: u64, _: &ChainSpec) -> Result<(), ForkChoiceError> {
+        // do nothing
+        Ok(())
+    }
+
     fn find_head(&mut self, _: &Hash256, _: &ChainSpec) -> Result<Hash256, ForkChoiceError> {
         let mut head_blocks: Vec<(usize, BeaconBlock)> = vec![];
         /*
@@ -105,4 +114,18 @@ impl<T: ClientDB + Sized> ForkChoice for LongestChain<T> {
 
         Ok(self.head_block_hashes[head_index.unwrap()])
     }
+
+    fn as_ssz_bytes(&self) -> Vec<u8> {
+        // The order of the heads is preserved, it is used to break ties.
+        ssz_encode(&self.head_block_hashes)
+    }
+
+    fn restore_from_ssz_bytes(&mut self, bytes: &[u8]) -> Result<(), ForkChoiceError> {
+        let head_block_hashes: Vec<Hash256> = decode_persisted(bytes)?;
+
+        verify_blocks_exist(&self.block_store, &head_block_hashes)?;
+
+        self.head_block_hashes = head_block_hashes;
+        Ok(())
+    }
 }
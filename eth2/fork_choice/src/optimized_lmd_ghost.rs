@@ -1,16 +1,20 @@
 //! The optimised bitwise LMD-GHOST fork choice rule.
 extern crate bit_vec;
 
-use crate::{ForkChoice, ForkChoiceError};
-use db::{
-    stores::{BeaconBlockStore, BeaconStateStore},
-    ClientDB,
+use crate::persistence::{
+    ancestors_from_persisted, ancestors_to_persisted, children_from_persisted,
+    children_to_persisted, decode_persisted, verify_blocks_exist, PersistedAncestor,
+    PersistedChildren,
 };
+use crate::{ForkChoice, ForkChoiceError, LatestVotes, PersistedLatestVotes, Vote};
+use db::{stores::BeaconBlockStore, ClientDB};
 use log::{debug, trace};
+use ssz::ssz_encode;
+use ssz_derive::{Decode, Encode};
 use std::cmp::Ordering;
 use std::collections::HashMap;
 use std::sync::Arc;
-use types::{BeaconBlock, ChainSpec, Hash256, Slot, SlotHeight};
+use types::{BeaconBlock, ChainSpec, Epoch, Hash256, SlotHeight};
 
 //TODO: Pruning - Children
 //TODO: Handle Syncing
@@ -42,13 +46,21 @@ pub struct OptimizedLMDGhost<T: ClientDB + Sized> {
     ancestors: Vec<HashMap<Hash256, Hash256>>,
     /// Stores the children for any given parent.
     children: HashMap<Hash256, Vec<Hash256>>,
-    /// The latest attestation targets as a map of validator index to block hash.
-    //TODO: Could this be a fixed size vec
-    latest_attestation_targets: HashMap<u64, Hash256>,
+    /// The latest vote of each validator.
+    latest_votes: LatestVotes,
     /// Block storage access.
     block_store: Arc<BeaconBlockStore<T>>,
-    /// State storage access.
-    state_store: Arc<BeaconStateStore<T>>,
+    max_known_height: SlotHeight,
+}
+
+/// The state of a `OptimizedLMDGhost`, as stored in the database.
+///
+/// The ancestor cache is not stored, it is rebuilt as required.
+#[derive(Debug, PartialEq, Encode, Decode)]
+struct PersistedOptimizedLMDGhost {
+    ancestors: Vec<Vec<PersistedAncestor>>,
+    children: Vec<PersistedChildren>,
+    latest_votes: PersistedLatestVotes,
     max_known_height: SlotHeight,
 }
 
@@ -56,56 +68,23 @@ impl<T> OptimizedLMDGhost<T>
 where
     T: ClientDB + Sized,
 {
-    pub fn new(
-        block_store: Arc<BeaconBlockStore<T>>,
-        state_store: Arc<BeaconStateStore<T>>,
-    ) -> Self {
+    pub fn new(block_store: Arc<BeaconBlockStore<T>>) -> Self {
         OptimizedLMDGhost {
             cache: HashMap::new(),
             ancestors: vec![HashMap::new(); 16],
-            latest_attestation_targets: HashMap::new(),
+            latest_votes: LatestVotes::default(),
             children: HashMap::new(),
             max_known_height: SlotHeight::new(0),
             block_store,
-            state_store,
         }
     }
 
     /// Finds the latest votes weighted by validator balance. Returns a hashmap of block_hash to
     /// weighted votes.
-    pub fn get_latest_votes(
-        &self,
-        state_root: &Hash256,
-        block_slot: Slot,
-        spec: &ChainSpec,
-    ) -> Result<HashMap<Hash256, u64>, ForkChoiceError> {
-        // get latest votes
-        // Note: Votes are weighted by min(balance, MAX_DEPOSIT_AMOUNT) //
-        // FORK_CHOICE_BALANCE_INCREMENT
-        // build a hashmap of block_hash to weighted votes
-        let mut latest_votes: HashMap<Hash256, u64> = HashMap::new();
-        // gets the current weighted votes
-        let current_state = self
-            .state_store
-            .get_deserialized(&state_root)?
-            .ok_or_else(|| ForkChoiceError::MissingBeaconState(*state_root))?;
-
-        let active_validator_indices =
-            current_state.get_active_validator_indices(block_slot.epoch(spec.slots_per_epoch));
-
-        for index in active_validator_indices {
-            let balance = std::cmp::min(
-                current_state.validator_balances[index],
-                spec.max_deposit_amount,
-            ) / spec.fork_choice_balance_increment;
-            if balance > 0 {
-                if let Some(target) = self.latest_attestation_targets.get(&(index as u64)) {
-                    *latest_votes.entry(*target).or_insert_with(|| 0) += balance;
-                }
-            }
-        }
+    pub fn get_latest_votes(&self, spec: &ChainSpec) -> HashMap<Hash256, u64> {
+        let latest_votes = self.latest_votes.weights(spec);
         trace!("Latest votes: {:?}", latest_votes);
-        Ok(latest_votes)
+        latest_votes
     }
 
     /// Gets the ancestor at a given height `at_height` of a block specified by `block_hash`.
@@ -176,8 +155,10 @@ where
             if let Some(ancestor) = self.get_ancestor(*hash, block_height, spec) {
                 let current_vote_value = current_votes.get(&ancestor).unwrap_or_else(|| &0);
                 current_votes.insert(ancestor, current_vote_value + *votes);
-                total_vote_count += votes;
             }
+            // votes for blocks below this height still count towards the total, otherwise they
+            // could outweigh the ancestors of the winner without preventing it from being chosen
+            total_vote_count += votes;
         }
         // Check if there is a clear block winner at this height. If so return it.
         for (hash, votes) in current_votes.iter() {
@@ -254,46 +235,40 @@ impl<T: ClientDB + Sized> ForkChoice for OptimizedLMDGhost<T> {
         &mut self,
         validator_index: u64,
         target_block_root: &Hash256,
-        spec: &ChainSpec,
+        target_epoch: Epoch,
+        balance: u64,
+        _: &ChainSpec,
     ) -> Result<(), ForkChoiceError> {
-        // simply add the attestation to the latest_attestation_target if the block_height is
-        // larger
         trace!(
             "Adding attestation of validator: {:?} for block: {}",
             validator_index,
             target_block_root
         );
-        let attestation_target = self
-            .latest_attestation_targets
-            .entry(validator_index)
-            .or_insert_with(|| *target_block_root);
-        // if we already have a value
-        if attestation_target != target_block_root {
-            trace!("Old attestation found: {:?}", attestation_target);
-            // get the height of the target block
-            let block_height = self
-                .block_store
-                .get_deserialized(&target_block_root)?
-                .ok_or_else(|| ForkChoiceError::MissingBeaconBlock(*target_block_root))?
-                .slot
-                .height(spec.genesis_slot);
-
-            // get the height of the past target block
-            let past_block_height = self
-                .block_store
-                .get_deserialized(&attestation_target)?
-                .ok_or_else(|| ForkChoiceError::MissingBeaconBlock(*attestation_target))?
-                .slot
-                .height(spec.genesis_slot);
-            // update the attestation only if the new target is higher
-            if past_block_height < block_height {
-                trace!("Updating old attestation");
-                *attestation_target = *target_block_root;
-            }
+        let vote = Vote {
+            target_root: *target_block_root,
+            target_epoch,
+            balance,
+        };
+        // only replace an existing vote with a strictly newer one
+        if !self.latest_votes.insert(validator_index, vote) {
+            trace!("Ignoring attestation that is not newer than the latest vote");
         }
         Ok(())
     }
 
+    fn add_equivocation(
+        &mut self,
+        validator_index: u64,
+        _: &ChainSpec,
+    ) -> Result<(), ForkChoiceError> {
+        trace!(
+            "Removing votes of equivocating validator: {}",
+            validator_index
+        );
+        self.latest_votes.insert_equivocation(validator_index);
+        Ok(())
+    }
+
     /// Perform lmd_ghost on the current chain to find the head.
     fn find_head(
         &mut self,
@@ -310,12 +285,11 @@ impl<T: ClientDB + Sized> ForkChoice for OptimizedLMDGhost<T> {
             .ok_or_else(|| ForkChoiceError::MissingBeaconBlock(*justified_block_start))?;
 
         let block_slot = block.slot;
-        let state_root = block.state_root;
         let mut block_height = block_slot.height(spec.genesis_slot);
 
         let mut current_head = *justified_block_start;
 
-        let mut latest_votes = self.get_latest_votes(&state_root, block_slot, spec)?;
+        let mut latest_votes = self.get_latest_votes(spec);
 
         // remove any votes that don't relate to our current head.
         latest_votes
@@ -417,6 +391,46 @@ impl<T: ClientDB + Sized> ForkChoice for OptimizedLMDGhost<T> {
             });
         }
     }
+
+    fn as_ssz_bytes(&self) -> Vec<u8> {
+        ssz_encode(&PersistedOptimizedLMDGhost {
+            ancestors: ancestors_to_persisted(&self.ancestors),
+            children: children_to_persisted(&self.children),
+            latest_votes: PersistedLatestVotes::from(&self.latest_votes),
+            max_known_height: self.max_known_height,
+        })
+    }
+
+    fn restore_from_ssz_bytes(&mut self, bytes: &[u8]) -> Result<(), ForkChoiceError> {
+        let persisted: PersistedOptimizedLMDGhost = decode_persisted(bytes)?;
+
+        if persisted.ancestors.len() != self.ancestors.len() {
+            return Err(ForkChoiceError::InvalidPersistedState(format!(
+                "Expected {} ancestor lookup tables, found {}",
+                self.ancestors.len(),
+                persisted.ancestors.len()
+            )));
+        }
+        verify_blocks_exist(
+            &self.block_store,
+            persisted
+                .ancestors
+                .iter()
+                .flatten()
+                .flat_map(|entry| vec![&entry.block_root, &entry.ancestor_root]),
+        )?;
+        verify_blocks_exist(
+            &self.block_store,
+            persisted.children.iter().flat_map(|entry| &entry.children),
+        )?;
+
+        self.cache = HashMap::new();
+        self.ancestors = ancestors_from_persisted(persisted.ancestors);
+        self.children = children_from_persisted(persisted.children);
+        self.latest_votes = persisted.latest_votes.into();
+        self.max_known_height = persisted.max_known_height;
+        Ok(())
+    }
 }
 
 /// Type for storing blocks in a memory cache. Key is comprised of block-hash plus the height.
@@ -0,0 +1,108 @@
+//! SSZ-serializable versions of the data structures shared by the fork choice implementations.
+//!
+//! Maps are stored as lists sorted by key, so that the same fork choice state always produces the
+//! same bytes.
+use crate::ForkChoiceError;
+use db::{stores::BeaconBlockStore, ClientDB};
+use ssz::{decode, Decodable};
+use ssz_derive::{Decode, Encode};
+use std::collections::HashMap;
+use types::Hash256;
+
+/// The children of a single block.
+#[derive(Debug, PartialEq, Clone, Encode, Decode)]
+pub struct PersistedChildren {
+    pub parent: Hash256,
+    pub children: Vec<Hash256>,
+}
+
+/// A single entry of an ancestor lookup table.
+#[derive(Debug, PartialEq, Clone, Encode, Decode)]
+pub struct PersistedAncestor {
+    pub block_root: Hash256,
+    pub ancestor_root: Hash256,
+}
+
+/// Convert a map of parent to children into a list, sorted by parent.
+pub fn children_to_persisted(children: &HashMap<Hash256, Vec<Hash256>>) -> Vec<PersistedChildren> {
+    let mut persisted: Vec<PersistedChildren> = children
+        .iter()
+        .map(|(parent, children)| PersistedChildren {
+            parent: *parent,
+            children: children.clone(),
+        })
+        .collect();
+    persisted.sort_by_key(|entry| entry.parent);
+    persisted
+}
+
+/// Inverse of `children_to_persisted`.
+pub fn children_from_persisted(
+    persisted: Vec<PersistedChildren>,
+) -> HashMap<Hash256, Vec<Hash256>> {
+    persisted
+        .into_iter()
+        .map(|entry| (entry.parent, entry.children))
+        .collect()
+}
+
+/// Convert ancestor lookup tables into lists, each sorted by block root.
+pub fn ancestors_to_persisted(
+    ancestors: &[HashMap<Hash256, Hash256>],
+) -> Vec<Vec<PersistedAncestor>> {
+    ancestors
+        .iter()
+        .map(|table| {
+            let mut persisted: Vec<PersistedAncestor> = table
+                .iter()
+                .map(|(block_root, ancestor_root)| PersistedAncestor {
+                    block_root: *block_root,
+                    ancestor_root: *ancestor_root,
+                })
+                .collect();
+            persisted.sort_by_key(|entry| entry.block_root);
+            persisted
+        })
+        .collect()
+}
+
+/// Inverse of `ancestors_to_persisted`.
+pub fn ancestors_from_persisted(
+    persisted: Vec<Vec<PersistedAncestor>>,
+) -> Vec<HashMap<Hash256, Hash256>> {
+    persisted
+        .into_iter()
+        .map(|table| {
+            table
+                .into_iter()
+                .map(|entry| (entry.block_root, entry.ancestor_root))
+                .collect()
+        })
+        .collect()
+}
+
+/// Decode some persisted fork choice state.
+pub fn decode_persisted<T: Decodable>(bytes: &[u8]) -> Result<T, ForkChoiceError> {
+    decode(bytes).map_err(|e| {
+        ForkChoiceError::InvalidPersistedState(format!("Unable to decode SSZ: {:?}", e))
+    })
+}
+
+/// Check that every block in `block_roots` is known to `block_store`.
+///
+/// Returns an error with the first unknown block root.
+pub fn verify_blocks_exist<'a, T, I>(
+    block_store: &BeaconBlockStore<T>,
+    block_roots: I,
+) -> Result<(), ForkChoiceError>
+where
+    T: ClientDB,
+    I: IntoIterator<Item = &'a Hash256>,
+{
+    for block_root in block_roots {
+        if !block_store.exists(block_root)? {
+            return Err(ForkChoiceError::MissingPersistedBlock(*block_root));
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,1020 @@
+//! A proto-array implementation of the LMD-GHOST fork choice rule.
+//!
+//! Blocks are stored in a flat array, ordered such that a parent always precedes its children.
+//! Each node tracks its cumulative weight along with its best child and best descendant, so that
+//! the head can be found from any node in constant time. Votes are applied as per-node deltas,
+//! which are propagated to ancestors in a single reverse pass over the array.
+//!
+//! Unlike the other implementations, the database is only accessed when a block is added. Nodes
+//! which precede the finalized block are pruned.
+use crate::persistence::{decode_persisted, verify_blocks_exist};
+use crate::{ForkChoice, ForkChoiceError, LatestVotes, PersistedLatestVotes, Vote};
+use db::{
+    stores::{BeaconBlockStore, BeaconStateStore},
+    ClientDB,
+};
+use log::{debug, trace};
+use ssz::ssz_encode;
+use ssz_derive::{Decode, Encode};
+use std::collections::HashMap;
+use std::sync::Arc;
+use types::{BeaconBlock, ChainSpec, Epoch, Hash256, Slot};
+
+/// The minimum number of nodes which must precede the finalized node before the array is pruned.
+///
+/// Pruning requires re-indexing the whole array, so it is batched.
+pub const DEFAULT_PRUNE_THRESHOLD: usize = 256;
+
+/// A single block in the proto-array.
+#[derive(Clone, PartialEq, Debug)]
+pub struct ProtoNode {
+    /// The slot of the block.
+    pub slot: Slot,
+    /// The root of the block.
+    pub root: Hash256,
+    /// The index of the parent node, if it is known.
+    pub parent: Option<usize>,
+    /// The `current_justified_epoch` of the post-state of the block.
+    pub justified_epoch: Epoch,
+    /// The `finalized_epoch` of the post-state of the block.
+    pub finalized_epoch: Epoch,
+    /// The total weight of the votes for this block and all of its descendants.
+    pub weight: u64,
+    /// The index of the child with the greatest weight which leads to a viable head.
+    pub best_child: Option<usize>,
+    /// The index of the viable head reached by following `best_child` pointers.
+    pub best_descendant: Option<usize>,
+}
+
+/// Represents `None` in the optional indices of a `PersistedProtoNode`.
+const PERSISTED_NONE: u64 = u64::max_value();
+
+/// A `ProtoNode`, as stored in the database.
+///
+/// Absent indices are represented by `PERSISTED_NONE`.
+#[derive(Clone, PartialEq, Debug, Encode, Decode)]
+struct PersistedProtoNode {
+    slot: Slot,
+    root: Hash256,
+    parent: u64,
+    justified_epoch: Epoch,
+    finalized_epoch: Epoch,
+    weight: u64,
+    best_child: u64,
+    best_descendant: u64,
+}
+
+fn index_to_persisted(index: Option<usize>) -> u64 {
+    index.map_or(PERSISTED_NONE, |index| index as u64)
+}
+
+fn index_from_persisted(index: u64) -> Option<usize> {
+    if index == PERSISTED_NONE {
+        None
+    } else {
+        Some(index as usize)
+    }
+}
+
+impl<'a> From<&'a ProtoNode> for PersistedProtoNode {
+    fn from(node: &'a ProtoNode) -> Self {
+        Self {
+            slot: node.slot,
+            root: node.root,
+            parent: index_to_persisted(node.parent),
+            justified_epoch: node.justified_epoch,
+            finalized_epoch: node.finalized_epoch,
+            weight: node.weight,
+            best_child: index_to_persisted(node.best_child),
+            best_descendant: index_to_persisted(node.best_descendant),
+        }
+    }
+}
+
+impl From<PersistedProtoNode> for ProtoNode {
+    fn from(node: PersistedProtoNode) -> Self {
+        Self {
+            slot: node.slot,
+            root: node.root,
+            parent: index_from_persisted(node.parent),
+            justified_epoch: node.justified_epoch,
+            finalized_epoch: node.finalized_epoch,
+            weight: node.weight,
+            best_child: index_from_persisted(node.best_child),
+            best_descendant: index_from_persisted(node.best_descendant),
+        }
+    }
+}
+
+/// The state of a `ProtoArrayForkChoice`, as stored in the database.
+///
+/// The array is uninitialised if `nodes` is empty. The block root indices are not stored, they
+/// are rebuilt from `nodes`.
+#[derive(Clone, PartialEq, Debug, Encode, Decode)]
+struct PersistedProtoArrayForkChoice {
+    prune_threshold: u64,
+    justified_epoch: Epoch,
+    finalized_epoch: Epoch,
+    genesis_epoch: Epoch,
+    nodes: Vec<PersistedProtoNode>,
+    latest_votes: PersistedLatestVotes,
+    votes: Vec<VoteTracker>,
+}
+
+/// The flat array of blocks and the indices required to navigate it.
+#[derive(Clone, PartialEq, Debug)]
+pub struct ProtoArray {
+    /// Do not prune the array unless the finalized node is at least this far into it.
+    pub prune_threshold: usize,
+    /// The justified epoch which heads must agree with to be viable.
+    pub justified_epoch: Epoch,
+    /// The finalized epoch which heads must agree with to be viable.
+    pub finalized_epoch: Epoch,
+    /// The epoch at which justification and finalization are considered to be unset.
+    pub genesis_epoch: Epoch,
+    /// All known blocks, a parent always precedes its children.
+    pub nodes: Vec<ProtoNode>,
+    /// Maps block roots to their index in `nodes`.
+    pub indices: HashMap<Hash256, usize>,
+}
+
+impl ProtoArray {
+    /// Create an array containing only the `anchor` block.
+    pub fn new(
+        anchor_root: Hash256,
+        anchor_slot: Slot,
+        justified_epoch: Epoch,
+        finalized_epoch: Epoch,
+        genesis_epoch: Epoch,
+    ) -> Self {
+        let mut proto_array = Self {
+            prune_threshold: DEFAULT_PRUNE_THRESHOLD,
+            justified_epoch,
+            finalized_epoch,
+            genesis_epoch,
+            nodes: vec![],
+            indices: HashMap::new(),
+        };
+        proto_array.nodes.push(ProtoNode {
+            slot: anchor_slot,
+            root: anchor_root,
+            parent: None,
+            justified_epoch,
+            finalized_epoch,
+            weight: 0,
+            best_child: None,
+            best_descendant: None,
+        });
+        proto_array.indices.insert(anchor_root, 0);
+        proto_array
+    }
+
+    /// Add a block to the array. Blocks which are already known are ignored.
+    ///
+    /// The parent of the block must already be in the array.
+    pub fn on_block(
+        &mut self,
+        slot: Slot,
+        root: Hash256,
+        parent_root: Hash256,
+        justified_epoch: Epoch,
+        finalized_epoch: Epoch,
+    ) -> Result<(), ForkChoiceError> {
+        if self.indices.contains_key(&root) {
+            return Ok(());
+        }
+
+        let parent_index = *self
+            .indices
+            .get(&parent_root)
+            .ok_or_else(|| ForkChoiceError::MissingBeaconBlock(parent_root))?;
+
+        let node_index = self.nodes.len();
+        self.nodes.push(ProtoNode {
+            slot,
+            root,
+            parent: Some(parent_index),
+            justified_epoch,
+            finalized_epoch,
+            weight: 0,
+            best_child: None,
+            best_descendant: None,
+        });
+        self.indices.insert(root, node_index);
+
+        self.maybe_update_best_child_and_descendant(parent_index, node_index);
+
+        Ok(())
+    }
+
+    /// Apply the per-node weight changes in `deltas` (which must be the same length as `nodes`)
+    /// and update the best child and descendant of every node.
+    ///
+    /// The justified and finalized epochs are updated first, so that nodes which no longer lead
+    /// to a viable head are excluded.
+    pub fn apply_score_changes(
+        &mut self,
+        mut deltas: Vec<i64>,
+        justified_epoch: Epoch,
+        finalized_epoch: Epoch,
+    ) -> Result<(), ForkChoiceError> {
+        if deltas.len() != self.nodes.len() {
+            return Err(ForkChoiceError::InvalidDeltaLength(deltas.len()));
+        }
+
+        self.justified_epoch = justified_epoch;
+        self.finalized_epoch = finalized_epoch;
+
+        // Iterate backwards so that all children are processed before their parent, allowing
+        // deltas to be propagated to the root in a single pass.
+        for node_index in (0..self.nodes.len()).rev() {
+            let node_delta = deltas[node_index];
+
+            let node = &mut self.nodes[node_index];
+            node.weight = if node_delta < 0 {
+                node.weight.checked_sub(node_delta.abs() as u64)
+            } else {
+                node.weight.checked_add(node_delta as u64)
+            }
+            .ok_or_else(|| ForkChoiceError::InvalidNodeDelta(node.root))?;
+
+            if let Some(parent_index) = node.parent {
+                deltas[parent_index] += node_delta;
+            }
+        }
+
+        // Best children are only updated once all weights are final, otherwise a child may be
+        // compared against the stale weight of a sibling.
+        for node_index in (0..self.nodes.len()).rev() {
+            if let Some(parent_index) = self.nodes[node_index].parent {
+                self.maybe_update_best_child_and_descendant(parent_index, node_index);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns the justified and finalized epochs which heads descending from `start_root` must
+    /// agree with.
+    ///
+    /// These are the epochs of the descendant of `start_root` (or `start_root` itself) with the
+    /// latest justification, so at least one descendant is always viable. Blocks which do not
+    /// descend from `start_root` can't become the head, so their epochs are ignored.
+    pub fn checkpoint_epochs(
+        &self,
+        start_root: &Hash256,
+    ) -> Result<(Epoch, Epoch), ForkChoiceError> {
+        let start_index = *self
+            .indices
+            .get(start_root)
+            .ok_or_else(|| ForkChoiceError::MissingBeaconBlock(*start_root))?;
+
+        // A parent always precedes its children, so descendants can be found in a single pass.
+        let mut is_descendant = vec![false; self.nodes.len()];
+        is_descendant[start_index] = true;
+        let mut epochs = (
+            self.nodes[start_index].justified_epoch,
+            self.nodes[start_index].finalized_epoch,
+        );
+
+        for (index, node) in self.nodes.iter().enumerate().skip(start_index + 1) {
+            if node.parent.map_or(false, |parent| is_descendant[parent]) {
+                is_descendant[index] = true;
+                epochs = std::cmp::max(epochs, (node.justified_epoch, node.finalized_epoch));
+            }
+        }
+
+        Ok(epochs)
+    }
+
+    /// Returns the root of the head, found by following the best descendant of `start_root`.
+    pub fn find_head(&self, start_root: &Hash256) -> Result<Hash256, ForkChoiceError> {
+        let start_index = *self
+            .indices
+            .get(start_root)
+            .ok_or_else(|| ForkChoiceError::MissingBeaconBlock(*start_root))?;
+
+        let best_index = self.nodes[start_index]
+            .best_descendant
+            .unwrap_or(start_index);
+        let best_node = &self.nodes[best_index];
+
+        if !self.node_is_viable_for_head(best_node) {
+            return Err(ForkChoiceError::HeadNotFound);
+        }
+
+        Ok(best_node.root)
+    }
+
+    /// Remove all nodes which precede the node with `finalized_root`, if there are at least
+    /// `prune_threshold` of them.
+    ///
+    /// Nodes which follow the finalized node but do not descend from it are retained, however
+    /// their pruned ancestors are replaced with `None`. These nodes will not be viable.
+    pub fn maybe_prune(&mut self, finalized_root: &Hash256) -> Result<(), ForkChoiceError> {
+        let finalized_index = *self
+            .indices
+            .get(finalized_root)
+            .ok_or_else(|| ForkChoiceError::MissingBeaconBlock(*finalized_root))?;
+
+        if finalized_index < self.prune_threshold {
+            return Ok(());
+        }
+
+        for node in &self.nodes[0..finalized_index] {
+            self.indices.remove(&node.root);
+        }
+        self.nodes = self.nodes.split_off(finalized_index);
+
+        for index in self.indices.values_mut() {
+            *index -= finalized_index;
+        }
+        for node in self.nodes.iter_mut() {
+            node.parent = node
+                .parent
+                .and_then(|index| index.checked_sub(finalized_index));
+            node.best_child = node
+                .best_child
+                .and_then(|index| index.checked_sub(finalized_index));
+            node.best_descendant = node
+                .best_descendant
+                .and_then(|index| index.checked_sub(finalized_index));
+        }
+
+        debug!(
+            "Pruned {} nodes from the proto-array, {} remain",
+            finalized_index,
+            self.nodes.len()
+        );
+
+        Ok(())
+    }
+
+    /// Build an array from persisted `nodes`, checking that every parent precedes its children
+    /// and that all indices are within the array.
+    fn from_persisted(
+        prune_threshold: usize,
+        justified_epoch: Epoch,
+        finalized_epoch: Epoch,
+        genesis_epoch: Epoch,
+        nodes: Vec<PersistedProtoNode>,
+    ) -> Result<Self, ForkChoiceError> {
+        let nodes: Vec<ProtoNode> = nodes.into_iter().map(Into::into).collect();
+        let mut indices = HashMap::with_capacity(nodes.len());
+
+        for (index, node) in nodes.iter().enumerate() {
+            let invalid = |reason: &str| {
+                Err(ForkChoiceError::InvalidPersistedState(format!(
+                    "Node {} with root {} {}",
+                    index, node.root, reason
+                )))
+            };
+
+            if node.parent.map_or(false, |parent| parent >= index) {
+                return invalid("does not follow its parent");
+            }
+            if node
+                .best_child
+                .map_or(false, |child| child <= index || child >= nodes.len())
+            {
+                return invalid("has an invalid best child");
+            }
+            if node.best_descendant.map_or(false, |descendant| {
+                descendant <= index || descendant >= nodes.len()
+            }) {
+                return invalid("has an invalid best descendant");
+            }
+            if indices.insert(node.root, index).is_some() {
+                return invalid("is duplicated");
+            }
+        }
+
+        Ok(Self {
+            prune_threshold,
+            justified_epoch,
+            finalized_epoch,
+            genesis_epoch,
+            nodes,
+            indices,
+        })
+    }
+
+    /// Consider `child_index` as the best child of `parent_index`, updating the best child and
+    /// best descendant of the parent if required.
+    ///
+    /// Ties are broken in favour of the smaller block root.
+    fn maybe_update_best_child_and_descendant(&mut self, parent_index: usize, child_index: usize) {
+        let child = &self.nodes[child_index];
+        let parent = &self.nodes[parent_index];
+
+        let child_leads_to_viable_head = self.node_leads_to_viable_head(child);
+        let to_child = (
+            Some(child_index),
+            Some(child.best_descendant.unwrap_or(child_index)),
+        );
+        let to_none = (None, None);
+        let no_change = (parent.best_child, parent.best_descendant);
+
+        let (best_child, best_descendant) = match parent.best_child {
+            Some(best_child_index) if best_child_index == child_index => {
+                if child_leads_to_viable_head {
+                    to_child
+                } else {
+                    to_none
+                }
+            }
+            Some(best_child_index) => {
+                let best_child = &self.nodes[best_child_index];
+                let best_child_leads_to_viable_head = self.node_leads_to_viable_head(best_child);
+
+                if child_leads_to_viable_head && !best_child_leads_to_viable_head {
+                    to_child
+                } else if !child_leads_to_viable_head && best_child_leads_to_viable_head {
+                    no_change
+                } else if child.weight == best_child.weight {
+                    if child.root < best_child.root {
+                        to_child
+                    } else {
+                        no_change
+                    }
+                } else if child.weight > best_child.weight {
+                    to_child
+                } else {
+                    no_change
+                }
+            }
+            None => {
+                if child_leads_to_viable_head {
+                    to_child
+                } else {
+                    no_change
+                }
+            }
+        };
+
+        let parent = &mut self.nodes[parent_index];
+        parent.best_child = best_child;
+        parent.best_descendant = best_descendant;
+    }
+
+    /// Returns `true` if `node` or its best descendant is viable for the head.
+    fn node_leads_to_viable_head(&self, node: &ProtoNode) -> bool {
+        let best_descendant_is_viable = node
+            .best_descendant
+            .map(|index| self.node_is_viable_for_head(&self.nodes[index]))
+            .unwrap_or(false);
+
+        best_descendant_is_viable || self.node_is_viable_for_head(node)
+    }
+
+    /// Returns `true` if the justified and finalized epochs of `node` agree with those of the
+    /// array. Nodes are always viable whilst the array epochs are still at genesis.
+    fn node_is_viable_for_head(&self, node: &ProtoNode) -> bool {
+        (node.justified_epoch == self.justified_epoch || self.justified_epoch == self.genesis_epoch)
+            && (node.finalized_epoch == self.finalized_epoch
+                || self.finalized_epoch == self.genesis_epoch)
+    }
+}
+
+/// The vote of a single validator, as applied to the `ProtoArray`.
+///
+/// `current_root` and `current_weight` are the vote which has been applied to the `ProtoArray`,
+/// `next_root` and `next_weight` are the vote which will be applied during the next call to
+/// `find_head`.
+#[derive(Clone, Copy, Default, PartialEq, Debug, Encode, Decode)]
+pub struct VoteTracker {
+    pub current_root: Hash256,
+    pub current_weight: u64,
+    pub next_root: Hash256,
+    pub next_weight: u64,
+}
+
+/// Returns the change in weight of each node in the array caused by applying all pending votes.
+///
+/// Votes for unknown blocks are not applied, they will be re-considered during the next call.
+pub fn compute_deltas(indices: &HashMap<Hash256, usize>, votes: &mut [VoteTracker]) -> Vec<i64> {
+    let mut deltas = vec![0_i64; indices.len()];
+
+    for vote in votes.iter_mut() {
+        if vote.current_root == vote.next_root && vote.current_weight == vote.next_weight {
+            continue;
+        }
+
+        if let Some(&index) = indices.get(&vote.current_root) {
+            deltas[index] -= vote.current_weight as i64;
+        }
+
+        match indices.get(&vote.next_root) {
+            Some(&index) => {
+                deltas[index] += vote.next_weight as i64;
+                vote.current_root = vote.next_root;
+                vote.current_weight = vote.next_weight;
+            }
+            None => {
+                vote.current_root = Hash256::zero();
+                vote.current_weight = 0;
+            }
+        }
+    }
+
+    deltas
+}
+
+/// Stores the necessary data structures to run the proto-array fork choice.
+pub struct ProtoArrayForkChoice<T: ClientDB + Sized> {
+    /// The block tree. `None` until the first block is known.
+    proto_array: Option<ProtoArray>,
+    /// The latest vote of each validator.
+    latest_votes: LatestVotes,
+    /// The applied and pending votes, indexed by validator index.
+    votes: Vec<VoteTracker>,
+    /// Block storage access.
+    block_store: Arc<BeaconBlockStore<T>>,
+    /// State storage access.
+    state_store: Arc<BeaconStateStore<T>>,
+}
+
+impl<T> ProtoArrayForkChoice<T>
+where
+    T: ClientDB + Sized,
+{
+    pub fn new(
+        block_store: Arc<BeaconBlockStore<T>>,
+        state_store: Arc<BeaconStateStore<T>>,
+    ) -> Self {
+        ProtoArrayForkChoice {
+            proto_array: None,
+            latest_votes: LatestVotes::default(),
+            votes: vec![],
+            block_store,
+            state_store,
+        }
+    }
+
+    /// Returns the `current_justified_epoch` and `finalized_epoch` of the post-state of `block`.
+    fn checkpoint_epochs(&self, block: &BeaconBlock) -> Result<(Epoch, Epoch), ForkChoiceError> {
+        let state = self
+            .state_store
+            .get_deserialized(&block.state_root)?
+            .ok_or_else(|| ForkChoiceError::MissingBeaconState(block.state_root))?;
+
+        Ok((state.current_justified_epoch, state.finalized_epoch))
+    }
+
+    /// Returns the array, initialising it with `anchor_root` as the root node if it is empty.
+    fn proto_array_or_anchor(
+        &mut self,
+        anchor_root: &Hash256,
+        spec: &ChainSpec,
+    ) -> Result<&mut ProtoArray, ForkChoiceError> {
+        if self.proto_array.is_none() {
+            let anchor = self
+                .block_store
+                .get_deserialized(anchor_root)?
+                .ok_or_else(|| ForkChoiceError::MissingBeaconBlock(*anchor_root))?;
+            let (justified_epoch, finalized_epoch) = self.checkpoint_epochs(&anchor)?;
+
+            self.proto_array = Some(ProtoArray::new(
+                *anchor_root,
+                anchor.slot,
+                justified_epoch,
+                finalized_epoch,
+                spec.genesis_epoch,
+            ));
+        }
+
+        Ok(self.proto_array.as_mut().expect("Initialised above"))
+    }
+
+    /// Returns the tracker for `validator_index`, extending `votes` if required.
+    fn vote_tracker_mut(&mut self, validator_index: u64) -> &mut VoteTracker {
+        let validator_index = validator_index as usize;
+        if validator_index >= self.votes.len() {
+            self.votes
+                .resize(validator_index + 1, VoteTracker::default());
+        }
+        &mut self.votes[validator_index]
+    }
+}
+
+impl<T: ClientDB + Sized> ForkChoice for ProtoArrayForkChoice<T> {
+    /// Adds `block` to the array.
+    ///
+    /// A block whose parent is not in the array does not descend from the finalized block (its
+    /// parent was pruned, or never added), so it can never become the head. Such blocks are
+    /// ignored rather than failing the import.
+    fn add_block(
+        &mut self,
+        block: &BeaconBlock,
+        block_hash: &Hash256,
+        spec: &ChainSpec,
+    ) -> Result<(), ForkChoiceError> {
+        let (justified_epoch, finalized_epoch) = self.checkpoint_epochs(block)?;
+
+        let proto_array = self.proto_array_or_anchor(&block.previous_block_root, spec)?;
+        if !proto_array.indices.contains_key(&block.previous_block_root) {
+            return Ok(());
+        }
+
+        proto_array.on_block(
+            block.slot,
+            *block_hash,
+            block.previous_block_root,
+            justified_epoch,
+            finalized_epoch,
+        )
+    }
+
+    fn add_attestation(
+        &mut self,
+        validator_index: u64,
+        target_block_root: &Hash256,
+        target_epoch: Epoch,
+        balance: u64,
+        spec: &ChainSpec,
+    ) -> Result<(), ForkChoiceError> {
+        trace!(
+            "Adding attestation of validator: {:?} for block: {}",
+            validator_index,
+            target_block_root
+        );
+
+        let vote = Vote {
+            target_root: *target_block_root,
+            target_epoch,
+            balance,
+        };
+
+        // only replace an existing vote with a strictly newer one
+        if self.latest_votes.insert(validator_index, vote) {
+            let tracker = self.vote_tracker_mut(validator_index);
+            tracker.next_root = vote.target_root;
+            tracker.next_weight = vote.weight(spec);
+        } else {
+            trace!("Ignoring attestation that is not newer than the latest vote");
+        }
+
+        Ok(())
+    }
+
+    fn add_equivocation(
+        &mut self,
+        validator_index: u64,
+        _spec: &ChainSpec,
+    ) -> Result<(), ForkChoiceError> {
+        trace!(
+            "Removing votes of equivocating validator: {}",
+            validator_index
+        );
+
+        // The weight is removed from the tree during the next call to `find_head`.
+        self.latest_votes.insert_equivocation(validator_index);
+        let tracker = self.vote_tracker_mut(validator_index);
+        tracker.next_root = Hash256::zero();
+        tracker.next_weight = 0;
+
+        Ok(())
+    }
+
+    /// Apply all votes received since the last call and find the head from `justified_start_block`.
+    fn find_head(
+        &mut self,
+        justified_start_block: &Hash256,
+        spec: &ChainSpec,
+    ) -> Result<Hash256, ForkChoiceError> {
+        debug!(
+            "Starting proto-array fork choice at block: {}",
+            justified_start_block
+        );
+
+        self.proto_array_or_anchor(justified_start_block, spec)?;
+        let proto_array = self.proto_array.as_mut().expect("Initialised above");
+
+        let (justified_epoch, finalized_epoch) =
+            proto_array.checkpoint_epochs(justified_start_block)?;
+
+        let deltas = compute_deltas(&proto_array.indices, &mut self.votes);
+        proto_array.apply_score_changes(deltas, justified_epoch, finalized_epoch)?;
+
+        let head = proto_array.find_head(justified_start_block)?;
+        debug!("Head found: {}", head);
+
+        Ok(head)
+    }
+
+    fn update_finalized_root(
+        &mut self,
+        finalized_root: &Hash256,
+        _spec: &ChainSpec,
+    ) -> Result<(), ForkChoiceError> {
+        match self.proto_array.as_mut() {
+            Some(proto_array) => proto_array.maybe_prune(finalized_root),
+            None => Ok(()),
+        }
+    }
+
+    fn as_ssz_bytes(&self) -> Vec<u8> {
+        let (prune_threshold, justified_epoch, finalized_epoch, genesis_epoch, nodes) =
+            match &self.proto_array {
+                Some(proto_array) => (
+                    proto_array.prune_threshold as u64,
+                    proto_array.justified_epoch,
+                    proto_array.finalized_epoch,
+                    proto_array.genesis_epoch,
+                    proto_array
+                        .nodes
+                        .iter()
+                        .map(Into::into)
+                        .collect::<Vec<PersistedProtoNode>>(),
+                ),
+                None => (
+                    DEFAULT_PRUNE_THRESHOLD as u64,
+                    Epoch::new(0),
+                    Epoch::new(0),
+                    Epoch::new(0),
+                    vec![],
+                ),
+            };
+
+        ssz_encode(&PersistedProtoArrayForkChoice {
+            prune_threshold,
+            justified_epoch,
+            finalized_epoch,
+            genesis_epoch,
+            nodes,
+            latest_votes: PersistedLatestVotes::from(&self.latest_votes),
+            votes: self.votes.clone(),
+        })
+    }
+
+    fn restore_from_ssz_bytes(&mut self, bytes: &[u8]) -> Result<(), ForkChoiceError> {
+        let persisted: PersistedProtoArrayForkChoice = decode_persisted(bytes)?;
+
+        let proto_array = if persisted.nodes.is_empty() {
+            None
+        } else {
+            let proto_array = ProtoArray::from_persisted(
+                persisted.prune_threshold as usize,
+                persisted.justified_epoch,
+                persisted.finalized_epoch,
+                persisted.genesis_epoch,
+                persisted.nodes,
+            )?;
+            verify_blocks_exist(
+                &self.block_store,
+                proto_array.nodes.iter().map(|node| &node.root),
+            )?;
+            Some(proto_array)
+        };
+
+        self.proto_array = proto_array;
+        self.latest_votes = persisted.latest_votes.into();
+        self.votes = persisted.votes;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn root(i: u64) -> Hash256 {
+        Hash256::from_low_u64_be(i)
+    }
+
+    fn epoch(i: u64) -> Epoch {
+        Epoch::new(i)
+    }
+
+    /// Builds the tree:
+    ///
+    /// ```ignore
+    ///     1
+    ///    / \
+    ///   2   3
+    ///   |
+    ///   4
+    /// ```
+    fn test_array() -> ProtoArray {
+        let mut proto_array = ProtoArray::new(root(1), Slot::new(0), epoch(0), epoch(0), epoch(0));
+        proto_array
+            .on_block(Slot::new(1), root(2), root(1), epoch(0), epoch(0))
+            .unwrap();
+        proto_array
+            .on_block(Slot::new(1), root(3), root(1), epoch(0), epoch(0))
+            .unwrap();
+        proto_array
+            .on_block(Slot::new(2), root(4), root(2), epoch(0), epoch(0))
+            .unwrap();
+        proto_array
+    }
+
+    #[test]
+    fn unknown_parent_is_rejected() {
+        let mut proto_array = test_array();
+        assert_eq!(
+            proto_array.on_block(Slot::new(3), root(5), root(42), epoch(0), epoch(0)),
+            Err(ForkChoiceError::MissingBeaconBlock(root(42)))
+        );
+    }
+
+    #[test]
+    fn ties_are_broken_by_smallest_root() {
+        let proto_array = test_array();
+        assert_eq!(proto_array.find_head(&root(1)), Ok(root(4)));
+    }
+
+    /// Returns `count` validators, all voting for `root` with `weight`.
+    fn votes_for(count: usize, root: Hash256, weight: u64) -> Vec<VoteTracker> {
+        vec![
+            VoteTracker {
+                next_root: root,
+                next_weight: weight,
+                ..VoteTracker::default()
+            };
+            count
+        ]
+    }
+
+    #[test]
+    fn votes_move_the_head() {
+        let mut proto_array = test_array();
+
+        // Two votes for 3 outweigh one vote for 4.
+        let mut votes = votes_for(3, root(3), 1);
+        votes[2].next_root = root(4);
+        let deltas = compute_deltas(&proto_array.indices, &mut votes);
+        assert_eq!(deltas, vec![0, 0, 2, 1]);
+        proto_array
+            .apply_score_changes(deltas, epoch(0), epoch(0))
+            .unwrap();
+        assert_eq!(proto_array.find_head(&root(1)), Ok(root(3)));
+        assert_eq!(proto_array.nodes[0].weight, 3);
+
+        // Moving a vote from 3 to 4 moves the head back.
+        votes[1].next_root = root(4);
+        let deltas = compute_deltas(&proto_array.indices, &mut votes);
+        assert_eq!(deltas, vec![0, 0, -1, 1]);
+        proto_array
+            .apply_score_changes(deltas, epoch(0), epoch(0))
+            .unwrap();
+        assert_eq!(proto_array.find_head(&root(1)), Ok(root(4)));
+        assert_eq!(proto_array.nodes[0].weight, 3);
+
+        // Applying the same votes again changes nothing.
+        let deltas = compute_deltas(&proto_array.indices, &mut votes);
+        assert_eq!(deltas, vec![0; 4]);
+    }
+
+    #[test]
+    fn weight_changes_are_applied() {
+        let mut proto_array = test_array();
+        let mut votes = votes_for(2, root(3), 2);
+        votes[1].next_root = root(4);
+        votes[1].next_weight = 1;
+
+        let deltas = compute_deltas(&proto_array.indices, &mut votes);
+        proto_array
+            .apply_score_changes(deltas, epoch(0), epoch(0))
+            .unwrap();
+        assert_eq!(proto_array.find_head(&root(1)), Ok(root(3)));
+
+        votes[1].next_weight = 3;
+        let deltas = compute_deltas(&proto_array.indices, &mut votes);
+        assert_eq!(deltas, vec![0, 0, 0, 2]);
+        proto_array
+            .apply_score_changes(deltas, epoch(0), epoch(0))
+            .unwrap();
+        assert_eq!(proto_array.find_head(&root(1)), Ok(root(4)));
+    }
+
+    #[test]
+    fn votes_for_unknown_blocks_are_deferred() {
+        let mut proto_array = test_array();
+        let mut votes = votes_for(1, root(5), 1);
+
+        assert_eq!(compute_deltas(&proto_array.indices, &mut votes), vec![0; 4]);
+
+        proto_array
+            .on_block(Slot::new(2), root(5), root(3), epoch(0), epoch(0))
+            .unwrap();
+        assert_eq!(
+            compute_deltas(&proto_array.indices, &mut votes),
+            vec![0, 0, 0, 0, 1]
+        );
+    }
+
+    #[test]
+    fn heads_with_stale_justification_are_not_viable() {
+        let mut proto_array = test_array();
+        proto_array
+            .on_block(Slot::new(3), root(5), root(3), epoch(1), epoch(0))
+            .unwrap();
+
+        let mut votes = votes_for(2, root(4), 1);
+        let deltas = compute_deltas(&proto_array.indices, &mut votes);
+
+        // The votes for 4 are ignored, as only 5 has the latest justified epoch.
+        proto_array
+            .apply_score_changes(deltas, epoch(1), epoch(0))
+            .unwrap();
+        assert_eq!(proto_array.find_head(&root(1)), Ok(root(5)));
+        assert_eq!(
+            proto_array.find_head(&root(2)),
+            Err(ForkChoiceError::HeadNotFound)
+        );
+    }
+
+    #[test]
+    fn justification_outside_the_start_block_is_ignored() {
+        let mut proto_array = test_array();
+        // 5 justifies a later epoch, but does not descend from 2.
+        proto_array
+            .on_block(Slot::new(3), root(5), root(3), epoch(1), epoch(0))
+            .unwrap();
+
+        let (justified_epoch, finalized_epoch) = proto_array.checkpoint_epochs(&root(2)).unwrap();
+        assert_eq!((justified_epoch, finalized_epoch), (epoch(0), epoch(0)));
+
+        let mut votes = votes_for(2, root(5), 1);
+        let deltas = compute_deltas(&proto_array.indices, &mut votes);
+        proto_array
+            .apply_score_changes(deltas, justified_epoch, finalized_epoch)
+            .unwrap();
+        assert_eq!(proto_array.find_head(&root(2)), Ok(root(4)));
+
+        // From the common ancestor, 5 has the latest justification.
+        assert_eq!(
+            proto_array.checkpoint_epochs(&root(1)),
+            Ok((epoch(1), epoch(0)))
+        );
+    }
+
+    #[test]
+    fn prune_removes_nodes_before_finalized() {
+        let mut proto_array = test_array();
+
+        proto_array.prune_threshold = 2;
+        proto_array.maybe_prune(&root(2)).unwrap();
+        assert_eq!(
+            proto_array.nodes.len(),
+            4,
+            "should not prune below threshold"
+        );
+
+        proto_array.prune_threshold = 1;
+        proto_array.maybe_prune(&root(2)).unwrap();
+        assert_eq!(proto_array.nodes.len(), 3);
+        assert_eq!(proto_array.indices.get(&root(1)), None);
+        assert_eq!(proto_array.indices.get(&root(2)), Some(&0));
+        assert_eq!(proto_array.nodes[0].parent, None);
+        assert_eq!(
+            proto_array.nodes[1].parent, None,
+            "3 is not a descendant of 2"
+        );
+        assert_eq!(proto_array.nodes[2].parent, Some(0));
+        assert_eq!(proto_array.find_head(&root(2)), Ok(root(4)));
+    }
+
+    fn persisted_nodes(proto_array: &ProtoArray) -> Vec<PersistedProtoNode> {
+        proto_array.nodes.iter().map(Into::into).collect()
+    }
+
+    #[test]
+    fn persisted_array_round_trip() {
+        let mut proto_array = test_array();
+        let mut votes = votes_for(2, root(3), 1);
+        let deltas = compute_deltas(&proto_array.indices, &mut votes);
+        proto_array
+            .apply_score_changes(deltas, epoch(0), epoch(0))
+            .unwrap();
+
+        let restored = ProtoArray::from_persisted(
+            proto_array.prune_threshold,
+            proto_array.justified_epoch,
+            proto_array.finalized_epoch,
+            proto_array.genesis_epoch,
+            persisted_nodes(&proto_array),
+        )
+        .unwrap();
+
+        assert_eq!(restored, proto_array);
+        assert_eq!(restored.find_head(&root(1)), Ok(root(3)));
+    }
+
+    #[test]
+    fn persisted_array_must_be_ordered() {
+        let proto_array = test_array();
+        let mut nodes = persisted_nodes(&proto_array);
+        nodes.swap(1, 3);
+
+        assert!(ProtoArray::from_persisted(
+            proto_array.prune_threshold,
+            proto_array.justified_epoch,
+            proto_array.finalized_epoch,
+            proto_array.genesis_epoch,
+            nodes,
+        )
+        .is_err());
+    }
+}
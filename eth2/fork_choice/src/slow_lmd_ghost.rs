@@ -1,80 +1,54 @@
 extern crate db;
 
-use crate::{ForkChoice, ForkChoiceError};
-use db::{
-    stores::{BeaconBlockStore, BeaconStateStore},
-    ClientDB,
+use crate::persistence::{
+    children_from_persisted, children_to_persisted, decode_persisted, verify_blocks_exist,
+    PersistedChildren,
 };
+use crate::{ForkChoice, ForkChoiceError, LatestVotes, PersistedLatestVotes, Vote};
+use db::{stores::BeaconBlockStore, ClientDB};
 use log::{debug, trace};
+use ssz::ssz_encode;
+use ssz_derive::{Decode, Encode};
 use std::collections::HashMap;
 use std::sync::Arc;
-use types::{BeaconBlock, ChainSpec, Hash256, Slot};
+use types::{BeaconBlock, ChainSpec, Epoch, Hash256};
 
 //TODO: Pruning and syncing
 
 pub struct SlowLMDGhost<T: ClientDB + Sized> {
-    /// The latest attestation targets as a map of validator index to block hash.
-    //TODO: Could this be a fixed size vec
-    latest_attestation_targets: HashMap<u64, Hash256>,
+    /// The latest vote of each validator.
+    latest_votes: LatestVotes,
     /// Stores the children for any given parent.
     children: HashMap<Hash256, Vec<Hash256>>,
     /// Block storage access.
     block_store: Arc<BeaconBlockStore<T>>,
-    /// State storage access.
-    state_store: Arc<BeaconStateStore<T>>,
+}
+
+/// The state of a `SlowLMDGhost`, as stored in the database.
+#[derive(Debug, PartialEq, Encode, Decode)]
+struct PersistedSlowLMDGhost {
+    latest_votes: PersistedLatestVotes,
+    children: Vec<PersistedChildren>,
 }
 
 impl<T> SlowLMDGhost<T>
 where
     T: ClientDB + Sized,
 {
-    pub fn new(
-        block_store: Arc<BeaconBlockStore<T>>,
-        state_store: Arc<BeaconStateStore<T>>,
-    ) -> Self {
+    pub fn new(block_store: Arc<BeaconBlockStore<T>>) -> Self {
         SlowLMDGhost {
-            latest_attestation_targets: HashMap::new(),
+            latest_votes: LatestVotes::default(),
             children: HashMap::new(),
             block_store,
-            state_store,
         }
     }
 
     /// Finds the latest votes weighted by validator balance. Returns a hashmap of block_hash to
     /// weighted votes.
-    pub fn get_latest_votes(
-        &self,
-        state_root: &Hash256,
-        block_slot: Slot,
-        spec: &ChainSpec,
-    ) -> Result<HashMap<Hash256, u64>, ForkChoiceError> {
-        // get latest votes
-        // Note: Votes are weighted by min(balance, MAX_DEPOSIT_AMOUNT) //
-        // FORK_CHOICE_BALANCE_INCREMENT
-        // build a hashmap of block_hash to weighted votes
-        let mut latest_votes: HashMap<Hash256, u64> = HashMap::new();
-        // gets the current weighted votes
-        let current_state = self
-            .state_store
-            .get_deserialized(&state_root)?
-            .ok_or_else(|| ForkChoiceError::MissingBeaconState(*state_root))?;
-
-        let active_validator_indices =
-            current_state.get_active_validator_indices(block_slot.epoch(spec.slots_per_epoch));
-
-        for index in active_validator_indices {
-            let balance = std::cmp::min(
-                current_state.validator_balances[index],
-                spec.max_deposit_amount,
-            ) / spec.fork_choice_balance_increment;
-            if balance > 0 {
-                if let Some(target) = self.latest_attestation_targets.get(&(index as u64)) {
-                    *latest_votes.entry(*target).or_insert_with(|| 0) += balance;
-                }
-            }
-        }
+    pub fn get_latest_votes(&self, spec: &ChainSpec) -> HashMap<Hash256, u64> {
+        let latest_votes = self.latest_votes.weights(spec);
         trace!("Latest votes: {:?}", latest_votes);
-        Ok(latest_votes)
+        latest_votes
     }
 
     /// Get the total number of votes for some given block root.
@@ -93,12 +67,13 @@ where
             .slot;
 
         for (vote_hash, votes) in latest_votes.iter() {
-            let (root_at_slot, _) = self
-                .block_store
-                .block_at_slot(&vote_hash, block_slot)?
-                .ok_or_else(|| ForkChoiceError::MissingBeaconBlock(*block_root))?;
-            if root_at_slot == *block_root {
-                count += votes;
+            // votes for blocks prior to `block_slot` cannot be for a descendant of `block_root`
+            if let Some((root_at_slot, _)) =
+                self.block_store.block_at_slot(&vote_hash, block_slot)?
+            {
+                if root_at_slot == *block_root {
+                    count += votes;
+                }
             }
         }
         Ok(count)
@@ -129,46 +104,40 @@ impl<T: ClientDB + Sized> ForkChoice for SlowLMDGhost<T> {
         &mut self,
         validator_index: u64,
         target_block_root: &Hash256,
-        spec: &ChainSpec,
+        target_epoch: Epoch,
+        balance: u64,
+        _: &ChainSpec,
     ) -> Result<(), ForkChoiceError> {
-        // simply add the attestation to the latest_attestation_target if the block_height is
-        // larger
         trace!(
             "Adding attestation of validator: {:?} for block: {}",
             validator_index,
             target_block_root
         );
-        let attestation_target = self
-            .latest_attestation_targets
-            .entry(validator_index)
-            .or_insert_with(|| *target_block_root);
-        // if we already have a value
-        if attestation_target != target_block_root {
-            trace!("Old attestation found: {:?}", attestation_target);
-            // get the height of the target block
-            let block_height = self
-                .block_store
-                .get_deserialized(&target_block_root)?
-                .ok_or_else(|| ForkChoiceError::MissingBeaconBlock(*target_block_root))?
-                .slot
-                .height(spec.genesis_slot);
-
-            // get the height of the past target block
-            let past_block_height = self
-                .block_store
-                .get_deserialized(&attestation_target)?
-                .ok_or_else(|| ForkChoiceError::MissingBeaconBlock(*attestation_target))?
-                .slot
-                .height(spec.genesis_slot);
-            // update the attestation only if the new target is higher
-            if past_block_height < block_height {
-                trace!("Updating old attestation");
-                *attestation_target = *target_block_root;
-            }
+        let vote = Vote {
+            target_root: *target_block_root,
+            target_epoch,
+            balance,
+        };
+        // only replace an existing vote with a strictly newer one
+        if !self.latest_votes.insert(validator_index, vote) {
+            trace!("Ignoring attestation that is not newer than the latest vote");
         }
         Ok(())
     }
 
+    fn add_equivocation(
+        &mut self,
+        validator_index: u64,
+        _: &ChainSpec,
+    ) -> Result<(), ForkChoiceError> {
+        trace!(
+            "Removing votes of equivocating validator: {}",
+            validator_index
+        );
+        self.latest_votes.insert_equivocation(validator_index);
+        Ok(())
+    }
+
     /// A very inefficient implementation of LMD ghost.
     fn find_head(
         &mut self,
@@ -176,14 +145,11 @@ impl<T: ClientDB + Sized> ForkChoice for SlowLMDGhost<T> {
         spec: &ChainSpec,
     ) -> Result<Hash256, ForkChoiceError> {
         debug!("Running LMD Ghost Fork-choice rule");
-        let start = self
-            .block_store
-            .get_deserialized(&justified_block_start)?
-            .ok_or_else(|| ForkChoiceError::MissingBeaconBlock(*justified_block_start))?;
-
-        let start_state_root = start.state_root;
+        if !self.block_store.exists(&justified_block_start)? {
+            return Err(ForkChoiceError::MissingBeaconBlock(*justified_block_start));
+        }
 
-        let latest_votes = self.get_latest_votes(&start_state_root, start.slot, spec)?;
+        let latest_votes = self.get_latest_votes(spec);
 
         let mut head_hash = *justified_block_start;
 
@@ -222,4 +188,24 @@ impl<T: ClientDB + Sized> ForkChoice for SlowLMDGhost<T> {
         }
         Ok(head_hash)
     }
+
+    fn as_ssz_bytes(&self) -> Vec<u8> {
+        ssz_encode(&PersistedSlowLMDGhost {
+            latest_votes: PersistedLatestVotes::from(&self.latest_votes),
+            children: children_to_persisted(&self.children),
+        })
+    }
+
+    fn restore_from_ssz_bytes(&mut self, bytes: &[u8]) -> Result<(), ForkChoiceError> {
+        let persisted: PersistedSlowLMDGhost = decode_persisted(bytes)?;
+
+        verify_blocks_exist(
+            &self.block_store,
+            persisted.children.iter().flat_map(|entry| &entry.children),
+        )?;
+
+        self.latest_votes = persisted.latest_votes.into();
+        self.children = children_from_persisted(persisted.children);
+        Ok(())
+    }
 }
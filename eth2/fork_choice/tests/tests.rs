@@ -18,14 +18,19 @@ use db::stores::{BeaconBlockStore, BeaconStateStore};
 use db::MemoryDB;
 // use env_logger::{Builder, Env};
 use fork_choice::{
-    BitwiseLMDGhost, ForkChoice, ForkChoiceAlgorithm, LongestChain, OptimizedLMDGhost, SlowLMDGhost,
+    BitwiseLMDGhost, ForkChoice, ForkChoiceAlgorithm, ForkChoiceError, LongestChain,
+    OptimizedLMDGhost, ProtoArrayForkChoice, SlowLMDGhost,
 };
 use ssz::ssz_encode;
 use std::collections::HashMap;
 use std::sync::Arc;
-use std::{fs::File, io::prelude::*, path::PathBuf};
-use types::test_utils::TestingBeaconStateBuilder;
-use types::{BeaconBlock, BeaconBlockBody, ChainSpec, Eth1Data, Hash256, Keypair, Slot};
+use std::{
+    fs::{create_dir_all, File},
+    io::prelude::*,
+    path::PathBuf,
+};
+use types::test_utils::{RngCore, SeedableRng, TestingBeaconStateBuilder, XorShiftRng};
+use types::{BeaconBlock, BeaconBlockBody, ChainSpec, Epoch, Eth1Data, Hash256, Keypair, Slot};
 use yaml_rust::yaml;
 
 // Note: We Assume the block Id's are hex-encoded.
@@ -63,6 +68,49 @@ fn test_slow_lmd_ghost() {
     );
 }
 
+#[test]
+fn test_proto_array() {
+    test_yaml_vectors(
+        ForkChoiceAlgorithm::ProtoArray,
+        "tests/lmd_ghost_test_vectors.yaml",
+        100,
+    );
+}
+
+#[test]
+fn test_proto_array_ignores_orphaned_blocks() {
+    // The parent of "00000003" is unknown, so it can never become the head.
+    let case = FuzzCase {
+        blocks: vec![
+            ("00000001".to_string(), "00000001".to_string()),
+            ("00000002".to_string(), "00000001".to_string()),
+            ("00000003".to_string(), "000000ff".to_string()),
+        ],
+        attestations: vec![(0, "00000003".to_string())],
+    };
+
+    assert_eq!(
+        run_fuzz_case(&ForkChoiceAlgorithm::ProtoArray, &case),
+        vec![(1, Ok(id_to_hash(&"00000002".to_string())))]
+    );
+}
+
+#[test]
+fn test_lmd_ghost_regressions() {
+    for fork_choice_algo in vec![
+        ForkChoiceAlgorithm::OptimizedLMDGhost,
+        ForkChoiceAlgorithm::BitwiseLMDGhost,
+        ForkChoiceAlgorithm::SlowLMDGhost,
+        ForkChoiceAlgorithm::ProtoArray,
+    ] {
+        test_yaml_vectors(
+            fork_choice_algo,
+            "tests/lmd_ghost_regression_test_vectors.yaml",
+            100,
+        );
+    }
+}
+
 #[test]
 fn test_longest_chain() {
     test_yaml_vectors(
@@ -104,7 +152,7 @@ fn test_yaml_vectors(
     // process the tests
     for test_case in test_cases {
         // setup a fresh test
-        let (mut fork_choice, block_store, state_root) =
+        let (mut fork_choice, block_store, state_store, state_root) =
             setup_inital_state(&fork_choice_algo, emulated_validators);
 
         // keep a hashmap of block_id's to block_hashes (random hashes to abstract block_id)
@@ -175,17 +223,46 @@ fn test_yaml_vectors(
                         "Not enough validators to emulate weights"
                     );
                     fork_choice
-                        .add_attestation(current_validator as u64, &block_root, &spec)
+                        .add_attestation(
+                            current_validator as u64,
+                            &block_root,
+                            block_slot[&block_root].epoch(spec.slots_per_epoch),
+                            spec.max_deposit_amount,
+                            &spec,
+                        )
                         .unwrap();
                     current_validator += 1;
                 }
             }
         }
 
+        // persist the fork choice and restore it into a fresh instance
+        let persisted = fork_choice.as_ssz_bytes();
+        let mut restored =
+            new_fork_choice(&fork_choice_algo, block_store.clone(), state_store.clone());
+        restored.restore_from_ssz_bytes(&persisted).unwrap();
+
+        // a fork choice without the blocks must refuse the persisted state
+        let empty_db = Arc::new(MemoryDB::open());
+        let mut inconsistent = new_fork_choice(
+            &fork_choice_algo,
+            Arc::new(BeaconBlockStore::new(empty_db.clone())),
+            Arc::new(BeaconStateStore::new(empty_db)),
+        );
+        match inconsistent.restore_from_ssz_bytes(&persisted) {
+            Err(ForkChoiceError::MissingPersistedBlock(_)) => {}
+            other => panic!("Expected a missing block, got {:?}", other),
+        }
+
         // everything is set up, run the fork choice, using genesis as the head
         let head = fork_choice
             .find_head(&genesis_hash.unwrap(), &spec)
             .unwrap();
+        assert_eq!(
+            restored.find_head(&genesis_hash.unwrap(), &spec),
+            Ok(head),
+            "Restored fork choice found a different head"
+        );
 
         // compare the result to the expected test
         let success = test_case["heads"]
@@ -220,26 +297,17 @@ fn load_test_cases_from_yaml(file_path: &str) -> Vec<yaml_rust::Yaml> {
 fn setup_inital_state(
     fork_choice_algo: &ForkChoiceAlgorithm,
     num_validators: usize,
-) -> (Box<ForkChoice>, Arc<BeaconBlockStore<MemoryDB>>, Hash256) {
+) -> (
+    Box<ForkChoice>,
+    Arc<BeaconBlockStore<MemoryDB>>,
+    Arc<BeaconStateStore<MemoryDB>>,
+    Hash256,
+) {
     let db = Arc::new(MemoryDB::open());
     let block_store = Arc::new(BeaconBlockStore::new(db.clone()));
     let state_store = Arc::new(BeaconStateStore::new(db.clone()));
 
-    // the fork choice instantiation
-    let fork_choice: Box<ForkChoice> = match fork_choice_algo {
-        ForkChoiceAlgorithm::OptimizedLMDGhost => Box::new(OptimizedLMDGhost::new(
-            block_store.clone(),
-            state_store.clone(),
-        )),
-        ForkChoiceAlgorithm::BitwiseLMDGhost => Box::new(BitwiseLMDGhost::new(
-            block_store.clone(),
-            state_store.clone(),
-        )),
-        ForkChoiceAlgorithm::SlowLMDGhost => {
-            Box::new(SlowLMDGhost::new(block_store.clone(), state_store.clone()))
-        }
-        ForkChoiceAlgorithm::LongestChain => Box::new(LongestChain::new(block_store.clone())),
-    };
+    let fork_choice = new_fork_choice(fork_choice_algo, block_store.clone(), state_store.clone());
 
     let spec = ChainSpec::foundation();
 
@@ -254,7 +322,24 @@ fn setup_inital_state(
         .unwrap();
 
     // return initialised vars
-    (fork_choice, block_store, state_root)
+    (fork_choice, block_store, state_store, state_root)
+}
+
+// instantiate an empty fork choice reading from the given stores.
+fn new_fork_choice(
+    fork_choice_algo: &ForkChoiceAlgorithm,
+    block_store: Arc<BeaconBlockStore<MemoryDB>>,
+    state_store: Arc<BeaconStateStore<MemoryDB>>,
+) -> Box<ForkChoice> {
+    match fork_choice_algo {
+        ForkChoiceAlgorithm::OptimizedLMDGhost => Box::new(OptimizedLMDGhost::new(block_store)),
+        ForkChoiceAlgorithm::BitwiseLMDGhost => Box::new(BitwiseLMDGhost::new(block_store)),
+        ForkChoiceAlgorithm::SlowLMDGhost => Box::new(SlowLMDGhost::new(block_store)),
+        ForkChoiceAlgorithm::ProtoArray => {
+            Box::new(ProtoArrayForkChoice::new(block_store, state_store))
+        }
+        ForkChoiceAlgorithm::LongestChain => Box::new(LongestChain::new(block_store)),
+    }
 }
 
 // convert a block_id into a Hash256 -- assume input is hex encoded;
@@ -268,3 +353,347 @@ fn id_to_hash(id: &String) -> Hash256 {
     }
     Hash256::from(fixed_bytes)
 }
+
+/*
+ * Differential fuzzing.
+ *
+ * Random block trees and attestation sequences are run through every LMD-GHOST implementation
+ * and the heads are compared against `SlowLMDGhost`, the reference implementation. Failing cases
+ * are shrunk and written out as YAML test vectors.
+ */
+
+/// The number of random cases generated by `test_differential_fork_choice`.
+const FUZZ_CASES: u64 = 100;
+/// The maximum number of blocks in a random tree, including genesis.
+const FUZZ_MAX_BLOCKS: usize = 24;
+/// The maximum number of attestations in a random case.
+const FUZZ_MAX_ATTESTATIONS: usize = 48;
+/// The number of validators which may attest in a random case.
+const FUZZ_VALIDATORS: usize = 16;
+/// The head is compared after every `FUZZ_CHECK_INTERVAL` attestations, as well as at the end.
+const FUZZ_CHECK_INTERVAL: usize = 4;
+/// The directory, relative to the crate, that shrunk failing cases are written to.
+const FUZZ_FAILURES_DIR: &str = "tests/fuzz_failures";
+
+/// A block tree and a sequence of attestations.
+#[derive(Debug, Clone, PartialEq)]
+struct FuzzCase {
+    /// The id and parent id of each block, parents first. The first block is genesis and is its
+    /// own parent.
+    blocks: Vec<(String, String)>,
+    /// The validator index and target block id of each attestation, in the order they are
+    /// received. Later attestations are always newer than earlier ones.
+    attestations: Vec<(u64, String)>,
+}
+
+impl FuzzCase {
+    /// Generate a random case from `seed`.
+    ///
+    /// Cases from even seeds are binary trees, so that `BitwiseLMDGhost` is exercised.
+    fn random(seed: u64) -> Self {
+        let mut rng_seed = [42; 16];
+        rng_seed[0..8].copy_from_slice(&seed.to_le_bytes());
+        let mut rng = XorShiftRng::from_seed(rng_seed);
+        let mut below = |n: usize| rng.next_u32() as usize % n;
+
+        let max_children = if seed % 2 == 0 { 2 } else { 4 };
+        let num_blocks = 2 + below(FUZZ_MAX_BLOCKS - 1);
+
+        // Random ids give random block roots, so that ties are broken arbitrarily.
+        let mut ids: Vec<String> = vec![];
+        while ids.len() < num_blocks {
+            let id = format!("{:08x}", 1 + below(u32::max_value() as usize - 1));
+            if !ids.contains(&id) {
+                ids.push(id);
+            }
+        }
+
+        let mut blocks = vec![(ids[0].clone(), ids[0].clone())];
+        let mut children = vec![0; num_blocks];
+        for id in ids.iter().skip(1) {
+            let parents: Vec<usize> = (0..blocks.len())
+                .filter(|&i| children[i] < max_children)
+                .collect();
+            let parent = parents[below(parents.len())];
+            children[parent] += 1;
+            blocks.push((id.clone(), blocks[parent].0.clone()));
+        }
+
+        // Genesis is never voted for, it is the start of every search.
+        let attestations = (0..below(FUZZ_MAX_ATTESTATIONS + 1))
+            .map(|_| {
+                let validator_index = below(FUZZ_VALIDATORS) as u64;
+                let target = blocks[1 + below(num_blocks - 1)].0.clone();
+                (validator_index, target)
+            })
+            .collect();
+
+        Self {
+            blocks,
+            attestations,
+        }
+    }
+
+    /// Returns the greatest number of children of any block.
+    fn max_children(&self) -> usize {
+        let mut children: HashMap<&String, usize> = HashMap::new();
+        for (id, parent) in self.blocks.iter().skip(1) {
+            *children.entry(parent).or_insert(0) += 1;
+            children.entry(id).or_insert(0);
+        }
+        children.values().cloned().max().unwrap_or(0)
+    }
+
+    /// Returns the cases which are one step simpler than this one, by removing either a single
+    /// attestation or a single leaf block along with the attestations for it.
+    fn shrink_candidates(&self) -> Vec<FuzzCase> {
+        let mut candidates = vec![];
+
+        for i in 0..self.attestations.len() {
+            let mut candidate = self.clone();
+            candidate.attestations.remove(i);
+            candidates.push(candidate);
+        }
+
+        for i in (1..self.blocks.len()).rev() {
+            let id = &self.blocks[i].0;
+            if self.blocks.iter().skip(1).any(|(_, parent)| parent == id) {
+                continue;
+            }
+            let mut candidate = self.clone();
+            candidate.blocks.remove(i);
+            candidate.attestations.retain(|(_, target)| target != id);
+            candidates.push(candidate);
+        }
+
+        candidates
+    }
+
+    /// Returns the number of latest votes for each block after the first `num_attestations`
+    /// attestations.
+    fn latest_vote_counts(&self, num_attestations: usize) -> Vec<(String, usize)> {
+        let mut latest: HashMap<u64, &String> = HashMap::new();
+        for (validator_index, target) in self.attestations.iter().take(num_attestations) {
+            latest.insert(*validator_index, target);
+        }
+
+        self.blocks
+            .iter()
+            .skip(1)
+            .map(|(id, _)| (id.clone(), latest.values().filter(|t| **t == id).count()))
+            .filter(|(_, count)| *count > 0)
+            .collect()
+    }
+}
+
+/// The result of `find_head` after some number of attestations, for a single implementation.
+type HeadCheck = (usize, Result<Hash256, ForkChoiceError>);
+
+/// An implementation which disagreed with the reference implementation.
+#[derive(Debug)]
+struct Divergence {
+    algorithm: ForkChoiceAlgorithm,
+    /// The number of attestations which had been applied when the heads differed.
+    num_attestations: usize,
+    expected: Result<Hash256, ForkChoiceError>,
+    found: Result<Hash256, ForkChoiceError>,
+}
+
+/// Returns the implementations which must agree with `SlowLMDGhost` on `case`.
+///
+/// `BitwiseLMDGhost` only agrees with LMD-GHOST when no block has more than two children.
+fn differential_algorithms(case: &FuzzCase) -> Vec<ForkChoiceAlgorithm> {
+    let mut algorithms = vec![
+        ForkChoiceAlgorithm::OptimizedLMDGhost,
+        ForkChoiceAlgorithm::ProtoArray,
+    ];
+    if case.max_children() <= 2 {
+        algorithms.push(ForkChoiceAlgorithm::BitwiseLMDGhost);
+    }
+    algorithms
+}
+
+/// Apply `case` to a fresh `fork_choice_algo`, returning the head after every
+/// `FUZZ_CHECK_INTERVAL` attestations and after the final attestation.
+///
+/// Stops at the first error.
+fn run_fuzz_case(fork_choice_algo: &ForkChoiceAlgorithm, case: &FuzzCase) -> Vec<HeadCheck> {
+    let spec = ChainSpec::foundation();
+    let (mut fork_choice, block_store, _state_store, state_root) =
+        setup_inital_state(fork_choice_algo, FUZZ_VALIDATORS);
+
+    let genesis_hash = id_to_hash(&case.blocks[0].0);
+    let mut block_slot: HashMap<Hash256, Slot> = HashMap::new();
+    for (block_id, parent_id) in &case.blocks {
+        let block_hash = id_to_hash(block_id);
+        let previous_block_root = id_to_hash(parent_id);
+        let slot = match block_slot.get(&previous_block_root) {
+            Some(parent_slot) if block_hash != genesis_hash => *parent_slot + 1,
+            _ => spec.genesis_slot,
+        };
+        block_slot.insert(block_hash, slot);
+
+        let beacon_block = BeaconBlock {
+            slot,
+            previous_block_root,
+            state_root,
+            signature: Signature::empty_signature(),
+            body: BeaconBlockBody {
+                eth1_data: Eth1Data {
+                    deposit_root: Hash256::zero(),
+                    block_hash: Hash256::zero(),
+                },
+                randao_reveal: Signature::empty_signature(),
+                proposer_slashings: vec![],
+                attester_slashings: vec![],
+                attestations: vec![],
+                deposits: vec![],
+                voluntary_exits: vec![],
+                transfers: vec![],
+            },
+        };
+        block_store
+            .put(&block_hash, &ssz_encode(&beacon_block)[..])
+            .unwrap();
+
+        if block_hash != genesis_hash {
+            if let Err(e) = fork_choice.add_block(&beacon_block, &block_hash, &spec) {
+                return vec![(0, Err(e))];
+            }
+        }
+    }
+
+    let mut checks = vec![];
+    for (i, (validator_index, target)) in case.attestations.iter().enumerate() {
+        if let Err(e) = fork_choice.add_attestation(
+            *validator_index,
+            &id_to_hash(target),
+            Epoch::new(i as u64),
+            spec.max_deposit_amount,
+            &spec,
+        ) {
+            checks.push((i, Err(e)));
+            return checks;
+        }
+
+        let num_attestations = i + 1;
+        if num_attestations % FUZZ_CHECK_INTERVAL == 0 {
+            let head = fork_choice.find_head(&genesis_hash, &spec);
+            let failed = head.is_err();
+            checks.push((num_attestations, head));
+            if failed {
+                return checks;
+            }
+        }
+    }
+    checks.push((
+        case.attestations.len(),
+        fork_choice.find_head(&genesis_hash, &spec),
+    ));
+
+    checks
+}
+
+/// Returns the first point at which an implementation disagrees with `SlowLMDGhost` on `case`.
+fn find_divergence(case: &FuzzCase) -> Option<Divergence> {
+    let expected = run_fuzz_case(&ForkChoiceAlgorithm::SlowLMDGhost, case);
+
+    for algorithm in differential_algorithms(case) {
+        let found = run_fuzz_case(&algorithm, case);
+
+        let mismatch = expected
+            .iter()
+            .zip(found.iter())
+            .find(|(expected, found)| expected != found)
+            .map(|(expected, found)| (expected.clone(), found.clone()));
+
+        if let Some(((num_attestations, expected), (_, found))) = mismatch {
+            return Some(Divergence {
+                algorithm,
+                num_attestations,
+                expected,
+                found,
+            });
+        }
+    }
+
+    None
+}
+
+/// Greedily simplify `case` whilst it still produces a divergence.
+fn shrink(mut case: FuzzCase, mut divergence: Divergence) -> (FuzzCase, Divergence) {
+    'outer: loop {
+        for candidate in case.shrink_candidates() {
+            if let Some(candidate_divergence) = find_divergence(&candidate) {
+                case = candidate;
+                divergence = candidate_divergence;
+                continue 'outer;
+            }
+        }
+        return (case, divergence);
+    }
+}
+
+/// Render `case` as a YAML test vector which can be run with `test_yaml_vectors`.
+///
+/// The vector describes the tree and latest votes at the point of divergence, with the head
+/// chosen by the reference implementation as the expected head.
+fn fuzz_case_to_yaml(case: &FuzzCase, divergence: &Divergence) -> String {
+    let mut yaml = String::new();
+    yaml.push_str("title: Fork-choice Tests\n");
+    yaml.push_str(&format!(
+        "summary: A shrunk differential fuzzing case on which {:?} disagreed with SlowLMDGhost.\n",
+        divergence.algorithm
+    ));
+    yaml.push_str("test_suite: Fork-Choice\n\n");
+    yaml.push_str("test_cases:\n");
+
+    yaml.push_str("- blocks:\n");
+    for (id, parent) in &case.blocks {
+        yaml.push_str(&format!("    - id: '{}'\n      parent: '{}'\n", id, parent));
+    }
+
+    yaml.push_str("  weights:\n");
+    for (id, count) in case.latest_vote_counts(divergence.num_attestations) {
+        yaml.push_str(&format!("    - {}: {}\n", id, count));
+    }
+
+    yaml.push_str("  heads:\n");
+    if let Ok(head) = &divergence.expected {
+        let id = case
+            .blocks
+            .iter()
+            .map(|(id, _)| id)
+            .find(|id| id_to_hash(id) == *head)
+            .expect("Head must be a known block");
+        yaml.push_str(&format!("    - id: '{}'\n", id));
+    }
+
+    yaml
+}
+
+#[test]
+fn test_differential_fork_choice() {
+    for seed in 0..FUZZ_CASES {
+        let case = FuzzCase::random(seed);
+
+        if let Some(divergence) = find_divergence(&case) {
+            let (case, divergence) = shrink(case, divergence);
+
+            let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+            path.push(FUZZ_FAILURES_DIR);
+            create_dir_all(&path).unwrap();
+            path.push(format!("seed_{}.yaml", seed));
+            File::create(&path)
+                .unwrap()
+                .write_all(fuzz_case_to_yaml(&case, &divergence).as_bytes())
+                .unwrap();
+
+            panic!(
+                "Fork choice implementations diverged on seed {}: {:?}. Shrunk case: {:?}. \
+                 Test vector written to {:?}",
+                seed, divergence, case, path
+            );
+        }
+    }
+}
@@ -0,0 +1,144 @@
+use criterion::Criterion;
+use criterion::{black_box, criterion_group, criterion_main, Benchmark};
+use itertools::Itertools;
+use operation_pool::OperationPool;
+use state_processing::per_block_processing::validate_attestation;
+use types::test_utils::{TestingAttestationBuilder, TestingBeaconStateBuilder};
+use types::*;
+
+/// The number of committees per slot in the benchmark state.
+pub const NUM_COMMITTEES: usize = 4;
+
+/// The size of each (overlapping) aggregate inserted into the pool.
+pub const AGGREGATE_SIZE: usize = 16;
+
+/// The distance between the first signers of consecutive aggregates.
+pub const AGGREGATE_STEP: usize = 8;
+
+/// Build a state with `NUM_COMMITTEES` committees per slot and a list of attestations for the
+/// previous slot, each signed by `AGGREGATE_SIZE` validators and overlapping the next by half.
+fn bench_state_and_attestations(spec: &ChainSpec) -> (BeaconState, Vec<Attestation>) {
+    let num_validators =
+        NUM_COMMITTEES * (spec.slots_per_epoch * spec.target_committee_size) as usize;
+    let mut state_builder =
+        TestingBeaconStateBuilder::from_default_keypairs_file_if_exists(num_validators, spec);
+    let slot = spec.genesis_slot + 1000 * spec.slots_per_epoch + spec.slots_per_epoch / 2;
+    state_builder.teleport_to_slot(slot, spec);
+    state_builder.build_caches(spec).unwrap();
+    let (mut state, keypairs) = state_builder.build();
+
+    let attestation_slot = state.slot - 1;
+    let committees = state
+        .get_crosslink_committees_at_slot(attestation_slot, spec)
+        .unwrap()
+        .clone();
+
+    let mut attestations = vec![];
+    for committee in &committees {
+        let committee_len = committee.committee.len();
+        for start in (0..committee_len - AGGREGATE_SIZE + 1).step_by(AGGREGATE_STEP) {
+            let mut builder = TestingAttestationBuilder::new(
+                &state,
+                &committee.committee,
+                attestation_slot,
+                committee.shard,
+                spec,
+            );
+            let signers = &committee.committee[start..start + AGGREGATE_SIZE];
+            let keys = signers.iter().map(|&i| &keypairs[i].sk).collect::<Vec<_>>();
+            builder.sign(signers, &keys, &state.fork, spec);
+            let attestation = builder.build();
+
+            state.latest_crosslinks[committee.shard as usize] = Crosslink {
+                crosslink_data_root: attestation.data.crosslink_data_root,
+                epoch: attestation_slot.epoch(spec.slots_per_epoch),
+            };
+
+            attestations.push(attestation);
+        }
+    }
+
+    state.slot += spec.min_attestation_inclusion_delay;
+
+    (state, attestations)
+}
+
+/// The previous approach: score each attestation by the number of its signers who are not yet
+/// included in the state, independently of the others, and take the best.
+///
+/// The benchmark state holds no attestations, so the check for superior attestations in the state
+/// is omitted.
+fn bit_count_scored(
+    attestations: &[Attestation],
+    state: &BeaconState,
+    spec: &ChainSpec,
+) -> Vec<Attestation> {
+    attestations
+        .iter()
+        .filter(|attestation| validate_attestation(state, attestation, spec).is_ok())
+        .map(|att| (att, bit_count_score(att, state, spec)))
+        .filter(|&(_, score)| score != 0)
+        .sorted_by_key(|&(_, score)| std::cmp::Reverse(score))
+        .take(spec.max_attestations as usize)
+        .map(|(att, _)| att)
+        .cloned()
+        .collect()
+}
+
+/// The number of signers of `attestation` whose attestations for the same shard are not yet in
+/// `state`.
+fn bit_count_score(attestation: &Attestation, state: &BeaconState, spec: &ChainSpec) -> usize {
+    let mut new_validators = attestation.aggregation_bitfield.clone();
+
+    let attestation_epoch = attestation.data.slot.epoch(spec.slots_per_epoch);
+
+    let state_attestations = if attestation_epoch == state.current_epoch(spec) {
+        &state.current_epoch_attestations
+    } else if attestation_epoch == state.previous_epoch(spec) {
+        &state.previous_epoch_attestations
+    } else {
+        return 0;
+    };
+
+    state_attestations
+        .iter()
+        .filter(|current_attestation| current_attestation.data.shard == attestation.data.shard)
+        .for_each(|current_attestation| {
+            new_validators.difference_inplace(&current_attestation.aggregation_bitfield);
+        });
+
+    new_validators.num_set_bits()
+}
+
+fn attestation_packing(c: &mut Criterion) {
+    let spec = ChainSpec::foundation();
+    let (state, attestations) = bench_state_and_attestations(&spec);
+
+    let op_pool = OperationPool::new();
+    for attestation in &attestations {
+        op_pool
+            .insert_attestation(attestation.clone(), &state, &spec)
+            .unwrap();
+    }
+
+    let max_cover_state = state.clone();
+    let max_cover_spec = spec.clone();
+    c.bench(
+        "attestation_packing",
+        Benchmark::new("max_cover", move |b| {
+            b.iter(|| black_box(op_pool.get_attestations(&max_cover_state, &max_cover_spec)))
+        })
+        .sample_size(10),
+    );
+
+    c.bench(
+        "attestation_packing",
+        Benchmark::new("bit_count_scored", move |b| {
+            b.iter(|| black_box(bit_count_scored(&attestations, &state, &spec)))
+        })
+        .sample_size(10),
+    );
+}
+
+criterion_group!(benches, attestation_packing);
+criterion_main!(benches);
@@ -0,0 +1,130 @@
+use crate::max_cover::MaxCover;
+use state_processing::per_epoch_processing::get_attestation_participants::get_attestation_participants;
+use std::collections::{HashMap, HashSet};
+use types::{Attestation, BeaconState, ChainSpec, Epoch};
+
+/// The validators who already have an attestation included in the state, for each of the
+/// previous and current epochs.
+///
+/// Built once per call to `get_attestations`, so that scoring an attestation is linear in the size
+/// of its committee rather than in the number of attestations in the state.
+#[derive(Debug, Default)]
+pub struct IncludedValidators {
+    previous_epoch: HashSet<usize>,
+    current_epoch: HashSet<usize>,
+}
+
+impl IncludedValidators {
+    /// Build the index from the `previous_epoch_attestations` and `current_epoch_attestations` of
+    /// `state`.
+    ///
+    /// Attestations for which the participants can't be determined are skipped.
+    pub fn new(state: &BeaconState, spec: &ChainSpec) -> Self {
+        let participants = |pending_attestations: &[types::PendingAttestation]| {
+            pending_attestations
+                .iter()
+                .filter_map(|att| {
+                    get_attestation_participants(state, &att.data, &att.aggregation_bitfield, spec)
+                        .ok()
+                })
+                .flatten()
+                .collect::<HashSet<usize>>()
+        };
+
+        Self {
+            previous_epoch: participants(&state.previous_epoch_attestations),
+            current_epoch: participants(&state.current_epoch_attestations),
+        }
+    }
+
+    /// Returns the validators with an included attestation for `epoch`, or `None` if `epoch` is
+    /// neither the previous nor current epoch of `state`.
+    pub fn for_epoch(
+        &self,
+        epoch: Epoch,
+        state: &BeaconState,
+        spec: &ChainSpec,
+    ) -> Option<&HashSet<usize>> {
+        if epoch == state.current_epoch(spec) {
+            Some(&self.current_epoch)
+        } else if epoch == state.previous_epoch(spec) {
+            Some(&self.previous_epoch)
+        } else {
+            None
+        }
+    }
+}
+
+/// An attestation paired with the validators it would newly reward if included in a block.
+pub struct AttMaxCover<'a> {
+    /// Underlying attestation.
+    att: &'a Attestation,
+    /// Mapping of validator indices to their effective balances, for validators whose
+    /// attestations are not yet included in the state or the block being built.
+    fresh_validators: HashMap<usize, u64>,
+}
+
+impl<'a> AttMaxCover<'a> {
+    /// Returns `None` if the participants of `att` can't be determined.
+    pub fn new(
+        att: &'a Attestation,
+        included: &IncludedValidators,
+        state: &BeaconState,
+        spec: &ChainSpec,
+    ) -> Option<Self> {
+        let epoch = att.data.slot.epoch(spec.slots_per_epoch);
+        let included = included.for_epoch(epoch, state, spec)?;
+
+        let participants =
+            get_attestation_participants(state, &att.data, &att.aggregation_bitfield, spec).ok()?;
+
+        let fresh_validators = participants
+            .into_iter()
+            .filter(|validator_index| !included.contains(validator_index))
+            .filter_map(|validator_index| {
+                let balance = state.get_effective_balance(validator_index, spec).ok()?;
+                Some((validator_index, balance))
+            })
+            .collect();
+
+        Some(Self {
+            att,
+            fresh_validators,
+        })
+    }
+}
+
+impl<'a> MaxCover for AttMaxCover<'a> {
+    type Object = Attestation;
+    type Set = HashMap<usize, u64>;
+
+    fn object(&self) -> Attestation {
+        self.att.clone()
+    }
+
+    fn covering_set(&self) -> &HashMap<usize, u64> {
+        &self.fresh_validators
+    }
+
+    /// Sneaky: we keep all the attestations together in one bucket, even though
+    /// their aggregation bitfields refer to different committees. In order to avoid
+    /// confusing committees when updating covering sets, we update only those attestations
+    /// whose slot and shard match the attestation being included in the solution, by the logic
+    /// that a validator can't have been in more than one committee during an epoch.
+    fn update_covering_set(
+        &mut self,
+        best_att: &Attestation,
+        covered_validators: &HashMap<usize, u64>,
+    ) {
+        if self.att.data.shard == best_att.data.shard && self.att.data.slot == best_att.data.slot {
+            self.fresh_validators
+                .retain(|k, _| !covered_validators.contains_key(k));
+        }
+    }
+
+    /// The score of an attestation is the total effective balance of the validators it would
+    /// newly reward, which is proportional to the reward the proposer receives for including it.
+    fn score(&self) -> u64 {
+        self.fresh_validators.values().sum()
+    }
+}
@@ -1,3 +1,11 @@
+mod attestation;
+mod max_cover;
+mod persistence;
+
+pub use attestation::{AttMaxCover, IncludedValidators};
+pub use max_cover::{maximum_cover, MaxCover};
+pub use persistence::PersistedOperationPool;
+
 use int_to_bytes::int_to_bytes8;
 use itertools::Itertools;
 use parking_lot::RwLock;
@@ -7,7 +15,7 @@ use state_processing::per_block_processing::errors::{
     ExitValidationError, ProposerSlashingValidationError, TransferValidationError,
 };
 use state_processing::per_block_processing::{
-    gather_attester_slashing_indices_modular, validate_attestation,
+    execute_transfer, gather_attester_slashing_indices_modular, validate_attestation,
     validate_attestation_time_independent_only, verify_attester_slashing, verify_deposit,
     verify_exit, verify_exit_time_independent_only, verify_proposer_slashing, verify_transfer,
     verify_transfer_time_independent_only,
@@ -68,39 +76,38 @@ impl AttestationId {
     }
 }
 
-/// Compute a fitness score for an attestation.
-///
-/// The score is calculated by determining the number of *new* attestations that
-/// the aggregate attestation introduces, and is proportional to the size of the reward we will
-/// receive for including it in a block.
-// TODO: this could be optimised with a map from validator index to whether that validator has
-// attested in each of the current and previous epochs. Currently quadractic in number of validators.
-fn attestation_score(attestation: &Attestation, state: &BeaconState, spec: &ChainSpec) -> usize {
-    // Bitfield of validators whose attestations are new/fresh.
-    let mut new_validators = attestation.aggregation_bitfield.clone();
-
-    let attestation_epoch = attestation.data.slot.epoch(spec.slots_per_epoch);
-
-    let state_attestations = if attestation_epoch == state.current_epoch(spec) {
-        &state.current_epoch_attestations
-    } else if attestation_epoch == state.previous_epoch(spec) {
-        &state.previous_epoch_attestations
-    } else {
-        return 0;
-    };
-
-    state_attestations
-        .iter()
-        // In a single epoch, an attester should only be attesting for one shard.
-        // TODO: we avoid including slashable attestations in the state here,
-        // but maybe we should do something else with them (like construct slashings).
-        .filter(|current_attestation| current_attestation.data.shard == attestation.data.shard)
-        .for_each(|current_attestation| {
-            // Remove the validators who have signed the existing attestation (they are not new)
-            new_validators.difference_inplace(&current_attestation.aggregation_bitfield);
-        });
+/// A count of operations of each kind, e.g., the number of operations removed by a prune.
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub struct OperationCounts {
+    pub attestations: usize,
+    pub deposits: usize,
+    pub attester_slashings: usize,
+    pub proposer_slashings: usize,
+    pub voluntary_exits: usize,
+    pub transfers: usize,
+}
 
-    new_validators.num_set_bits()
+impl OperationCounts {
+    /// The sum of all counts.
+    pub fn total(&self) -> usize {
+        self.attestations
+            + self.deposits
+            + self.attester_slashings
+            + self.proposer_slashings
+            + self.voluntary_exits
+            + self.transfers
+    }
+}
+
+impl std::ops::AddAssign for OperationCounts {
+    fn add_assign(&mut self, other: Self) {
+        self.attestations += other.attestations;
+        self.deposits += other.deposits;
+        self.attester_slashings += other.attester_slashings;
+        self.proposer_slashings += other.proposer_slashings;
+        self.voluntary_exits += other.voluntary_exits;
+        self.transfers += other.transfers;
+    }
 }
 
 #[derive(Debug, PartialEq, Clone)]
@@ -175,8 +182,9 @@ impl OperationPool {
         let current_epoch = state.current_epoch(spec);
         let prev_domain_bytes = AttestationId::compute_domain_bytes(prev_epoch, state, spec);
         let curr_domain_bytes = AttestationId::compute_domain_bytes(current_epoch, state, spec);
-        self.attestations
-            .read()
+        let included = IncludedValidators::new(state, spec);
+        let reader = self.attestations.read();
+        let valid_attestations = reader
             .iter()
             .filter(|(key, _)| {
                 key.domain_bytes_match(&prev_domain_bytes)
@@ -187,31 +195,34 @@ impl OperationPool {
             .filter(|attestation| !superior_attestation_exists_in_state(state, attestation))
             // That are valid...
             .filter(|attestation| validate_attestation(state, attestation, spec).is_ok())
-            // Scored by the number of new attestations they introduce (descending)
-            // TODO: need to consider attestations introduced in THIS block
-            .map(|att| (att, attestation_score(att, state, spec)))
-            // Don't include any useless attestations (score 0)
-            .filter(|&(_, score)| score != 0)
-            .sorted_by_key(|&(_, score)| std::cmp::Reverse(score))
-            // Limited to the maximum number of attestations per block
-            .take(spec.max_attestations as usize)
-            .map(|(att, _)| att)
-            .cloned()
-            .collect()
+            // Scored by the total balance of the validators they newly reward.
+            .flat_map(|att| AttMaxCover::new(att, &included, state, spec));
+
+        // Greedily pick the attestations with the highest marginal score, limited to the maximum
+        // number of attestations per block. Attestations that add nothing are never included.
+        maximum_cover(valid_attestations, spec.max_attestations as usize)
     }
 
     /// Remove attestations which are too old to be included in a block.
+    ///
+    /// Returns the number of attestations removed.
     // TODO: we could probably prune other attestations here:
     // - ones that are completely covered by attestations included in the state
     // - maybe ones invalidated by the confirmation of one fork over another
-    pub fn prune_attestations(&self, finalized_state: &BeaconState, spec: &ChainSpec) {
+    pub fn prune_attestations(&self, finalized_state: &BeaconState, spec: &ChainSpec) -> usize {
+        let mut pruned = 0;
         self.attestations.write().retain(|_, attestations| {
             // All the attestations in this bucket have the same data, so we only need to
             // check the first one.
-            attestations.first().map_or(false, |att| {
+            let keep = attestations.first().map_or(false, |att| {
                 finalized_state.slot < att.data.slot + spec.slots_per_epoch
-            })
+            });
+            if !keep {
+                pruned += attestations.len();
+            }
+            keep
         });
+        pruned
     }
 
     /// Add a deposit to the pool.
@@ -370,7 +381,13 @@ impl OperationPool {
     }
 
     /// Prune proposer slashings for all slashed or withdrawn validators.
-    pub fn prune_proposer_slashings(&self, finalized_state: &BeaconState, spec: &ChainSpec) {
+    ///
+    /// Returns the number of slashings removed.
+    pub fn prune_proposer_slashings(
+        &self,
+        finalized_state: &BeaconState,
+        spec: &ChainSpec,
+    ) -> usize {
         prune_validator_hash_map(
             &mut self.proposer_slashings.write(),
             |validator| {
@@ -378,13 +395,21 @@ impl OperationPool {
                     || validator.is_withdrawable_at(finalized_state.current_epoch(spec))
             },
             finalized_state,
-        );
+        )
     }
 
     /// Prune attester slashings for all slashed or withdrawn validators, or attestations on another
     /// fork.
-    pub fn prune_attester_slashings(&self, finalized_state: &BeaconState, spec: &ChainSpec) {
-        self.attester_slashings.write().retain(|id, slashing| {
+    ///
+    /// Returns the number of slashings removed.
+    pub fn prune_attester_slashings(
+        &self,
+        finalized_state: &BeaconState,
+        spec: &ChainSpec,
+    ) -> usize {
+        let mut attester_slashings = self.attester_slashings.write();
+        let len_before = attester_slashings.len();
+        attester_slashings.retain(|id, slashing| {
             let fork_ok = &Self::attester_slashing_id(slashing, finalized_state, spec) == id;
             let curr_epoch = finalized_state.current_epoch(spec);
             let slashing_ok = gather_attester_slashing_indices_modular(
@@ -396,6 +421,7 @@ impl OperationPool {
             .is_ok();
             fork_ok && slashing_ok
         });
+        len_before - attester_slashings.len()
     }
 
     /// Insert a voluntary exit, validating it almost-entirely (future exits are permitted).
@@ -422,12 +448,14 @@ impl OperationPool {
     }
 
     /// Prune if validator has already exited at the last finalized state.
-    pub fn prune_voluntary_exits(&self, finalized_state: &BeaconState, spec: &ChainSpec) {
+    ///
+    /// Returns the number of exits removed.
+    pub fn prune_voluntary_exits(&self, finalized_state: &BeaconState, spec: &ChainSpec) -> usize {
         prune_validator_hash_map(
             &mut self.voluntary_exits.write(),
             |validator| validator.is_exited_at(finalized_state.current_epoch(spec)),
             finalized_state,
-        );
+        )
     }
 
     /// Insert a transfer into the pool, checking it for validity in the process.
@@ -446,34 +474,86 @@ impl OperationPool {
     }
 
     /// Get a list of transfers for inclusion in a block.
-    // TODO: improve the economic optimality of this function by accounting for
-    // dependencies between transfers in the same block e.g. A pays B, B pays C
+    ///
+    /// Transfers are applied in order of decreasing fee to a scratch copy of `state`, keeping only
+    /// those which are still valid after the transfers before them. Passes are repeated while any
+    /// transfer is added, so that transfers which depend on the outputs of lower-fee transfers
+    /// (e.g. A pays B, B pays C) can still be included.
+    ///
+    /// The transfers are returned in the order they must be processed.
     pub fn get_transfers(&self, state: &BeaconState, spec: &ChainSpec) -> Vec<Transfer> {
-        self.transfers
+        let mut candidates: Vec<Transfer> = self
+            .transfers
             .read()
             .iter()
-            .filter(|transfer| verify_transfer(state, transfer, spec).is_ok())
-            .sorted_by_key(|transfer| std::cmp::Reverse(transfer.fee))
-            .take(spec.max_transfers as usize)
+            .filter(|transfer| transfer.slot == state.slot)
             .cloned()
-            .collect()
+            .sorted_by_key(|transfer| std::cmp::Reverse(transfer.fee))
+            .collect();
+
+        let mut scratch_state = state.clone();
+        let mut transfers = Vec::with_capacity(spec.max_transfers as usize);
+
+        loop {
+            let len_before = transfers.len();
+
+            let mut i = 0;
+            while i < candidates.len() && transfers.len() < spec.max_transfers as usize {
+                if verify_transfer(&scratch_state, &candidates[i], spec).is_err() {
+                    i += 1;
+                    continue;
+                }
+                // `execute_transfer` only modifies balances, but may leave them partially
+                // modified on failure, so they are restored before trying the next transfer.
+                let balances = scratch_state.validator_balances.clone();
+                if execute_transfer(&mut scratch_state, &candidates[i], spec).is_err() {
+                    scratch_state.validator_balances = balances;
+                    i += 1;
+                    continue;
+                }
+                transfers.push(candidates.remove(i));
+            }
+
+            if transfers.len() == len_before || transfers.len() == spec.max_transfers as usize {
+                return transfers;
+            }
+        }
     }
 
     /// Prune the set of transfers by removing all those whose slot has already passed.
-    pub fn prune_transfers(&self, finalized_state: &BeaconState) {
-        self.transfers
-            .write()
-            .retain(|transfer| transfer.slot > finalized_state.slot)
+    ///
+    /// Returns the number of transfers removed.
+    pub fn prune_transfers(&self, finalized_state: &BeaconState) -> usize {
+        let mut transfers = self.transfers.write();
+        let len_before = transfers.len();
+        transfers.retain(|transfer| transfer.slot > finalized_state.slot);
+        len_before - transfers.len()
     }
 
     /// Prune all types of transactions given the latest finalized state.
-    pub fn prune_all(&self, finalized_state: &BeaconState, spec: &ChainSpec) {
-        self.prune_attestations(finalized_state, spec);
-        self.prune_deposits(finalized_state);
-        self.prune_proposer_slashings(finalized_state, spec);
-        self.prune_attester_slashings(finalized_state, spec);
-        self.prune_voluntary_exits(finalized_state, spec);
-        self.prune_transfers(finalized_state);
+    ///
+    /// Returns the number of operations of each type that were removed.
+    pub fn prune_all(&self, finalized_state: &BeaconState, spec: &ChainSpec) -> OperationCounts {
+        OperationCounts {
+            attestations: self.prune_attestations(finalized_state, spec),
+            deposits: self.prune_deposits(finalized_state).len(),
+            proposer_slashings: self.prune_proposer_slashings(finalized_state, spec),
+            attester_slashings: self.prune_attester_slashings(finalized_state, spec),
+            voluntary_exits: self.prune_voluntary_exits(finalized_state, spec),
+            transfers: self.prune_transfers(finalized_state),
+        }
+    }
+
+    /// The number of operations of each type stored in the pool.
+    pub fn counts(&self) -> OperationCounts {
+        OperationCounts {
+            attestations: self.num_attestations(),
+            deposits: self.num_deposits(),
+            attester_slashings: self.attester_slashings.read().len(),
+            proposer_slashings: self.proposer_slashings.read().len(),
+            voluntary_exits: self.voluntary_exits.read().len(),
+            transfers: self.transfers.read().len(),
+        }
     }
 }
 
@@ -522,19 +602,24 @@ where
 /// The keys in the map should be validator indices, which will be looked up
 /// in the state's validator registry and then passed to `prune_if`.
 /// Entries for unknown validators will be kept.
+///
+/// Returns the number of entries removed.
 fn prune_validator_hash_map<T, F>(
     map: &mut HashMap<u64, T>,
     prune_if: F,
     finalized_state: &BeaconState,
-) where
+) -> usize
+where
     F: Fn(&Validator) -> bool,
 {
+    let len_before = map.len();
     map.retain(|&validator_index, _| {
         finalized_state
             .validator_registry
             .get(validator_index as usize)
             .map_or(true, |validator| !prune_if(validator))
     });
+    len_before - map.len()
 }
 
 #[cfg(test)]
@@ -648,6 +733,33 @@ mod tests {
         assert_eq!(op_pool.num_deposits(), 0);
     }
 
+    #[test]
+    fn prune_all_counts_pruned_operations() {
+        let rng = &mut XorShiftRng::from_seed([42; 16]);
+        let (spec, mut state) = test_state(rng);
+        let op_pool = OperationPool::new();
+
+        for slot in 0..4 {
+            let mut transfer = Transfer::random_for_test(rng);
+            transfer.slot = Slot::new(slot);
+            op_pool.transfers.write().insert(transfer);
+        }
+        assert_eq!(op_pool.counts().transfers, 4);
+
+        state.slot = Slot::new(1);
+        let pruned = op_pool.prune_all(&state, &spec);
+
+        assert_eq!(
+            pruned,
+            OperationCounts {
+                transfers: 2,
+                ..OperationCounts::default()
+            }
+        );
+        assert_eq!(pruned.total(), 2);
+        assert_eq!(op_pool.counts().transfers, 2);
+    }
+
     // Create a random deposit (with a valid proof of posession)
     fn make_deposit(rng: &mut XorShiftRng, state: &BeaconState, spec: &ChainSpec) -> Deposit {
         let keypair = Keypair::random();
@@ -753,6 +865,15 @@ mod tests {
         };
     }
 
+    /// The score of an attestation as computed by `AttMaxCover`.
+    #[cfg(not(debug_assertions))]
+    fn attestation_score(att: &Attestation, state: &BeaconState, spec: &ChainSpec) -> u64 {
+        let included = IncludedValidators::new(state, spec);
+        AttMaxCover::new(att, &included, state, spec)
+            .expect("should determine participants")
+            .score()
+    }
+
     #[test]
     #[cfg(not(debug_assertions))]
     fn test_attestation_score() {
@@ -769,7 +890,7 @@ mod tests {
             let att2 = signed_attestation(&committee, keypairs, .., slot, state, spec, None);
 
             assert_eq!(
-                att1.aggregation_bitfield.num_set_bits(),
+                att1.aggregation_bitfield.num_set_bits() as u64 * spec.max_deposit_amount,
                 attestation_score(&att1, state, spec)
             );
 
@@ -778,12 +899,37 @@ mod tests {
                 .push(PendingAttestation::from_attestation(&att1, state.slot));
 
             assert_eq!(
-                committee.committee.len() - 2,
+                (committee.committee.len() - 2) as u64 * spec.max_deposit_amount,
                 attestation_score(&att2, state, spec)
             );
         }
     }
 
+    /// The score of an attestation should be weighted by the balances of its fresh validators.
+    #[test]
+    #[cfg(not(debug_assertions))]
+    fn test_attestation_score_balance_weighted() {
+        let spec = &ChainSpec::foundation();
+        let (ref mut state, ref keypairs) = attestation_test_state(spec, 1);
+        let slot = state.slot - 1;
+        let committees = state
+            .get_crosslink_committees_at_slot(slot, spec)
+            .unwrap()
+            .clone();
+
+        for committee in committees {
+            let att = signed_attestation(&committee, keypairs, ..2, slot, state, spec, None);
+
+            let poor_validator = committee.committee[0];
+            state.validator_balances[poor_validator] = spec.max_deposit_amount / 2;
+
+            assert_eq!(
+                spec.max_deposit_amount + spec.max_deposit_amount / 2,
+                attestation_score(&att, state, spec)
+            );
+        }
+    }
+
     /// End-to-end test of basic attestation handling.
     #[test]
     #[cfg(not(debug_assertions))]
@@ -983,5 +1129,164 @@ mod tests {
         }
     }
 
+    /// Two overlapping aggregates should not both be included if the second adds nothing once
+    /// the first has been chosen.
+    #[test]
+    #[cfg(not(debug_assertions))]
+    fn attestation_get_skips_redundant_aggregates() {
+        let spec = &ChainSpec::foundation();
+        let (ref mut state, ref keypairs) = attestation_test_state(spec, 1);
+        let op_pool = OperationPool::new();
+
+        let slot = state.slot - 1;
+        let committees = state
+            .get_crosslink_committees_at_slot(slot, spec)
+            .unwrap()
+            .clone();
+
+        for committee in &committees {
+            let len = committee.committee.len();
+            // Three aggregates {0..len/2 + 1}, {len/2 - 1..len} and {0..len}. The third covers
+            // both of the others, so it is the only attestation worth including.
+            for range in vec![0..len / 2 + 1, len / 2 - 1..len, 0..len] {
+                let att = signed_attestation(committee, keypairs, range, slot, state, spec, None);
+                fake_latest_crosslink(&att, state, spec);
+                op_pool.insert_attestation(att, state, spec).unwrap();
+            }
+        }
+
+        assert_eq!(op_pool.num_attestations(), 3 * committees.len());
+
+        state.slot += spec.min_attestation_inclusion_delay;
+        let best_attestations = op_pool.get_attestations(state, spec);
+
+        assert_eq!(best_attestations.len(), committees.len());
+        for (att, committee) in best_attestations.iter().zip(committees.iter()) {
+            assert_eq!(
+                att.aggregation_bitfield.num_set_bits(),
+                committee.committee.len()
+            );
+        }
+    }
+
+    /// Create a signed transfer at the slot of `state`, making the sender withdrawable.
+    #[cfg(not(debug_assertions))]
+    fn signed_transfer(
+        sender: usize,
+        recipient: usize,
+        amount: u64,
+        fee: u64,
+        keypairs: &[Keypair],
+        state: &mut BeaconState,
+        spec: &ChainSpec,
+    ) -> Transfer {
+        state.validator_registry[sender].withdrawable_epoch = state.previous_epoch(spec);
+
+        let mut builder =
+            TestingTransferBuilder::new(sender as u64, recipient as u64, amount, state.slot);
+        builder.set_fee(fee);
+        builder.sign(keypairs[sender].clone(), &state.fork, spec);
+        builder.build()
+    }
+
+    /// Transfers which depend on lower-fee transfers should be included after them, and a sender
+    /// should never be able to overspend across several transfers.
+    #[test]
+    #[cfg(not(debug_assertions))]
+    fn transfer_get_respects_dependencies() {
+        use state_processing::per_block_processing::process_transfers;
+
+        let spec = &ChainSpec::foundation();
+        let (ref mut state, ref keypairs) = attestation_test_state(spec, 1);
+        let balance = spec.max_deposit_amount;
+        let op_pool = OperationPool::new();
+
+        // 0 pays 1, which allows 1 to pay more than its initial balance to 2.
+        let a_to_b = signed_transfer(0, 1, 3 * spec.min_deposit_amount, 1, keypairs, state, spec);
+        let b_to_c = signed_transfer(
+            1,
+            2,
+            balance + spec.min_deposit_amount,
+            10,
+            keypairs,
+            state,
+            spec,
+        );
+        // 3 can afford only one of these.
+        let spend_1 = signed_transfer(3, 4, balance / 2, 5, keypairs, state, spec);
+        let spend_2 = signed_transfer(3, 5, balance / 2, 4, keypairs, state, spec);
+
+        for transfer in vec![a_to_b.clone(), b_to_c.clone(), spend_1.clone(), spend_2] {
+            op_pool.insert_transfer(transfer, state, spec).unwrap();
+        }
+
+        let transfers = op_pool.get_transfers(state, spec);
+        assert_eq!(transfers, vec![spend_1, a_to_b, b_to_c]);
+
+        assert_eq!(
+            process_transfers(&mut state.clone(), &transfers, spec),
+            Ok(())
+        );
+    }
+
+    /// A transfer which fails to execute should not prevent lower-fee transfers from being
+    /// included.
+    #[test]
+    #[cfg(not(debug_assertions))]
+    fn transfer_get_skips_failed_execution() {
+        let spec = &ChainSpec::foundation();
+        let (ref mut state, ref keypairs) = attestation_test_state(spec, 1);
+        let op_pool = OperationPool::new();
+
+        // The recipient is only checked when the transfer is executed.
+        let unknown_recipient = state.validator_registry.len();
+        let bad = signed_transfer(
+            0,
+            unknown_recipient,
+            spec.min_deposit_amount,
+            10,
+            keypairs,
+            state,
+            spec,
+        );
+        let good_1 = signed_transfer(1, 2, spec.min_deposit_amount, 5, keypairs, state, spec);
+        let good_2 = signed_transfer(3, 4, spec.min_deposit_amount, 1, keypairs, state, spec);
+
+        for transfer in vec![bad, good_1.clone(), good_2.clone()] {
+            op_pool.insert_transfer(transfer, state, spec).unwrap();
+        }
+
+        assert_eq!(op_pool.get_transfers(state, spec), vec![good_1, good_2]);
+    }
+
+    /// No more than `max_transfers` should be returned, preferring the highest fees.
+    #[test]
+    #[cfg(not(debug_assertions))]
+    fn transfer_get_max() {
+        let spec = &ChainSpec::foundation();
+        let (ref mut state, ref keypairs) = attestation_test_state(spec, 1);
+        let op_pool = OperationPool::new();
+
+        let num_transfers = spec.max_transfers as usize + 4;
+        for sender in 0..num_transfers {
+            let transfer = signed_transfer(
+                sender,
+                sender + 1,
+                spec.min_deposit_amount,
+                sender as u64,
+                keypairs,
+                state,
+                spec,
+            );
+            op_pool.insert_transfer(transfer, state, spec).unwrap();
+        }
+
+        let transfers = op_pool.get_transfers(state, spec);
+        assert_eq!(transfers.len(), spec.max_transfers as usize);
+        assert!(transfers
+            .iter()
+            .all(|transfer| transfer.fee >= (num_transfers - spec.max_transfers as usize) as u64));
+    }
+
     // TODO: more tests
 }
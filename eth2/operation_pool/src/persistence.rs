@@ -0,0 +1,134 @@
+use crate::{OperationCounts, OperationPool};
+use ssz_derive::{Decode, Encode};
+use types::{
+    Attestation, AttesterSlashing, BeaconState, ChainSpec, Deposit, ProposerSlashing, Transfer,
+    VoluntaryExit,
+};
+
+/// SSZ-serializable version of `OperationPool`.
+///
+/// Operations are stored as flat lists, the indices of the `OperationPool` are rebuilt when the
+/// operations are re-inserted.
+#[derive(Debug, PartialEq, Clone, Default, Encode, Decode)]
+pub struct PersistedOperationPool {
+    pub attestations: Vec<Attestation>,
+    pub deposits: Vec<Deposit>,
+    pub attester_slashings: Vec<AttesterSlashing>,
+    pub proposer_slashings: Vec<ProposerSlashing>,
+    pub voluntary_exits: Vec<VoluntaryExit>,
+    pub transfers: Vec<Transfer>,
+}
+
+impl PersistedOperationPool {
+    /// Take a snapshot of all operations in `operation_pool`.
+    pub fn from_operation_pool(operation_pool: &OperationPool) -> Self {
+        Self {
+            attestations: operation_pool
+                .attestations
+                .read()
+                .values()
+                .flatten()
+                .cloned()
+                .collect(),
+            deposits: operation_pool.deposits.read().values().cloned().collect(),
+            attester_slashings: operation_pool
+                .attester_slashings
+                .read()
+                .values()
+                .cloned()
+                .collect(),
+            proposer_slashings: operation_pool
+                .proposer_slashings
+                .read()
+                .values()
+                .cloned()
+                .collect(),
+            voluntary_exits: operation_pool
+                .voluntary_exits
+                .read()
+                .values()
+                .cloned()
+                .collect(),
+            transfers: operation_pool.transfers.read().iter().cloned().collect(),
+        }
+    }
+
+    /// Re-validate each persisted operation against `state` and insert the valid ones into
+    /// `operation_pool`. Stale operations are then removed with `OperationPool::prune_all`.
+    ///
+    /// Operations that fail validation are silently dropped. Returns the number of operations of
+    /// each type in `operation_pool` after insertion.
+    pub fn insert_into(
+        self,
+        operation_pool: &OperationPool,
+        state: &BeaconState,
+        spec: &ChainSpec,
+    ) -> OperationCounts {
+        for attestation in self.attestations {
+            let _ = operation_pool.insert_attestation(attestation, state, spec);
+        }
+        for deposit in self.deposits {
+            let _ = operation_pool.insert_deposit(deposit, state, spec);
+        }
+        for slashing in self.attester_slashings {
+            let _ = operation_pool.insert_attester_slashing(slashing, state, spec);
+        }
+        for slashing in self.proposer_slashings {
+            let _ = operation_pool.insert_proposer_slashing(slashing, state, spec);
+        }
+        for exit in self.voluntary_exits {
+            let _ = operation_pool.insert_voluntary_exit(exit, state, spec);
+        }
+        for transfer in self.transfers {
+            let _ = operation_pool.insert_transfer(transfer, state, spec);
+        }
+
+        operation_pool.prune_all(state, spec);
+
+        operation_pool.counts()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ssz::{decode, ssz_encode};
+    use types::test_utils::{SeedableRng, TestRandom, XorShiftRng};
+
+    #[test]
+    fn ssz_round_trip() {
+        let mut rng = XorShiftRng::from_seed([42; 16]);
+
+        let persisted = PersistedOperationPool {
+            attestations: vec![Attestation::random_for_test(&mut rng)],
+            deposits: vec![Deposit::random_for_test(&mut rng)],
+            attester_slashings: vec![AttesterSlashing::random_for_test(&mut rng)],
+            proposer_slashings: vec![ProposerSlashing::random_for_test(&mut rng)],
+            voluntary_exits: vec![VoluntaryExit::random_for_test(&mut rng)],
+            transfers: vec![Transfer::random_for_test(&mut rng)],
+        };
+
+        let bytes = ssz_encode(&persisted);
+        let decoded: PersistedOperationPool = decode(&bytes).unwrap();
+
+        assert_eq!(persisted, decoded);
+    }
+
+    #[test]
+    fn invalid_operations_are_dropped() {
+        let mut rng = XorShiftRng::from_seed([42; 16]);
+        let spec = ChainSpec::foundation();
+        let state = BeaconState::random_for_test(&mut rng);
+
+        let persisted = PersistedOperationPool {
+            voluntary_exits: vec![VoluntaryExit::random_for_test(&mut rng)],
+            transfers: vec![Transfer::random_for_test(&mut rng)],
+            ..PersistedOperationPool::default()
+        };
+
+        let op_pool = OperationPool::new();
+        let restored = persisted.insert_into(&op_pool, &state, &spec);
+
+        assert_eq!(restored, OperationCounts::default());
+    }
+}
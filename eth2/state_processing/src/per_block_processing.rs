@@ -21,6 +21,7 @@ pub use verify_transfer::{
 };
 
 pub mod errors;
+mod tests;
 mod validate_attestation;
 mod verify_attester_slashing;
 mod verify_deposit;
@@ -455,14 +456,10 @@ pub fn process_transfers(
         Invalid::MaxTransfersExceed
     );
 
-    transfers
-        .par_iter()
-        .enumerate()
-        .try_for_each(|(i, transfer)| {
-            verify_transfer(&state, transfer, spec).map_err(|e| e.into_with_index(i))
-        })?;
-
+    // Transfers are verified against the state produced by all prior transfers in the block, so
+    // they can't be verified in parallel.
     for (i, transfer) in transfers.iter().enumerate() {
+        verify_transfer(&state, transfer, spec).map_err(|e| e.into_with_index(i))?;
         execute_transfer(state, transfer, spec).map_err(|e| e.into_with_index(i))?;
     }
 
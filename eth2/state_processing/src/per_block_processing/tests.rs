@@ -0,0 +1,87 @@
+#![cfg(test)]
+use crate::per_block_processing::errors::{BlockInvalid, BlockProcessingError, TransferInvalid};
+use crate::per_block_processing::process_transfers;
+use types::test_utils::{TestingBeaconStateBuilder, TestingTransferBuilder};
+use types::*;
+
+/// Returns a state four epochs after genesis, with caches built.
+fn transfer_test_state(spec: &ChainSpec) -> (BeaconState, Vec<Keypair>) {
+    let mut builder = TestingBeaconStateBuilder::from_deterministic_keypairs(8, spec);
+    let slot = (spec.genesis_epoch + 4).start_slot(spec.slots_per_epoch);
+    builder.teleport_to_slot(slot, spec);
+    builder.build_caches(spec).unwrap();
+    builder.build()
+}
+
+/// Create a signed transfer at the slot of `state`, making the sender withdrawable.
+fn signed_transfer(
+    sender: usize,
+    recipient: usize,
+    amount: u64,
+    keypairs: &[Keypair],
+    state: &mut BeaconState,
+    spec: &ChainSpec,
+) -> Transfer {
+    state.validator_registry[sender].withdrawable_epoch = state.previous_epoch(spec);
+
+    let mut builder =
+        TestingTransferBuilder::new(sender as u64, recipient as u64, amount, state.slot);
+    builder.sign(keypairs[sender].clone(), &state.fork, spec);
+    builder.build()
+}
+
+/// A transfer may spend funds received from an earlier transfer in the same block.
+#[test]
+fn transfers_see_the_effects_of_prior_transfers() {
+    let spec = &ChainSpec::few_validators();
+    let (ref mut state, ref keypairs) = transfer_test_state(spec);
+    let balance = state.validator_balances[1];
+
+    let to_1 = signed_transfer(0, 1, spec.min_deposit_amount, keypairs, state, spec);
+    let from_1 = signed_transfer(
+        1,
+        2,
+        balance + spec.min_deposit_amount,
+        keypairs,
+        state,
+        spec,
+    );
+
+    process_transfers(state, &[to_1, from_1], spec).unwrap();
+
+    assert_eq!(state.validator_balances[1], 0);
+    assert_eq!(
+        state.validator_balances[2],
+        balance * 2 + spec.min_deposit_amount
+    );
+}
+
+/// Transfers which are each valid against the pre-state must not be able to overspend together.
+#[test]
+fn transfers_are_verified_against_the_updated_state() {
+    let spec = &ChainSpec::few_validators();
+    let (ref mut state, ref keypairs) = transfer_test_state(spec);
+    let balance = state.validator_balances[0];
+
+    // Together these leave less than `min_deposit_amount` in the sender's balance.
+    let first = signed_transfer(0, 1, balance / 2, keypairs, state, spec);
+    let second = signed_transfer(
+        0,
+        2,
+        balance / 2 - spec.min_deposit_amount / 2,
+        keypairs,
+        state,
+        spec,
+    );
+
+    match process_transfers(state, &[first, second], spec) {
+        Err(BlockProcessingError::Invalid(BlockInvalid::TransferInvalid(
+            1,
+            TransferInvalid::InvalidResultingFromBalance(..),
+        ))) => {}
+        other => panic!(
+            "Expected the second transfer to be invalid, got {:?}",
+            other
+        ),
+    }
+}
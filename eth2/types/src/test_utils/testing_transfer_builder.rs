@@ -26,6 +26,11 @@ impl TestingTransferBuilder {
         Self { transfer }
     }
 
+    /// Sets the fee paid to the block proposer.
+    pub fn set_fee(&mut self, fee: u64) {
+        self.transfer.fee = fee;
+    }
+
     /// Signs the transfer.
     ///
     /// The keypair must match that of the `from` validator index.